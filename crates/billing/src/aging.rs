@@ -0,0 +1,1153 @@
+//! The accounts-receivable aging report: who owes money, and for how long.
+//!
+//! A client's outstanding balance is worked out from their charges and
+//! payments as of a date. Payments (and credits, i.e. negative charges)
+//! pay off the oldest charges first, so whatever is still unpaid is the
+//! newest charges, each aged from the date it was issued. Voided charges
+//! and their voids cancel out and are left out. A client who has paid more
+//! than they've been charged has a credit, shown as a negative current
+//! amount.
+//!
+//! Each client is grouped under the trainer their newest charge is counted
+//! for on the revenue report (see `charge_trainer_sql`), as it's the newest
+//! charges that are still unpaid. Clients with no charges are grouped under
+//! the trainer they're with on the date of the report.
+use crate::{
+    Money, MoneyError, charge_trainer_sql,
+    client_trainer_sql, latex_backend, money,
+    render_error, sql_error,
+};
+use chrono::NaiveDate;
+use clap::{Arg, ArgMatches, Command};
+use db_commands::{
+    CommandOutputContextExt, OutputRows,
+};
+use documents::{
+    DataTable, DocumentData,
+    write_document_with_options,
+};
+use dolmen::prelude::*;
+use latex::Document;
+use reliquary::prelude::*;
+use std::path::PathBuf;
+use tabled::builder::Builder as TabledBuilder;
+
+#[cfg(feature = "tui")]
+use ratatui::{
+    buffer::Buffer,
+    crossterm::event::{KeyCode, KeyModifiers},
+    layout::{Constraint, Rect},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table, Widget},
+};
+#[cfg(feature = "tui")]
+use tui::{KeyBind, TabImpl, TuiContextExt};
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// The number of days each aging bucket spans.
+pub const AGING_BUCKET_DAYS: i64 = 30;
+
+/// Outstanding amounts by how long they've been owed.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+pub struct AgingBuckets {
+    /// Charges issued less than 30 days ago, less any credit.
+    pub current: Money,
+
+    /// Charges issued 30 to 59 days ago.
+    pub days_30: Money,
+
+    /// Charges issued 60 to 89 days ago.
+    pub days_60: Money,
+
+    /// Charges issued 90 or more days ago.
+    pub days_90_plus: Money,
+
+    /// The total outstanding amount.
+    pub total: Money,
+}
+
+impl AgingBuckets {
+    /// Gets the amounts in order, from current to 90+ days, followed by
+    /// the total.
+    pub fn amounts(&self) -> [Money; 5] {
+        [
+            self.current,
+            self.days_30,
+            self.days_60,
+            self.days_90_plus,
+            self.total,
+        ]
+    }
+
+    /// Adds up two sets of buckets. Fails if they're in different
+    /// currencies.
+    pub fn checked_add(
+        &self,
+        other: &AgingBuckets,
+    ) -> Result<AgingBuckets, MoneyError> {
+        Ok(AgingBuckets {
+            current: self
+                .current
+                .checked_add(other.current)?,
+            days_30: self
+                .days_30
+                .checked_add(other.days_30)?,
+            days_60: self
+                .days_60
+                .checked_add(other.days_60)?,
+            days_90_plus: self
+                .days_90_plus
+                .checked_add(other.days_90_plus)?,
+            total: self
+                .total
+                .checked_add(other.total)?,
+        })
+    }
+}
+
+/// A client's outstanding balance on the aging report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientAging {
+    /// The row ID of the client.
+    pub client: RowId,
+
+    /// The client's name.
+    pub client_name: String,
+
+    /// The client's outstanding amounts.
+    pub buckets: AgingBuckets,
+}
+
+/// The clients grouped under a trainer on the aging report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainerAging {
+    /// The row ID of the trainer, or `None` for clients with no payments or
+    /// sessions to take a trainer from.
+    pub trainer: Option<RowId>,
+
+    /// The trainer's name.
+    pub trainer_name: String,
+
+    /// The trainer's clients with outstanding balances, ordered by name.
+    pub clients: Vec<ClientAging>,
+
+    /// The totals of the trainer's clients.
+    pub buckets: AgingBuckets,
+}
+
+/// The accounts-receivable aging report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgingReport {
+    /// The date the report is worked out on.
+    pub date: NaiveDate,
+
+    /// The trainers with clients who owe money (or are in credit), ordered
+    /// by name. Clients without a trainer come last.
+    pub trainers: Vec<TrainerAging>,
+
+    /// The totals of every client.
+    pub total: AgingBuckets,
+}
+
+/// Works out the aging report: every client's outstanding balance at the
+/// end of a date, bucketed by how long it's been owed. Clients whose
+/// balance is zero are left out.
+///
+/// * `db_connection` - A connection to the database.
+/// * `date` - The date to work the report out on.
+pub fn aging_report(
+    db_connection: &mut DbConnection,
+    date: NaiveDate,
+) -> dolmen::Result<AgingReport> {
+    let connection = db_connection.connection()?;
+    let clients = {
+        let mut stmt = connection
+            .prepare(
+                "SELECT id, COALESCE(name, '') FROM client
+                    ORDER BY name, id",
+            )
+            .map_err(sql_error)?;
+        stmt.query_map([], |r| {
+            Ok((
+                RowId(r.get(0)?),
+                r.get::<_, String>(1)?,
+            ))
+        })
+        .map_err(sql_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sql_error)?
+    };
+
+    let mut trainers: Vec<TrainerAging> = Vec::new();
+    let mut total = AgingBuckets::default();
+    for (client, client_name) in clients {
+        let buckets =
+            client_aging(connection, client, date)?;
+        if buckets.amounts().iter().all(Money::is_zero)
+        {
+            continue;
+        }
+        total = total.checked_add(&buckets)?;
+
+        let (trainer, trainer_name) =
+            client_trainer(connection, client, date)?;
+        let index = match trainers
+            .iter()
+            .position(|t| t.trainer == trainer)
+        {
+            Some(index) => index,
+            None => {
+                trainers.push(TrainerAging {
+                    trainer,
+                    trainer_name,
+                    clients: Vec::new(),
+                    buckets: AgingBuckets::default(),
+                });
+                trainers.len() - 1
+            }
+        };
+        trainers[index].buckets = trainers[index]
+            .buckets
+            .checked_add(&buckets)?;
+        trainers[index].clients.push(ClientAging {
+            client,
+            client_name,
+            buckets,
+        });
+    }
+    trainers.sort_by(|a, b| {
+        (a.trainer.is_none(), &a.trainer_name).cmp(&(
+            b.trainer.is_none(),
+            &b.trainer_name,
+        ))
+    });
+
+    Ok(AgingReport {
+        date,
+        trainers,
+        total,
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// The column headings of the aging table, after the trainer and client.
+const BUCKET_HEADINGS: [&str; 5] = [
+    "Current", "30 days", "60 days", "90+ days",
+    "Total",
+];
+
+/// The name grouping clients who have no trainer.
+const NO_TRAINER: &str = "No trainer";
+
+/// Works out a client's outstanding amounts at the end of a date.
+fn client_aging(
+    connection: &rusqlite::Connection,
+    client: RowId,
+    date: NaiveDate,
+) -> dolmen::Result<AgingBuckets> {
+    // voided charges and their voids cancel out, so neither is aged
+    let mut stmt = connection
+        .prepare_cached(&format!(
+            "SELECT c.date, {}, c.tax
+                FROM charge c
+                WHERE c.client = ?1 AND c.date <= ?2
+                    AND c.voids IS NULL
+                    AND NOT EXISTS (SELECT 1 FROM charge v
+                        WHERE v.voids = c.id AND v.date <= ?2)
+                ORDER BY c.date, c.id",
+            money::money_sql("c.amount")
+        ))
+        .map_err(sql_error)?;
+    let charges = stmt
+        .query_map(
+            rusqlite::params![client.0, date],
+            |r| {
+                let amount = money::read_money(r, 1)?;
+                Ok((
+                    r.get::<_, NaiveDate>(0)?,
+                    amount,
+                    r.get::<_, Option<Money>>(3)?.map(
+                        |tax| {
+                            tax.with_currency(
+                                amount.currency(),
+                            )
+                        },
+                    ),
+                ))
+            },
+        )
+        .map_err(sql_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sql_error)?
+        .into_iter()
+        .map(|(date, amount, tax)| {
+            Ok((
+                date,
+                amount.checked_add(
+                    tax.unwrap_or_default(),
+                )?,
+            ))
+        })
+        .collect::<dolmen::Result<Vec<_>>>()?;
+
+    // refunds are negative payments, so they take back from the credit
+    let mut credit = connection
+        .query_row(
+            &format!(
+                "SELECT {} FROM payment
+                    WHERE client = ?1 AND date <= ?2",
+                money::total_sql("amount")
+            ),
+            rusqlite::params![client.0, date],
+            |r| money::read_total(r, 0),
+        )
+        .map_err(sql_error)?;
+    credit = credit.checked_sub(Money::total(
+        charges
+            .iter()
+            .map(|(_, amount)| *amount)
+            .filter(|amount| amount.is_negative()),
+    )?)?;
+
+    // pay off the oldest charges first
+    let mut buckets = AgingBuckets::default();
+    for (charge_date, amount) in charges {
+        if amount.is_negative() {
+            continue;
+        }
+        let paid = if credit.is_negative() {
+            Money::zero()
+        } else if credit.checked_cmp(amount)?.is_lt() {
+            credit
+        } else {
+            amount
+        };
+        credit = credit.checked_sub(paid)?;
+        let unpaid = amount.checked_sub(paid)?;
+        let age = (date - charge_date).num_days();
+        let bucket = match age / AGING_BUCKET_DAYS {
+            0 => &mut buckets.current,
+            1 => &mut buckets.days_30,
+            2 => &mut buckets.days_60,
+            _ => &mut buckets.days_90_plus,
+        };
+        *bucket = bucket.checked_add(unpaid)?;
+        buckets.total =
+            buckets.total.checked_add(unpaid)?;
+    }
+    buckets.current =
+        buckets.current.checked_sub(credit)?;
+    buckets.total =
+        buckets.total.checked_sub(credit)?;
+
+    Ok(buckets)
+}
+
+/// Gets the trainer a client is grouped under on the aging report, and
+/// their name. See the module docs.
+fn client_trainer(
+    connection: &rusqlite::Connection,
+    client: RowId,
+    date: NaiveDate,
+) -> dolmen::Result<(Option<RowId>, String)> {
+    let trainer = connection
+        .query_row(
+            &format!(
+                "SELECT COALESCE(
+                    (SELECT {} FROM charge c
+                        WHERE c.client = ?1 AND c.date <= ?2
+                        ORDER BY c.date DESC, c.id DESC LIMIT 1),
+                    {})",
+                charge_trainer_sql(),
+                client_trainer_sql("?1", "?2")
+            ),
+            rusqlite::params![client.0, date],
+            |r| r.get::<_, Option<i64>>(0),
+        )
+        .map_err(sql_error)?;
+    let Some(trainer) = trainer else {
+        return Ok((None, NO_TRAINER.into()));
+    };
+    let name = connection
+        .query_row(
+            "SELECT COALESCE(name, '') FROM trainer WHERE id = ?1",
+            [trainer],
+            |r| r.get::<_, String>(0),
+        )
+        .map_err(sql_error)?;
+    Ok((Some(RowId(trainer)), name))
+}
+
+/// A row of the aging table.
+struct AgingRow {
+    /// The trainer, the client (or `"Total"`) and the amounts in each
+    /// bucket.
+    cells: [String; 7],
+    /// Whether the row totals a trainer's clients or everyone's.
+    is_total: bool,
+}
+
+/// Gets the rows of the aging table: a row per client, a total row per
+/// trainer, and a total row for everyone.
+fn aging_rows(report: &AgingReport) -> Vec<AgingRow> {
+    let row = |trainer: &str,
+               client: &str,
+               buckets: &AgingBuckets,
+               is_total: bool| {
+        let [
+            current,
+            days_30,
+            days_60,
+            days_90,
+            total,
+        ] = buckets.amounts().map(|m| m.to_string());
+        AgingRow {
+            cells: [
+                trainer.into(),
+                client.into(),
+                current,
+                days_30,
+                days_60,
+                days_90,
+                total,
+            ],
+            is_total,
+        }
+    };
+
+    let mut rows = Vec::new();
+    for trainer in &report.trainers {
+        for client in &trainer.clients {
+            rows.push(row(
+                &trainer.trainer_name,
+                &client.client_name,
+                &client.buckets,
+                false,
+            ));
+        }
+        rows.push(row(
+            &trainer.trainer_name,
+            "Total",
+            &trainer.buckets,
+            true,
+        ));
+    }
+    rows.push(row(
+        "All trainers",
+        "Total",
+        &report.total,
+        true,
+    ));
+    rows
+}
+
+/// Generates a LaTeX document from the aging report.
+///
+/// * `report` - The aging report.
+fn generate_aging_latex(
+    report: &AgingReport,
+) -> dolmen::Result<Document> {
+    let mut data = DocumentData::default();
+    data.set("asof", report.date.to_string());
+    data.set(
+        "currency",
+        report.total.current.currency().code(),
+    );
+    for (name, amount) in [
+        "totalcurrent",
+        "totaldays30",
+        "totaldays60",
+        "totaldays90",
+        "total",
+    ]
+    .into_iter()
+    .zip(report.total.amounts())
+    {
+        data.set(name, amount.to_decimal_string());
+    }
+
+    let mut aging_data = DataTable::new([
+        "heading", "client", "current", "days30",
+        "days60", "days90", "total", "istotal",
+    ]);
+    let push_row =
+        |aging_data: &mut DataTable,
+         heading: &str,
+         client: &str,
+         buckets: Option<&AgingBuckets>,
+         is_total: bool| {
+            let amounts = buckets
+                .map(|b| {
+                    b.amounts()
+                        .map(|m| m.to_decimal_string())
+                })
+                .unwrap_or_default();
+            let [
+                current,
+                days_30,
+                days_60,
+                days_90,
+                total,
+            ] = amounts;
+            aging_data.push_row([
+                heading.into(),
+                client.into(),
+                current,
+                days_30,
+                days_60,
+                days_90,
+                total,
+                if is_total { "1" } else { "" }.into(),
+            ]);
+        };
+    for trainer in &report.trainers {
+        push_row(
+            &mut aging_data,
+            &trainer.trainer_name,
+            "",
+            None,
+            false,
+        );
+        for client in &trainer.clients {
+            push_row(
+                &mut aging_data,
+                "",
+                &client.client_name,
+                Some(&client.buckets),
+                false,
+            );
+        }
+        push_row(
+            &mut aging_data,
+            "",
+            "Total",
+            Some(&trainer.buckets),
+            true,
+        );
+    }
+    data.set_table("agingdata", aging_data);
+
+    latex_backend(include_str!("aging_template.tex"))
+        .render(&data)
+        .map_err(render_error)
+}
+
+/// Creates the `aging` subcommand of the `billing` command.
+pub(crate) fn aging_command() -> Command {
+    crate::structured(Command::new("aging")
+        .about("Shows every client's outstanding balance by how long it's \
+            been owed, with totals per trainer")
+        .arg(Arg::new("date")
+            .long("date")
+            .value_parser(clap::value_parser!(NaiveDate))
+            .help("The date to work the balances out on (YYYY-MM-DD). \
+                Defaults to today.")
+        )
+        .arg(Arg::new("out-dir")
+            .long("out-dir")
+            .value_parser(clap::value_parser!(PathBuf))
+            .help("A folder to also output the report to as a PDF")
+        )
+        .arg(Arg::new("verbose")
+            .long("verbose")
+            .action(clap::ArgAction::SetTrue)
+            .help("Print the LaTeX command and its output")
+        ))
+}
+
+/// Processes the `aging` subcommand of the `billing` command.
+pub(crate) fn process_aging_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let date = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(chrono::Local::now().date_naive());
+    let out_folder =
+        arg_matches.get_one::<PathBuf>("out-dir");
+
+    let report =
+        aging_report(context.db_connection()?, date)?;
+
+    let mut output = OutputRows::new([
+        ("trainer", "String"),
+        ("client", "String"),
+        ("current", "Money"),
+        ("days_30", "Money"),
+        ("days_60", "Money"),
+        ("days_90_plus", "Money"),
+        ("total", "Money"),
+    ]);
+    for trainer in &report.trainers {
+        for client in &trainer.clients {
+            let [
+                current,
+                days_30,
+                days_60,
+                days_90,
+                total,
+            ] = client
+                .buckets
+                .amounts()
+                .map(|m| m.to_decimal_string());
+            output.push_row([
+                trainer.trainer_name.clone().into(),
+                client.client_name.clone().into(),
+                current.into(),
+                days_30.into(),
+                days_60.into(),
+                days_90.into(),
+                total.into(),
+            ]);
+        }
+    }
+    context.set_output_rows(output);
+
+    let mut response_text = if report
+        .trainers
+        .is_empty()
+    {
+        format!("No outstanding balances on {}.", date)
+    } else {
+        let mut tabled_builder =
+            TabledBuilder::default();
+        tabled_builder.push_record(
+            ["Trainer", "Client"]
+                .into_iter()
+                .chain(BUCKET_HEADINGS),
+        );
+        for row in aging_rows(&report) {
+            tabled_builder.push_record(row.cells);
+        }
+        format!(
+            "Accounts receivable aging as of {}:\n{}",
+            date,
+            tabled_builder.build()
+        )
+    };
+
+    if let Some(out_folder) = out_folder {
+        let doc = generate_aging_latex(&report)?;
+        write_document_with_options(
+            out_folder.as_path(),
+            "aging",
+            &doc,
+            &crate::write_options(arg_matches),
+        )
+        .map_err(|e| {
+            dolmen::Error::new(format!(
+                "failed to write document: {}",
+                e
+            ))
+        })?;
+        response_text += &format!(
+            "\nSuccessfully generated aging report at {}.",
+            out_folder.join("aging.pdf").display()
+        );
+    }
+
+    Ok(CommandResponse::new(response_text))
+}
+
+/// A TUI tab showing the aging report.
+#[cfg(feature = "tui")]
+pub(crate) struct AgingTabImpl;
+
+#[cfg(feature = "tui")]
+pub(crate) struct AgingTabState {
+    /// The date the report is worked out on.
+    date: NaiveDate,
+    /// The report for `date`, worked out when the tab is first drawn
+    /// and again after the date changes or the tab is refreshed.
+    report: Option<AgingReport>,
+}
+
+#[cfg(feature = "tui")]
+impl Default for AgingTabState {
+    fn default() -> Self {
+        Self {
+            date: chrono::Local::now().date_naive(),
+            report: None,
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+impl TabImpl for AgingTabImpl {
+    type State = AgingTabState;
+
+    fn title() -> String {
+        "Aging".into()
+    }
+
+    fn render(
+        context: &mut Context,
+        buffer: &mut Buffer,
+        rect: Rect,
+        block: Block,
+        tab_id: usize,
+    ) {
+        if let Err(e) = render_aging(
+            context,
+            tab_id,
+            block.clone(),
+            rect,
+            buffer,
+        ) {
+            Paragraph::new(Line::from(
+                e.message()
+                    .clone()
+                    .unwrap_or_default(),
+            ))
+            .block(block)
+            .render(rect, buffer);
+        }
+    }
+
+    fn keybinds() -> Vec<KeyBind> {
+        vec![
+            KeyBind {
+                name: "prev_day".into(),
+                display_key: "Left".into(),
+                display_name: "Prev Day".into(),
+                key_code: KeyCode::Left,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "next_day".into(),
+                display_key: "Right".into(),
+                display_name: "Next Day".into(),
+                key_code: KeyCode::Right,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "prev_period".into(),
+                display_key: "PgUp".into(),
+                display_name: "Back 30 Days".into(),
+                key_code: KeyCode::PageUp,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "next_period".into(),
+                display_key: "PgDn".into(),
+                display_name: "Forward 30 Days".into(),
+                key_code: KeyCode::PageDown,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "today".into(),
+                display_key: "Home".into(),
+                display_name: "Today".into(),
+                key_code: KeyCode::Home,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "refresh".into(),
+                display_key: "R".into(),
+                display_name: "Refresh".into(),
+                key_code: KeyCode::Char('r'),
+                modifiers: KeyModifiers::NONE,
+            },
+        ]
+    }
+
+    fn handle_key(
+        context: &mut Context,
+        bind: &str,
+        tab_id: usize,
+    ) {
+        let Ok(state) = context
+            .tab_state_mut::<AgingTabState>(tab_id)
+        else {
+            return;
+        };
+        match bind {
+            "prev_day" => {
+                state.date -= chrono::Duration::days(1)
+            }
+            "next_day" => {
+                state.date += chrono::Duration::days(1)
+            }
+            "prev_period" => {
+                state.date -= chrono::Duration::days(
+                    AGING_BUCKET_DAYS,
+                )
+            }
+            "next_period" => {
+                state.date += chrono::Duration::days(
+                    AGING_BUCKET_DAYS,
+                )
+            }
+            "today" => {
+                state.date =
+                    chrono::Local::now().date_naive()
+            }
+            "refresh" => {}
+            _ => return,
+        }
+        // work the report out again on the next draw
+        state.report = None;
+    }
+
+    fn handle_text(
+        _: &mut Context,
+        _: ratatui::crossterm::event::Event,
+        _: usize,
+    ) {
+    }
+}
+
+#[cfg(feature = "tui")]
+fn render_aging(
+    context: &mut Context,
+    tab_id: usize,
+    block: Block,
+    rect: Rect,
+    buffer: &mut Buffer,
+) -> dolmen::Result<()> {
+    let date = context
+        .tab_state::<AgingTabState>(tab_id)?
+        .date;
+    if context
+        .tab_state::<AgingTabState>(tab_id)?
+        .report
+        .is_none()
+    {
+        let report = aging_report(
+            context.db_connection()?,
+            date,
+        )?;
+        context
+            .tab_state_mut::<AgingTabState>(tab_id)?
+            .report = Some(report);
+    }
+    let Some(report) = &context
+        .tab_state::<AgingTabState>(tab_id)?
+        .report
+    else {
+        return Ok(());
+    };
+
+    let block = block.title(Line::from(format!(
+        " Aging as of {} ",
+        date.format("%B %-d, %Y")
+    )));
+    if report.trainers.is_empty() {
+        Paragraph::new(Line::from(format!(
+            "No outstanding balances on {}.",
+            date
+        )))
+        .block(block)
+        .render(rect, buffer);
+        return Ok(());
+    }
+
+    let rows =
+        aging_rows(report).into_iter().map(|row| {
+            let cells = Row::new(row.cells);
+            if row.is_total {
+                cells.bold()
+            } else {
+                cells
+            }
+        });
+    let widths = [
+        Constraint::Fill(2),
+        Constraint::Fill(2),
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+    ];
+    let table = Table::new(rows, widths)
+        .column_spacing(1)
+        .header(
+            Row::new(
+                ["Trainer", "Client"]
+                    .into_iter()
+                    .chain(BUCKET_HEADINGS),
+            )
+            .style(Style::new().reversed()),
+        )
+        .block(block);
+    Widget::render(table, rect, buffer);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{
+        add_test_charge, add_test_payment,
+        add_test_row, add_test_session, date,
+        date_time, setup_test_context,
+    };
+
+    // Clarissa's $40 payment pays off most of her oldest charge, leaving
+    // $10 in the 90+ bucket. Her voided charge is left out. Carl has paid
+    // more than he owes, so he's in credit. Cora owes nothing and isn't
+    // listed.
+    #[test]
+    fn test_aging_report() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let tara = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let theo = add_test_row(
+            db_connection,
+            "trainer",
+            "Theo",
+        )?;
+        let clarissa = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        let carl = add_test_row(
+            db_connection,
+            "client",
+            "Carl",
+        )?;
+        add_test_row(db_connection, "client", "Cora")?;
+
+        for (day, dollars) in [
+            ("2026-01-20", 50),
+            ("2026-02-25", 25),
+            ("2026-03-16", 50),
+            ("2026-04-20", 50),
+            ("2026-05-01", 50),
+        ] {
+            add_test_charge(
+                db_connection,
+                clarissa,
+                day,
+                dollars,
+            )?;
+        }
+        let voided = add_test_charge(
+            db_connection,
+            clarissa,
+            "2026-02-19",
+            80,
+        )?;
+        crate::void_charge(
+            db_connection,
+            voided,
+            date("2026-02-20"),
+            "booked twice",
+        )?;
+        add_test_payment(
+            db_connection,
+            clarissa,
+            tara,
+            "2026-04-01",
+            40,
+        )?;
+
+        add_test_charge(
+            db_connection,
+            carl,
+            "2026-04-25",
+            20,
+        )?;
+        add_test_payment(
+            db_connection,
+            carl,
+            theo,
+            "2026-04-26",
+            50,
+        )?;
+
+        let report = aging_report(
+            db_connection,
+            date("2026-04-30"),
+        )?;
+        let clarissa_buckets = AgingBuckets {
+            current: Money::from_dollars(50)?,
+            days_30: Money::from_dollars(50)?,
+            days_60: Money::from_dollars(25)?,
+            days_90_plus: Money::from_dollars(10)?,
+            total: Money::from_dollars(135)?,
+        };
+        let carl_buckets = AgingBuckets {
+            current: Money::from_dollars(-30)?,
+            total: Money::from_dollars(-30)?,
+            ..Default::default()
+        };
+        assert_eq!(
+            report.trainers,
+            vec![
+                TrainerAging {
+                    trainer: Some(tara),
+                    trainer_name: "Tara".into(),
+                    clients: vec![ClientAging {
+                        client: clarissa,
+                        client_name: "Clarissa".into(),
+                        buckets: clarissa_buckets,
+                    }],
+                    buckets: clarissa_buckets,
+                },
+                TrainerAging {
+                    trainer: Some(theo),
+                    trainer_name: "Theo".into(),
+                    clients: vec![ClientAging {
+                        client: carl,
+                        client_name: "Carl".into(),
+                        buckets: carl_buckets,
+                    }],
+                    buckets: carl_buckets,
+                },
+            ]
+        );
+        assert_eq!(
+            report.total.total,
+            Money::from_dollars(105)?
+        );
+
+        let response = context.execute(
+            "billing aging --date=2026-04-30",
+        )?;
+        let text = response.text().unwrap();
+        assert!(text.starts_with(
+            "Accounts receivable aging as of 2026-04-30:"
+        ));
+        assert!(text.contains("Clarissa"));
+        assert!(!text.contains("Cora"));
+
+        let latex = generate_aging_latex(&report)?;
+        let rendered = latex::print(&latex).unwrap();
+        assert!(rendered.contains(
+            "\\multicolumn{6}{|l|}{\\textbf{Tara}} \\\\"
+        ));
+        assert!(rendered.contains(
+            "Clarissa & 50.00 & 50.00 & 25.00 & 10.00 & 135.00 \\\\"
+        ));
+
+        Ok(())
+    }
+
+    // Cleo's newest charge billed a session with Theo, so she's grouped
+    // under him even though she last paid Tara, just as the revenue report
+    // counts what she owes for him.
+    #[test]
+    fn test_aging_trainer() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let tara = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let theo = add_test_row(
+            db_connection,
+            "trainer",
+            "Theo",
+        )?;
+        let cleo = add_test_row(
+            db_connection,
+            "client",
+            "Cleo",
+        )?;
+        add_test_payment(
+            db_connection,
+            cleo,
+            tara,
+            "2026-04-01",
+            10,
+        )?;
+        let session = add_test_session(
+            db_connection,
+            theo,
+            cleo,
+            date_time("2026-04-10T09:00:00"),
+            60,
+        )?;
+        let charge = add_test_charge(
+            db_connection,
+            cleo,
+            "2026-04-10",
+            60,
+        )?;
+        db_connection.set_field_in_table(
+            "session", session, "charge", charge.0,
+        )?;
+
+        let report = aging_report(
+            db_connection,
+            date("2026-04-30"),
+        )?;
+        assert_eq!(
+            report
+                .trainers
+                .iter()
+                .map(|t| t.trainer)
+                .collect::<Vec<_>>(),
+            vec![Some(theo)]
+        );
+
+        let revenue = crate::revenue_report(
+            db_connection,
+            date("2026-04-01"),
+            date("2026-04-30"),
+            crate::RevenueGrouping::Trainer,
+        )?;
+        assert_eq!(
+            revenue
+                .lines
+                .iter()
+                .map(|l| (
+                    l.label.as_str(),
+                    l.outstanding
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "Tara",
+                    Some(Money::from_dollars(-10)?)
+                ),
+                (
+                    "Theo",
+                    Some(Money::from_dollars(60)?)
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_aging_nothing_owed() -> dolmen::Result<()>
+    {
+        let mut context = setup_test_context()?;
+        add_test_row(
+            context.db_connection()?,
+            "client",
+            "Cora",
+        )?;
+
+        let response = context.execute(
+            "billing aging --date=2026-04-30",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "No outstanding balances on 2026-04-30."
+        );
+
+        Ok(())
+    }
+}
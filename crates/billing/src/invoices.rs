@@ -0,0 +1,1449 @@
+//! Invoices: bills issued to a client before they pay.
+//!
+//! A receipt (see `invoice generate --payment-id`) is written after a
+//! payment. An invoice comes first: it collects a client's charges that
+//! haven't been invoiced yet, gets its own number from the trainer's
+//! invoice pattern, and asks for payment by a due date. Payments are then
+//! applied against it. Its status moves from draft to sent when it's sent,
+//! to overdue once the due date has passed, and to paid once payments
+//! cover the total. An invoice with nothing to pay (e.g. its charges are
+//! voided on it too) is paid straight away.
+use crate::receipts::allocate_invoice_number;
+use crate::{
+    CHARGE_COLUMNS, CHARGE_TABLE, Money,
+    charge_trainer_sql, latex_backend, money,
+    render_error, set_trainer_data, sql_error,
+};
+use chrono::NaiveDate;
+use clap::{Arg, ArgMatches, Command};
+use db_commands::{
+    CommandOutputContextExt, OutputRows,
+    with_transaction,
+};
+use documents::{
+    DataTable, DocumentData, WriteOptions,
+    write_document_with_options,
+};
+use dolmen::prelude::*;
+use latex::Document;
+use reliquary::prelude::*;
+use rusqlite::types::{
+    FromSql, FromSqlError, FromSqlResult, ToSql,
+    ToSqlOutput, ValueRef,
+};
+use std::path::{Path, PathBuf};
+use tabled::builder::Builder as TabledBuilder;
+use training::{Client, Trainer};
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// The number of days after the issue date an invoice is due, unless a due
+/// date is given.
+pub const DEFAULT_PAYMENT_TERMS_DAYS: i64 = 14;
+
+/// A table row storing an invoice issued to a client. Stored in the table
+/// `invoice`. Its line items are the charges whose `invoice` is this row.
+#[derive(TableRow, Debug)]
+pub struct Invoice {
+    /// The client the invoice is issued to.
+    #[display_table("client", "name")]
+    pub client: RowId,
+
+    /// The trainer issuing the invoice.
+    #[display_table("trainer", "name")]
+    pub trainer: RowId,
+
+    /// The invoice number (e.g. `"INV-2026-0001"`). See
+    /// `allocate_invoice_number`.
+    pub number: String,
+
+    /// The date the invoice was issued.
+    pub issue_date: NaiveDate,
+
+    /// The date payment is due by.
+    pub due_date: NaiveDate,
+
+    /// Where the invoice is in its life.
+    pub status: InvoiceStatus,
+}
+
+/// The status of an invoice. Stored in the database as lowercase text
+/// (e.g. `"overdue"`).
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+pub enum InvoiceStatus {
+    /// The invoice has been issued but not sent to the client yet.
+    #[default]
+    Draft,
+
+    /// The invoice has been sent and isn't due yet.
+    Sent,
+
+    /// Payments applied to the invoice cover its total.
+    Paid,
+
+    /// The invoice has been sent and its due date has passed without it
+    /// being paid.
+    Overdue,
+}
+
+impl InvoiceStatus {
+    /// All invoice statuses.
+    pub const ALL: [InvoiceStatus; 4] = [
+        InvoiceStatus::Draft,
+        InvoiceStatus::Sent,
+        InvoiceStatus::Paid,
+        InvoiceStatus::Overdue,
+    ];
+
+    /// Gets the name the status is stored and parsed as.
+    pub fn name(&self) -> &'static str {
+        match self {
+            InvoiceStatus::Draft => "draft",
+            InvoiceStatus::Sent => "sent",
+            InvoiceStatus::Paid => "paid",
+            InvoiceStatus::Overdue => "overdue",
+        }
+    }
+}
+
+impl std::fmt::Display for InvoiceStatus {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for InvoiceStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        InvoiceStatus::ALL
+            .into_iter()
+            .find(|status| {
+                status.name().eq_ignore_ascii_case(s.trim())
+            })
+            .ok_or(format!(
+                "unknown invoice status: {} (expected one of \
+                    draft, sent, paid, overdue)",
+                s
+            ))
+    }
+}
+
+impl ToSql for InvoiceStatus {
+    fn to_sql(
+        &self,
+    ) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.name()))
+    }
+}
+
+impl FromSql for InvoiceStatus {
+    /// Reads a status stored as text. Invoices added by hand without a
+    /// status (NULL) are drafts.
+    fn column_result(
+        value: ValueRef<'_>,
+    ) -> FromSqlResult<Self> {
+        if let ValueRef::Null = value {
+            return Ok(InvoiceStatus::Draft);
+        }
+        value.as_str()?.parse().map_err(|e: String| {
+            FromSqlError::Other(e.into())
+        })
+    }
+}
+
+/// Issues a draft invoice for every charge of a client's for the trainer
+/// that hasn't been invoiced yet, up to and including the issue date.
+/// Charges that can't be tied to any trainer (see `revenue`) are included
+/// too. Returns the row ID of the invoice, which is paid straight away if
+/// its total isn't above zero.
+///
+/// Fails if the client has no such charges, or if the invoice would be
+/// due before it's issued.
+///
+/// * `db_connection` - A connection to the database.
+/// * `client` - The row ID of the client.
+/// * `trainer` - The row ID of the trainer issuing the invoice.
+/// * `issue_date` - The date the invoice is issued.
+/// * `due_date` - The date payment is due by.
+pub fn issue_invoice(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    trainer: RowId,
+    issue_date: NaiveDate,
+    due_date: NaiveDate,
+) -> dolmen::Result<RowId> {
+    if due_date < issue_date {
+        return Err(dolmen::Error::new(format!(
+            "invoice due date {} is before its issue date {}",
+            due_date, issue_date
+        )));
+    }
+
+    let charges = {
+        let connection = db_connection.connection()?;
+        let mut stmt = connection
+            .prepare_cached(&format!(
+                "SELECT c.id FROM charge c
+                    WHERE c.client = ?1 AND c.invoice IS NULL
+                        AND c.date <= ?2
+                        AND COALESCE({}, ?3) = ?3
+                    ORDER BY c.date, c.id",
+                charge_trainer_sql()
+            ))
+            .map_err(sql_error)?;
+        stmt.query_map(
+            rusqlite::params![
+                client.0, issue_date, trainer.0
+            ],
+            |r| r.get::<_, i64>(0),
+        )
+        .map_err(sql_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sql_error)?
+    };
+    if charges.is_empty() {
+        return Err(dolmen::Error::new(format!(
+            "client {} has no charges for trainer {} to invoice up to {}",
+            client.0, trainer.0, issue_date
+        )));
+    }
+
+    with_transaction(db_connection, |db_connection| {
+        insert_invoice(
+            db_connection,
+            client,
+            trainer,
+            issue_date,
+            due_date,
+            &charges,
+        )
+    })
+}
+
+/// Gets the total of an invoice: the sum of the charges on it, tax
+/// included.
+///
+/// * `db_connection` - A connection to the database.
+/// * `invoice` - The row ID of the invoice.
+pub fn invoice_total(
+    db_connection: &mut DbConnection,
+    invoice: RowId,
+) -> dolmen::Result<Money> {
+    db_connection
+        .connection()?
+        .query_row(
+            &format!(
+                "SELECT {} FROM charge WHERE invoice = ?1",
+                money::total_with_sql("amount", Some("tax"))
+            ),
+            [invoice.0],
+            |r| money::read_total(r, 0),
+        )
+        .map_err(sql_error)
+}
+
+/// Gets the amount paid against an invoice: the payments applied to it,
+/// less anything refunded of them.
+///
+/// * `db_connection` - A connection to the database.
+/// * `invoice` - The row ID of the invoice.
+pub fn invoice_paid(
+    db_connection: &mut DbConnection,
+    invoice: RowId,
+) -> dolmen::Result<Money> {
+    db_connection
+        .connection()?
+        .query_row(
+            &format!(
+                "SELECT {} FROM payment
+                    WHERE invoice = ?1
+                        OR refunds IN
+                            (SELECT id FROM payment WHERE invoice = ?1)",
+                money::total_sql("amount")
+            ),
+            [invoice.0],
+            |r| money::read_total(r, 0),
+        )
+        .map_err(sql_error)
+}
+
+/// Marks a draft invoice as sent. Returns its new status, which is overdue
+/// straight away if it's sent after its due date.
+///
+/// * `db_connection` - A connection to the database.
+/// * `invoice` - The row ID of the invoice.
+/// * `today` - The date the invoice is sent.
+pub fn send_invoice(
+    db_connection: &mut DbConnection,
+    invoice: RowId,
+    today: NaiveDate,
+) -> dolmen::Result<InvoiceStatus> {
+    let status = db_connection
+        .get_field_in_table_row::<InvoiceStatus>(
+            "invoice", invoice, "status",
+        )?;
+    if status != InvoiceStatus::Draft {
+        return Err(dolmen::Error::new(format!(
+            "invoice {} has already been sent (status: {})",
+            invoice.0, status
+        )));
+    }
+    with_transaction(db_connection, |db_connection| {
+        db_connection.set_field_in_table(
+            "invoice",
+            invoice,
+            "status",
+            InvoiceStatus::Sent,
+        )?;
+        update_invoice_status(
+            db_connection,
+            invoice,
+            today,
+        )
+    })
+}
+
+/// Applies a payment against an invoice. Returns the invoice's new status.
+///
+/// Fails if the payment is a refund, has already been applied to an
+/// invoice, is from a different client, or is more than the amount still
+/// due on the invoice.
+///
+/// * `db_connection` - A connection to the database.
+/// * `payment` - The row ID of the payment.
+/// * `invoice` - The row ID of the invoice.
+/// * `today` - The date to work the invoice's status out on.
+pub fn apply_payment(
+    db_connection: &mut DbConnection,
+    payment: RowId,
+    invoice: RowId,
+    today: NaiveDate,
+) -> dolmen::Result<InvoiceStatus> {
+    let (client, amount, refunds, applied) =
+        db_connection
+            .connection()?
+            .query_row(
+                &format!(
+                    "SELECT client, {}, refunds, invoice
+                        FROM payment WHERE id = ?1",
+                    money::money_sql("amount")
+                ),
+                [payment.0],
+                |r| {
+                    Ok((
+                        r.get::<_, i64>(0)?,
+                        money::read_money(r, 1)?,
+                        r.get::<_, Option<i64>>(3)?,
+                        r.get::<_, Option<i64>>(4)?,
+                    ))
+                },
+            )
+            .map_err(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => {
+                    dolmen::Error::new(format!(
+                        "no payment with ID {}",
+                        payment.0
+                    ))
+                }
+                e => sql_error(e),
+            })?;
+    if let Some(refunded) = refunds {
+        return Err(dolmen::Error::new(format!(
+            "payment {} is a refund of payment {}, it can't be applied \
+                to an invoice",
+            payment.0, refunded
+        )));
+    }
+    if let Some(applied) = applied {
+        return Err(dolmen::Error::new(format!(
+            "payment {} has already been applied to invoice {}",
+            payment.0, applied
+        )));
+    }
+
+    let invoice_row = Invoice::from_table_row(
+        db_connection,
+        "invoice".into(),
+        invoice,
+    )?;
+    if invoice_row.client.0 != client {
+        return Err(dolmen::Error::new(format!(
+            "payment {} is from a different client than invoice {}",
+            payment.0, invoice.0
+        )));
+    }
+    let due = invoice_total(db_connection, invoice)?
+        .checked_sub(invoice_paid(
+        db_connection,
+        invoice,
+    )?)?;
+    // also checks the payment is in the invoice's currency
+    if due.checked_sub(amount)?.is_negative() {
+        return Err(dolmen::Error::new(format!(
+            "payment {} of {} is more than the {} due on invoice {}",
+            payment.0, amount, due, invoice.0
+        )));
+    }
+
+    with_transaction(db_connection, |db_connection| {
+        db_connection.set_field_in_table(
+            "payment", payment, "invoice", invoice.0,
+        )?;
+        update_invoice_status(
+            db_connection,
+            invoice,
+            today,
+        )
+    })
+}
+
+/// Brings the status of every invoice up to date: sent invoices past their
+/// due date become overdue, and invoices become paid (or stop being paid,
+/// after a refund) as payments are applied. Returns the invoices whose
+/// status changed, with their new status.
+///
+/// * `db_connection` - A connection to the database.
+/// * `today` - The date to work the statuses out on.
+pub fn update_invoice_statuses(
+    db_connection: &mut DbConnection,
+    today: NaiveDate,
+) -> dolmen::Result<Vec<(RowId, InvoiceStatus)>> {
+    with_transaction(db_connection, |db_connection| {
+        let mut changed = Vec::new();
+        for invoice in db_connection
+            .get_table_row_ids("invoice")?
+        {
+            let before = db_connection
+                .get_field_in_table_row::<InvoiceStatus>(
+                    "invoice", invoice, "status",
+                )?;
+            let after = update_invoice_status(
+                db_connection,
+                invoice,
+                today,
+            )?;
+            if after != before {
+                changed.push((invoice, after));
+            }
+        }
+        Ok(changed)
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// Adds an invoice for a client's charges, and numbers it. See
+/// `issue_invoice`.
+fn insert_invoice(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    trainer: RowId,
+    issue_date: NaiveDate,
+    due_date: NaiveDate,
+    charges: &[i64],
+) -> dolmen::Result<RowId> {
+    let invoice =
+        db_connection.new_row_in_table("invoice")?;
+    db_connection.set_field_in_table(
+        "invoice", invoice, "client", client.0,
+    )?;
+    db_connection.set_field_in_table(
+        "invoice", invoice, "trainer", trainer.0,
+    )?;
+    db_connection.set_field_in_table(
+        "invoice",
+        invoice,
+        "issue_date",
+        issue_date,
+    )?;
+    db_connection.set_field_in_table(
+        "invoice", invoice, "due_date", due_date,
+    )?;
+    db_connection.set_field_in_table(
+        "invoice",
+        invoice,
+        "status",
+        InvoiceStatus::Draft,
+    )?;
+    for charge in charges {
+        db_connection.set_field_in_table(
+            "charge",
+            RowId(*charge),
+            "invoice",
+            invoice.0,
+        )?;
+    }
+    allocate_invoice_number(db_connection, invoice)?;
+    update_invoice_status(
+        db_connection,
+        invoice,
+        issue_date,
+    )?;
+
+    Ok(invoice)
+}
+
+/// Works out an invoice's status from its payments and due date, and
+/// stores it. Drafts stay drafts until they're sent or paid. An invoice
+/// whose total isn't above zero is paid, as there's nothing to pay.
+fn update_invoice_status(
+    db_connection: &mut DbConnection,
+    invoice: RowId,
+    today: NaiveDate,
+) -> dolmen::Result<InvoiceStatus> {
+    let invoice_row = Invoice::from_table_row(
+        db_connection,
+        "invoice".into(),
+        invoice,
+    )?;
+    let total = invoice_total(db_connection, invoice)?;
+    let paid = invoice_paid(db_connection, invoice)?;
+    let has_payments: bool = db_connection
+        .connection()?
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM payment WHERE invoice = ?1)",
+            [invoice.0],
+            |r| r.get(0),
+        )
+        .map_err(sql_error)?;
+
+    let covered =
+        !total.checked_sub(paid)?.is_positive();
+    let status = if covered
+        && (has_payments || !total.is_positive())
+    {
+        InvoiceStatus::Paid
+    } else if invoice_row.status
+        == InvoiceStatus::Draft
+    {
+        InvoiceStatus::Draft
+    } else if invoice_row.due_date < today {
+        InvoiceStatus::Overdue
+    } else {
+        InvoiceStatus::Sent
+    };
+
+    if status != invoice_row.status {
+        db_connection.set_field_in_table(
+            "invoice", invoice, "status", status,
+        )?;
+    }
+    Ok(status)
+}
+
+/// Gets the data filling in an invoice document.
+///
+/// * `db_connection` - A connection to the database.
+/// * `invoice_row_id` - The row ID of the invoice.
+fn invoice_document_data(
+    db_connection: &mut DbConnection,
+    invoice_row_id: RowId,
+) -> dolmen::Result<DocumentData> {
+    // invoices added by hand get their number when they're first written
+    allocate_invoice_number(
+        db_connection,
+        invoice_row_id,
+    )?;
+    let invoice = Invoice::from_table_row(
+        db_connection,
+        "invoice".into(),
+        invoice_row_id,
+    )?;
+    let trainer = Trainer::from_table_row(
+        db_connection,
+        "trainer".into(),
+        invoice.trainer,
+    )?;
+    let client = Client::from_table_row(
+        db_connection,
+        "client".into(),
+        invoice.client,
+    )?;
+    let total =
+        invoice_total(db_connection, invoice_row_id)?;
+    let paid =
+        invoice_paid(db_connection, invoice_row_id)?;
+
+    let connection = db_connection.connection()?;
+    let mut stmt = connection
+        .prepare_cached(&format!(
+            "SELECT id, date, COALESCE(description, ''), {}
+                FROM charge WHERE invoice = ?1
+                ORDER BY date, id",
+            money::money_sql("amount")
+        ))
+        .map_err(sql_error)?;
+    let charges = stmt
+        .query_map([invoice_row_id.0], |r| {
+            Ok((
+                RowId(r.get(0)?),
+                r.get::<_, NaiveDate>(1)?,
+                r.get::<_, String>(2)?,
+                money::read_money(r, 3)?,
+            ))
+        })
+        .map_err(sql_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sql_error)?;
+    drop(stmt);
+
+    let mut data = DocumentData::default();
+    set_trainer_data(&mut data, &trainer)?;
+    data.set("clientname", client.name().clone());
+    data.set("invoicenumber", invoice.number);
+    data.set(
+        "issuedate",
+        invoice.issue_date.to_string(),
+    );
+    data.set("duedate", invoice.due_date.to_string());
+    data.set("currency", total.currency().code());
+
+    let mut charge_data = DataTable::new(
+        CHARGE_COLUMNS
+            .iter()
+            .map(|column| column.name),
+    );
+    for (_, date, description, amount) in &charges {
+        charge_data.push_row([
+            date.to_string(),
+            description.clone(),
+            amount.to_decimal_string(),
+        ]);
+    }
+    data.set_table(CHARGE_TABLE, charge_data);
+    crate::set_tax_data(
+        &mut data,
+        db_connection,
+        &charges
+            .iter()
+            .map(|(id, ..)| *id)
+            .collect::<Vec<_>>(),
+    )?;
+
+    data.set(
+        "subtotal",
+        Money::total(
+            charges.iter().map(|(.., amount)| *amount),
+        )?
+        .to_decimal_string(),
+    );
+    data.set("total", total.to_decimal_string());
+    data.set("amountpaid", paid.to_decimal_string());
+    data.set(
+        "amountdue",
+        total.checked_sub(paid)?.to_decimal_string(),
+    );
+
+    Ok(data)
+}
+
+/// Generates a LaTeX document from an invoice.
+///
+/// * `db_connection` - A connection to the database.
+/// * `invoice_row_id` - The row ID of the invoice.
+pub(crate) fn generate_invoice_latex(
+    db_connection: &mut DbConnection,
+    invoice_row_id: RowId,
+) -> dolmen::Result<Document> {
+    let data = invoice_document_data(
+        db_connection,
+        invoice_row_id,
+    )?;
+    latex_backend(include_str!(
+        "issued_invoice_template.tex"
+    ))
+    .render(&data)
+    .map_err(render_error)
+}
+
+/// Writes an invoice as a PDF named after its number. Returns the path of
+/// the document.
+///
+/// * `db_connection` - A connection to the database.
+/// * `out_path` - The directory to output the document to.
+/// * `invoice_row_id` - The row ID of the invoice.
+/// * `options` - Options for exporting the document.
+pub(crate) fn write_invoice(
+    db_connection: &mut DbConnection,
+    out_path: &Path,
+    invoice_row_id: RowId,
+    options: &WriteOptions,
+) -> dolmen::Result<PathBuf> {
+    let doc = generate_invoice_latex(
+        db_connection,
+        invoice_row_id,
+    )?;
+    let number = db_connection
+        .get_field_in_table_row::<String>(
+            "invoice",
+            invoice_row_id,
+            "number",
+        )?;
+    // invoice numbers can contain slashes and the like
+    let file_name = format!(
+        "invoice-{}",
+        number
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric()
+                    || c == '-'
+                    || c == '_'
+                {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>()
+    );
+    write_document_with_options(
+        out_path, &file_name, &doc, options,
+    )
+    .map_err(|e| {
+        dolmen::Error::new(format!(
+            "failed to write document: {}",
+            e
+        ))
+    })?;
+    Ok(out_path.join(format!("{}.pdf", file_name)))
+}
+
+/// Creates the `create` subcommand of the `invoice` command.
+pub(crate) fn create_command() -> Command {
+    crate::structured(Command::new("create")
+        .about("Issues a draft invoice for a client's charges that haven't \
+            been invoiced yet")
+        .arg(Arg::new("client-id")
+            .long("client-id")
+            .value_parser(clap::value_parser!(i64))
+            .required(true)
+            .help("The client row ID to invoice.")
+        )
+        .arg(Arg::new("trainer-id")
+            .long("trainer-id")
+            .value_parser(clap::value_parser!(i64))
+            .required(true)
+            .help("The trainer row ID issuing the invoice.")
+        )
+        .arg(Arg::new("date")
+            .long("date")
+            .value_parser(clap::value_parser!(NaiveDate))
+            .help("The issue date (YYYY-MM-DD). Charges up to this date \
+                are invoiced. Defaults to today.")
+        )
+        .arg(Arg::new("due")
+            .long("due")
+            .value_parser(clap::value_parser!(NaiveDate))
+            .help("The due date (YYYY-MM-DD). Defaults to 14 days after \
+                the issue date.")
+        ))
+}
+
+/// Creates the `send` subcommand of the `invoice` command.
+pub(crate) fn send_command() -> Command {
+    Command::new("send")
+        .about("Marks a draft invoice as sent to the client")
+        .arg(Arg::new("invoice-id")
+            .long("invoice-id")
+            .value_parser(clap::value_parser!(i64))
+            .required(true)
+            .help("The invoice row ID to mark as sent.")
+        )
+        .arg(Arg::new("date")
+            .long("date")
+            .value_parser(clap::value_parser!(NaiveDate))
+            .help("The date the invoice is sent (YYYY-MM-DD). Defaults to \
+                today.")
+        )
+}
+
+/// Creates the `apply-payment` subcommand of the `invoice` command.
+pub(crate) fn apply_payment_command() -> Command {
+    Command::new("apply-payment")
+        .about("Applies a payment against an invoice")
+        .arg(Arg::new("payment-id")
+            .long("payment-id")
+            .value_parser(clap::value_parser!(i64))
+            .required(true)
+            .help("The payment row ID to apply.")
+        )
+        .arg(Arg::new("invoice-id")
+            .long("invoice-id")
+            .value_parser(clap::value_parser!(i64))
+            .required(true)
+            .help("The invoice row ID to apply the payment to.")
+        )
+}
+
+/// Creates the `list` subcommand of the `invoice` command.
+pub(crate) fn list_command() -> Command {
+    crate::structured(Command::new("list")
+        .about("Lists invoices with their totals and statuses, bringing \
+            the statuses up to date first")
+        .arg(Arg::new("client-id")
+            .long("client-id")
+            .value_parser(clap::value_parser!(i64))
+            .help("Only list the invoices of this client row ID.")
+        )
+        .arg(Arg::new("date")
+            .long("date")
+            .value_parser(clap::value_parser!(NaiveDate))
+            .help("The date to work out statuses on (YYYY-MM-DD). \
+                Defaults to today.")
+        ))
+}
+
+/// Processes the `create` subcommand of the `invoice` command.
+pub(crate) fn process_create_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let client = RowId(
+        *arg_matches
+            .get_one::<i64>("client-id")
+            .expect("Missing required argument"),
+    );
+    let trainer = RowId(
+        *arg_matches
+            .get_one::<i64>("trainer-id")
+            .expect("Missing required argument"),
+    );
+    let issue_date = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(chrono::Local::now().date_naive());
+    let due_date = arg_matches
+        .get_one::<NaiveDate>("due")
+        .copied()
+        .unwrap_or(
+            issue_date
+                + chrono::Duration::days(
+                    DEFAULT_PAYMENT_TERMS_DAYS,
+                ),
+        );
+
+    let db_connection = context.db_connection()?;
+    let invoice = issue_invoice(
+        db_connection,
+        client,
+        trainer,
+        issue_date,
+        due_date,
+    )?;
+    let number = db_connection
+        .get_field_in_table_row::<String>(
+            "invoice", invoice, "number",
+        )?;
+    let total = invoice_total(db_connection, invoice)?;
+
+    let mut output = OutputRows::new([
+        ("invoice", "i64"),
+        ("number", "String"),
+    ]);
+    output.push_row([
+        invoice.0.into(),
+        number.clone().into(),
+    ]);
+    context.set_output_rows(output);
+
+    Ok(CommandResponse::new(format!(
+        "Issued invoice {} (invoice {}) for {}, due {}.",
+        number, invoice.0, total, due_date
+    )))
+}
+
+/// Processes the `send` subcommand of the `invoice` command.
+pub(crate) fn process_send_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let invoice = RowId(
+        *arg_matches
+            .get_one::<i64>("invoice-id")
+            .expect("Missing required argument"),
+    );
+    let today = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(chrono::Local::now().date_naive());
+
+    let status = send_invoice(
+        context.db_connection()?,
+        invoice,
+        today,
+    )?;
+
+    Ok(CommandResponse::new(format!(
+        "Sent invoice {} (status: {}).",
+        invoice.0, status
+    )))
+}
+
+/// Processes the `apply-payment` subcommand of the `invoice` command.
+pub(crate) fn process_apply_payment_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let payment = RowId(
+        *arg_matches
+            .get_one::<i64>("payment-id")
+            .expect("Missing required argument"),
+    );
+    let invoice = RowId(
+        *arg_matches
+            .get_one::<i64>("invoice-id")
+            .expect("Missing required argument"),
+    );
+
+    let db_connection = context.db_connection()?;
+    let status = apply_payment(
+        db_connection,
+        payment,
+        invoice,
+        chrono::Local::now().date_naive(),
+    )?;
+    let due = invoice_total(db_connection, invoice)?
+        .checked_sub(invoice_paid(
+        db_connection,
+        invoice,
+    )?)?;
+
+    Ok(CommandResponse::new(format!(
+        "Applied payment {} to invoice {} ({} due, status: {}).",
+        payment.0, invoice.0, due, status
+    )))
+}
+
+/// Processes the `list` subcommand of the `invoice` command.
+pub(crate) fn process_list_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let client = arg_matches
+        .get_one::<i64>("client-id")
+        .map(|c| RowId(*c));
+    let today = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(chrono::Local::now().date_naive());
+
+    let db_connection = context.db_connection()?;
+    update_invoice_statuses(db_connection, today)?;
+
+    let mut invoices = Vec::new();
+    for invoice in
+        db_connection.get_table_row_ids("invoice")?
+    {
+        let row = Invoice::from_table_row(
+            db_connection,
+            "invoice".into(),
+            invoice,
+        )?;
+        if client.is_some_and(|c| c != row.client) {
+            continue;
+        }
+        let client_name = Client::from_table_row(
+            db_connection,
+            "client".into(),
+            row.client,
+        )?
+        .name()
+        .clone();
+        let total =
+            invoice_total(db_connection, invoice)?;
+        let paid =
+            invoice_paid(db_connection, invoice)?;
+        invoices.push((
+            invoice,
+            row,
+            client_name,
+            total,
+            paid,
+        ));
+    }
+    invoices.sort_by_key(|(id, row, ..)| {
+        (row.issue_date, id.0)
+    });
+
+    let mut output = OutputRows::new([
+        ("invoice", "i64"),
+        ("number", "String"),
+        ("client", "String"),
+        ("issue_date", "NaiveDate"),
+        ("due_date", "NaiveDate"),
+        ("total", "Money"),
+        ("paid", "Money"),
+        ("status", "String"),
+    ]);
+    for (id, row, client_name, total, paid) in
+        &invoices
+    {
+        output.push_row([
+            id.0.into(),
+            row.number.clone().into(),
+            client_name.clone().into(),
+            row.issue_date.to_string().into(),
+            row.due_date.to_string().into(),
+            total.to_decimal_string().into(),
+            paid.to_decimal_string().into(),
+            row.status.name().into(),
+        ]);
+    }
+    context.set_output_rows(output);
+
+    if invoices.is_empty() {
+        return Ok(CommandResponse::new(
+            "No invoices.",
+        ));
+    }
+
+    let mut tabled_builder = TabledBuilder::default();
+    tabled_builder.push_record([
+        "Invoice", "Number", "Client", "Issued",
+        "Due", "Total", "Paid", "Status",
+    ]);
+    for (id, row, client_name, total, paid) in invoices
+    {
+        tabled_builder.push_record([
+            id.0.to_string(),
+            row.number,
+            client_name,
+            row.issue_date.to_string(),
+            row.due_date.to_string(),
+            total.to_string(),
+            paid.to_string(),
+            row.status.to_string(),
+        ]);
+    }
+    Ok(CommandResponse::new(
+        tabled_builder.build().to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{
+        add_test_charge, add_test_payment,
+        add_test_receipt, add_test_row, date,
+        setup_test_context,
+    };
+
+    // Two charges are invoiced, a third comes after the issue date and is
+    // left for the next invoice. Payments move the invoice through its
+    // statuses.
+    #[test]
+    fn test_invoice_lifecycle() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        let first = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        let second = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-11",
+            45,
+        )?;
+        let later = add_test_charge(
+            db_connection,
+            client,
+            "2026-02-01",
+            50,
+        )?;
+
+        let invoice = issue_invoice(
+            db_connection,
+            client,
+            trainer,
+            date("2026-01-31"),
+            date("2026-02-14"),
+        )?;
+        let invoice_row = Invoice::from_table_row(
+            db_connection,
+            "invoice".into(),
+            invoice,
+        )?;
+        assert_eq!(
+            invoice_row.number,
+            "INV-2026-0001"
+        );
+        assert_eq!(
+            invoice_row.status,
+            InvoiceStatus::Draft
+        );
+        for (charge, expected) in [
+            (first, Some(invoice.0)),
+            (second, Some(invoice.0)),
+            (later, None),
+        ] {
+            assert_eq!(
+                db_connection
+                    .get_field_in_table_row::<Option<i64>>(
+                        "charge", charge, "invoice",
+                    )?,
+                expected
+            );
+        }
+        assert_eq!(
+            invoice_total(db_connection, invoice)?,
+            Money::from_dollars(95)?
+        );
+
+        // nothing left to invoice up to the same date
+        assert_eq!(
+            issue_invoice(
+                db_connection,
+                client,
+                trainer,
+                date("2026-01-31"),
+                date("2026-02-14"),
+            )
+            .unwrap_err()
+            .message()
+            .clone()
+            .unwrap(),
+            "client 1 has no charges for trainer 1 to invoice up to \
+                2026-01-31"
+        );
+
+        assert_eq!(
+            send_invoice(
+                db_connection,
+                invoice,
+                date("2026-02-01"),
+            )?,
+            InvoiceStatus::Sent
+        );
+        assert_eq!(
+            update_invoice_statuses(
+                db_connection,
+                date("2026-02-15"),
+            )?,
+            vec![(invoice, InvoiceStatus::Overdue)]
+        );
+
+        let too_much = add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-02-16",
+            100,
+        )?;
+        assert_eq!(
+            apply_payment(
+                db_connection,
+                too_much,
+                invoice,
+                date("2026-02-16"),
+            )
+            .unwrap_err()
+            .message()
+            .clone()
+            .unwrap(),
+            "payment 1 of $100.00 is more than the $95.00 due on \
+                invoice 1"
+        );
+
+        let partial = add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-02-16",
+            50,
+        )?;
+        assert_eq!(
+            apply_payment(
+                db_connection,
+                partial,
+                invoice,
+                date("2026-02-16"),
+            )?,
+            InvoiceStatus::Overdue
+        );
+        let rest = add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-02-17",
+            45,
+        )?;
+        assert_eq!(
+            apply_payment(
+                db_connection,
+                rest,
+                invoice,
+                date("2026-02-17"),
+            )?,
+            InvoiceStatus::Paid
+        );
+        assert_eq!(
+            apply_payment(
+                db_connection,
+                rest,
+                invoice,
+                date("2026-02-17"),
+            )
+            .unwrap_err()
+            .message()
+            .clone()
+            .unwrap(),
+            "payment 3 has already been applied to invoice 1"
+        );
+
+        // a refund of an applied payment reopens the invoice
+        crate::refund_payment(
+            db_connection,
+            rest,
+            Some(Money::from_dollars(20)?),
+            date("2026-02-18"),
+            "overcharged",
+        )?;
+        assert_eq!(
+            invoice_paid(db_connection, invoice)?,
+            Money::from_dollars(75)?
+        );
+        assert_eq!(
+            update_invoice_statuses(
+                db_connection,
+                date("2026-02-18"),
+            )?,
+            vec![(invoice, InvoiceStatus::Overdue)]
+        );
+
+        Ok(())
+    }
+
+    // Clarissa trains with Tara, then Theo. Each invoice only takes the
+    // charges of its own trainer, and one whose charges cancel out is
+    // paid straight away. Invoices added by hand start as drafts.
+    #[test]
+    fn test_invoice_trainers() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let tara = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let theo = add_test_row(
+            db_connection,
+            "trainer",
+            "Theo",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2026-01-01",
+            50,
+        )?;
+        let taras = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        add_test_payment(
+            db_connection,
+            client,
+            theo,
+            "2026-01-10",
+            45,
+        )?;
+        let theos = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-11",
+            45,
+        )?;
+
+        let invoice = issue_invoice(
+            db_connection,
+            client,
+            tara,
+            date("2026-01-31"),
+            date("2026-02-14"),
+        )?;
+        for (charge, expected) in
+            [(taras, Some(invoice.0)), (theos, None)]
+        {
+            assert_eq!(
+                db_connection
+                    .get_field_in_table_row::<Option<i64>>(
+                        "charge", charge, "invoice",
+                    )?,
+                expected
+            );
+        }
+        issue_invoice(
+            db_connection,
+            client,
+            theo,
+            date("2026-01-31"),
+            date("2026-02-14"),
+        )?;
+
+        let booked_twice = add_test_charge(
+            db_connection,
+            client,
+            "2026-02-01",
+            45,
+        )?;
+        crate::void_charge(
+            db_connection,
+            booked_twice,
+            date("2026-02-02"),
+            "booked twice",
+        )?;
+        let nothing_due = issue_invoice(
+            db_connection,
+            client,
+            theo,
+            date("2026-02-28"),
+            date("2026-03-14"),
+        )?;
+        assert_eq!(
+            invoice_total(db_connection, nothing_due)?,
+            Money::zero()
+        );
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<InvoiceStatus>(
+                    "invoice",
+                    nothing_due,
+                    "status",
+                )?,
+            InvoiceStatus::Paid
+        );
+
+        let by_hand = db_connection
+            .new_row_in_table("invoice")?;
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<InvoiceStatus>(
+                    "invoice", by_hand, "status",
+                )?,
+            InvoiceStatus::Draft
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invoice_document() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        db_connection.set_field_in_table(
+            "trainer",
+            trainer,
+            "invoice_pattern",
+            "T{trainer}-{seq:03}",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        let payment = add_test_receipt(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-04",
+            20,
+        )?;
+
+        context.execute(&format!(
+            "invoice create --client-id={} --trainer-id={} \
+                --date=2026-01-05",
+            client.0, trainer.0
+        ))?;
+        let response = context.execute(&format!(
+            "invoice apply-payment --payment-id={} --invoice-id=1",
+            payment.0
+        ))?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Applied payment 1 to invoice 1 ($30.00 due, status: \
+                draft)."
+        );
+
+        let latex = generate_invoice_latex(
+            context.db_connection()?,
+            RowId(1),
+        )?;
+        let rendered = latex::print(&latex).unwrap();
+        assert!(rendered.contains(
+            "\\noindent{\\Large\\textbf{Invoice}} \\\\"
+        ));
+        assert!(rendered.contains(
+            "\\noindent{\\textbf{Invoice Number:} T1-001} \\\\"
+        ));
+        assert!(rendered.contains(
+            "\\noindent{\\textbf{Due Date:} 2026-01-19} \\\\"
+        ));
+        assert!(rendered.contains(
+            "2026-01-04 & Personal training session (60 min) & \
+                50.00 \\\\"
+        ));
+        assert!(rendered.contains(
+            "\\multicolumn{2}{|r|}{\\textbf{Amount due}} & 30.00"
+        ));
+
+        // the receipt for the same payment keeps its own title and
+        // numbering
+        let receipt = crate::generate_latex(
+            context.db_connection()?,
+            payment,
+            None,
+        )?;
+        let rendered = latex::print(&receipt).unwrap();
+        assert!(rendered.contains(
+            "\\noindent{\\Large\\textbf{Receipt}} \\\\"
+        ));
+        assert!(rendered.contains(
+            "\\noindent{\\textbf{Receipt Number:} 2026-0001} \\\\"
+        ));
+
+        Ok(())
+    }
+}
@@ -1,14 +1,89 @@
 //! A plugin for generating invoices and tracking charges.
-use clap::{Arg, ArgMatches, Command};
-use documents::write_document;
-use dolmen::prelude::*;
-use latex::{
-    Document, DocumentClass, Element, PreambleElement,
+mod aging;
+mod invoices;
+mod money;
+mod packages;
+mod receipts;
+mod revenue;
+mod services;
+mod session_charges;
+mod tax;
+mod templates;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod voids;
+
+use chrono::NaiveDate;
+use clap::{Arg, ArgGroup, ArgMatches, Command};
+use documents::{
+    DataTable, DocumentBackend, DocumentData,
+    DocumentError, LatexBackend, Verbosity,
+    WriteOptions, write_document_with_options,
 };
+use dolmen::prelude::*;
+use latex::Document;
 use reliquary::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use templates::{
+    InvoiceFormat, invoice_backend,
+    load_invoice_template,
+};
 use training::{Client, Trainer};
 
+pub use aging::{
+    AGING_BUCKET_DAYS, AgingBuckets, AgingReport,
+    ClientAging, TrainerAging, aging_report,
+};
+pub use invoices::{
+    DEFAULT_PAYMENT_TERMS_DAYS, Invoice,
+    InvoiceStatus, apply_payment, invoice_paid,
+    invoice_total, issue_invoice, send_invoice,
+    update_invoice_statuses,
+};
+pub use money::{
+    Currency, Money, MoneyError, ParseCurrencyError,
+    ParseMoneyError, get_money, set_money,
+};
+pub use packages::{
+    CreditUse, Package, client_credits_on,
+    sell_package, use_session_credit,
+};
+pub use receipts::{
+    DEFAULT_INVOICE_PATTERN, DEFAULT_RECEIPT_PATTERN,
+    ReceiptPattern, allocate_invoice_number,
+    allocate_receipt_number,
+};
+pub use revenue::{
+    RevenueGrouping, RevenueLine, RevenueReport,
+    revenue_report,
+};
+pub use services::{
+    Service, ServicePrice, add_service,
+    charge_service, find_service_price,
+    find_session_service, set_service_price,
+};
+pub use session_charges::{
+    Rate, charge_session, find_session_rate,
+};
+pub use tax::{
+    Percentage, TaxRate, TaxReportLine, add_tax_rate,
+    refresh_charge_tax, set_charge_tax_rate, tax_on,
+    tax_report,
+};
+pub use templates::{
+    CHARGE_COLUMNS, CHARGE_TABLE, INVOICE_VARIABLES,
+    TAX_COLUMNS, TAX_TABLE, TemplateConfig,
+    TemplateVariable, check_invoice_template,
+};
+pub use voids::{
+    LATE_CANCELLATION_HOURS, refund_payment,
+    void_charge,
+};
+
+use db_commands::{
+    CommandOutputContextExt, FieldParsersContextExt,
+    MigrationsContextExt, structured,
+};
 #[cfg(feature = "tui")]
 use tui::{KeyBind, TabImpl};
 
@@ -37,11 +112,36 @@ pub struct Charge {
     /// (e.g. `"Personal training session (60 min)"`)
     pub description: String,
 
-    /// The amount charged.
-    // TODO: replace this with a proper currency field
-    pub amount: i32,
+    /// The amount charged, before tax.
+    pub amount: Money,
+
+    /// The currency of the amount and the tax.
+    pub currency: Currency,
 
     pub client: RowId,
+
+    /// The charge this charge reverses, if it's a void (see `void_charge`).
+    pub voids: Option<RowId>,
+
+    /// Why the charge was voided, if it's a void.
+    pub reason: Option<String>,
+
+    /// The invoice the charge is billed on, or `None` if it hasn't been
+    /// invoiced yet (see `issue_invoice`).
+    pub invoice: Option<RowId>,
+
+    /// The service from the catalog the charge is for, or `None` if it
+    /// wasn't charged from the catalog (see `charge_service`).
+    pub service: Option<RowId>,
+
+    /// The rate the charge is taxed at, or `None` if it isn't taxed (see
+    /// `set_charge_tax_rate`).
+    pub tax_rate: Option<RowId>,
+
+    /// The tax on the charge, worked out when its rate was set and again
+    /// whenever its amount or rate is set. The client owes the amount plus
+    /// the tax.
+    pub tax: Option<Money>,
 }
 
 #[derive(TableRow, Debug)]
@@ -52,12 +152,66 @@ pub struct Payment {
 
     pub client: RowId,
 
-    // TODO: replace this with a proper currency field
-    pub amount: u32,
+    pub amount: Money,
+
+    pub currency: Currency,
 
     pub paid_via: String,
 
     pub receipt_number: String,
+
+    /// The payment this payment pays back, if it's a refund (see
+    /// `refund_payment`). Refunds have negative amounts.
+    pub refunds: Option<RowId>,
+
+    /// Why the payment was refunded, if it's a refund.
+    pub reason: Option<String>,
+
+    /// The invoice the payment is applied against, if any (see
+    /// `apply_payment`).
+    pub invoice: Option<RowId>,
+}
+
+/// Reads a charge, with its amount and tax in the charge's currency.
+/// `Charge::from_table_row` reads amounts in the default currency, as a
+/// column on its own doesn't know the row's currency.
+///
+/// * `db_connection` - A connection to the database.
+/// * `charge` - The row ID of the charge.
+pub fn get_charge(
+    db_connection: &mut DbConnection,
+    charge: RowId,
+) -> dolmen::Result<Charge> {
+    let mut charge = Charge::from_table_row(
+        db_connection,
+        "charge".into(),
+        charge,
+    )?;
+    charge.amount =
+        charge.amount.with_currency(charge.currency);
+    charge.tax = charge
+        .tax
+        .map(|tax| tax.with_currency(charge.currency));
+    Ok(charge)
+}
+
+/// Reads a payment, with its amount in the payment's currency (see
+/// `get_charge`).
+///
+/// * `db_connection` - A connection to the database.
+/// * `payment` - The row ID of the payment.
+pub fn get_payment(
+    db_connection: &mut DbConnection,
+    payment: RowId,
+) -> dolmen::Result<Payment> {
+    let mut payment = Payment::from_table_row(
+        db_connection,
+        "payment".into(),
+        payment,
+    )?;
+    payment.amount =
+        payment.amount.with_currency(payment.currency);
+    Ok(payment)
 }
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -75,36 +229,307 @@ impl Plugin for BillingPlugin {
             ))
             .add_table(TableConfig::new::<Payment>(
                 "payment",
+            ))
+            .add_table(TableConfig::new::<Rate>(
+                "rate",
+            ))
+            .add_table(TableConfig::new::<Package>(
+                "package",
+            ))
+            .add_table(TableConfig::new::<CreditUse>(
+                "credit_use",
+            ))
+            .add_table(TableConfig::new::<Invoice>(
+                "invoice",
+            ))
+            .add_table(TableConfig::new::<Service>(
+                "service",
+            ))
+            .add_table(
+                TableConfig::new::<ServicePrice>(
+                    "service_price",
+                ),
+            )
+            .add_table(TableConfig::new::<TaxRate>(
+                "tax_rate",
             ));
 
+        // let the `set` command parse amounts, currencies, invoice statuses
+        // and tax rates, and `export` write amounts as plain decimals
+        context.add_field_type::<Money>();
+        context.add_field_export::<Money>(
+            &[("", "decimal")],
+            money::export_money,
+        );
+        context.add_field_type::<Currency>();
+        context.add_field_type::<InvoiceStatus>();
+        context.add_field_type::<Percentage>();
+
+        // voids and refunds refer to rows of their own tables
+        context.add_field_reference(
+            "charge", "voids", "charge",
+        );
+        context.add_field_reference(
+            "payment", "refunds", "payment",
+        );
+
+        // payments are numbered as soon as they're recorded
+        for field in ["date", "trainer", "amount"] {
+            context.add_field_set_hook(
+                "payment",
+                field,
+                receipts::number_recorded_payment,
+            );
+        }
+
+        // and keep their numbers from then on
+        context.add_table_setup(
+            "payment",
+            receipts::create_receipt_number_triggers,
+        );
+
+        // a charge's tax is worked out from its amount and tax rate
+        for field in ["amount", "tax_rate"] {
+            context.add_field_set_hook(
+                "charge",
+                field,
+                |context, charge| {
+                    refresh_charge_tax(
+                        context.db_connection()?,
+                        charge,
+                    )?;
+                    Ok(None)
+                },
+            );
+        }
+
+        // amounts used to be stored as whole dollars
+        context.add_migration(
+            "charge",
+            1,
+            "store amounts in cents",
+            |c| amounts_to_cents(c, "charge"),
+        );
+        context.add_migration(
+            "payment",
+            1,
+            "store amounts in cents",
+            |c| amounts_to_cents(c, "payment"),
+        );
+        context.add_migration(
+            "charge",
+            2,
+            "add voids and reason",
+            |c| {
+                db_commands::add_column(
+                    c, "charge", "voids", "INTEGER",
+                )?;
+                db_commands::add_column(
+                    c, "charge", "reason", "TEXT",
+                )
+            },
+        );
+        context.add_migration(
+            "payment",
+            2,
+            "add refunds and reason",
+            |c| {
+                db_commands::add_column(
+                    c, "payment", "refunds", "INTEGER",
+                )?;
+                db_commands::add_column(
+                    c, "payment", "reason", "TEXT",
+                )
+            },
+        );
+        context.add_migration(
+            "charge",
+            3,
+            "add invoice",
+            |c| {
+                db_commands::add_column(
+                    c, "charge", "invoice", "INTEGER",
+                )
+            },
+        );
+        context.add_migration(
+            "charge",
+            4,
+            "add service",
+            |c| {
+                db_commands::add_column(
+                    c, "charge", "service", "INTEGER",
+                )
+            },
+        );
+        context.add_migration(
+            "charge",
+            5,
+            "add tax_rate and tax",
+            |c| {
+                db_commands::add_column(
+                    c, "charge", "tax_rate", "INTEGER",
+                )?;
+                db_commands::add_column(
+                    c, "charge", "tax", "INTEGER",
+                )
+            },
+        );
+        context.add_migration(
+            "service",
+            1,
+            "add tax_rate",
+            |c| {
+                db_commands::add_column(
+                    c, "service", "tax_rate",
+                    "INTEGER",
+                )
+            },
+        );
+        context.add_migration(
+            "service",
+            2,
+            "add trainer",
+            |c| {
+                db_commands::add_column(
+                    c, "service", "trainer", "INTEGER",
+                )
+            },
+        );
+        context.add_migration(
+            "payment",
+            3,
+            "add invoice",
+            |c| {
+                db_commands::add_column(
+                    c, "payment", "invoice", "INTEGER",
+                )
+            },
+        );
+        context.add_migration(
+            "payment",
+            4,
+            "number receipts on recording",
+            receipts::number_existing_receipts,
+        );
+
+        // amounts used to be in dollars only
+        context.add_migration(
+            "charge",
+            6,
+            "add currency",
+            |c| add_currency(c, "charge"),
+        );
+        context.add_migration(
+            "payment",
+            5,
+            "add currency",
+            |c| add_currency(c, "payment"),
+        );
+
+        // every balance query filters on a client and a date range, so the
+        // charge and payment tables are indexed on (client, date); package
+        // credits are counted per package
+        context.add_table_setup("charge", |c| {
+            c.execute_batch(
+                "CREATE INDEX IF NOT EXISTS charge_client_date
+                    ON charge (client, date);",
+            )
+        });
+        context.add_table_setup("payment", |c| {
+            c.execute_batch(
+                "CREATE INDEX IF NOT EXISTS payment_client_date
+                    ON payment (client, date);",
+            )
+        });
+        context.add_table_setup("package", |c| {
+            c.execute_batch(
+                "CREATE INDEX IF NOT EXISTS package_client
+                    ON package (client);",
+            )
+        });
+        context.add_table_setup(
+            "credit_use",
+            |c| {
+                c.execute_batch(
+                    "CREATE INDEX IF NOT EXISTS credit_use_package
+                        ON credit_use (package, date);",
+                )
+            },
+        );
+
+        // charge sessions as soon as they're completed
+        training::add_session_status_hook(
+            context,
+            session_charges::charge_completed_session,
+        );
+
+        // void or bill cancelled sessions depending on the notice given
+        training::add_session_status_hook(
+            context,
+            voids::apply_cancellation_policy,
+        );
+
+        // look for invoice templates in the config dir unless the
+        // application says otherwise
+        if !context.has_resource::<TemplateConfig>() {
+            context.add_resource(
+                TemplateConfig::default(),
+            );
+        }
+
         // set up invoice command
-        context
-            .add_command(Command::new("invoice")
-                .alias("inv")
-                .about("Invoice related commands")
-                .subcommand(Command::new("generate")
-                    .alias("gen")
-                    .about("Generates an invoice document")
-                    .arg(Arg::new("payment-id")
-                        .long("payment-id")
-                        .value_parser(clap::value_parser!(i64))
-                        .required(true)
-                        .help("The invoice row ID to \
-                            generate a document from.")
-                    )
-                    .arg(Arg::new("out-dir")
-                        .long("out-dir")
-                        .value_parser(clap::value_parser!(PathBuf))
-                        .required(true)
-                        .help("The folder to output the document to")
-                    )
-                ),
-                process_invoice_command
+        context.add_command(
+            invoice_command(),
+            process_invoice_command,
+        )?;
+
+        // set up package command
+        context.add_command(
+            packages::package_command(),
+            packages::process_package_command,
+        )?;
+
+        // set up service command
+        context.add_command(
+            services::service_command(),
+            services::process_service_command,
+        )?;
+
+        // set up tax command
+        context.add_command(
+            tax::tax_command(),
+            tax::process_tax_command,
+        )?;
+
+        // set up report command
+        context.add_command(
+            revenue::report_command(),
+            revenue::process_report_command,
+        )?;
+
+        // set up billing command
+        context.add_command(
+            billing_command(),
+            process_billing_command,
         )?;
 
+        // the commands that can print rows with --format json or csv
+        for command in [
+            invoice_command(),
+            packages::package_command(),
+            services::service_command(),
+            tax::tax_command(),
+            revenue::report_command(),
+            billing_command(),
+        ] {
+            context.add_output_command(command);
+        }
+
         #[cfg(feature="tui")]
         if let Some(new_tab_types) = context.get_resource_mut::<tui::TuiNewTabTypes>() {
             new_tab_types.register_new_tab_type::<ExportInvoiceTabImpl>("Export Invoice");
+            new_tab_types.register_new_tab_type::<aging::AgingTabImpl>("Aging");
         }
 
         context.add_new_window_type::<InvoiceExportWindow>("Export Invoice");
@@ -113,32 +538,260 @@ impl Plugin for BillingPlugin {
     }
 }
 
+/// Creates the `invoice` command.
+fn invoice_command() -> Command {
+    Command::new("invoice")
+        .alias("inv")
+        .about("Invoice related commands")
+        .subcommand(Command::new("generate")
+            .alias("gen")
+            .about("Generates a receipt for a payment, or an \
+                invoice document")
+            .arg(Arg::new("payment-id")
+                .long("payment-id")
+                .value_parser(clap::value_parser!(i64))
+                .help("The payment row ID to \
+                    generate a receipt from.")
+            )
+            .arg(Arg::new("invoice-id")
+                .long("invoice-id")
+                .value_parser(clap::value_parser!(i64))
+                .help("The invoice row ID to \
+                    generate an invoice from.")
+            )
+            .group(ArgGroup::new("document")
+                .args(["payment-id", "invoice-id"])
+                .required(true)
+            )
+            .arg(Arg::new("out-dir")
+                .long("out-dir")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("The folder to output the document to")
+            )
+            .arg(Arg::new("format")
+                .long("format")
+                .default_value("latex")
+                .help("The format to write: latex (PDF), html \
+                    or text")
+            )
+            .arg(Arg::new("verbose")
+                .long("verbose")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print the LaTeX command and its output")
+            )
+        )
+        .subcommand(Command::new("statement")
+            .about("Generates an account statement for a client \
+                over a date range")
+            .arg(Arg::new("client-id")
+                .long("client-id")
+                .value_parser(clap::value_parser!(i64))
+                .required(true)
+                .help("The client row ID to generate a \
+                    statement for.")
+            )
+            .arg(Arg::new("from")
+                .long("from")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .required(true)
+                .help("The first date to include (YYYY-MM-DD)")
+            )
+            .arg(Arg::new("to")
+                .long("to")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .required(true)
+                .help("The last date to include (YYYY-MM-DD)")
+            )
+            .arg(Arg::new("trainer-id")
+                .long("trainer-id")
+                .value_parser(clap::value_parser!(i64))
+                .help("The trainer row ID whose details head the \
+                    statement. Defaults to the trainer of the \
+                    client's latest payment.")
+            )
+            .arg(Arg::new("out-dir")
+                .long("out-dir")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("The folder to output the document to")
+            )
+            .arg(Arg::new("verbose")
+                .long("verbose")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print the LaTeX command and its output")
+            )
+        )
+        .subcommand(templates::template_command())
+        .subcommand(invoices::create_command())
+        .subcommand(invoices::send_command())
+        .subcommand(invoices::apply_payment_command())
+        .subcommand(invoices::list_command())
+}
+
+/// Creates the `billing` command.
+fn billing_command() -> Command {
+    Command::new("billing")
+        .about("Billing related commands")
+        .subcommand(structured(Command::new("charge-sessions")
+            .about("Bills completed sessions that haven't been \
+                billed yet, using up package credits first")
+            .arg(Arg::new("from")
+                .long("from")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .required(true)
+                .help("The first session date to include (YYYY-MM-DD)")
+            )
+            .arg(Arg::new("to")
+                .long("to")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .required(true)
+                .help("The last session date to include (YYYY-MM-DD)")
+            )
+        ))
+        .subcommand(services::charge_command())
+        .subcommand(structured(Command::new("number-receipts")
+            .about("Gives every payment without a receipt number the \
+                next number from its trainer's receipt pattern \
+                (payments are numbered once they have a date, a \
+                trainer and an amount, so this is only needed for \
+                ones added another way)")
+        ))
+        .subcommand(voids::void_command())
+        .subcommand(voids::refund_command())
+        .subcommand(aging::aging_command())
+        .subcommand_required(true)
+}
+
 struct InvoiceExportWindow;
 
 /// Processes the `generate` subcommand of the `invoice` command.
 fn process_invoice_generate_command(
     arg_matches: &ArgMatches,
     db_connection: &mut DbConnection,
+    template_dir: Option<&Path>,
+) -> dolmen::Result<CommandResponse> {
+    // get the command arguments
+    let out_folder = arg_matches
+        .get_one::<PathBuf>("out-dir")
+        .expect("Missing required argument");
+    let format = arg_matches
+        .get_one::<String>("format")
+        .expect("Missing required argument")
+        .parse::<InvoiceFormat>()
+        .map_err(dolmen::Error::new)?;
+
+    let path = match arg_matches
+        .get_one::<i64>("invoice-id")
+    {
+        // export an issued invoice
+        Some(invoice_row_id) => {
+            if format != InvoiceFormat::Latex {
+                return Err(dolmen::Error::new(
+                    "invoices can only be generated as latex for now",
+                ));
+            }
+            invoices::write_invoice(
+                db_connection,
+                out_folder.as_path(),
+                RowId(*invoice_row_id),
+                &write_options(arg_matches),
+            )?
+        }
+
+        // create and export the receipt
+        None => {
+            let payment_row_id = arg_matches
+                .get_one::<i64>("payment-id")
+                .expect("Missing required argument");
+            create_invoice(
+                db_connection,
+                out_folder.clone(),
+                RowId(*payment_row_id),
+                format,
+                template_dir,
+                &write_options(arg_matches),
+            )?
+        }
+    };
+
+    // return the command response
+    Ok(CommandResponse::new(format!(
+        "Successfully generated invoice at {}.",
+        path.display()
+    )))
+}
+
+/// Gets the document export options from the `--verbose` flag of a
+/// subcommand.
+fn write_options(
+    arg_matches: &ArgMatches,
+) -> WriteOptions {
+    WriteOptions {
+        verbosity: if arg_matches.get_flag("verbose") {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Quiet
+        },
+    }
+}
+
+/// Processes the `statement` subcommand of the `invoice` command.
+fn process_invoice_statement_command(
+    arg_matches: &ArgMatches,
+    db_connection: &mut DbConnection,
 ) -> dolmen::Result<CommandResponse> {
     // get the command arguments
-    let invoice_row_id = arg_matches
-        .get_one::<i64>("payment-id")
+    let client = RowId(
+        *arg_matches
+            .get_one::<i64>("client-id")
+            .expect("Missing required argument"),
+    );
+    let from = *arg_matches
+        .get_one::<NaiveDate>("from")
         .expect("Missing required argument");
+    let to = *arg_matches
+        .get_one::<NaiveDate>("to")
+        .expect("Missing required argument");
+    let trainer = arg_matches
+        .get_one::<i64>("trainer-id")
+        .map(|t| RowId(*t));
     let out_folder = arg_matches
         .get_one::<PathBuf>("out-dir")
         .expect("Missing required argument");
 
-    // create and export the invoice
-    create_invoice(
+    if from > to {
+        return Err(dolmen::Error::new(format!(
+            "statement start date {} is after end date {}",
+            from, to
+        )));
+    }
+
+    // generate and export the statement
+    let doc = generate_statement_latex(
         db_connection,
-        out_folder.clone(),
-        RowId(*invoice_row_id),
+        client,
+        trainer,
+        from,
+        to,
     )?;
+    write_document_with_options(
+        out_folder.as_path(),
+        "statement",
+        &doc,
+        &write_options(arg_matches),
+    )
+    .map_err(|e| {
+        dolmen::Error::new(format!(
+            "failed to write document: {}",
+            e
+        ))
+    })?;
 
     // return the command response
     Ok(CommandResponse::new(format!(
-        "Successfully generated invoice at {}.",
-        out_folder.join("invoice.pdf").display()
+        "Successfully generated statement at {}.",
+        out_folder.join("statement.pdf").display()
     )))
 }
 
@@ -147,17 +800,56 @@ fn process_invoice_command(
     context: &mut Context,
     arg_matches: &ArgMatches,
 ) -> dolmen::Result<CommandResponse> {
+    let template_dir = context
+        .get_resource::<TemplateConfig>()
+        .and_then(|config| {
+            config.template_dir.clone()
+        });
+
     // get the database connection
     let db_connection = context.db_connection()?;
 
-    // check for the generate subcommand and run it if desired
-    if let Some(("generate", sub_m)) =
-        arg_matches.subcommand()
-    {
-        return process_invoice_generate_command(
-            sub_m,
-            db_connection,
-        );
+    // check for the subcommands and run them if desired
+    match arg_matches.subcommand() {
+        Some(("generate", sub_m)) => {
+            return process_invoice_generate_command(
+                sub_m,
+                db_connection,
+                template_dir.as_deref(),
+            );
+        }
+        Some(("template", sub_m)) => {
+            return templates::process_invoice_template_command(
+                sub_m,
+            );
+        }
+        Some(("statement", sub_m)) => {
+            return process_invoice_statement_command(
+                sub_m,
+                db_connection,
+            );
+        }
+        Some(("create", sub_m)) => {
+            return invoices::process_create_command(
+                context, sub_m,
+            );
+        }
+        Some(("send", sub_m)) => {
+            return invoices::process_send_command(
+                context, sub_m,
+            );
+        }
+        Some(("apply-payment", sub_m)) => {
+            return invoices::process_apply_payment_command(
+                context, sub_m,
+            );
+        }
+        Some(("list", sub_m)) => {
+            return invoices::process_list_command(
+                context, sub_m,
+            );
+        }
+        _ => {}
     }
 
     Err(dolmen::Error::new(format!(
@@ -165,219 +857,890 @@ fn process_invoice_command(
     )))
 }
 
-/// A LaTeX preamble element to create a new command. This is used to pass
-/// data into the generated document.
-struct NewCommand(String, String);
-
-impl From<NewCommand> for PreambleElement {
-    fn from(val: NewCommand) -> Self {
-        PreambleElement::UserDefined(format!(
-            "\\newcommand{{\\{}}}{{{}}}",
-            val.0, val.1
-        ))
+/// Processes the main `billing` command.
+fn process_billing_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    match arg_matches.subcommand() {
+        Some(("charge-sessions", sub_m)) => {
+            session_charges::process_charge_sessions_command(
+                context,
+                sub_m,
+            )
+        }
+        Some(("charge", sub_m)) => {
+            services::process_charge_command(
+                context, sub_m,
+            )
+        }
+        Some(("void", sub_m)) => {
+            voids::process_void_command(context, sub_m)
+        }
+        Some(("refund", sub_m)) => {
+            voids::process_refund_command(context, sub_m)
+        }
+        Some(("aging", sub_m)) => {
+            aging::process_aging_command(context, sub_m)
+        }
+        Some(("number-receipts", sub_m)) => {
+            receipts::process_number_receipts_command(
+                context, sub_m,
+            )
+        }
+        _ => Err(dolmen::Error::new(
+            "subcommand not recognized",
+        )),
     }
 }
 
-/// Creates a PDF document from an invoice.
+/// Creates a document from an invoice. Returns the path of the document.
 ///
 /// * `db_connection` - A connection to the database.
 /// * `out_path` - The directory to output the document to.
 /// * `invoice_row_id` - The row ID in the `invoice` table corresponding to
 ///   the invoice to generate.
+/// * `format` - The format to write the document in.
+/// * `template_dir` - The directory to look for templates in, if any.
+/// * `options` - Options for exporting the document.
 fn create_invoice(
     db_connection: &mut DbConnection,
     out_path: PathBuf,
     invoice_row_id: RowId,
-) -> dolmen::Result<()> {
-    // generate the LaTeX document
-    let doc =
-        generate_latex(db_connection, invoice_row_id)?;
-
-    // export the PDF
-    write_document(
-        out_path.as_path(),
-        "invoice",
-        &doc,
-    )
-    .expect("failed to write document");
+    format: InvoiceFormat,
+    template_dir: Option<&Path>,
+    options: &WriteOptions,
+) -> dolmen::Result<PathBuf> {
+    let (trainer, data) =
+        invoice_data(db_connection, invoice_row_id)?;
+    let template = load_invoice_template(
+        template_dir,
+        &trainer,
+        format,
+    )?;
+    let backend: Box<dyn DocumentBackend> =
+        invoice_backend(format, template);
 
-    Ok(())
+    // export the document
+    backend
+        .write(
+            out_path.as_path(),
+            "invoice",
+            &data,
+            options,
+        )
+        .map_err(|e| {
+            dolmen::Error::new(format!(
+                "failed to write document: {}",
+                e
+            ))
+        })
 }
 
+/// The balances and charges covered by a single payment receipt.
 struct ReceiptInfo {
-    // TODO: replace these with a proper currency field
-    start_balance: i32,
-    end_balance: i32,
+    /// The client's balance as of their previous payment.
+    start_balance: Money,
+
+    /// The client's balance after this payment.
+    end_balance: Money,
+
+    /// The charges issued since the previous payment, up to and including
+    /// the date of this payment.
     charges: Vec<RowId>,
-    charge_total: u32,
-    last_payment_date: String,
-}
 
-fn get_receipt_info(
-    db_connection: &mut DbConnection,
-    payment_row_id: RowId,
-) -> dolmen::Result<ReceiptInfo> {
-    let payment = Payment::from_table_row(
-        db_connection,
-        "payment".into(),
-        payment_row_id,
-    )?;
+    /// The sum of the amounts of `charges`, tax included.
+    charge_total: Money,
 
-    let payment_row_ids =
-        db_connection.get_table_row_ids("payment")?;
-
-    let charge_row_ids =
-        db_connection.get_table_row_ids("charge")?;
-
-    let payments_for_client = payment_row_ids
-        .iter()
-        .filter(|p| {
-            **p != payment_row_id.0
-                && db_connection
-                    .get_field_in_table_row::<String>(
-                        "payment",
-                        RowId(**p),
-                        "date",
-                    )
-                    .unwrap()
-                    < payment.date.to_string()
-                && db_connection
-                    .get_field_in_table_row::<RowId>(
-                        "payment",
-                        RowId(**p),
-                        "client",
-                    )
-                    .unwrap()
-                    == payment.client
-        })
-        .collect::<Vec<_>>();
+    /// The refunds (see `refund_payment`) paid out since the previous
+    /// payment, up to and including the date of this payment.
+    refunds: Vec<RowId>,
 
-    // get last payment date
-    let last_payment_date = payments_for_client
-        .iter()
-        .map(|p| {
-            db_connection
-                .get_field_in_table_row::<String>(
-                    "payment",
-                    RowId(**p),
-                    "date",
-                )
-                .unwrap()
-        })
-        .max()
-        .unwrap_or("0000-01-01".into());
+    /// The sum of the amounts paid back by `refunds`, as a positive amount.
+    refund_total: Money,
 
-    let charges_for_client = charge_row_ids
-        .iter()
-        .filter(|c| {
-            db_connection
-                .get_field_in_table_row::<RowId>(
-                    "charge",
-                    RowId(**c),
-                    "client",
-                )
-                .unwrap()
-                == payment.client
-        })
-        .collect::<Vec<_>>();
+    /// The date of the client's previous payment, or `None` if this is
+    /// their first.
+    last_payment_date: Option<NaiveDate>,
 
-    let charges_for_last_receipt = charges_for_client
-        .iter()
-        .filter(|c| {
-            db_connection
-                .get_field_in_table_row::<String>(
-                    "charge",
-                    RowId(***c),
-                    "date",
-                )
-                .unwrap()
-                <= last_payment_date
-        })
-        .collect::<Vec<_>>();
+    /// The number of package session credits the client has left on the
+    /// date of this payment.
+    credits_remaining: u32,
+}
 
-    let charges_for_this_receipt = charges_for_client
-        .iter()
-        .filter(|c| {
-            let charge_date = db_connection
-                .get_field_in_table_row::<String>(
-                    "charge",
-                    RowId(***c),
-                    "date",
-                )
-                .unwrap();
-            charge_date > last_payment_date
-                && charge_date
-                    <= payment.date.to_string()
-        })
-        .collect::<Vec<_>>();
+/// Builds a SQL expression for the trainer a client is with on a date: the
+/// trainer of their latest payment up to the date, or else of their latest
+/// session up to it, or else of their first payment after it.
+///
+/// * `client` - A SQL expression for the client's row ID (e.g. `"?1"`).
+/// * `date` - A SQL expression for the date (e.g. `"c.date"`).
+pub(crate) fn client_trainer_sql(
+    client: &str,
+    date: &str,
+) -> String {
+    format!(
+        "COALESCE(
+            (SELECT p.trainer FROM payment p
+                WHERE p.client = {client} AND p.date <= {date}
+                ORDER BY p.date DESC, p.id DESC LIMIT 1),
+            (SELECT s.trainer FROM session s
+                WHERE s.client = {client} AND s.date <= {date}
+                ORDER BY s.date DESC, s.id DESC LIMIT 1),
+            (SELECT p.trainer FROM payment p
+                WHERE p.client = {client} AND p.date > {date}
+                ORDER BY p.date, p.id LIMIT 1))"
+    )
+}
 
-    let mut payment_total = 0;
-    for payment in payments_for_client.iter() {
-        payment_total += db_connection
-            .get_field_in_table_row::<u32>(
-                "payment",
-                RowId(**payment),
-                "amount",
-            )
-            .unwrap()
+/// Builds a SQL expression for the trainer a charge `c` is counted for:
+/// the trainer of the session it billed (or, for a void, of the session
+/// the voided charge billed), or else the trainer the client is with on
+/// the date of the charge (see `client_trainer_sql`).
+pub(crate) fn charge_trainer_sql() -> String {
+    format!(
+        "COALESCE(
+            (SELECT s.trainer FROM session s
+                WHERE s.charge = COALESCE(c.voids, c.id) LIMIT 1),
+            {})",
+        client_trainer_sql("c.client", "c.date")
+    )
+}
+
+/// Converts a SQLite error into a `dolmen::Error`. Totals of amounts in
+/// more than one currency (see `read_total`) report the currencies, and
+/// amounts that can't be stored say why.
+fn sql_error(e: rusqlite::Error) -> dolmen::Error {
+    let source = match &e {
+        rusqlite::Error::FromSqlConversionFailure(
+            _,
+            _,
+            source,
+        )
+        | rusqlite::Error::ToSqlConversionFailure(
+            source,
+        ) => Some(source),
+        _ => None,
+    };
+    if let Some(e) = source.and_then(|source| {
+        source.downcast_ref::<MoneyError>()
+    }) {
+        return (*e).into();
     }
+    dolmen::Error::new(e.to_string())
+}
 
-    let mut last_receipt_charge_total = 0;
-    for charge in charges_for_last_receipt.iter() {
-        last_receipt_charge_total += db_connection
-            .get_field_in_table_row::<u32>(
-                "charge",
-                RowId(***charge),
-                "amount",
-            )
-            .unwrap()
-    }
+/// Converts the amounts of a table from whole dollars to cents. Used by the
+/// first migration of the `charge` and `payment` tables, which only runs on
+/// tables from before migrations: tables created with the current schema
+/// are stamped at their latest version instead.
+fn amounts_to_cents(
+    connection: &rusqlite::Connection,
+    table: &str,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!(
+            "UPDATE {} SET amount = amount * 100",
+            table
+        ),
+        [],
+    )?;
+    Ok(())
+}
 
-    let start_balance = last_receipt_charge_total
-        as i32
-        - payment_total as i32;
+/// Adds the `currency` column to a table from before amounts had a
+/// currency, with the existing amounts in the default currency.
+///
+/// * `connection` - The connection (or transaction) to use.
+/// * `table` - The name of the table.
+fn add_currency(
+    connection: &rusqlite::Connection,
+    table: &str,
+) -> rusqlite::Result<()> {
+    db_commands::add_column(
+        connection,
+        table,
+        money::CURRENCY_FIELD,
+        "TEXT",
+    )?;
+    connection.execute(
+        &format!(
+            "UPDATE {} SET {} = ?1 WHERE {} IS NULL",
+            table,
+            money::CURRENCY_FIELD,
+            money::CURRENCY_FIELD
+        ),
+        [Currency::default()],
+    )?;
+    Ok(())
+}
 
-    let mut charge_total = 0;
-    charges_for_this_receipt.iter().for_each(|c| {
-        charge_total += db_connection
-            .get_field_in_table_row::<u32>(
-                "charge",
-                RowId(***c),
-                "amount",
-            )
-            .unwrap();
-    });
+/// Gets a client's balance (charges and their tax, minus payments) at the
+/// end of `date`. A positive balance means the client owes money.
+fn client_balance_on(
+    connection: &rusqlite::Connection,
+    client: RowId,
+    date: NaiveDate,
+) -> dolmen::Result<Money> {
+    let (charged, paid) = connection
+        .query_row(
+            &format!(
+                "SELECT * FROM
+                    (SELECT {} FROM charge
+                        WHERE client = ?1 AND date <= ?2),
+                    (SELECT {} FROM payment
+                        WHERE client = ?1 AND date <= ?2)",
+                money::total_with_sql("amount", Some("tax")),
+                money::total_sql("amount")
+            ),
+            rusqlite::params![client.0, date],
+            |r| {
+                Ok((
+                    money::read_total(r, 0)?,
+                    money::read_total(r, 2)?,
+                ))
+            },
+        )
+        .map_err(sql_error)?;
+    Ok(charged.checked_sub(paid)?)
+}
+
+/// Gets the IDs and amounts (tax included) of a client's charges issued
+/// after `after` (or from the beginning, if `None`) up to and including
+/// `until`, ordered by date.
+fn client_charges_between(
+    connection: &rusqlite::Connection,
+    client: RowId,
+    after: Option<NaiveDate>,
+    until: NaiveDate,
+) -> dolmen::Result<Vec<(RowId, Money)>> {
+    let mut stmt = connection
+        .prepare_cached(&format!(
+            "SELECT id, {}, tax FROM charge
+                WHERE client = ?1
+                    AND (?2 IS NULL OR date > ?2)
+                    AND date <= ?3
+                ORDER BY date, id",
+            money::money_sql("amount")
+        ))
+        .map_err(sql_error)?;
+    stmt.query_map(
+        rusqlite::params![client.0, after, until],
+        |r| {
+            let amount = money::read_money(r, 1)?;
+            Ok((
+                RowId(r.get(0)?),
+                amount,
+                r.get::<_, Option<Money>>(3)?.map(
+                    |tax| {
+                        tax.with_currency(
+                            amount.currency(),
+                        )
+                    },
+                ),
+            ))
+        },
+    )
+    .map_err(sql_error)?
+    .map(|row| {
+        let (id, amount, tax) =
+            row.map_err(sql_error)?;
+        Ok((
+            id,
+            amount.checked_add(
+                tax.unwrap_or_default(),
+            )?,
+        ))
+    })
+    .collect()
+}
+
+/// Gets the IDs and (negative) amounts of a client's refunds paid out after
+/// `after` (or from the beginning, if `None`) up to and including `until`,
+/// ordered by date.
+fn client_refunds_between(
+    connection: &rusqlite::Connection,
+    client: RowId,
+    after: Option<NaiveDate>,
+    until: NaiveDate,
+) -> dolmen::Result<Vec<(RowId, Money)>> {
+    let mut stmt = connection
+        .prepare_cached(&format!(
+            "SELECT id, {} FROM payment
+                WHERE client = ?1
+                    AND refunds IS NOT NULL
+                    AND (?2 IS NULL OR date > ?2)
+                    AND date <= ?3
+                ORDER BY date, id",
+            money::money_sql("amount")
+        ))
+        .map_err(sql_error)?;
+    stmt.query_map(
+        rusqlite::params![client.0, after, until],
+        |r| {
+            Ok((
+                RowId(r.get(0)?),
+                money::read_money(r, 1)?,
+            ))
+        },
+    )
+    .map_err(sql_error)?
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(sql_error)
+}
 
-    let end_balance = start_balance as i32
-        + charge_total as i32
-        - payment.amount as i32;
+/// Computes the receipt information for a payment.
+///
+/// The receipt covers everything since the client's previous payment: the
+/// start balance is the client's balance on the date of that payment, and
+/// the charges and refunds are the ones issued after it, up to and
+/// including the date of this payment. Refunds aren't payments of their
+/// own, so they never start a new receipt.
+fn get_receipt_info(
+    db_connection: &mut DbConnection,
+    payment_row_id: RowId,
+) -> dolmen::Result<ReceiptInfo> {
+    let payment =
+        get_payment(db_connection, payment_row_id)?;
+
+    let connection = db_connection.connection()?;
+
+    let last_payment_date = connection
+        .query_row(
+            "SELECT MAX(date) FROM payment
+                WHERE client = ?1 AND date < ?2 AND id != ?3
+                    AND refunds IS NULL",
+            rusqlite::params![
+                payment.client.0,
+                payment.date,
+                payment_row_id.0
+            ],
+            |r| r.get::<_, Option<NaiveDate>>(0),
+        )
+        .map_err(sql_error)?;
+
+    let start_balance = match last_payment_date {
+        Some(date) => client_balance_on(
+            connection,
+            payment.client,
+            date,
+        )?,
+        None => Money::zero(),
+    };
+
+    let charges = client_charges_between(
+        connection,
+        payment.client,
+        last_payment_date,
+        payment.date,
+    )?;
+    let charge_total = Money::total(
+        charges.iter().map(|(_, amount)| *amount),
+    )?;
+
+    let refunds = client_refunds_between(
+        connection,
+        payment.client,
+        last_payment_date,
+        payment.date,
+    )?;
+    let refund_total = Money::total(
+        refunds.iter().map(|(_, amount)| *amount),
+    )?
+    .checked_neg()?;
+
+    let end_balance = Money::total([
+        start_balance,
+        charge_total,
+        refund_total,
+        payment.amount.checked_neg()?,
+    ])?;
+
+    let credits_remaining = packages::client_credits(
+        connection,
+        payment.client,
+        payment.date,
+    )?;
 
     Ok(ReceiptInfo {
         start_balance,
         end_balance,
-        charges: charges_for_this_receipt
-            .iter()
-            .map(|c| RowId(***c))
-            .collect::<Vec<_>>(),
+        charges: charges
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect(),
         charge_total,
+        refunds: refunds
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect(),
+        refund_total,
         last_payment_date,
+        credits_remaining,
+    })
+}
+
+/// A single charge, payment or package credit use on a client's account
+/// statement.
+struct StatementEntry {
+    date: NaiveDate,
+    description: String,
+
+    /// The amount charged, if this entry is a charge.
+    charge: Option<Money>,
+
+    /// The amount paid, if this entry is a payment.
+    payment: Option<Money>,
+
+    /// The client's balance after this entry.
+    balance: Money,
+}
+
+/// The entries and balances on a client's account statement.
+struct StatementInfo {
+    /// The client's balance at the end of the day before the statement
+    /// starts.
+    opening_balance: Money,
+
+    /// The client's balance at the end of the last day of the statement.
+    closing_balance: Money,
+
+    /// The charges issued in the date range.
+    charges: Vec<RowId>,
+
+    /// Every charge, payment and package credit use in the date range,
+    /// ordered by date. On the same date, charges come before payments,
+    /// and payments before credit uses. Refunds are listed with the
+    /// charges, since they add to what the client owes.
+    entries: Vec<StatementEntry>,
+
+    /// The number of package session credits the client has left at the
+    /// end of the last day of the statement.
+    credits_remaining: u32,
+}
+
+/// Computes the account statement for a client over a date range
+/// (inclusive). Uses the same balance query as `get_receipt_info`, so a
+/// statement's closing balance always agrees with the receipts.
+fn get_statement_info(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> dolmen::Result<StatementInfo> {
+    let connection = db_connection.connection()?;
+
+    let opening_balance = match from.pred_opt() {
+        Some(day_before) => client_balance_on(
+            connection, client, day_before,
+        )?,
+        None => Money::zero(),
+    };
+
+    let mut stmt = connection
+        .prepare_cached(&format!(
+            "SELECT date, 0 AS kind, id,
+                    COALESCE(description, ''), {amount}, tax, 0
+                FROM charge
+                WHERE client = ?1 AND date >= ?2 AND date <= ?3
+            UNION ALL
+            SELECT date, 1 AS kind, id,
+                    'Payment via ' || COALESCE(paid_via, '')
+                        || ' (receipt '
+                        || COALESCE(receipt_number, '') || ')',
+                    {amount}, NULL, 0
+                FROM payment
+                WHERE client = ?1 AND date >= ?2 AND date <= ?3
+                    AND refunds IS NULL
+            UNION ALL
+            SELECT r.date, 0 AS kind, r.id, {refund}, {refund_amount},
+                    NULL, 1
+                FROM payment r LEFT JOIN payment o ON o.id = r.refunds
+                WHERE r.client = ?1 AND r.date >= ?2 AND r.date <= ?3
+                    AND r.refunds IS NOT NULL
+            UNION ALL
+            SELECT u.date, 2 AS kind, u.id,
+                    'Session paid with package credit (package '
+                        || u.package || ')',
+                    0, NULL, NULL, 0
+                FROM credit_use u JOIN package p ON p.id = u.package
+                WHERE p.client = ?1 AND u.date >= ?2 AND u.date <= ?3
+            ORDER BY date, kind, id",
+            amount = money::money_sql("amount"),
+            refund = voids::REFUND_DESCRIPTION,
+            refund_amount = money::money_sql("r.amount")
+        ))
+        .map_err(sql_error)?;
+    let rows = stmt
+        .query_map(
+            rusqlite::params![client.0, from, to],
+            |r| {
+                let amount = money::read_money(r, 4)?;
+                Ok((
+                    r.get::<_, NaiveDate>(0)?,
+                    r.get::<_, i64>(1)?,
+                    r.get::<_, String>(3)?,
+                    amount,
+                    r.get::<_, Option<Money>>(6)?.map(
+                        |tax| {
+                            tax.with_currency(
+                                amount.currency(),
+                            )
+                        },
+                    ),
+                    r.get::<_, bool>(7)?,
+                ))
+            },
+        )
+        .map_err(sql_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sql_error)?;
+
+    let mut balance = opening_balance;
+    let mut entries = Vec::new();
+    for (
+        date,
+        kind,
+        description,
+        amount,
+        tax,
+        negate,
+    ) in rows
+    {
+        // charges are listed with their tax, and refunds (negative
+        // payments) as charges
+        let mut amount = amount
+            .checked_add(tax.unwrap_or_default())?;
+        if negate {
+            amount = amount.checked_neg()?;
+        }
+        // credit uses (kind 2) don't change the balance
+        let is_charge = kind == 0;
+        let is_payment = kind == 1;
+        if is_charge {
+            balance = balance.checked_add(amount)?;
+        } else if is_payment {
+            balance = balance.checked_sub(amount)?;
+        }
+        entries.push(StatementEntry {
+            date,
+            description,
+            charge: is_charge.then_some(amount),
+            payment: is_payment.then_some(amount),
+            balance,
+        });
+    }
+
+    let charges = connection
+        .prepare_cached(
+            "SELECT id FROM charge
+                WHERE client = ?1 AND date >= ?2 AND date <= ?3
+                ORDER BY date, id",
+        )
+        .map_err(sql_error)?
+        .query_map(
+            rusqlite::params![client.0, from, to],
+            |r| Ok(RowId(r.get(0)?)),
+        )
+        .map_err(sql_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sql_error)?;
+
+    let credits_remaining = packages::client_credits(
+        connection, client, to,
+    )?;
+
+    Ok(StatementInfo {
+        opening_balance,
+        closing_balance: balance,
+        charges,
+        entries,
+        credits_remaining,
     })
 }
 
-/// Generates a LaTeX document from an invoice.
+/// Picks the trainer whose details head a client's statement: the trainer
+/// of the client's latest payment up to `to`, or the first trainer if the
+/// client has never paid.
+fn statement_trainer(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    to: NaiveDate,
+) -> dolmen::Result<RowId> {
+    let connection = db_connection.connection()?;
+    let trainer = connection
+        .query_row(
+            "SELECT COALESCE(
+                (SELECT trainer FROM payment
+                    WHERE client = ?1 AND date <= ?2
+                    ORDER BY date DESC, id DESC LIMIT 1),
+                (SELECT MIN(id) FROM trainer))",
+            rusqlite::params![client.0, to],
+            |r| r.get::<_, Option<i64>>(0),
+        )
+        .map_err(sql_error)?;
+    trainer.map(RowId).ok_or(dolmen::Error::new(
+        "no trainer to put on the statement",
+    ))
+}
+
+/// Generates a LaTeX account statement for a client.
 ///
 /// * `db_connection` - A connection to the database.
-/// * `invoice_row_id` - The row ID in the `invoice` table corresponding to
-///   the invoice to generate.
-fn generate_latex(
+/// * `client_row_id` - The row ID in the `client` table of the client.
+/// * `trainer_row_id` - The row ID in the `trainer` table of the trainer
+///   whose details head the statement, or `None` to pick one with
+///   `statement_trainer`.
+/// * `from` - The first date to include.
+/// * `to` - The last date to include.
+fn generate_statement_latex(
     db_connection: &mut DbConnection,
-    payment_row_id: RowId,
+    client_row_id: RowId,
+    trainer_row_id: Option<RowId>,
+    from: NaiveDate,
+    to: NaiveDate,
 ) -> dolmen::Result<Document> {
-    let payment = Payment::from_table_row(
+    let trainer_row_id = match trainer_row_id {
+        Some(trainer) => trainer,
+        None => statement_trainer(
+            db_connection,
+            client_row_id,
+            to,
+        )?,
+    };
+    let trainer = Trainer::from_table_row(
         db_connection,
-        "payment".into(),
-        payment_row_id,
+        "trainer".into(),
+        trainer_row_id,
+    )?;
+    let client = Client::from_table_row(
+        db_connection,
+        "client".into(),
+        client_row_id,
+    )?;
+
+    let statement_info = get_statement_info(
+        db_connection,
+        client_row_id,
+        from,
+        to,
+    )?;
+
+    let mut data = DocumentData::default();
+    set_trainer_data(&mut data, &trainer)?;
+    data.set("clientname", client.name().clone());
+    data.set("statementfrom", from.to_string());
+    data.set("statementto", to.to_string());
+    data.set(
+        "currency",
+        statement_info
+            .opening_balance
+            .currency()
+            .code(),
+    );
+    data.set(
+        "openingbalance",
+        statement_info
+            .opening_balance
+            .to_decimal_string(),
+    );
+    data.set(
+        "closingbalance",
+        statement_info
+            .closing_balance
+            .to_decimal_string(),
+    );
+    data.set(
+        "creditsremaining",
+        statement_info.credits_remaining.to_string(),
+    );
+
+    let mut entry_data = DataTable::new([
+        "date",
+        "description",
+        "charge",
+        "payment",
+        "balance",
+    ]);
+    for entry in &statement_info.entries {
+        entry_data.push_row([
+            entry.date.to_string(),
+            entry.description.clone(),
+            entry
+                .charge
+                .map(|c| c.to_decimal_string())
+                .unwrap_or_default(),
+            entry
+                .payment
+                .map(|p| p.to_decimal_string())
+                .unwrap_or_default(),
+            entry.balance.to_decimal_string(),
+        ]);
+    }
+    data.set_table("entrydata", entry_data);
+    set_tax_data(
+        &mut data,
+        db_connection,
+        &statement_info.charges,
     )?;
+
+    latex_backend(include_str!(
+        "statement_template.tex"
+    ))
+    .render(&data)
+    .map_err(render_error)
+}
+
+/// Sets the trainer's contact details in a document's data.
+///
+/// Fails if the trainer's logo doesn't exist or its path has characters
+/// that mean something to LaTeX, as templates insert it unescaped.
+///
+/// * `data` - The document data to add to.
+/// * `trainer` - The trainer.
+fn set_trainer_data(
+    data: &mut DocumentData,
+    trainer: &Trainer,
+) -> dolmen::Result<()> {
+    if let Some(logo_path) = trainer
+        .logo_path()
+        .as_ref()
+        .filter(|path| !path.is_empty())
+    {
+        if logo_path
+            .contains(['{', '}', '\\', '%', '#'])
+        {
+            return Err(dolmen::Error::new(format!(
+                "logo path {} of trainer {} can't contain {{, }}, \\, % \
+                    or #",
+                logo_path,
+                trainer.name()
+            )));
+        }
+        if !std::path::Path::new(logo_path).is_file() {
+            return Err(dolmen::Error::new(format!(
+                "logo {} of trainer {} doesn't exist",
+                logo_path,
+                trainer.name()
+            )));
+        }
+    }
+
+    data.set("trainername", trainer.name().clone());
+    data.set(
+        "companyname",
+        trainer.company_name().clone(),
+    );
+    data.set(
+        "companyaddress",
+        trainer.address().clone(),
+    );
+    data.set("companyemail", trainer.email().clone());
+    data.set("companyphone", trainer.phone().clone());
+    data.set(
+        "logopath",
+        trainer
+            .logo_path()
+            .clone()
+            .unwrap_or_default(),
+    );
+    Ok(())
+}
+
+/// Sets the tax subtotals of a set of charges in a document's data: the
+/// table `TAX_TABLE`, with a row per tax rate, and their total as
+/// `taxtotal`.
+///
+/// * `data` - The document data to add to.
+/// * `db_connection` - A connection to the database.
+/// * `charges` - The charges on the document.
+pub(crate) fn set_tax_data(
+    data: &mut DocumentData,
+    db_connection: &mut DbConnection,
+    charges: &[RowId],
+) -> dolmen::Result<()> {
+    let subtotals =
+        tax::tax_subtotals(db_connection, charges)?;
+    data.set(
+        "taxtotal",
+        Money::total(
+            subtotals.iter().map(|(_, tax)| *tax),
+        )?
+        .to_decimal_string(),
+    );
+    let mut tax_data = DataTable::new(
+        TAX_COLUMNS.iter().map(|column| column.name),
+    );
+    for (name, tax) in subtotals {
+        tax_data
+            .push_row([name, tax.to_decimal_string()]);
+    }
+    data.set_table(TAX_TABLE, tax_data);
+    Ok(())
+}
+
+/// Creates a LaTeX backend with the packages used by the billing templates.
+///
+/// * `template` - The LaTeX template of the document body.
+pub(crate) fn latex_backend(
+    template: impl Into<String>,
+) -> LatexBackend {
+    LatexBackend::new(template)
+        .with_package("hhline", None)
+        .with_package("geometry", Some("margin=0.5in"))
+        .with_package("fontenc", Some("T1"))
+        .with_package("graphicx", None)
+        .with_package("array", None)
+}
+
+/// Converts an error rendering a document into a `dolmen::Error`.
+fn render_error(e: DocumentError) -> dolmen::Error {
+    dolmen::Error::new(format!(
+        "failed to render document: {}",
+        e
+    ))
+}
+
+/// Gets the data filling in an invoice.
+///
+/// * `db_connection` - A connection to the database.
+/// * `payment_row_id` - The row ID in the `payment` table corresponding to
+///   the invoice to generate.
+fn invoice_data(
+    db_connection: &mut DbConnection,
+    payment_row_id: RowId,
+) -> dolmen::Result<(Trainer, DocumentData)> {
+    if let Some(refunded) = db_connection
+        .get_field_in_table_row::<Option<i64>>(
+            "payment",
+            payment_row_id,
+            "refunds",
+        )?
+    {
+        return Err(dolmen::Error::new(format!(
+            "payment {} is a refund of payment {}, it's listed on the \
+                client's next receipt",
+            payment_row_id.0, refunded
+        )));
+    }
+
+    // payments are numbered when they're recorded, not here, so the
+    // numbers follow the order they were paid in
+    if db_connection
+        .get_field_in_table_row::<Option<String>>(
+            "payment",
+            payment_row_id,
+            "receipt_number",
+        )?
+        .is_none_or(|n| n.is_empty())
+    {
+        return Err(dolmen::Error::new(format!(
+            "payment {} has no receipt number yet, run `billing \
+                number-receipts` to number it",
+            payment_row_id.0
+        )));
+    }
+    let payment =
+        get_payment(db_connection, payment_row_id)?;
     // get the relevant rows from the database
     let trainer = Trainer::from_table_row(
         db_connection,
@@ -393,144 +1756,130 @@ fn generate_latex(
     let receipt_info = get_receipt_info(
         db_connection,
         payment_row_id,
-    )
-    .unwrap();
-
-    // Create the document and set up the preamble with all the needed data.
-    let mut doc =
-        Document::new(DocumentClass::Article);
-    doc.preamble.use_package("hhline");
-    doc.preamble.push(PreambleElement::UsePackage {
-        package: "geometry".into(),
-        argument: Some("margin=0.5in".into()),
-    });
-    /*doc.preamble.push(PreambleElement::UsePackage {
-        package: "quattrocento".into(),
-        argument: Some("sfdefault".into()),
-    });*/
-    doc.preamble.push(PreambleElement::UsePackage {
-        package: "fontenc".into(),
-        argument: Some("T1".into()),
-    });
-    doc.preamble.push(PreambleElement::UsePackage {
-        package: "graphicx".into(),
-        argument: None,
-    });
-    doc.preamble.push(PreambleElement::UsePackage {
-        package: "array".into(),
-        argument: None,
-    });
-    doc.preamble.push(NewCommand(
-        "trainername".into(),
-        trainer.name().clone(),
-    ));
-    doc.preamble.push(NewCommand(
-        "companyname".into(),
-        trainer.company_name().clone(),
-    ));
-    doc.preamble.push(NewCommand(
-        "companyaddress".into(),
-        trainer.address().clone(),
-    ));
-    doc.preamble.push(NewCommand(
-        "companyemail".into(),
-        trainer.email().clone(),
-    ));
-    doc.preamble.push(NewCommand(
-        "companyphone".into(),
-        trainer.phone().clone(),
-    ));
-    doc.preamble.push(NewCommand(
-        "clientname".into(),
-        client.name().clone(),
-    ));
-    doc.preamble.push(NewCommand(
-        "invoicenumber".into(),
-        payment.receipt_number,
-    ));
-    doc.preamble.push(NewCommand(
-        "paymentmade".into(),
-        payment.date.to_string(),
-    ));
-    doc.preamble.push(NewCommand(
-        "paidvia".into(),
-        payment.paid_via,
-    ));
-    doc.preamble.push(NewCommand(
-        "lastpayment".into(),
-        receipt_info.last_payment_date,
-    ));
-    doc.preamble.push(NewCommand(
-        "subtotal".into(),
-        format!(
-            "{}",
-            receipt_info.charge_total as i32
-                + receipt_info.start_balance
-        ),
-    ));
+    )?;
 
-    let mut charge_data = String::new();
-    receipt_info.charges.iter().for_each(|c| {
-        let charge = Charge::from_table_row(
-            db_connection,
-            "charge".into(),
-            *c,
-        )
-        .unwrap();
-        charge_data += format!(
-            "{} & {} & {} \\\\ ",
+    let mut data = DocumentData::default();
+    set_trainer_data(&mut data, &trainer)?;
+    data.set("clientname", client.name().clone());
+    data.set("invoicenumber", payment.receipt_number);
+    data.set("paymentmade", payment.date.to_string());
+    data.set("paidvia", payment.paid_via);
+    data.set(
+        "lastpayment",
+        receipt_info
+            .last_payment_date
+            .map(|d| d.to_string())
+            .unwrap_or_default(),
+    );
+    data.set("currency", payment.currency.code());
+    data.set(
+        "subtotal",
+        Money::total([
+            receipt_info.charge_total,
+            receipt_info.refund_total,
+            receipt_info.start_balance,
+        ])?
+        .to_decimal_string(),
+    );
+
+    let mut charge_data = DataTable::new(
+        CHARGE_COLUMNS
+            .iter()
+            .map(|column| column.name),
+    );
+    let mut lines = Vec::new();
+    for c in &receipt_info.charges {
+        let charge = get_charge(db_connection, *c)?;
+        lines.push((
             charge.date,
             charge.description,
-            charge.amount
-        )
-        .as_str();
-    });
-
-    doc.preamble.push(NewCommand(
-        "chargedata".into(),
-        charge_data,
-    ));
-
-    doc.preamble.push(NewCommand(
-        "paymentamount".into(),
-        format!("{}", payment.amount),
-    ));
-
-    doc.preamble.push(NewCommand(
-        "balancestart".into(),
-        format!("{}", receipt_info.start_balance),
-    ));
-
-    doc.preamble.push(NewCommand(
-        "balanceend".into(),
-        format!("{}", receipt_info.end_balance),
-    ));
-
-    let company_header =
-        if let Some(logo_path) = trainer.logo_path() {
-            format!(
-                "\\includegraphics[width=256px]{{{}}}",
-                logo_path
-            )
-        } else {
-            format!(
-                "\\Large\\textbf{{{}}}",
-                trainer.company_name()
-            )
-        };
-    // TODO: get rid of this unwrap
-    doc.preamble.push(NewCommand(
-        "companyheader".into(),
-        company_header,
-    ));
+            charge.amount,
+        ));
+    }
+    // refunds add to what the client owes, so they're listed with the
+    // charges
+    for r in &receipt_info.refunds {
+        let date = db_connection
+            .get_field_in_table_row::<NaiveDate>(
+                "payment", *r, "date",
+            )?;
+        let amount = get_money(
+            db_connection,
+            "payment",
+            *r,
+            "amount",
+        )?
+        .unwrap_or_default();
+        lines.push((
+            date,
+            voids::refund_description(
+                db_connection.connection()?,
+                *r,
+            )?,
+            amount.checked_neg()?,
+        ));
+    }
+    // sorting is stable, so charges come first on the same date
+    lines.sort_by_key(|(date, _, _)| *date);
+    for (date, description, amount) in lines {
+        charge_data.push_row([
+            date.to_string(),
+            description,
+            amount.to_decimal_string(),
+        ]);
+    }
+    data.set_table(CHARGE_TABLE, charge_data);
+    set_tax_data(
+        &mut data,
+        db_connection,
+        &receipt_info.charges,
+    )?;
 
-    // push the invoice template into the document now that all commands are
-    // set
-    doc.push(Element::UserDefined(
-        include_str!("invoice_template.tex").into(),
-    ));
+    data.set(
+        "paymentamount",
+        payment.amount.to_decimal_string(),
+    );
+    data.set(
+        "balancestart",
+        receipt_info.start_balance.to_decimal_string(),
+    );
+    data.set(
+        "balanceend",
+        receipt_info.end_balance.to_decimal_string(),
+    );
+    data.set(
+        "creditsremaining",
+        receipt_info.credits_remaining.to_string(),
+    );
+
+    Ok((trainer, data))
+}
 
-    Ok(doc)
+/// Generates a LaTeX receipt for a payment, using the trainer's template
+/// (see `load_invoice_template`). Commands render through the format's
+/// backend in `create_invoice`; this is for tests that check the LaTeX
+/// directly.
+///
+/// * `db_connection` - A connection to the database.
+/// * `payment_row_id` - The row ID in the `payment` table of the payment
+///   to generate the receipt for.
+/// * `template_dir` - The directory to look for templates in, if any.
+#[cfg(test)]
+fn generate_latex(
+    db_connection: &mut DbConnection,
+    payment_row_id: RowId,
+    template_dir: Option<&Path>,
+) -> dolmen::Result<Document> {
+    let (trainer, data) =
+        invoice_data(db_connection, payment_row_id)?;
+    let template = load_invoice_template(
+        template_dir,
+        &trainer,
+        InvoiceFormat::Latex,
+    )?;
+    latex_backend(template)
+        .render(&data)
+        .map_err(render_error)
 }
 
 // TODO: implement this
@@ -580,147 +1929,44 @@ impl TabImpl for ExportInvoiceTabImpl {
 
 #[cfg(test)]
 mod test {
-    use crate::{BillingPlugin, get_receipt_info};
+    use crate::test_util::{
+        add_test_charge, add_test_payment,
+        add_test_receipt, add_test_row,
+        add_test_trainer, setup_test_context,
+    };
+    use crate::{
+        BillingPlugin, CHARGE_TABLE, Currency,
+        INVOICE_VARIABLES, Money, TAX_TABLE,
+        TemplateConfig, get_charge, get_money,
+        get_payment, get_receipt_info,
+        get_statement_info, invoice_data, set_money,
+        sql_error,
+    };
+    use chrono::Datelike;
     use dolmen::prelude::*;
     use reliquary::prelude::*;
     use training::TrainingPlugin;
 
-    fn add_test_trainer(
-        db_connection: &mut DbConnection,
-    ) -> dolmen::Result<RowId> {
-        let trainer = db_connection
-            .new_row_in_table("trainer")?;
-        db_connection.set_field_in_table(
-            "trainer",
-            trainer,
-            "name",
-            "Tara Trainer",
-        )?;
-        db_connection.set_field_in_table(
-            "trainer",
-            trainer,
-            "company_name",
-            "Tara Fitness",
-        )?;
-        db_connection.set_field_in_table(
-            "trainer",
-            trainer,
-            "address",
-            "2127 Xanthia St, Denver, CO 80220",
-        )?;
-        db_connection.set_field_in_table(
-            "trainer",
-            trainer,
-            "email",
-            "tara@gmail.com",
-        )?;
-        db_connection.set_field_in_table(
-            "trainer",
-            trainer,
-            "phone",
-            "(303) 175-3098",
-        )?;
-
-        Ok(trainer)
-    }
-
-    fn add_test_client(
-        db_connection: &mut DbConnection,
-        name: &str,
-    ) -> dolmen::Result<RowId> {
-        // TODO: setting each field like this is super verbose and not
-        // typesafe, this should be wrapped
-        let client = db_connection
-            .new_row_in_table("client")?;
-        db_connection.set_field_in_table(
-            "client", client, "name", name,
-        )?;
-
-        Ok(client)
-    }
-
-    fn add_test_charge(
-        db_connection: &mut DbConnection,
-        date: &str,
-        amount: u32,
-        client: RowId,
-    ) -> dolmen::Result<RowId> {
-        let charge = db_connection
-            .new_row_in_table("charge")?;
-        db_connection.set_field_in_table(
-            "charge", charge, "date", date,
-        )?;
-        db_connection.set_field_in_table(
-            "charge",
-            charge,
-            "description",
-            "Personal training session (60 min)",
-        )?;
-        db_connection.set_field_in_table(
-            "charge", charge, "amount", amount,
-        )?;
-        db_connection.set_field_in_table(
-            "charge", charge, "client", client.0,
-        )?;
-
-        Ok(charge)
-    }
-
-    fn add_test_payment(
-        db_connection: &mut DbConnection,
-        client: RowId,
-        trainer: RowId,
-        date: String,
-        amount: u32,
-    ) -> dolmen::Result<RowId> {
-        let payment = db_connection
-            .new_row_in_table("payment")?;
-        // TODO: I don't like passing client.0 here, RowId should implement
-        // ToSql
-        db_connection.set_field_in_table(
-            "payment", payment, "client", client.0,
-        )?;
-        db_connection.set_field_in_table(
-            "payment", payment, "trainer", trainer.0,
-        )?;
-        db_connection.set_field_in_table(
-            "payment",
-            payment,
-            "receipt_number",
-            "2025-0532",
-        )?;
-        db_connection.set_field_in_table(
-            "payment", payment, "amount", amount,
-        )?;
-        db_connection.set_field_in_table(
-            "payment", payment, "date", date,
-        )?;
-        db_connection.set_field_in_table(
-            "payment", payment, "paid_via", "Cash",
-        )?;
-
-        Ok(payment)
-    }
-
     fn setup_invoice_data(
         db_connection: &mut DbConnection,
     ) -> dolmen::Result<RowId> {
         let trainer = add_test_trainer(db_connection)?;
-        let client = add_test_client(
+        let client = add_test_row(
             db_connection,
+            "client",
             "Clarissa Client",
         )?;
         let _charge = add_test_charge(
             db_connection,
+            client,
             "2026-01-04",
             50,
-            client,
         )?;
-        let payment = add_test_payment(
+        let payment = add_test_receipt(
             db_connection,
             client,
             trainer,
-            "2026-01-04".into(),
+            "2026-01-04",
             50,
         )?;
 
@@ -748,6 +1994,7 @@ mod test {
         let latex = crate::generate_latex(
             db_connection,
             invoice,
+            None,
         )?;
 
         let rendered = latex::print(&latex).unwrap();
@@ -779,6 +2026,135 @@ mod test {
         Ok(())
     }
 
+    // Text from the database is escaped, so special characters are typeset
+    // as written and can't inject TeX.
+    #[test]
+    fn invoice_special_characters_test()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+
+        let trainer = add_test_trainer(db_connection)?;
+        db_connection.set_field_in_table(
+            "trainer",
+            trainer,
+            "company_name",
+            "Tara's Fitness & Co.",
+        )?;
+        db_connection.set_field_in_table(
+            "trainer",
+            trainer,
+            "address",
+            "#4, 100% Main_St {rear}",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Smith & Sons",
+        )?;
+        let charge = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        db_connection.set_field_in_table(
+            "charge",
+            charge,
+            "description",
+            "50% off: $5 ~session~ #1 \\input{secrets}",
+        )?;
+        let payment = add_test_receipt(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-04",
+            50,
+        )?;
+        db_connection.set_field_in_table(
+            "payment",
+            payment,
+            "receipt_number",
+            "2026_#7 [a^b]",
+        )?;
+
+        let latex = crate::generate_latex(
+            db_connection,
+            payment,
+            None,
+        )?;
+
+        let rendered = latex::print(&latex).unwrap();
+
+        insta::assert_snapshot!(rendered);
+
+        Ok(())
+    }
+
+    // The logo path goes into LaTeX unescaped, so one that could inject
+    // commands is rejected, as is one that doesn't exist.
+    #[test]
+    fn test_logo_path() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_trainer(db_connection)?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        let payment = add_test_receipt(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-04",
+            50,
+        )?;
+
+        db_connection.set_field_in_table(
+            "trainer",
+            trainer,
+            "logo_path",
+            "logo.png}\\input{/etc/passwd",
+        )?;
+        assert_eq!(
+            crate::generate_latex(
+                db_connection,
+                payment,
+                None
+            )
+            .unwrap_err()
+            .message()
+            .clone()
+            .unwrap(),
+            "logo path logo.png}\\input{/etc/passwd of trainer Tara \
+                Trainer can't contain {, }, \\, % or #"
+        );
+
+        db_connection.set_field_in_table(
+            "trainer",
+            trainer,
+            "logo_path",
+            "no-such-logo.png",
+        )?;
+        assert!(
+            crate::generate_latex(
+                db_connection,
+                payment,
+                None
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_billing_commands() -> dolmen::Result<()> {
         let mut context = Context::new();
@@ -801,6 +2177,7 @@ mod test {
         let latex = crate::generate_latex(
             db_connection,
             invoice,
+            None,
         )?;
 
         let out_path = std::env::temp_dir();
@@ -831,23 +2208,198 @@ mod test {
         Ok(())
     }
 
-    fn setup_test_context() -> dolmen::Result<Context>
-    {
-        let mut context = Context::new();
+    #[test]
+    fn test_invoice_formats() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let invoice = setup_invoice_data(
+            context.db_connection()?,
+        )?;
+        let out_dir = std::env::temp_dir()
+            .join("training_assistant_formats_test");
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let out_path = out_dir.to_str().unwrap();
+
+        let response = context.execute(&format!(
+            "invoice generate --payment-id={} --out-dir={} \
+                --format=html",
+            invoice.0, out_path
+        ))?;
+        let html_path = out_dir.join("invoice.html");
+        assert_eq!(
+            response.text().unwrap(),
+            format!(
+                "Successfully generated invoice at {}.",
+                html_path.display()
+            )
+        );
+        let html = std::fs::read_to_string(html_path)
+            .unwrap();
+        assert!(
+            html.contains("<dd>Clarissa Client</dd>")
+        );
+        assert!(html.contains(
+            "<td>2026-01-04</td><td>Personal training session \
+                (60 min)</td>"
+        ));
+
+        context.execute(&format!(
+            "invoice generate --payment-id={} --out-dir={} \
+                --format=text",
+            invoice.0, out_path
+        ))?;
+        let text = std::fs::read_to_string(
+            out_dir.join("invoice.txt"),
+        )
+        .unwrap();
+        assert!(text.contains(
+            "Client Name:    Clarissa Client"
+        ));
+        assert!(text.contains("Amount paid: 50.00"));
 
-        context
-            .add_plugin(DbPlugin)?
-            .add_plugin(BillingPlugin)?
-            .add_plugin(TrainingPlugin)?;
+        assert_eq!(
+            context
+                .execute(&format!(
+                    "invoice generate --payment-id={} \
+                        --out-dir={} --format=docx",
+                    invoice.0, out_path
+                ))
+                .unwrap_err()
+                .message()
+                .clone()
+                .unwrap(),
+            "unknown invoice format docx (expected latex, html or \
+                text)"
+        );
+
+        std::fs::remove_dir_all(out_dir).unwrap();
 
+        Ok(())
+    }
+
+    #[test]
+    fn test_invoice_templates() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let invoice = setup_invoice_data(
+            context.db_connection()?,
+        )?;
+        let out_dir = std::env::temp_dir()
+            .join("training_assistant_templates_test");
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let out_path = out_dir.to_str().unwrap();
+        let generate = format!(
+            "invoice generate --payment-id={} --out-dir={} \
+                --format=text",
+            invoice.0, out_path
+        );
+        let read_invoice = || {
+            std::fs::read_to_string(
+                out_dir.join("invoice.txt"),
+            )
+            .unwrap()
+        };
+
+        // a template in the template directory replaces the built-in one
+        std::fs::write(
+            out_dir.join("invoice.txt"),
+            "Receipt for {{clientname}}: {{paymentamount}}",
+        )
+        .unwrap();
         context
-            .get_resource_mut::<DbConfig>()
+            .get_resource_mut::<TemplateConfig>()
             .unwrap()
-            .open_db_in_memory = true;
+            .template_dir = Some(out_dir.clone());
+        context.execute(&generate)?;
+        assert_eq!(
+            read_invoice(),
+            "Receipt for Clarissa Client: 50.00"
+        );
 
-        context.startup()?;
+        // a trainer's own template comes first
+        let trainer_template =
+            out_dir.join("tara.txt");
+        std::fs::write(
+            &trainer_template,
+            "{{companyname}} thanks {{clientname}}",
+        )
+        .unwrap();
+        context.db_connection()?.set_field_in_table(
+            "trainer",
+            RowId(1),
+            "invoice_template",
+            trainer_template.to_str().unwrap(),
+        )?;
+        context.execute(&generate)?;
+        assert_eq!(
+            read_invoice(),
+            "Tara Fitness thanks Clarissa Client"
+        );
+
+        // templates are checked against the documented variables
+        let response = context.execute(&format!(
+            "invoice template check --file={}",
+            trainer_template.display()
+        ))?;
+        assert_eq!(
+            response.text().unwrap(),
+            format!(
+                "Invoice template {} is valid (txt format).",
+                trainer_template.display()
+            )
+        );
+        std::fs::write(
+            &trainer_template,
+            "{{#each chargedata}}{{tax}}",
+        )
+        .unwrap();
+        assert_eq!(
+            context
+                .execute(&format!(
+                    "invoice template check --file={}",
+                    trainer_template.display()
+                ))
+                .unwrap_err()
+                .message()
+                .clone()
+                .unwrap(),
+            format!(
+                "invalid invoice template {}: line 1: {{{{#each \
+                    chargedata}}}} is never closed",
+                trainer_template.display()
+            )
+        );
+
+        std::fs::remove_dir_all(out_dir).unwrap();
+
+        Ok(())
+    }
+
+    // Every variable an invoice is filled with is documented, and vice
+    // versa.
+    #[test]
+    fn test_invoice_variables() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let invoice =
+            setup_invoice_data(db_connection)?;
+
+        let (_, data) =
+            invoice_data(db_connection, invoice)?;
+        let mut names: Vec<&str> = data
+            .entries()
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        let mut documented: Vec<&str> =
+            INVOICE_VARIABLES
+                .iter()
+                .map(|variable| variable.name)
+                .chain([CHARGE_TABLE, TAX_TABLE])
+                .collect();
+        documented.sort();
+        assert_eq!(names, documented);
 
-        Ok(context)
+        Ok(())
     }
 
     // Simplest test case: one client, one charge, one payment. Same date, for 50.
@@ -859,21 +2411,22 @@ mod test {
         let db_connection = context.db_connection()?;
 
         let trainer = add_test_trainer(db_connection)?;
-        let client = add_test_client(
+        let client = add_test_row(
             db_connection,
+            "client",
             "Clarissa Client",
         )?;
         let charge = add_test_charge(
             db_connection,
+            client,
             "2026-01-14",
             50,
-            client,
         )?;
-        let payment = add_test_payment(
+        let payment = add_test_receipt(
             db_connection,
             client,
             trainer,
-            "2026-01-14".into(),
+            "2026-01-14",
             50,
         )?;
 
@@ -883,9 +2436,18 @@ mod test {
         assert!(
             receipt_info.charges.contains(&charge)
         );
-        assert_eq!(receipt_info.start_balance, 0);
-        assert_eq!(receipt_info.end_balance, 0);
-        assert_eq!(receipt_info.charge_total, 50);
+        assert_eq!(
+            receipt_info.start_balance,
+            Money::from_dollars(0)?
+        );
+        assert_eq!(
+            receipt_info.end_balance,
+            Money::from_dollars(0)?
+        );
+        assert_eq!(
+            receipt_info.charge_total,
+            Money::from_dollars(50)?
+        );
 
         Ok(())
     }
@@ -897,35 +2459,42 @@ mod test {
         let mut context = setup_test_context()?;
         let db_connection = context.db_connection()?;
         let trainer = add_test_trainer(db_connection)?;
-        let client = add_test_client(
+        let client = add_test_row(
             db_connection,
+            "client",
             "Clarissa Client",
         )?;
         let charge_1 = add_test_charge(
             db_connection,
+            client,
             "2026-02-10",
             50,
-            client,
         )?;
         let charge_2 = add_test_charge(
             db_connection,
+            client,
             "2026-02-11",
             50,
-            client,
         )?;
-        let payment = add_test_payment(
+        let payment = add_test_receipt(
             db_connection,
             client,
             trainer,
-            "2026-02-12".into(),
+            "2026-02-12",
             90,
         )?;
 
         let receipt_info =
             get_receipt_info(db_connection, payment)?;
 
-        assert_eq!(receipt_info.start_balance, 0);
-        assert_eq!(receipt_info.end_balance, 10);
+        assert_eq!(
+            receipt_info.start_balance,
+            Money::from_dollars(0)?
+        );
+        assert_eq!(
+            receipt_info.end_balance,
+            Money::from_dollars(10)?
+        );
         assert_eq!(receipt_info.charges.len(), 2);
         assert!(
             receipt_info.charges.contains(&charge_1)
@@ -933,7 +2502,10 @@ mod test {
         assert!(
             receipt_info.charges.contains(&charge_2)
         );
-        assert_eq!(receipt_info.charge_total, 100);
+        assert_eq!(
+            receipt_info.charge_total,
+            Money::from_dollars(100)?
+        );
 
         Ok(())
     }
@@ -949,40 +2521,41 @@ mod test {
         let mut context = setup_test_context()?;
         let db_connection = context.db_connection()?;
         let trainer = add_test_trainer(db_connection)?;
-        let client = add_test_client(
+        let client = add_test_row(
             db_connection,
+            "client",
             "Clarissa Client",
         )?;
         let charge_1 = add_test_charge(
             db_connection,
+            client,
             "2026-03-01",
             50,
-            client,
         )?;
         let charge_2 = add_test_charge(
             db_connection,
+            client,
             "2026-03-03",
             50,
-            client,
         )?;
         let charge_3 = add_test_charge(
             db_connection,
+            client,
             "2026-03-04",
             50,
-            client,
         )?;
-        let payment_1 = add_test_payment(
+        let payment_1 = add_test_receipt(
             db_connection,
             client,
             trainer,
-            "2026-03-02".into(),
+            "2026-03-02",
             60,
         )?;
-        let payment_2 = add_test_payment(
+        let payment_2 = add_test_receipt(
             db_connection,
             client,
             trainer,
-            "2026-03-05".into(),
+            "2026-03-05",
             90,
         )?;
 
@@ -996,16 +2569,31 @@ mod test {
             payment_2,
         )?;
 
-        assert_eq!(receipt_info_1.start_balance, 0);
-        assert_eq!(receipt_info_1.end_balance, -10);
+        assert_eq!(
+            receipt_info_1.start_balance,
+            Money::from_dollars(0)?
+        );
+        assert_eq!(
+            receipt_info_1.end_balance,
+            Money::from_dollars(-10)?
+        );
         assert_eq!(receipt_info_1.charges.len(), 1);
         assert!(
             receipt_info_1.charges.contains(&charge_1)
         );
-        assert_eq!(receipt_info_1.charge_total, 50);
+        assert_eq!(
+            receipt_info_1.charge_total,
+            Money::from_dollars(50)?
+        );
 
-        assert_eq!(receipt_info_2.start_balance, -10);
-        assert_eq!(receipt_info_2.end_balance, 0);
+        assert_eq!(
+            receipt_info_2.start_balance,
+            Money::from_dollars(-10)?
+        );
+        assert_eq!(
+            receipt_info_2.end_balance,
+            Money::from_dollars(0)?
+        );
         assert_eq!(receipt_info_2.charges.len(), 2);
         assert!(
             receipt_info_2.charges.contains(&charge_2)
@@ -1013,7 +2601,755 @@ mod test {
         assert!(
             receipt_info_2.charges.contains(&charge_3)
         );
-        assert_eq!(receipt_info_2.charge_total, 100);
+        assert_eq!(
+            receipt_info_2.charge_total,
+            Money::from_dollars(100)?
+        );
+
+        Ok(())
+    }
+
+    // One client with a long history: a year of daily charges for 50 and a
+    // payment for 1500 at the end of each month. Checks the last payment
+    // only sees the charges since the previous one, and that the start
+    // balance carries the unpaid amount forward.
+    #[test]
+    fn test_receipt_info_4() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_trainer(db_connection)?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+
+        let start = chrono::NaiveDate::from_ymd_opt(
+            2026, 1, 1,
+        )
+        .unwrap();
+        let mut payment = None;
+        for day in 0..365 {
+            let date = start + chrono::Days::new(day);
+            add_test_charge(
+                db_connection,
+                date.to_string().as_str(),
+                50,
+                client,
+            )?;
+            if (date + chrono::Days::new(1)).day() == 1
+            {
+                payment = Some(add_test_receipt(
+                    db_connection,
+                    client,
+                    trainer,
+                    &date.to_string(),
+                    1500,
+                )?);
+            }
+        }
+
+        let receipt_info = get_receipt_info(
+            db_connection,
+            payment.unwrap(),
+        )?;
+
+        // 365 days of charges, 11 payments before December's
+        assert_eq!(
+            receipt_info.start_balance,
+            Money::from_dollars(334 * 50 - 11 * 1500)?
+        );
+        assert_eq!(receipt_info.charges.len(), 31);
+        assert_eq!(
+            receipt_info.charge_total,
+            Money::from_dollars(31 * 50)?
+        );
+        assert_eq!(
+            receipt_info.end_balance,
+            Money::from_dollars(365 * 50 - 12 * 1500)?
+        );
+
+        Ok(())
+    }
+
+    // One client, charges before and during the statement period, with a
+    // payment in the middle. Expect the opening balance to cover the charge
+    // before the period, charges to sort before a payment on the same day,
+    // and the running balance to end at the closing balance.
+    #[test]
+    fn test_statement_info() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_trainer(db_connection)?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        let other_client = add_test_row(
+            db_connection,
+            "client",
+            "Otto Other",
+        )?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-04-28",
+            50,
+        )?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-05-02",
+            50,
+        )?;
+        add_test_receipt(
+            db_connection,
+            client,
+            trainer,
+            "2026-05-02",
+            80,
+        )?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-05-09",
+            50,
+        )?;
+        add_test_charge(
+            db_connection,
+            other_client,
+            "2026-05-09",
+            70,
+        )?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-06-01",
+            50,
+        )?;
+
+        let from = chrono::NaiveDate::from_ymd_opt(
+            2026, 5, 1,
+        )
+        .unwrap();
+        let to = chrono::NaiveDate::from_ymd_opt(
+            2026, 5, 31,
+        )
+        .unwrap();
+        let statement_info = get_statement_info(
+            db_connection,
+            client,
+            from,
+            to,
+        )?;
+
+        assert_eq!(
+            statement_info.opening_balance,
+            Money::from_dollars(50)?
+        );
+        assert_eq!(statement_info.entries.len(), 3);
+        assert_eq!(
+            statement_info.entries[0].charge,
+            Some(Money::from_dollars(50)?)
+        );
+        assert_eq!(
+            statement_info.entries[0].balance,
+            Money::from_dollars(100)?
+        );
+        assert_eq!(
+            statement_info.entries[1].payment,
+            Some(Money::from_dollars(80)?)
+        );
+        assert_eq!(
+            statement_info.entries[1].balance,
+            Money::from_dollars(20)?
+        );
+        assert_eq!(
+            statement_info.entries[2].balance,
+            Money::from_dollars(70)?
+        );
+        assert_eq!(
+            statement_info.closing_balance,
+            Money::from_dollars(70)?
+        );
+        assert_eq!(
+            statement_info.closing_balance,
+            crate::client_balance_on(
+                db_connection.connection()?,
+                client,
+                to
+            )?
+        );
+
+        Ok(())
+    }
+
+    // A client billed in two currencies has no balance to report.
+    #[test]
+    fn test_mixed_currencies() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-05-02",
+            50,
+        )?;
+        let charge = add_test_charge(
+            db_connection,
+            client,
+            "2026-05-09",
+            20,
+        )?;
+        set_money(
+            db_connection,
+            "charge",
+            charge,
+            "amount",
+            Some(Money::new(2000, Currency::Eur)),
+        )?;
+        assert_eq!(
+            get_money(
+                db_connection,
+                "charge",
+                charge,
+                "amount",
+            )?,
+            Some(Money::new(2000, Currency::Eur))
+        );
+        // the amount itself is stored as cents, with the code alongside
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<i64>(
+                    "charge", charge, "amount",
+                )?,
+            2000
+        );
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<String>(
+                    "charge", charge, "currency",
+                )?,
+            "EUR"
+        );
+        // and can't be written without it
+        assert!(
+            db_connection
+                .set_field_in_table(
+                    "charge",
+                    charge,
+                    "amount",
+                    Money::new(2000, Currency::Eur),
+                )
+                .is_err()
+        );
+
+        let date = chrono::NaiveDate::from_ymd_opt(
+            2026, 5, 31,
+        )
+        .unwrap();
+        let mixed = Some(
+            "can't add amounts in USD and EUR"
+                .to_string(),
+        );
+        assert_eq!(
+            crate::client_balance_on(
+                db_connection.connection()?,
+                client,
+                date
+            )
+            .unwrap_err()
+            .message()
+            .clone(),
+            mixed
+        );
+        assert!(
+            get_statement_info(
+                db_connection,
+                client,
+                date,
+                date
+            )
+            .is_err()
+        );
+        assert!(
+            crate::aging::aging_report(
+                db_connection,
+                date
+            )
+            .is_err()
+        );
+        assert!(
+            context
+                .execute(
+                    "billing aging --date=2026-05-31"
+                )
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    // Exported payments name their client and trainer, though neither
+    // field has a `#[display_table]`, and give amounts as plain decimals
+    // with their currency.
+    #[test]
+    fn test_export_payments() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        context.add_plugin(
+            db_commands::DbCommandsPlugin,
+        )?;
+
+        let db_connection = context.db_connection()?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        let trainer = db_connection
+            .new_row_in_table("trainer")?;
+        db_connection.set_field_in_table(
+            "trainer", trainer, "name", "Tara",
+        )?;
+        let payment = add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-04",
+            1234,
+        )?;
+        db_connection.set_field_in_table(
+            "payment",
+            payment,
+            "amount",
+            Money::from_cents(123450),
+        )?;
+
+        let dir = std::env::temp_dir().join(
+            "training_assistant_test_export_payments",
+        );
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv_file = dir.join("payments.csv");
+        context.execute(&format!(
+            "export --table=payment --out-file={}",
+            csv_file.display()
+        ))?;
+        let csv = std::fs::read_to_string(&csv_file)
+            .unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with(
+            "id,date,trainer,trainer_display,client,\
+                client_display,amount,currency,"
+        ));
+        assert!(lines.next().unwrap().starts_with(
+            "1,2026-01-04,1,Tara,1,Clarissa Client,1234.50,USD,"
+        ));
+
+        Ok(())
+    }
+
+    // `set` parses amounts into cents, so they sum correctly.
+    #[test]
+    fn test_set_amount() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        context.add_plugin(
+            db_commands::DbCommandsPlugin,
+        )?;
+
+        let db_connection = context.db_connection()?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+
+        context.execute(
+            "set --table=charge --row-id=1 --field=amount \
+                --value=$12.50",
+        )?;
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_field_in_table_row::<i64>(
+                    "charge",
+                    RowId(1),
+                    "amount",
+                )?,
+            1250
+        );
+        assert!(
+            context
+                .execute(
+                    "set --table=charge --row-id=1 --field=amount \
+                        --value=12.505"
+                )
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    // Databases from before `Money` stored whole dollars; the first
+    // migration converts them to cents, once.
+    #[test]
+    fn test_amounts_migration() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        db_commands::run_migrations(&mut context)?;
+
+        // tables from before migrations are at version 0, missing the
+        // fields added since
+        let db_connection = context.db_connection()?;
+        db_connection
+            .connection()?
+            .execute_batch(
+                "ALTER TABLE charge DROP COLUMN tax;
+                ALTER TABLE payment DROP COLUMN invoice;
+                UPDATE schema_version SET version = 0
+                    WHERE table_name IN ('charge', 'payment');",
+            )
+            .map_err(sql_error)?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        let charge = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        db_connection.set_field_in_table(
+            "charge", charge, "amount", 50,
+        )?;
+
+        let applied =
+            db_commands::run_migrations(&mut context)?;
+        assert_eq!(
+            applied,
+            vec![
+                "charge v1: store amounts in cents",
+                "charge v2: add voids and reason",
+                "charge v3: add invoice",
+                "charge v4: add service",
+                "charge v5: add tax_rate and tax",
+                "charge v6: add currency",
+                "payment v1: store amounts in cents",
+                "payment v2: add refunds and reason",
+                "payment v3: add invoice",
+                "payment v4: number receipts on recording",
+                "payment v5: add currency",
+            ]
+        );
+        assert!(
+            db_commands::run_migrations(&mut context)?
+                .is_empty()
+        );
+        assert_eq!(
+            get_money(
+                context.db_connection()?,
+                "charge",
+                charge,
+                "amount",
+            )?,
+            Some(Money::from_dollars(50)?)
+        );
+
+        Ok(())
+    }
+
+    // Amounts used to be in dollars only; a migration adds the currency
+    // column, with the existing amounts in the default currency.
+    #[test]
+    fn test_currency_migration() -> dolmen::Result<()>
+    {
+        let mut context = setup_test_context()?;
+
+        let db_connection = context.db_connection()?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        let charge = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        // a charge table at version 5, from before currencies (reading the
+        // version creates the version table)
+        db_commands::schema_version(
+            db_connection,
+            "charge",
+        )?;
+        db_connection
+            .connection()?
+            .execute_batch(
+                "ALTER TABLE charge DROP COLUMN currency;
+                INSERT INTO schema_version (table_name, version)
+                    VALUES ('charge', 5);",
+            )
+            .map_err(sql_error)?;
+
+        assert_eq!(
+            db_commands::run_migrations(&mut context)?,
+            vec!["charge v6: add currency"]
+        );
+        let db_connection = context.db_connection()?;
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<String>(
+                    "charge", charge, "currency",
+                )?,
+            "USD"
+        );
+        assert_eq!(
+            get_charge(db_connection, charge)?.amount,
+            Money::from_dollars(50)?
+        );
+
+        Ok(())
+    }
+
+    // Charges and payments are read with their amounts in the row's
+    // currency.
+    #[test]
+    fn test_read_currency() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+
+        let db_connection = context.db_connection()?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        let trainer = add_test_trainer(db_connection)?;
+        let charge = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            20,
+        )?;
+        set_money(
+            db_connection,
+            "charge",
+            charge,
+            "tax",
+            Some(Money::new(165, Currency::Eur)),
+        )?;
+        let payment = add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-05",
+            20,
+        )?;
+        set_money(
+            db_connection,
+            "payment",
+            payment,
+            "amount",
+            Some(Money::new(2000, Currency::Eur)),
+        )?;
+
+        let charge =
+            get_charge(db_connection, charge)?;
+        assert_eq!(
+            charge.amount,
+            Money::new(2000, Currency::Eur)
+        );
+        assert_eq!(
+            charge.tax,
+            Some(Money::new(165, Currency::Eur))
+        );
+        assert_eq!(
+            get_payment(db_connection, payment)?
+                .amount,
+            Money::new(2000, Currency::Eur)
+        );
+
+        Ok(())
+    }
+
+    // Payments recorded before receipts were numbered on recording are
+    // numbered, oldest first, by a migration. Refunds aren't, and a
+    // payment whose trainer's pattern is invalid is left for
+    // `billing number-receipts`.
+    #[test]
+    fn test_receipt_number_migration()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        context.add_plugin(
+            db_commands::DbCommandsPlugin,
+        )?;
+        db_commands::run_migrations(&mut context)?;
+
+        let db_connection = context.db_connection()?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        let tara = add_test_trainer(db_connection)?;
+        let theo = db_connection
+            .new_row_in_table("trainer")?;
+        db_connection.set_field_in_table(
+            "trainer",
+            theo,
+            "receipt_pattern",
+            "{year}",
+        )?;
+        let later = add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2026-02-01",
+            50,
+        )?;
+        let earlier = add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2026-01-04",
+            50,
+        )?;
+        let refund = add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2026-01-05",
+            -50,
+        )?;
+        db_connection.set_field_in_table(
+            "payment", refund, "refunds", earlier.0,
+        )?;
+        let invalid = add_test_payment(
+            db_connection,
+            client,
+            theo,
+            "2026-01-06",
+            50,
+        )?;
+        db_connection
+            .connection()?
+            .execute(
+                "UPDATE schema_version SET version = 3
+                    WHERE table_name = 'payment'",
+                [],
+            )
+            .map_err(sql_error)?;
+
+        assert_eq!(
+            db_commands::run_migrations(&mut context)?,
+            vec![
+                "payment v4: number receipts on recording",
+                "payment v5: add currency",
+            ]
+        );
+        let db_connection = context.db_connection()?;
+        let mut number = |payment| {
+            db_connection.get_field_in_table_row::<
+                Option<String>,
+            >("payment", payment, "receipt_number")
+        };
+        assert_eq!(
+            number(earlier)?,
+            Some("2026-0001".to_string())
+        );
+        assert_eq!(
+            number(later)?,
+            Some("2026-0002".to_string())
+        );
+        assert_eq!(number(refund)?, None);
+        assert_eq!(number(invalid)?, None);
+
+        Ok(())
+    }
+
+    // A table that wasn't in the database when migrations last ran was
+    // created with the current schema, so it already stores cents, even if
+    // rows were added to it before migrations ran again.
+    #[test]
+    fn test_amounts_migration_new_database()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        db_commands::run_migrations(&mut context)?;
+
+        let db_connection = context.db_connection()?;
+        db_connection
+            .connection()?
+            .execute(
+                "DELETE FROM schema_version
+                    WHERE table_name = 'charge'",
+                [],
+            )
+            .map_err(sql_error)?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa Client",
+        )?;
+        let charge = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+
+        for _ in 0..2 {
+            assert!(
+                db_commands::run_migrations(
+                    &mut context
+                )?
+                .is_empty()
+            );
+            assert_eq!(
+                get_money(
+                    context.db_connection()?,
+                    "charge",
+                    charge,
+                    "amount",
+                )?,
+                Some(Money::from_dollars(50)?)
+            );
+        }
+        assert_eq!(
+            db_commands::schema_version(
+                context.db_connection()?,
+                "charge"
+            )?,
+            6
+        );
+
+        // it gets the indices the balance queries rely on too
+        let indices: i64 = context
+            .db_connection()?
+            .connection()?
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master
+                    WHERE type = 'index' AND name IN (
+                        'charge_client_date',
+                        'payment_client_date',
+                        'package_client',
+                        'credit_use_package')",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(indices, 4);
 
         Ok(())
     }
@@ -0,0 +1,1017 @@
+//! A fixed-point money type for charge and payment amounts.
+use dolmen::prelude::*;
+use rusqlite::types::{
+    FromSql, FromSqlError, FromSqlResult, ToSql,
+    ToSqlOutput, Type, ValueRef,
+};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// A currency that amounts can be billed in.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+pub enum Currency {
+    /// US dollars.
+    #[default]
+    Usd,
+    /// Canadian dollars.
+    Cad,
+    /// Australian dollars.
+    Aud,
+    /// Euros.
+    Eur,
+    /// Pounds sterling.
+    Gbp,
+}
+
+impl Currency {
+    /// All supported currencies.
+    pub const ALL: [Currency; 5] = [
+        Currency::Usd,
+        Currency::Cad,
+        Currency::Aud,
+        Currency::Eur,
+        Currency::Gbp,
+    ];
+
+    /// Gets the ISO 4217 code of the currency (e.g. `"USD"`).
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Usd => "USD",
+            Currency::Cad => "CAD",
+            Currency::Aud => "AUD",
+            Currency::Eur => "EUR",
+            Currency::Gbp => "GBP",
+        }
+    }
+
+    /// Gets the symbol used when displaying amounts (e.g. `"$"`).
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Currency::Usd => "$",
+            Currency::Cad => "CA$",
+            Currency::Aud => "A$",
+            Currency::Eur => "€",
+            Currency::Gbp => "£",
+        }
+    }
+
+    /// Looks up a currency from its ISO 4217 code, ignoring case.
+    pub fn from_code(code: &str) -> Option<Currency> {
+        Currency::ALL.into_iter().find(|c| {
+            c.code().eq_ignore_ascii_case(code)
+        })
+    }
+}
+
+impl fmt::Display for Currency {
+    /// Formats the currency as its code (e.g. `"USD"`).
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// An error returned when parsing a `Currency` from a string fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCurrencyError(String);
+
+impl fmt::Display for ParseCurrencyError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "unknown currency: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseCurrencyError {}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    /// Parses a currency from its code, ignoring case and surrounding
+    /// spaces.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Currency::from_code(s.trim()).ok_or_else(
+            || ParseCurrencyError(s.into()),
+        )
+    }
+}
+
+impl ToSql for Currency {
+    /// Writes the currency as its code.
+    fn to_sql(
+        &self,
+    ) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.code()))
+    }
+}
+
+impl FromSql for Currency {
+    /// Reads a currency from its code. NULL is the default currency, which
+    /// is what rows have until their currency is set.
+    fn column_result(
+        value: ValueRef<'_>,
+    ) -> FromSqlResult<Self> {
+        if let ValueRef::Null = value {
+            return Ok(Currency::default());
+        }
+        value.as_str()?.parse().map_err(|e| {
+            FromSqlError::Other(Box::new(e))
+        })
+    }
+}
+
+/// An amount of money, stored as a whole number of cents so that sums never
+/// pick up floating-point error.
+///
+/// In the database an amount is stored as an `INTEGER` number of cents, and
+/// its currency in the `currency` column of the same row, so every table
+/// with amounts has one. The currency isn't part of the stored amount:
+/// read amounts with `get_money` or `read_money`, write them with
+/// `set_money`, and add them up in queries with `total_sql` and
+/// `read_total`.
+///
+/// Amounts in different currencies can't be added, so there are no `+` and
+/// `-` operators: use `checked_add`, `checked_sub`, `checked_neg` and
+/// `total`, which return a `MoneyError` instead. Likewise amounts are only
+/// ordered within one currency; use `checked_cmp` to compare amounts that
+/// may be in different ones.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash,
+)]
+pub struct Money {
+    cents: i64,
+    currency: Currency,
+}
+
+impl Money {
+    /// Creates an amount from a number of cents (or the currency's
+    /// equivalent minor unit).
+    pub fn new(
+        cents: i64,
+        currency: Currency,
+    ) -> Self {
+        Self { cents, currency }
+    }
+
+    /// Creates an amount in the default currency from a number of cents.
+    pub fn from_cents(cents: i64) -> Self {
+        Self::new(cents, Currency::default())
+    }
+
+    /// Creates an amount in the default currency from a whole number of
+    /// dollars (or the currency's equivalent major unit).
+    pub fn from_dollars(
+        dollars: i64,
+    ) -> Result<Money, MoneyError> {
+        dollars
+            .checked_mul(100)
+            .map(Self::from_cents)
+            .ok_or(MoneyError::Overflow)
+    }
+
+    /// An amount of zero in the default currency.
+    pub fn zero() -> Self {
+        Self::default()
+    }
+
+    /// Gets the amount as a number of cents.
+    pub fn cents(&self) -> i64 {
+        self.cents
+    }
+
+    /// Gets the currency of the amount.
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Gets the same number of cents in another currency. Used to put
+    /// amounts read from a table row in the row's currency.
+    pub fn with_currency(
+        self,
+        currency: Currency,
+    ) -> Money {
+        Money::new(self.cents, currency)
+    }
+
+    /// Returns whether the amount is zero.
+    pub fn is_zero(&self) -> bool {
+        self.cents == 0
+    }
+
+    /// Returns whether the amount is below zero.
+    pub fn is_negative(&self) -> bool {
+        self.cents < 0
+    }
+
+    /// Returns whether the amount is above zero.
+    pub fn is_positive(&self) -> bool {
+        self.cents > 0
+    }
+
+    /// Adds two amounts. A zero amount can be added to an amount in any
+    /// currency; otherwise the currencies have to match.
+    pub fn checked_add(
+        self,
+        other: Money,
+    ) -> Result<Money, MoneyError> {
+        let currency = if self.is_zero() {
+            other.currency
+        } else if other.is_zero()
+            || self.currency == other.currency
+        {
+            self.currency
+        } else {
+            return Err(MoneyError::CurrencyMismatch(
+                self.currency,
+                other.currency,
+            ));
+        };
+        let cents = self
+            .cents
+            .checked_add(other.cents)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(cents, currency))
+    }
+
+    /// Subtracts an amount from this one, as `checked_add` does.
+    pub fn checked_sub(
+        self,
+        other: Money,
+    ) -> Result<Money, MoneyError> {
+        self.checked_add(other.checked_neg()?)
+    }
+
+    /// Negates the amount, keeping its currency.
+    pub fn checked_neg(
+        self,
+    ) -> Result<Money, MoneyError> {
+        let cents = self
+            .cents
+            .checked_neg()
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(cents, self.currency))
+    }
+
+    /// Compares two amounts. As with `checked_add`, a zero amount compares
+    /// with an amount in any currency; otherwise the currencies have to
+    /// match.
+    pub fn checked_cmp(
+        self,
+        other: Money,
+    ) -> Result<Ordering, MoneyError> {
+        if self.currency != other.currency
+            && !self.is_zero()
+            && !other.is_zero()
+        {
+            return Err(MoneyError::CurrencyMismatch(
+                self.currency,
+                other.currency,
+            ));
+        }
+        Ok(self.cents.cmp(&other.cents))
+    }
+
+    /// Adds up amounts with `checked_add`. The total of no amounts is zero.
+    pub fn total(
+        amounts: impl IntoIterator<Item = Money>,
+    ) -> Result<Money, MoneyError> {
+        amounts.into_iter().try_fold(
+            Money::zero(),
+            Money::checked_add,
+        )
+    }
+
+    /// Formats the amount as a plain decimal number without a currency
+    /// symbol or separators (e.g. `"-1234.50"`). Useful where the currency
+    /// is printed elsewhere, such as a column header.
+    pub fn to_decimal_string(&self) -> String {
+        let sign =
+            if self.cents < 0 { "-" } else { "" };
+        let abs = self.cents.unsigned_abs();
+        format!(
+            "{}{}.{:02}",
+            sign,
+            abs / 100,
+            abs % 100
+        )
+    }
+}
+
+impl PartialOrd for Money {
+    /// Orders amounts in the same currency. Amounts in different currencies
+    /// aren't ordered, not even against zero, as they aren't equal either.
+    fn partial_cmp(
+        &self,
+        other: &Money,
+    ) -> Option<Ordering> {
+        (self.currency == other.currency)
+            .then(|| self.cents.cmp(&other.cents))
+    }
+}
+
+impl fmt::Display for Money {
+    /// Formats the amount with its currency symbol and thousands separators
+    /// (e.g. `"-$1,234.50"`).
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        let sign =
+            if self.cents < 0 { "-" } else { "" };
+        let abs = self.cents.unsigned_abs();
+        let whole = (abs / 100).to_string();
+        let mut grouped = String::new();
+        for (i, c) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 {
+                grouped.push(',');
+            }
+            grouped.push(c);
+        }
+        write!(
+            f,
+            "{}{}{}.{:02}",
+            sign,
+            self.currency.symbol(),
+            grouped,
+            abs % 100
+        )
+    }
+}
+
+/// An error returned when adding up or storing amounts fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoneyError {
+    /// The amounts are in different currencies.
+    CurrencyMismatch(Currency, Currency),
+
+    /// The result is too large to store.
+    Overflow,
+
+    /// The amount isn't in the default currency, so it can't be stored
+    /// without its row's currency (see `set_money`).
+    CurrencyNotStored(Currency),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch(a, b) => {
+                write!(
+                    f,
+                    "can't add amounts in {} and {}",
+                    a.code(),
+                    b.code()
+                )
+            }
+            MoneyError::Overflow => {
+                write!(f, "amount is too large")
+            }
+            MoneyError::CurrencyNotStored(
+                currency,
+            ) => {
+                write!(
+                    f,
+                    "an amount in {} can't be stored on its \
+                        own: give it without a code and set the \
+                        row's currency to {}",
+                    currency.code(),
+                    currency.code()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+impl From<MoneyError> for dolmen::Error {
+    fn from(e: MoneyError) -> Self {
+        dolmen::Error::new(e.to_string())
+    }
+}
+
+/// An error returned when parsing a `Money` from a string fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMoneyError(String);
+
+impl fmt::Display for ParseMoneyError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "invalid amount: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseMoneyError {}
+
+impl FromStr for Money {
+    type Err = ParseMoneyError;
+
+    /// Parses an amount such as `"50"`, `"12.5"`, `"-$1,234.50"` or
+    /// `"20.00 EUR"`. At most two decimal places are accepted.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseMoneyError(s.to_string());
+
+        let mut text = s.trim();
+        let mut currency = None;
+
+        // trailing currency code, e.g. "20.00 EUR"
+        if let Some((amount, code)) =
+            text.rsplit_once(' ')
+        {
+            currency = Some(
+                Currency::from_code(code.trim())
+                    .ok_or_else(err)?,
+            );
+            text = amount.trim();
+        }
+
+        // a single leading minus sign, so "--5" isn't read as -5
+        let negative = match text.strip_prefix('-') {
+            Some(rest) => {
+                text = rest;
+                true
+            }
+            None => false,
+        };
+
+        // leading currency symbol, e.g. "$12.50"
+        if let Some(c) = Currency::ALL
+            .into_iter()
+            .filter(|c| text.starts_with(c.symbol()))
+            .max_by_key(|c| c.symbol().len())
+        {
+            if currency.is_some_and(|other| other != c)
+            {
+                return Err(err());
+            }
+            currency = Some(c);
+            text = &text[c.symbol().len()..];
+        }
+
+        let (whole, fraction) =
+            text.split_once('.').unwrap_or((text, ""));
+        // thousands separators have to group the digits in threes
+        let groups =
+            whole.split(',').collect::<Vec<_>>();
+        if groups.len() > 1
+            && (groups[0].is_empty()
+                || groups[0].len() > 3
+                || groups[1..]
+                    .iter()
+                    .any(|g| g.len() != 3))
+        {
+            return Err(err());
+        }
+        let whole = whole.replace(',', "");
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(err());
+        }
+        if !whole.chars().all(|c| c.is_ascii_digit())
+            || !fraction
+                .chars()
+                .all(|c| c.is_ascii_digit())
+            || fraction.len() > 2
+        {
+            return Err(err());
+        }
+
+        let whole: i64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| err())?
+        };
+        let fraction: i64 =
+            format!("{:0<2}", fraction)
+                .parse()
+                .map_err(|_| err())?;
+
+        let cents = whole
+            .checked_mul(100)
+            .and_then(|c| c.checked_add(fraction))
+            .ok_or_else(err)?;
+
+        Ok(Money::new(
+            if negative { -cents } else { cents },
+            currency.unwrap_or_default(),
+        ))
+    }
+}
+
+impl ToSql for Money {
+    /// Writes the amount as an integer number of cents. Amounts in other
+    /// currencies than the default are refused, as their currency would be
+    /// lost: write those with `set_money`, which sets the row's currency
+    /// too.
+    fn to_sql(
+        &self,
+    ) -> rusqlite::Result<ToSqlOutput<'_>> {
+        if self.currency != Currency::default() {
+            return Err(
+                rusqlite::Error::ToSqlConversionFailure(
+                    Box::new(
+                        MoneyError::CurrencyNotStored(
+                            self.currency,
+                        ),
+                    ),
+                ),
+            );
+        }
+        Ok(ToSqlOutput::from(self.cents))
+    }
+}
+
+impl FromSql for Money {
+    /// Reads an amount stored as an integer number of cents, in the default
+    /// currency, as the column doesn't hold its row's currency. Read
+    /// charges and payments with `get_charge` and `get_payment`, and other
+    /// amounts with `get_money` or `read_money`, to get them in the row's
+    /// currency. Other values are rejected.
+    fn column_result(
+        value: ValueRef<'_>,
+    ) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Integer(cents) => {
+                Ok(Money::from_cents(cents))
+            }
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// Reads an amount from a field of a table row, in the row's currency.
+/// Returns `None` if the field is NULL.
+///
+/// * `db_connection` - A connection to the database.
+/// * `table` - The name of the table.
+/// * `row_id` - The ID of the row.
+/// * `field` - The name of the money field.
+pub fn get_money(
+    db_connection: &mut DbConnection,
+    table: &str,
+    row_id: RowId,
+    field: &str,
+) -> dolmen::Result<Option<Money>> {
+    let cents = db_connection
+        .get_field_in_table_row::<Option<i64>>(
+            table, row_id, field,
+        )?;
+    let currency = db_connection
+        .get_field_in_table_row::<Currency>(
+            table,
+            row_id,
+            CURRENCY_FIELD,
+        )?;
+    Ok(cents.map(|cents| Money::new(cents, currency)))
+}
+
+/// Writes an amount to a field of a table row along with the row's
+/// currency. Writing `None` clears the field and leaves the currency as it
+/// is.
+///
+/// * `db_connection` - A connection to the database.
+/// * `table` - The name of the table.
+/// * `row_id` - The ID of the row.
+/// * `field` - The name of the money field.
+/// * `amount` - The amount to write.
+pub fn set_money(
+    db_connection: &mut DbConnection,
+    table: &str,
+    row_id: RowId,
+    field: &str,
+    amount: Option<Money>,
+) -> dolmen::Result<()> {
+    db_connection.set_field_in_table(
+        table,
+        row_id,
+        field,
+        amount.map(|amount| amount.cents()),
+    )?;
+    if let Some(amount) = amount {
+        db_connection.set_field_in_table(
+            table,
+            row_id,
+            CURRENCY_FIELD,
+            amount.currency(),
+        )?;
+    }
+    Ok(())
+}
+
+/// The name of the field storing the currency of a row's amounts.
+pub(crate) const CURRENCY_FIELD: &str = "currency";
+
+/// Gets SQL selecting a money column as the two values `read_money` reads:
+/// the number of cents and the row's currency.
+///
+/// * `column` - The column, optionally qualified with a table alias (e.g.
+///   `"c.amount"`).
+pub(crate) fn money_sql(column: &str) -> String {
+    format!("{}, {}", column, currency_sql(column))
+}
+
+/// Reads an amount selected with `money_sql`, starting at column `index`
+/// of a row.
+///
+/// * `row` - The row to read from.
+/// * `index` - The index of the first of the two columns.
+pub(crate) fn read_money(
+    row: &rusqlite::Row<'_>,
+    index: usize,
+) -> rusqlite::Result<Money> {
+    Ok(Money::new(
+        row.get(index)?,
+        row.get(index + 1)?,
+    ))
+}
+
+/// Reads an amount selected with `money_sql` that may be NULL, as
+/// `read_money` does.
+///
+/// * `row` - The row to read from.
+/// * `index` - The index of the first of the two columns.
+pub(crate) fn read_optional_money(
+    row: &rusqlite::Row<'_>,
+    index: usize,
+) -> rusqlite::Result<Option<Money>> {
+    let cents: Option<i64> = row.get(index)?;
+    let currency: Currency = row.get(index + 1)?;
+    Ok(cents.map(|cents| Money::new(cents, currency)))
+}
+
+/// Gets SQL for the currency code of the row a money column belongs to,
+/// with the default currency for rows that don't have one set.
+///
+/// * `column` - The money column, optionally qualified with a table alias.
+fn currency_sql(column: &str) -> String {
+    let currency = match column.rsplit_once('.') {
+        Some((alias, _)) => {
+            format!("{}.{}", alias, CURRENCY_FIELD)
+        }
+        None => CURRENCY_FIELD.to_string(),
+    };
+    format!(
+        "COALESCE({}, '{}')",
+        currency,
+        Currency::default().code()
+    )
+}
+
+/// Gets SQL selecting the total of a money column as the two values
+/// `read_total` reads: the total number of cents and the codes of the
+/// currencies the amounts are in.
+///
+/// * `column` - The column to add up, optionally qualified with a table
+///   alias.
+pub(crate) fn total_sql(column: &str) -> String {
+    total_with_sql(column, None)
+}
+
+/// Gets SQL selecting the total of a money column plus an optional money
+/// column of the same rows (such as a charge's amount and its tax), as
+/// `total_sql` does.
+///
+/// * `column` - The column to add up.
+/// * `extra` - The column to add to it, if any. NULLs count as zero.
+pub(crate) fn total_with_sql(
+    column: &str,
+    extra: Option<&str>,
+) -> String {
+    let cents = match extra {
+        Some(extra) => format!(
+            "{} + COALESCE({}, 0)",
+            column, extra
+        ),
+        None => column.to_string(),
+    };
+    format!(
+        "COALESCE(SUM({}), 0),
+            GROUP_CONCAT(DISTINCT CASE WHEN {} IS NOT NULL
+                THEN {} END)",
+        cents,
+        column,
+        currency_sql(column)
+    )
+}
+
+/// Reads a total selected with `total_sql`, starting at column `index` of a
+/// row. Fails if the amounts added up were in more than one currency.
+///
+/// * `row` - The row to read from.
+/// * `index` - The index of the first of the two columns.
+pub(crate) fn read_total(
+    row: &rusqlite::Row<'_>,
+    index: usize,
+) -> rusqlite::Result<Money> {
+    let cents: i64 = row.get(index)?;
+    let codes: Option<String> = row.get(index + 1)?;
+    let mut currencies = Vec::new();
+    for code in
+        codes.iter().flat_map(|codes| codes.split(','))
+    {
+        currencies.push(
+            code.parse::<Currency>().map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    index + 1,
+                    Type::Text,
+                    Box::new(e),
+                )
+            })?,
+        );
+    }
+    match currencies[..] {
+        [] => Ok(Money::from_cents(cents)),
+        [currency] => Ok(Money::new(cents, currency)),
+        [a, b, ..] => Err(
+            rusqlite::Error::FromSqlConversionFailure(
+                index + 1,
+                Type::Text,
+                Box::new(
+                    MoneyError::CurrencyMismatch(a, b),
+                ),
+            ),
+        ),
+    }
+}
+
+/// Gets a displayed amount as a plain decimal number, the column amounts
+/// are exported as (their currency is exported from the row's `currency`
+/// field). Text that isn't an amount is kept as it is.
+///
+/// * `text` - The displayed amount (e.g. `"$1,234.50"`).
+pub(crate) fn export_money(
+    text: &str,
+) -> Vec<db_commands::OutputValue> {
+    use db_commands::OutputValue;
+
+    if text.trim().is_empty() {
+        return vec![OutputValue::Null];
+    }
+    match text.parse::<Money>() {
+        Ok(amount) => vec![OutputValue::Text(
+            amount.to_decimal_string(),
+        )],
+        Err(_) => vec![OutputValue::Text(text.into())],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::{
+        Currency, Money, MoneyError, money_sql,
+        read_money, read_total, total_with_sql,
+    };
+
+    #[test]
+    fn test_money_parse() {
+        assert_eq!(
+            "50".parse::<Money>(),
+            Ok(Money::from_cents(5000))
+        );
+        assert_eq!(
+            "12.5".parse::<Money>(),
+            Ok(Money::from_cents(1250))
+        );
+        assert_eq!(
+            "-$1,234.05".parse::<Money>(),
+            Ok(Money::from_cents(-123405))
+        );
+        assert_eq!(
+            "20.00 EUR".parse::<Money>(),
+            Ok(Money::new(2000, Currency::Eur))
+        );
+        assert!("12.345".parse::<Money>().is_err());
+        assert!("1.2.3".parse::<Money>().is_err());
+        assert!("abc".parse::<Money>().is_err());
+        assert!("".parse::<Money>().is_err());
+        assert!("$5 EUR".parse::<Money>().is_err());
+        assert!("--5".parse::<Money>().is_err());
+        assert!("-$-5".parse::<Money>().is_err());
+        assert_eq!(
+            "1,234,567".parse::<Money>(),
+            Ok(Money::from_cents(123456700))
+        );
+        assert!("1,2,3".parse::<Money>().is_err());
+        assert!("1234,567".parse::<Money>().is_err());
+        assert!(",123".parse::<Money>().is_err());
+        assert!("1,23.50".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn test_money_format() {
+        assert_eq!(
+            Money::from_cents(123405).to_string(),
+            "$1,234.05"
+        );
+        assert_eq!(
+            Money::from_cents(-50).to_string(),
+            "-$0.50"
+        );
+        assert_eq!(
+            Money::new(99, Currency::Gbp).to_string(),
+            "£0.99"
+        );
+        assert_eq!(
+            Money::from_cents(-123405)
+                .to_decimal_string(),
+            "-1234.05"
+        );
+    }
+
+    #[test]
+    fn test_money_arithmetic() {
+        assert_eq!(
+            Money::total([
+                Money::from_cents(10),
+                Money::from_cents(20),
+            ]),
+            Ok(Money::from_cents(30))
+        );
+        assert_eq!(
+            Money::from_dollars(5)
+                .unwrap()
+                .checked_sub(
+                    Money::from_dollars(7).unwrap()
+                ),
+            Ok(Money::from_cents(-200))
+        );
+        assert_eq!(
+            Money::from_cents(1).checked_add(
+                Money::new(1, Currency::Eur)
+            ),
+            Err(MoneyError::CurrencyMismatch(
+                Currency::Usd,
+                Currency::Eur
+            ))
+        );
+        // zero goes with any currency
+        assert_eq!(
+            Money::total([
+                Money::new(150, Currency::Eur),
+                Money::zero(),
+                Money::new(-50, Currency::Eur),
+            ]),
+            Ok(Money::new(100, Currency::Eur))
+        );
+        // amounts are only ordered within a currency
+        assert!(
+            Money::from_cents(100)
+                < Money::from_cents(200)
+        );
+        assert_eq!(
+            Money::new(100, Currency::Eur)
+                .partial_cmp(&Money::from_cents(200)),
+            None
+        );
+        assert_eq!(
+            Money::new(100, Currency::Eur)
+                .checked_cmp(Money::from_cents(200)),
+            Err(MoneyError::CurrencyMismatch(
+                Currency::Eur,
+                Currency::Usd
+            ))
+        );
+        assert_eq!(
+            Money::new(100, Currency::Eur)
+                .checked_cmp(Money::zero()),
+            Ok(std::cmp::Ordering::Greater)
+        );
+        assert_eq!(
+            Money::from_cents(i64::MAX)
+                .checked_add(Money::from_cents(1)),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::new(5, Currency::Eur).checked_neg(),
+            Ok(Money::new(-5, Currency::Eur))
+        );
+        assert_eq!(
+            Money::from_cents(i64::MIN).checked_neg(),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::from_dollars(-2),
+            Ok(Money::from_cents(-200))
+        );
+        assert_eq!(
+            Money::from_dollars(i64::MAX),
+            Err(MoneyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_money_sql() {
+        let connection =
+            rusqlite::Connection::open_in_memory()
+                .unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE t (
+                    amount INTEGER,
+                    tax INTEGER,
+                    currency TEXT
+                )",
+            )
+            .unwrap();
+        for (amount, tax) in [
+            (Money::from_cents(1250), None),
+            (
+                Money::new(-2000, Currency::Eur),
+                Some(Money::new(-165, Currency::Eur)),
+            ),
+            (
+                Money::new(500, Currency::Eur),
+                Some(Money::new(41, Currency::Eur)),
+            ),
+        ] {
+            connection
+                .execute(
+                    "INSERT INTO t (amount, tax, currency)
+                        VALUES (?1, ?2, ?3)",
+                    rusqlite::params![
+                        amount.cents(),
+                        tax.map(|tax| tax.cents()),
+                        amount.currency()
+                    ],
+                )
+                .unwrap();
+        }
+        // rows without a currency are in the default one
+        connection
+            .execute("UPDATE t SET currency = NULL WHERE tax IS NULL", [])
+            .unwrap();
+
+        // the currency survives the round trip
+        let amounts = connection
+            .prepare(&format!(
+                "SELECT {} FROM t",
+                money_sql("amount")
+            ))
+            .unwrap()
+            .query_map([], |r| read_money(r, 0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            amounts,
+            vec![
+                Money::from_cents(1250),
+                Money::new(-2000, Currency::Eur),
+                Money::new(500, Currency::Eur),
+            ]
+        );
+
+        // amounts in other currencies can't be stored without theirs
+        assert!(
+            connection
+                .execute(
+                    "INSERT INTO t (amount) VALUES (?1)",
+                    [Money::new(500, Currency::Eur)],
+                )
+                .is_err()
+        );
+
+        let total = |filter: &str| {
+            connection.query_row(
+                &format!(
+                    "SELECT {} FROM t WHERE {}",
+                    total_with_sql(
+                        "amount",
+                        Some("tax")
+                    ),
+                    filter
+                ),
+                [],
+                |r| read_total(r, 0),
+            )
+        };
+        assert_eq!(
+            total("currency = 'EUR'").unwrap(),
+            Money::new(-1624, Currency::Eur)
+        );
+        assert_eq!(
+            total("amount IS NULL").unwrap(),
+            Money::zero()
+        );
+        assert!(total("1").is_err());
+    }
+}
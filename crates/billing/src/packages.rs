@@ -0,0 +1,759 @@
+//! Session packages: prepaid bundles of sessions and the credits they give.
+use crate::{Currency, Money, set_money, sql_error};
+use chrono::NaiveDate;
+use clap::{Arg, ArgMatches, Command};
+use db_commands::{
+    CommandOutputContextExt, OutputRows,
+};
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use tabled::builder::Builder as TabledBuilder;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A package of sessions sold to a client up front. Stored in the table
+/// `package`. Each session in the package is a credit that a completed
+/// session can draw down instead of being charged.
+#[derive(TableRow, Debug)]
+pub struct Package {
+    /// The client the package was sold to.
+    #[display_table("client", "name")]
+    pub client: RowId,
+
+    /// The number of sessions in the package.
+    pub size: u32,
+
+    /// The price paid for the whole package.
+    pub price: Money,
+
+    /// The currency of the price.
+    pub currency: Currency,
+
+    /// The date the package was sold.
+    pub purchase_date: NaiveDate,
+
+    /// The last date the package's credits can be used on, or `None` if
+    /// they never expire.
+    pub expiry_date: Option<NaiveDate>,
+
+    /// The charge issued for the package's price.
+    pub charge: Option<RowId>,
+}
+
+/// A single package credit used up by a completed session. Stored in the
+/// table `credit_use`.
+#[derive(TableRow, Debug)]
+pub struct CreditUse {
+    /// The package the credit came from.
+    pub package: RowId,
+
+    /// The session the credit paid for.
+    pub session: RowId,
+
+    /// The date of the session.
+    pub date: NaiveDate,
+}
+
+/// Sells a package of sessions to a client. Issues a charge for the price
+/// on the purchase date, so the package shows up in receipts and
+/// statements like any other charge. Returns the row ID of the new
+/// package.
+///
+/// * `db_connection` - A connection to the database.
+/// * `client` - The row ID of the client buying the package.
+/// * `size` - The number of sessions in the package.
+/// * `price` - The price of the whole package.
+/// * `purchase_date` - The date the package is sold.
+/// * `expiry_date` - The last date credits can be used, if they expire.
+pub fn sell_package(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    size: u32,
+    price: Money,
+    purchase_date: NaiveDate,
+    expiry_date: Option<NaiveDate>,
+) -> dolmen::Result<RowId> {
+    if size == 0 {
+        return Err(dolmen::Error::new(
+            "a package must contain at least one session",
+        ));
+    }
+    if expiry_date.is_some_and(|e| e < purchase_date) {
+        return Err(dolmen::Error::new(
+            "a package can't expire before it's sold",
+        ));
+    }
+
+    let charge =
+        db_connection.new_row_in_table("charge")?;
+    db_connection.set_field_in_table(
+        "charge",
+        charge,
+        "date",
+        purchase_date,
+    )?;
+    db_connection.set_field_in_table(
+        "charge",
+        charge,
+        "description",
+        format!("{}-session package", size),
+    )?;
+    set_money(
+        db_connection,
+        "charge",
+        charge,
+        "amount",
+        Some(price),
+    )?;
+    db_connection.set_field_in_table(
+        "charge", charge, "client", client.0,
+    )?;
+
+    let package =
+        db_connection.new_row_in_table("package")?;
+    db_connection.set_field_in_table(
+        "package", package, "client", client.0,
+    )?;
+    db_connection.set_field_in_table(
+        "package", package, "size", size,
+    )?;
+    set_money(
+        db_connection,
+        "package",
+        package,
+        "price",
+        Some(price),
+    )?;
+    db_connection.set_field_in_table(
+        "package",
+        package,
+        "purchase_date",
+        purchase_date,
+    )?;
+    if let Some(expiry_date) = expiry_date {
+        db_connection.set_field_in_table(
+            "package",
+            package,
+            "expiry_date",
+            expiry_date,
+        )?;
+    }
+    db_connection.set_field_in_table(
+        "package", package, "charge", charge.0,
+    )?;
+
+    Ok(package)
+}
+
+/// Gets the number of session credits a client has left to use on `date`:
+/// the unused credits of every package bought on or before `date` that
+/// hasn't expired by then.
+///
+/// * `db_connection` - A connection to the database.
+/// * `client` - The row ID of the client.
+/// * `date` - The date to count credits on.
+pub fn client_credits_on(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    date: NaiveDate,
+) -> dolmen::Result<u32> {
+    client_credits(
+        db_connection.connection()?,
+        client,
+        date,
+    )
+}
+
+/// Uses one of a client's package credits to pay for a completed session.
+/// Credits are taken from the package that expires first. Returns the row
+/// ID of the package the credit came from, or `None` if the client has no
+/// package active on the session's date with a credit that hasn't been
+/// used by any session, earlier or later.
+///
+/// * `db_connection` - A connection to the database.
+/// * `session` - The row ID of the session to pay for.
+/// * `client` - The row ID of the client who attended the session.
+/// * `date` - The date of the session.
+pub fn use_session_credit(
+    db_connection: &mut DbConnection,
+    session: RowId,
+    client: RowId,
+    date: NaiveDate,
+) -> dolmen::Result<Option<RowId>> {
+    let connection = db_connection.connection()?;
+    if let Some(package) =
+        session_credit_package(connection, session)?
+    {
+        return Err(dolmen::Error::new(format!(
+            "session {} already used a credit from package {}",
+            session.0, package.0
+        )));
+    }
+
+    let package = connection
+        .query_row(
+            &format!(
+                "SELECT p.id FROM package p
+                    WHERE p.client = ?1
+                        AND {}
+                        AND {} > 0
+                    ORDER BY p.expiry_date IS NULL, p.expiry_date,
+                        p.purchase_date, p.id
+                    LIMIT 1",
+                PACKAGE_ACTIVE_ON, PACKAGE_UNUSED
+            ),
+            rusqlite::params![client.0, date],
+            |r| r.get::<_, i64>(0),
+        )
+        .map(Some)
+        .or_else(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => Ok(None),
+            e => Err(e),
+        })
+        .map_err(sql_error)?;
+    let Some(package) = package.map(RowId) else {
+        return Ok(None);
+    };
+
+    let credit_use = db_connection
+        .new_row_in_table("credit_use")?;
+    db_connection.set_field_in_table(
+        "credit_use",
+        credit_use,
+        "package",
+        package.0,
+    )?;
+    db_connection.set_field_in_table(
+        "credit_use",
+        credit_use,
+        "session",
+        session.0,
+    )?;
+    db_connection.set_field_in_table(
+        "credit_use",
+        credit_use,
+        "date",
+        date,
+    )?;
+
+    Ok(Some(package))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// A SQL condition that's true for a package `p` that can be used on the
+/// date `?2`.
+const PACKAGE_ACTIVE_ON: &str = "p.purchase_date <= ?2
+    AND (p.expiry_date IS NULL OR p.expiry_date >= ?2)";
+
+/// A SQL expression for the number of unused credits in a package `p` as
+/// of the date `?2`.
+const PACKAGE_REMAINING: &str =
+    "(COALESCE(p.size, 0) - (
+    SELECT COUNT(*) FROM credit_use u
+        WHERE u.package = p.id AND u.date <= ?2))";
+
+/// A SQL expression for the number of credits in a package `p` that haven't
+/// been used at all. Sessions can be billed out of order, so a credit used
+/// by a later session can't be used again by an earlier one.
+const PACKAGE_UNUSED: &str =
+    "(COALESCE(p.size, 0) - (
+    SELECT COUNT(*) FROM credit_use u WHERE u.package = p.id))";
+
+/// Counts a client's usable credits on a date. See `client_credits_on`.
+pub(crate) fn client_credits(
+    connection: &rusqlite::Connection,
+    client: RowId,
+    date: NaiveDate,
+) -> dolmen::Result<u32> {
+    connection
+        .query_row(
+            &format!(
+                "SELECT COALESCE(SUM(MAX({}, 0)), 0) FROM package p
+                    WHERE p.client = ?1 AND {}",
+                PACKAGE_REMAINING, PACKAGE_ACTIVE_ON
+            ),
+            rusqlite::params![client.0, date],
+            |r| r.get::<_, u32>(0),
+        )
+        .map_err(sql_error)
+}
+
+/// Gets the package a session's credit came from, or `None` if the session
+/// hasn't used a credit.
+pub(crate) fn session_credit_package(
+    connection: &rusqlite::Connection,
+    session: RowId,
+) -> dolmen::Result<Option<RowId>> {
+    connection
+        .query_row(
+            "SELECT package FROM credit_use WHERE session = ?1
+                ORDER BY id LIMIT 1",
+            [session.0],
+            |r| r.get::<_, i64>(0),
+        )
+        .map(|p| Some(RowId(p)))
+        .or_else(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => Ok(None),
+            e => Err(e),
+        })
+        .map_err(sql_error)
+}
+
+pub(crate) fn package_command() -> Command {
+    Command::new("package")
+        .alias("pkg")
+        .about("Session package related commands")
+        .subcommand(Command::new("sell")
+            .about("Sells a package of prepaid sessions to a client")
+            .arg(Arg::new("client-id")
+                .long("client-id")
+                .value_parser(clap::value_parser!(i64))
+                .required(true)
+                .help("The client row ID to sell the package to.")
+            )
+            .arg(Arg::new("size")
+                .long("size")
+                .value_parser(clap::value_parser!(u32))
+                .required(true)
+                .help("The number of sessions in the package")
+            )
+            .arg(Arg::new("price")
+                .long("price")
+                .value_parser(clap::value_parser!(Money))
+                .required(true)
+                .help("The price of the whole package")
+            )
+            .arg(Arg::new("date")
+                .long("date")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .help("The date of the sale (YYYY-MM-DD). Defaults to \
+                    today.")
+            )
+            .arg(Arg::new("expires")
+                .long("expires")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .help("The last date the credits can be used \
+                    (YYYY-MM-DD). Credits never expire if not set.")
+            )
+        )
+        .subcommand(crate::structured(Command::new("credits")
+            .about("Shows a client's remaining session credits")
+            .arg(Arg::new("client-id")
+                .long("client-id")
+                .value_parser(clap::value_parser!(i64))
+                .required(true)
+                .help("The client row ID to show credits for.")
+            )
+            .arg(Arg::new("date")
+                .long("date")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .help("The date to count credits on (YYYY-MM-DD). \
+                    Defaults to today.")
+            )
+        ))
+        .subcommand_required(true)
+}
+
+/// Processes the main `package` command.
+pub(crate) fn process_package_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    match arg_matches.subcommand() {
+        Some(("sell", sub_m)) => {
+            process_package_sell_command(
+                sub_m,
+                context.db_connection()?,
+            )
+        }
+        Some(("credits", sub_m)) => {
+            process_package_credits_command(
+                context, sub_m,
+            )
+        }
+        _ => Err(dolmen::Error::new(
+            "subcommand not recognized",
+        )),
+    }
+}
+
+/// Processes the `sell` subcommand of the `package` command.
+fn process_package_sell_command(
+    arg_matches: &ArgMatches,
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<CommandResponse> {
+    let client = RowId(
+        *arg_matches
+            .get_one::<i64>("client-id")
+            .expect("Missing required argument"),
+    );
+    let size = *arg_matches
+        .get_one::<u32>("size")
+        .expect("Missing required argument");
+    let price = *arg_matches
+        .get_one::<Money>("price")
+        .expect("Missing required argument");
+    let date = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(chrono::Local::now().date_naive());
+    let expiry_date = arg_matches
+        .get_one::<NaiveDate>("expires")
+        .copied();
+
+    let package = sell_package(
+        db_connection,
+        client,
+        size,
+        price,
+        date,
+        expiry_date,
+    )?;
+
+    Ok(CommandResponse::new(format!(
+        "Sold package (id: {}) of {} sessions for {} to client {}. \
+            Client now has {} session credit(s).",
+        package.0,
+        size,
+        price,
+        client.0,
+        client_credits_on(
+            db_connection,
+            client,
+            date
+        )?
+    )))
+}
+
+/// Processes the `credits` subcommand of the `package` command.
+fn process_package_credits_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let client = RowId(
+        *arg_matches
+            .get_one::<i64>("client-id")
+            .expect("Missing required argument"),
+    );
+    let date = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(chrono::Local::now().date_naive());
+
+    let connection =
+        context.db_connection()?.connection()?;
+    let mut stmt = connection
+        .prepare(&format!(
+            "SELECT p.id, p.purchase_date, p.expiry_date,
+                    COALESCE(p.size, 0), MAX({}, 0)
+                FROM package p
+                WHERE p.client = ?1 AND {}
+                ORDER BY p.expiry_date IS NULL, p.expiry_date,
+                    p.purchase_date, p.id",
+            PACKAGE_REMAINING, PACKAGE_ACTIVE_ON
+        ))
+        .map_err(sql_error)?;
+    let packages = stmt
+        .query_map(
+            rusqlite::params![client.0, date],
+            |r| {
+                Ok((
+                    r.get::<_, i64>(0)?,
+                    r.get::<_, NaiveDate>(1)?,
+                    r.get::<_, Option<NaiveDate>>(2)?,
+                    r.get::<_, u32>(3)?,
+                    r.get::<_, u32>(4)?,
+                ))
+            },
+        )
+        .map_err(sql_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sql_error)?;
+
+    drop(stmt);
+
+    let mut output = OutputRows::new([
+        ("package", "i64"),
+        ("purchased", "NaiveDate"),
+        ("expires", "NaiveDate"),
+        ("size", "u32"),
+        ("remaining", "u32"),
+    ]);
+    for (id, purchased, expires, size, remaining) in
+        &packages
+    {
+        output.push_row([
+            (*id).into(),
+            purchased.to_string().into(),
+            expires.map(|e| e.to_string()).into(),
+            (*size).into(),
+            (*remaining).into(),
+        ]);
+    }
+    context.set_output_rows(output);
+
+    let total: u32 = packages
+        .iter()
+        .map(|(.., remaining)| remaining)
+        .sum();
+    let mut response_text = format!(
+        "Client {} has {} session credit(s) on {}.",
+        client.0, total, date
+    );
+    if !packages.is_empty() {
+        let mut tabled_builder =
+            TabledBuilder::default();
+        tabled_builder.push_record([
+            "Package",
+            "Purchased",
+            "Expires",
+            "Size",
+            "Remaining",
+        ]);
+        for (
+            id,
+            purchased,
+            expires,
+            size,
+            remaining,
+        ) in packages
+        {
+            tabled_builder.push_record([
+                id.to_string(),
+                purchased.to_string(),
+                expires
+                    .map(|e| e.to_string())
+                    .unwrap_or("never".into()),
+                size.to_string(),
+                remaining.to_string(),
+            ]);
+        }
+        response_text += "\n";
+        response_text += tabled_builder
+            .build()
+            .to_string()
+            .as_str();
+    }
+
+    Ok(CommandResponse::new(response_text))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::{
+        add_test_row, add_test_session, date,
+        date_time, setup_test_context,
+    };
+    use crate::{Money, client_credits_on};
+    use dolmen::prelude::*;
+    use reliquary::prelude::*;
+
+    // A 2-session package is sold, then three sessions are completed. The
+    // first two draw down credits, the third is charged at the client's
+    // rate because the package is used up.
+    #[test]
+    fn test_package_credits() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        let rate =
+            db_connection.new_row_in_table("rate")?;
+        db_connection.set_field_in_table(
+            "rate",
+            rate,
+            "hourly_rate",
+            Money::from_dollars(70)?,
+        )?;
+
+        let response = context.execute(
+            "package sell --client-id=1 --size=2 --price=120 \
+                --date=2026-06-01 --expires=2026-12-31",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Sold package (id: 1) of 2 sessions for $120.00 to client 1. \
+                Client now has 2 session credit(s)."
+        );
+
+        let mut responses = Vec::new();
+        for starts in [
+            "2026-06-02T09:00:00",
+            "2026-06-03T09:00:00",
+            "2026-06-04T09:00:00",
+        ] {
+            let session = add_test_session(
+                context.db_connection()?,
+                trainer,
+                client,
+                date_time(starts),
+                60,
+            )?;
+            responses.push(
+                context
+                    .execute(&format!(
+                        "session status --session-id={} --status=completed",
+                        session.0
+                    ))?
+                    .text()
+                    .unwrap()
+                    .clone(),
+            );
+        }
+        assert_eq!(
+            responses,
+            vec![
+                "Marked session 1 as completed.\n\
+                    Used a credit from package 1 for session 1 (1 left).",
+                "Marked session 2 as completed.\n\
+                    Used a credit from package 1 for session 2 (0 left).",
+                "Marked session 3 as completed.\n\
+                    Created charge 2 ($70.00) for session 3.",
+            ]
+        );
+
+        let db_connection = context.db_connection()?;
+        assert_eq!(
+            client_credits_on(
+                db_connection,
+                client,
+                date("2026-06-02")
+            )?,
+            1
+        );
+        assert_eq!(
+            client_credits_on(
+                db_connection,
+                client,
+                date("2026-05-31")
+            )?,
+            0
+        );
+
+        let response =
+            context.execute("package credits --client-id=1 --date=2026-06-02")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Client 1 has 1 session credit(s) on 2026-06-02.\n\
+                +---------+------------+------------+------+-----------+\n\
+                | Package | Purchased  | Expires    | Size | Remaining |\n\
+                +---------+------------+------------+------+-----------+\n\
+                | 1       | 2026-06-01 | 2026-12-31 | 2    | 1         |\n\
+                +---------+------------+------------+------+-----------+"
+        );
+
+        Ok(())
+    }
+
+    // Credits can't be used after the package expires.
+    #[test]
+    fn test_package_expiry() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        crate::sell_package(
+            db_connection,
+            client,
+            10,
+            Money::from_dollars(600)?,
+            date("2026-01-01"),
+            Some(date("2026-03-31")),
+        )?;
+
+        assert_eq!(
+            client_credits_on(
+                db_connection,
+                client,
+                date("2026-03-31")
+            )?,
+            10
+        );
+        assert_eq!(
+            client_credits_on(
+                db_connection,
+                client,
+                date("2026-04-01")
+            )?,
+            0
+        );
+        assert_eq!(
+            crate::use_session_credit(
+                db_connection,
+                RowId(1),
+                client,
+                date("2026-04-01")
+            )?
+            .map(|p| p.0),
+            None
+        );
+
+        Ok(())
+    }
+    // A session billed after a later one can't reuse the credit the later
+    // session used.
+    #[test]
+    fn test_package_credits_out_of_order()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        let package = crate::sell_package(
+            db_connection,
+            client,
+            1,
+            Money::from_dollars(60)?,
+            date("2026-06-01"),
+            None,
+        )?;
+
+        assert_eq!(
+            crate::use_session_credit(
+                db_connection,
+                RowId(2),
+                client,
+                date("2026-06-09")
+            )?,
+            Some(package)
+        );
+        assert_eq!(
+            crate::use_session_credit(
+                db_connection,
+                RowId(1),
+                client,
+                date("2026-06-02")
+            )?,
+            None
+        );
+        assert_eq!(
+            client_credits_on(
+                db_connection,
+                client,
+                date("2026-06-09")
+            )?,
+            0
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1244 @@
+//! Receipt and invoice numbers, allocated in sequence from per-trainer
+//! patterns.
+//!
+//! A payment is numbered when it's recorded: as soon as `set` (or the edit
+//! tab, or `import`) has given it a date, a trainer and an amount, in any
+//! order, so a payment abandoned before its amount is entered doesn't use
+//! up a number and can still be removed. It keeps its number from then on, so receipts, statements and refund descriptions
+//! all show the same one: the database refuses to change the number or
+//! remove the payment. Payments without a number (added some other way,
+//! or left unnumbered by the migration that numbered older payments) are
+//! numbered by `billing number-receipts`, and their receipts can't be
+//! generated until then. Refunds are listed on the client's next receipt,
+//! so they don't get numbers of their own. Invoices are numbered when
+//! they're issued. Numbers come from a counter per trainer and pattern
+//! scope stored in the `receipt_sequence` table, so a number is never
+//! handed out twice, even if the payment it went to is later refunded or
+//! the invoice removed. Invoices count in scopes of their own, so they never
+//! share a sequence with receipts.
+use crate::sql_error;
+use chrono::{Datelike, NaiveDate};
+use clap::ArgMatches;
+use db_commands::{
+    CommandOutputContextExt, OutputRows,
+};
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use rusqlite::OptionalExtension;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// The receipt pattern used for trainers who haven't set their own.
+pub const DEFAULT_RECEIPT_PATTERN: &str =
+    "{year}-{seq:04}";
+
+/// The invoice pattern used for trainers who haven't set their own.
+pub const DEFAULT_INVOICE_PATTERN: &str =
+    "INV-{year}-{seq:04}";
+
+/// A pattern receipt (or invoice) numbers are made from (e.g.
+/// `{year}-{seq:04}`).
+///
+/// * `{year}` is the year of the payment or invoice (e.g. `2026`).
+/// * `{month}` is the month of the payment or invoice, as two digits (e.g.
+///   `01`).
+/// * `{trainer}` is the row ID of the trainer.
+/// * `{seq}` is the number of the receipt in its sequence. `{seq:04}`
+///   pads it with zeros to four digits.
+///
+/// Each distinct value of everything but `{seq}` is its own sequence
+/// starting at 1, so `{year}-{seq:04}` starts again each year, and `R{seq}`
+/// counts up forever. A pattern has to contain `{seq}` exactly once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceiptPattern {
+    parts: Vec<PatternPart>,
+}
+
+impl std::str::FromStr for ReceiptPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(PatternPart::Text(
+                    rest[..start].into(),
+                ));
+            }
+            let after = &rest[start + 1..];
+            let end = after.find('}').ok_or(format!(
+                "unclosed {{ in receipt pattern {}",
+                s
+            ))?;
+            parts.push(parse_placeholder(
+                &after[..end],
+            )?);
+            rest = &after[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(PatternPart::Text(rest.into()));
+        }
+
+        let seq_count = parts
+            .iter()
+            .filter(|p| {
+                matches!(p, PatternPart::Seq(_))
+            })
+            .count();
+        if seq_count != 1 {
+            return Err(format!(
+                "receipt pattern {} must contain {{seq}} exactly once",
+                s
+            ));
+        }
+
+        Ok(ReceiptPattern { parts })
+    }
+}
+
+impl ReceiptPattern {
+    /// Formats a receipt number.
+    ///
+    /// * `date` - The date of the payment.
+    /// * `trainer` - The row ID of the trainer.
+    /// * `seq` - The number of the receipt in its sequence.
+    pub fn format(
+        &self,
+        date: NaiveDate,
+        trainer: RowId,
+        seq: i64,
+    ) -> String {
+        self.render(date, trainer, |width| {
+            format!("{:0width$}", seq, width = width)
+        })
+    }
+
+    /// Gets the name of the sequence a receipt for a date belongs to: the
+    /// pattern with everything but `{seq}` filled in.
+    ///
+    /// * `date` - The date of the payment.
+    /// * `trainer` - The row ID of the trainer.
+    pub fn scope(
+        &self,
+        date: NaiveDate,
+        trainer: RowId,
+    ) -> String {
+        self.render(date, trainer, |_| "{seq}".into())
+    }
+
+    fn render(
+        &self,
+        date: NaiveDate,
+        trainer: RowId,
+        seq: impl Fn(usize) -> String,
+    ) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                PatternPart::Text(text) => {
+                    text.clone()
+                }
+                PatternPart::Year => {
+                    date.year().to_string()
+                }
+                PatternPart::Month => {
+                    format!("{:02}", date.month())
+                }
+                PatternPart::Trainer => {
+                    trainer.0.to_string()
+                }
+                PatternPart::Seq(width) => seq(*width),
+            })
+            .collect()
+    }
+}
+
+/// Gets a payment's receipt number, allocating the next one in its
+/// trainer's sequence if it doesn't have one yet. Fails if the payment
+/// needs a number but has no trainer.
+///
+/// The allocation takes the database's write lock before reading anything
+/// (see `with_write_lock`), so concurrent allocations (even from other
+/// processes) queue up behind each other and each payment gets its own
+/// number. It can run inside a transaction the caller already has open.
+/// Numbers already used by the trainer's payments (e.g. entered by hand)
+/// are skipped.
+///
+/// * `db_connection` - A connection to the database.
+/// * `payment` - The row ID of the payment.
+pub fn allocate_receipt_number(
+    db_connection: &mut DbConnection,
+    payment: RowId,
+) -> dolmen::Result<String> {
+    with_write_lock(db_connection, |connection| {
+        assign_receipt_number(connection, payment)
+    })
+}
+
+/// Gets an invoice's number, allocating the next one in its trainer's
+/// invoice sequence if it doesn't have one yet. Works like
+/// `allocate_receipt_number`, using the trainer's invoice pattern and the
+/// invoice's issue date.
+///
+/// * `db_connection` - A connection to the database.
+/// * `invoice` - The row ID of the invoice.
+pub fn allocate_invoice_number(
+    db_connection: &mut DbConnection,
+    invoice: RowId,
+) -> dolmen::Result<String> {
+    with_write_lock(db_connection, |connection| {
+        ensure_receipt_sequence_table(connection)
+            .map_err(sql_error)?;
+
+        let (number, date, trainer, pattern) = connection
+            .query_row(
+                "SELECT invoice.number, invoice.issue_date,
+                        invoice.trainer, trainer.invoice_pattern
+                    FROM invoice
+                    LEFT JOIN trainer ON trainer.id = invoice.trainer
+                    WHERE invoice.id = ?1",
+                [invoice.0],
+                |r| {
+                    Ok((
+                        r.get::<_, Option<String>>(0)?,
+                        r.get::<_, NaiveDate>(1)?,
+                        r.get::<_, Option<i64>>(2)?,
+                        r.get::<_, Option<String>>(3)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(sql_error)?
+            .ok_or(dolmen::Error::new(format!(
+                "no invoice with ID {}",
+                invoice.0
+            )))?;
+
+        if let Some(number) =
+            number.filter(|n| !n.is_empty())
+        {
+            return Ok(number);
+        }
+        let trainer = trainer.map(RowId).ok_or(
+            dolmen::Error::new(format!(
+                "invoice {} has no trainer to take a number from",
+                invoice.0
+            )),
+        )?;
+
+        let number = next_number(
+            connection,
+            NumberedTable::Invoice,
+            trainer,
+            pattern,
+            date,
+        )?;
+
+        connection
+            .execute(
+                "UPDATE invoice SET number = ?1 WHERE id = ?2",
+                rusqlite::params![number, invoice.0],
+            )
+            .map_err(sql_error)?;
+        Ok(number)
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// A field set hook numbering a payment's receipt when it's recorded, as
+/// soon as it has a date, a trainer and an amount. Refunds and payments
+/// that already have a number are left alone.
+///
+/// * `context` - The context to use.
+/// * `payment` - The row ID of the payment.
+pub(crate) fn number_recorded_payment(
+    context: &mut Context,
+    payment: RowId,
+) -> dolmen::Result<Option<String>> {
+    let db_connection = context.db_connection()?;
+    let unnumbered: bool = db_connection
+        .connection()?
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM payment
+                WHERE id = ?1
+                    AND date IS NOT NULL
+                    AND trainer IS NOT NULL
+                    AND amount IS NOT NULL
+                    AND amount <> 0
+                    AND refunds IS NULL
+                    AND (receipt_number IS NULL
+                        OR receipt_number = ''))",
+            [payment.0],
+            |r| r.get(0),
+        )
+        .map_err(sql_error)?;
+    if !unnumbered {
+        return Ok(None);
+    }
+
+    let number = allocate_receipt_number(
+        db_connection,
+        payment,
+    )?;
+    Ok(Some(format!(
+        "Numbered payment {} as receipt {}.",
+        payment.0, number
+    )))
+}
+
+/// Numbers every payment recorded before receipts were numbered on
+/// recording, oldest first. Used by a migration of the `payment` table.
+/// Payments without a date, trainer or amount, refunds, and payments whose
+/// trainer's receipt pattern is invalid are left for `billing
+/// number-receipts`, so a bad pattern can't stop the database from
+/// opening. Any other failure fails the migration.
+///
+/// * `connection` - The migration's transaction.
+pub(crate) fn number_existing_receipts(
+    connection: &rusqlite::Connection,
+) -> rusqlite::Result<()> {
+    // the trainer table's own migration adding receipt patterns runs after
+    // this one
+    db_commands::add_column(
+        connection,
+        "trainer",
+        "receipt_pattern",
+        "TEXT",
+    )?;
+    ensure_receipt_sequence_table(connection)?;
+
+    let payments = connection
+        .prepare(
+            "SELECT payment.id, trainer.receipt_pattern
+                FROM payment
+                LEFT JOIN trainer ON trainer.id = payment.trainer
+                WHERE (payment.receipt_number IS NULL
+                        OR payment.receipt_number = '')
+                    AND payment.refunds IS NULL
+                    AND payment.trainer IS NOT NULL
+                    AND payment.date IS NOT NULL
+                    AND payment.amount IS NOT NULL
+                    AND payment.amount <> 0
+                ORDER BY payment.date, payment.id",
+        )?
+        .query_map([], |r| {
+            Ok((
+                r.get::<_, i64>(0)?,
+                r.get::<_, Option<String>>(1)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (payment, pattern) in payments {
+        if pattern.is_some_and(|p| {
+            !p.is_empty()
+                && p.parse::<ReceiptPattern>().is_err()
+        }) {
+            continue;
+        }
+        assign_receipt_number(
+            connection,
+            RowId(payment),
+        )
+        .map_err(|e| {
+            rusqlite::Error::ToSqlConversionFailure(
+                e.message()
+                    .clone()
+                    .unwrap_or_default()
+                    .into(),
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Creates the triggers keeping receipt numbers once they're allocated.
+/// The database refuses to change a payment's receipt number once it has
+/// one, or to remove a payment that has one (refund it instead), whether
+/// with `set`, `remove`, the edit tab or plain SQL. Used as the `payment`
+/// table's setup.
+///
+/// * `connection` - The connection to the database.
+pub(crate) fn create_receipt_number_triggers(
+    connection: &rusqlite::Connection,
+) -> rusqlite::Result<()> {
+    connection.execute_batch(
+        "CREATE TRIGGER IF NOT EXISTS payment_keep_receipt_number
+            BEFORE UPDATE OF receipt_number ON payment
+            WHEN COALESCE(old.receipt_number, '') != ''
+                AND new.receipt_number IS NOT old.receipt_number
+            BEGIN
+                SELECT RAISE(ABORT, 'a payment''s receipt number can''t be \
+                    changed once it has one');
+            END;
+        CREATE TRIGGER IF NOT EXISTS payment_keep_numbered
+            BEFORE DELETE ON payment
+            WHEN COALESCE(old.receipt_number, '') != ''
+            BEGIN
+                SELECT RAISE(ABORT, 'a payment with a receipt number can''t \
+                    be removed: refund it instead');
+            END;",
+    )
+}
+
+/// Gets a payment's receipt number, handing out the next one in its
+/// trainer's sequence if it doesn't have one yet. See
+/// `allocate_receipt_number`, which takes the write lock first.
+///
+/// * `connection` - The connection allocating the number, in a
+///   transaction.
+/// * `payment` - The row ID of the payment.
+fn assign_receipt_number(
+    connection: &rusqlite::Connection,
+    payment: RowId,
+) -> dolmen::Result<String> {
+    ensure_receipt_sequence_table(connection)
+        .map_err(sql_error)?;
+
+    let (number, date, trainer, pattern) = connection
+        .query_row(
+            "SELECT payment.receipt_number, payment.date,
+                    payment.trainer, trainer.receipt_pattern
+                FROM payment
+                LEFT JOIN trainer ON trainer.id = payment.trainer
+                WHERE payment.id = ?1",
+            [payment.0],
+            |r| {
+                Ok((
+                    r.get::<_, Option<String>>(0)?,
+                    r.get::<_, NaiveDate>(1)?,
+                    r.get::<_, Option<i64>>(2)?,
+                    r.get::<_, Option<String>>(3)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(sql_error)?
+        .ok_or(dolmen::Error::new(format!(
+            "no payment with ID {}",
+            payment.0
+        )))?;
+
+    if let Some(number) =
+        number.filter(|n| !n.is_empty())
+    {
+        return Ok(number);
+    }
+    let trainer = trainer.map(RowId).ok_or(
+        dolmen::Error::new(format!(
+            "payment {} has no trainer to take a receipt number from",
+            payment.0
+        )),
+    )?;
+
+    let number = next_number(
+        connection,
+        NumberedTable::Payment,
+        trainer,
+        pattern,
+        date,
+    )?;
+
+    connection
+        .execute(
+            "UPDATE payment SET receipt_number = ?1 WHERE id = ?2",
+            rusqlite::params![number, payment.0],
+        )
+        .map_err(sql_error)?;
+    Ok(number)
+}
+
+/// A table whose rows are numbered from a trainer's pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NumberedTable {
+    Payment,
+    Invoice,
+}
+
+impl NumberedTable {
+    /// Gets the name of the pattern, for error messages.
+    fn pattern_name(&self) -> &'static str {
+        match self {
+            NumberedTable::Payment => "receipt",
+            NumberedTable::Invoice => "invoice",
+        }
+    }
+
+    /// Gets the pattern used when the trainer hasn't set their own.
+    fn default_pattern(&self) -> &'static str {
+        match self {
+            NumberedTable::Payment => {
+                DEFAULT_RECEIPT_PATTERN
+            }
+            NumberedTable::Invoice => {
+                DEFAULT_INVOICE_PATTERN
+            }
+        }
+    }
+
+    /// Gets a query checking whether a trainer (`?1`) has used a number
+    /// (`?2`) already.
+    fn taken_query(&self) -> &'static str {
+        match self {
+            NumberedTable::Payment => {
+                "SELECT EXISTS(SELECT 1 FROM payment
+                    WHERE trainer = ?1 AND receipt_number = ?2)"
+            }
+            NumberedTable::Invoice => {
+                "SELECT EXISTS(SELECT 1 FROM invoice
+                    WHERE trainer = ?1 AND number = ?2)"
+            }
+        }
+    }
+
+    /// Gets the name of the sequence a number belongs to. Invoice scopes
+    /// are prefixed, so the same pattern counts separately for receipts
+    /// and invoices.
+    fn scope(
+        &self,
+        pattern: &ReceiptPattern,
+        date: NaiveDate,
+        trainer: RowId,
+    ) -> String {
+        match self {
+            NumberedTable::Payment => {
+                pattern.scope(date, trainer)
+            }
+            NumberedTable::Invoice => format!(
+                "invoice:{}",
+                pattern.scope(date, trainer)
+            ),
+        }
+    }
+}
+
+/// Hands out the next number in a trainer's sequence, skipping numbers
+/// that are already in use.
+///
+/// * `connection` - The connection allocating the number, in a
+///   transaction.
+/// * `table` - The table the number is for.
+/// * `trainer` - The row ID of the trainer.
+/// * `pattern` - The trainer's own pattern, if they've set one.
+/// * `date` - The date of the payment or invoice.
+fn next_number(
+    connection: &rusqlite::Connection,
+    table: NumberedTable,
+    trainer: RowId,
+    pattern: Option<String>,
+    date: NaiveDate,
+) -> dolmen::Result<String> {
+    let pattern = pattern
+        .filter(|p| !p.is_empty())
+        .unwrap_or(table.default_pattern().into())
+        .parse::<ReceiptPattern>()
+        .map_err(|e| {
+            dolmen::Error::new(format!(
+                "trainer {} has an invalid {} pattern: {}",
+                trainer.0,
+                table.pattern_name(),
+                e
+            ))
+        })?;
+    let scope = table.scope(&pattern, date, trainer);
+
+    loop {
+        let seq: i64 = connection
+            .query_row(
+                "INSERT INTO receipt_sequence (trainer, scope, last)
+                    VALUES (?1, ?2, 1)
+                    ON CONFLICT (trainer, scope)
+                        DO UPDATE SET last = last + 1
+                    RETURNING last",
+                rusqlite::params![trainer.0, scope],
+                |r| r.get(0),
+            )
+            .map_err(sql_error)?;
+        let number =
+            pattern.format(date, trainer, seq);
+        let taken: bool = connection
+            .query_row(
+                table.taken_query(),
+                rusqlite::params![trainer.0, number],
+                |r| r.get(0),
+            )
+            .map_err(sql_error)?;
+        if !taken {
+            return Ok(number);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PatternPart {
+    Text(String),
+    Year,
+    Month,
+    Trainer,
+    Seq(usize),
+}
+
+fn parse_placeholder(
+    placeholder: &str,
+) -> Result<PatternPart, String> {
+    match placeholder {
+        "year" => Ok(PatternPart::Year),
+        "month" => Ok(PatternPart::Month),
+        "trainer" => Ok(PatternPart::Trainer),
+        "seq" => Ok(PatternPart::Seq(0)),
+        _ => {
+            let width = placeholder
+                .strip_prefix("seq:")
+                .and_then(|w| w.parse::<usize>().ok())
+                .filter(|w| *w <= 20)
+                .ok_or(format!(
+                    "unknown placeholder {{{}}} in receipt pattern \
+                        (expected {{year}}, {{month}}, {{trainer}}, \
+                        {{seq}} or {{seq:04}})",
+                    placeholder
+                ))?;
+            Ok(PatternPart::Seq(width))
+        }
+    }
+}
+
+/// Runs `f` with the database's write lock held, committing its changes if
+/// it succeeds and rolling them back if it fails. Outside a transaction
+/// this begins an immediate one, so the lock is taken before `f` reads
+/// anything. Inside the caller's transaction (e.g. `with_transaction`) it
+/// runs in a savepoint instead, since SQLite can't begin a transaction
+/// within a transaction; the lock is then the caller's.
+///
+/// * `db_connection` - A connection to the database.
+/// * `f` - The function to run.
+fn with_write_lock<T>(
+    db_connection: &mut DbConnection,
+    f: impl FnOnce(
+        &rusqlite::Connection,
+    ) -> dolmen::Result<T>,
+) -> dolmen::Result<T> {
+    let connection = db_connection.connection_mut()?;
+    if connection.is_autocommit() {
+        let transaction = connection
+            .transaction_with_behavior(
+                rusqlite::TransactionBehavior::Immediate,
+            )
+            .map_err(sql_error)?;
+        let value = f(&transaction)?;
+        transaction.commit().map_err(sql_error)?;
+        Ok(value)
+    } else {
+        let savepoint = connection
+            .savepoint()
+            .map_err(sql_error)?;
+        let value = f(&savepoint)?;
+        savepoint.commit().map_err(sql_error)?;
+        Ok(value)
+    }
+}
+
+/// Creates the table of receipt counters if it doesn't exist yet. `last`
+/// is the last sequence number handed out in a trainer's scope.
+fn ensure_receipt_sequence_table(
+    connection: &rusqlite::Connection,
+) -> rusqlite::Result<()> {
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS receipt_sequence (
+            trainer INTEGER NOT NULL,
+            scope TEXT NOT NULL,
+            last INTEGER NOT NULL,
+            PRIMARY KEY (trainer, scope)
+        );",
+    )
+}
+
+/// Processes the `number-receipts` subcommand of the `billing` command.
+/// Numbers every payment that doesn't have a receipt number yet, oldest
+/// first. Refunds are listed on the client's next receipt, so they don't
+/// get numbers of their own. Payments without a trainer are left
+/// unnumbered and counted in the response.
+pub(crate) fn process_number_receipts_command(
+    context: &mut Context,
+    _arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let db_connection = context.db_connection()?;
+    let connection = db_connection.connection()?;
+    let mut stmt = connection
+        .prepare(
+            "SELECT id, trainer FROM payment
+                WHERE (receipt_number IS NULL OR receipt_number = '')
+                    AND refunds IS NULL
+                ORDER BY date, id",
+        )
+        .map_err(sql_error)?;
+    let payments = stmt
+        .query_map([], |r| {
+            Ok((
+                r.get::<_, i64>(0)?,
+                r.get::<_, Option<i64>>(1)?,
+            ))
+        })
+        .map_err(sql_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(sql_error)?;
+    drop(stmt);
+
+    let mut numbered = Vec::new();
+    let mut without_trainer = 0;
+    for (payment, trainer) in payments {
+        if trainer.is_none() {
+            without_trainer += 1;
+            continue;
+        }
+        let number = allocate_receipt_number(
+            db_connection,
+            RowId(payment),
+        )?;
+        numbered.push((payment, number));
+    }
+
+    let mut output = OutputRows::new([
+        ("payment", "i64"),
+        ("receipt_number", "String"),
+    ]);
+    for (payment, number) in &numbered {
+        output.push_row([
+            (*payment).into(),
+            number.clone().into(),
+        ]);
+    }
+    context.set_output_rows(output);
+
+    let mut response_text = if numbered.is_empty()
+        && without_trainer == 0
+    {
+        "Every payment already has a receipt number."
+            .to_string()
+    } else {
+        format!(
+            "Numbered {} receipt(s):",
+            numbered.len()
+        )
+    };
+    for (payment, number) in &numbered {
+        response_text += &format!(
+            "\npayment {}: {}",
+            payment, number
+        );
+    }
+    if without_trainer > 0 {
+        response_text += &format!(
+            "\n{} payment(s) have no trainer, set their trainer to \
+                number them.",
+            without_trainer
+        );
+    }
+    Ok(CommandResponse::new(response_text))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{
+        add_test_payment, setup_test_context,
+    };
+
+    #[test]
+    fn test_receipt_pattern() {
+        let pattern: ReceiptPattern =
+            DEFAULT_RECEIPT_PATTERN.parse().unwrap();
+        let date = NaiveDate::from_ymd_opt(2026, 1, 4)
+            .unwrap();
+        assert_eq!(
+            pattern.format(date, RowId(3), 7),
+            "2026-0007"
+        );
+        assert_eq!(
+            pattern.scope(date, RowId(3)),
+            "2026-{seq}"
+        );
+
+        let pattern: ReceiptPattern =
+            "T{trainer}/{year}{month}/{seq}"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            pattern.format(date, RowId(3), 12345),
+            "T3/202601/12345"
+        );
+
+        assert!(
+            "{year}"
+                .parse::<ReceiptPattern>()
+                .is_err()
+        );
+        assert!(
+            "{seq}-{seq}"
+                .parse::<ReceiptPattern>()
+                .is_err()
+        );
+        assert!(
+            "{day}-{seq}"
+                .parse::<ReceiptPattern>()
+                .is_err()
+        );
+        assert!(
+            "{seq".parse::<ReceiptPattern>().is_err()
+        );
+    }
+
+    #[test]
+    fn test_allocate_receipt_number()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+
+        let tara = db_connection
+            .new_row_in_table("trainer")?;
+        let theo = db_connection
+            .new_row_in_table("trainer")?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        db_connection.set_field_in_table(
+            "trainer",
+            theo,
+            "receipt_pattern",
+            "TH{seq:03}",
+        )?;
+
+        let p1 = add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2026-01-04",
+            50,
+        )?;
+        let p2 = add_test_payment(
+            db_connection,
+            client,
+            theo,
+            "2026-01-05",
+            50,
+        )?;
+        let p3 = add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2026-02-01",
+            50,
+        )?;
+        let p4 = add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2027-01-02",
+            50,
+        )?;
+        // a number entered by hand is kept, and skipped by the sequence
+        let p5 = add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2026-03-01",
+            50,
+        )?;
+        db_connection.set_field_in_table(
+            "payment",
+            p5,
+            "receipt_number",
+            "2026-0003",
+        )?;
+        let p6 = add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2026-03-02",
+            50,
+        )?;
+
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                p1
+            )?,
+            "2026-0001"
+        );
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                p2
+            )?,
+            "TH001"
+        );
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                p3
+            )?,
+            "2026-0002"
+        );
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                p4
+            )?,
+            "2027-0001"
+        );
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                p6
+            )?,
+            "2026-0004"
+        );
+
+        // a payment keeps its number
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                p1
+            )?,
+            "2026-0001"
+        );
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                p5
+            )?,
+            "2026-0003"
+        );
+
+        // removing a payment doesn't free its number up
+        let connection = db_connection.connection()?;
+        connection
+            .execute(
+                "DELETE FROM payment WHERE id = ?1",
+                [p6.0],
+            )
+            .unwrap();
+        let p7 = add_test_payment(
+            db_connection,
+            client,
+            tara,
+            "2026-03-03",
+            50,
+        )?;
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                p7
+            )?,
+            "2026-0005"
+        );
+
+        Ok(())
+    }
+
+    // A payment recorded with `set` is numbered as soon as it has a date,
+    // a trainer and an amount, and keeps its number when edited
+    // afterwards.
+    #[test]
+    fn test_number_recorded_payment()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        context.add_plugin(
+            db_commands::DbCommandsPlugin,
+        )?;
+        let db_connection = context.db_connection()?;
+        db_connection.new_row_in_table("trainer")?;
+        db_connection.new_row_in_table("client")?;
+
+        context.execute("new --table=payment")?;
+        let response = context.execute(
+            "set --table=payment --row-id=1 --field=date \
+                --value=2026-01-04",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Set field date of row 1 in table payment."
+        );
+        let response = context.execute(
+            "set --table=payment --row-id=1 --field=trainer \
+                --value=1",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Set field trainer of row 1 in table payment."
+        );
+        let response = context.execute(
+            "set --table=payment --row-id=1 --field=amount \
+                --value=50",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Set field amount of row 1 in table payment.\n\
+                Numbered payment 1 as receipt 2026-0001."
+        );
+
+        let response = context.execute(
+            "set --table=payment --row-id=1 --field=date \
+                --value=2027-01-04",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Set field date of row 1 in table payment."
+        );
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_field_in_table_row::<String>(
+                    "payment",
+                    RowId(1),
+                    "receipt_number",
+                )?,
+            "2026-0001"
+        );
+
+        Ok(())
+    }
+
+    // Once a payment has a receipt number, neither `set` nor `remove` can
+    // take it away. Unnumbered payments can still be numbered by hand and
+    // removed.
+    #[test]
+    fn test_receipt_number_kept() -> dolmen::Result<()>
+    {
+        let mut context = setup_test_context()?;
+        context.add_plugin(
+            db_commands::DbCommandsPlugin,
+        )?;
+        db_commands::run_migrations(&mut context)?;
+        let db_connection = context.db_connection()?;
+        db_connection.new_row_in_table("trainer")?;
+        db_connection.new_row_in_table("client")?;
+
+        context.execute("new --table=payment")?;
+        context.execute(
+            "set --table=payment --row-id=1 --field=date \
+                --value=2026-01-04",
+        )?;
+        context.execute(
+            "set --table=payment --row-id=1 --field=trainer \
+                --value=1",
+        )?;
+        context.execute(
+            "set --table=payment --row-id=1 --field=amount \
+                --value=50",
+        )?;
+
+        assert!(
+            context
+                .execute(
+                    "set --table=payment --row-id=1 \
+                        --field=receipt_number --value=R-1",
+                )
+                .is_err()
+        );
+        assert!(
+            context
+                .execute(
+                    "set --table=payment --row-id=1 \
+                        --field=receipt_number --value=",
+                )
+                .is_err()
+        );
+        assert!(
+            context
+                .execute(
+                    "rm --table=payment --row-id=1"
+                )
+                .is_err()
+        );
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_field_in_table_row::<String>(
+                    "payment",
+                    RowId(1),
+                    "receipt_number",
+                )?,
+            "2026-0001"
+        );
+
+        context.execute("new --table=payment")?;
+        context.execute(
+            "set --table=payment --row-id=2 \
+                --field=receipt_number --value=R-1",
+        )?;
+        context.execute("new --table=payment")?;
+        context.execute(
+            "rm --table=payment --row-id=3",
+        )?;
+
+        // a payment abandoned before its amount is entered isn't numbered,
+        // so it can still be removed
+        context.execute("new --table=payment")?;
+        context.execute(
+            "set --table=payment --row-id=4 --field=date \
+                --value=2026-01-05",
+        )?;
+        context.execute(
+            "set --table=payment --row-id=4 --field=trainer \
+                --value=1",
+        )?;
+        context.execute(
+            "rm --table=payment --row-id=4",
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_number_receipts_command()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = db_connection
+            .new_row_in_table("trainer")?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-05",
+            50,
+        )?;
+        add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-04",
+            50,
+        )?;
+
+        let response = context
+            .execute("billing number-receipts")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Numbered 2 receipt(s):\n\
+            payment 2: 2026-0001\n\
+            payment 1: 2026-0002"
+        );
+
+        let response = context
+            .execute("billing number-receipts")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Every payment already has a receipt number."
+        );
+
+        Ok(())
+    }
+
+    // Numbers can be allocated inside a transaction the caller already has
+    // open, and are rolled back along with it.
+    #[test]
+    fn test_allocate_in_transaction()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = db_connection
+            .new_row_in_table("trainer")?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        let payment = add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-04",
+            50,
+        )?;
+
+        let result = db_commands::with_transaction(
+            db_connection,
+            |db_connection| {
+                assert_eq!(
+                    allocate_receipt_number(
+                        db_connection,
+                        payment
+                    )?,
+                    "2026-0001"
+                );
+                Err::<(), _>(dolmen::Error::new(
+                    "undo",
+                ))
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<Option<String>>(
+                    "payment",
+                    payment,
+                    "receipt_number",
+                )?,
+            None
+        );
+
+        db_commands::with_transaction(
+            db_connection,
+            |db_connection| {
+                allocate_receipt_number(
+                    db_connection,
+                    payment,
+                )
+            },
+        )?;
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                payment
+            )?,
+            "2026-0001"
+        );
+
+        Ok(())
+    }
+
+    // A payment recorded without a trainer has no pattern to be numbered
+    // from. Allocating its number fails with a clear error, and
+    // number-receipts skips it and says so.
+    #[test]
+    fn test_receipt_number_without_trainer()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let payment = db_connection
+            .new_row_in_table("payment")?;
+        db_connection.set_field_in_table(
+            "payment",
+            payment,
+            "date",
+            "2026-01-04",
+        )?;
+
+        assert_eq!(
+            allocate_receipt_number(
+                db_connection,
+                payment
+            )
+            .unwrap_err()
+            .message()
+            .clone()
+            .unwrap(),
+            "payment 1 has no trainer to take a receipt number from"
+        );
+
+        let response = context
+            .execute("billing number-receipts")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Numbered 0 receipt(s):\n\
+            1 payment(s) have no trainer, set their trainer to number \
+            them."
+        );
+
+        Ok(())
+    }
+}
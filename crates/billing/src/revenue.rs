@@ -0,0 +1,922 @@
+//! The revenue report: charges billed, payments received and the balance
+//! left outstanding over a date range, by month, trainer or payment method.
+//!
+//! Payments record the trainer they were made to, but charges don't. A
+//! charge is counted for the trainer of the session it billed (or, for a
+//! void, of the session the voided charge billed). Other charges, such as
+//! package sales, are counted for the trainer the client is with on the
+//! date of the charge, the same one the aging report groups them under
+//! (see `charge_trainer_sql`). Charges are counted with their tax, as
+//! that's what clients owe.
+use crate::{
+    Money, charge_trainer_sql, latex_backend, money,
+    render_error, sql_error,
+};
+use chrono::{Datelike, NaiveDate};
+use clap::{Arg, ArgMatches, Command};
+use db_commands::{
+    CommandOutputContextExt, OutputRows,
+};
+use documents::{
+    DataTable, DocumentData,
+    write_document_with_options,
+};
+use dolmen::prelude::*;
+use latex::Document;
+use reliquary::prelude::*;
+use rusqlite::types::FromSql;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::PathBuf;
+use tabled::builder::Builder as TabledBuilder;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// How the lines of the revenue report are grouped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RevenueGrouping {
+    /// A line per calendar month.
+    Month,
+
+    /// A line per trainer.
+    Trainer,
+
+    /// A line per payment method (`Payment::paid_via`). Charges aren't paid
+    /// by any method, so only payments are broken down.
+    Method,
+}
+
+impl RevenueGrouping {
+    /// Gets the name the grouping is parsed as.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RevenueGrouping::Month => "month",
+            RevenueGrouping::Trainer => "trainer",
+            RevenueGrouping::Method => "method",
+        }
+    }
+
+    /// Gets the heading of the column labelling each line.
+    fn heading(&self) -> &'static str {
+        match self {
+            RevenueGrouping::Month => "Month",
+            RevenueGrouping::Trainer => "Trainer",
+            RevenueGrouping::Method => "Method",
+        }
+    }
+}
+
+impl std::str::FromStr for RevenueGrouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            RevenueGrouping::Month,
+            RevenueGrouping::Trainer,
+            RevenueGrouping::Method,
+        ]
+        .into_iter()
+        .find(|g| g.name().eq_ignore_ascii_case(s.trim()))
+        .ok_or(format!(
+            "unknown grouping {} (expected month, trainer or method)",
+            s
+        ))
+    }
+}
+
+/// A line of the revenue report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevenueLine {
+    /// What the line covers (e.g. `"2026-01"`, a trainer's name or a
+    /// payment method).
+    pub label: String,
+
+    /// The total of the charges billed, or `None` if charges can't be
+    /// broken down this way.
+    pub charges: Option<Money>,
+
+    /// The total of the payments received, less refunds.
+    pub payments: Money,
+
+    /// The balance still owed at the end of the line's period (or of the
+    /// report, for trainers), or `None` if it can't be broken down this
+    /// way.
+    pub outstanding: Option<Money>,
+}
+
+/// The revenue report over a date range.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevenueReport {
+    /// The first date covered.
+    pub from: NaiveDate,
+
+    /// The last date covered.
+    pub to: NaiveDate,
+
+    /// How the lines are grouped.
+    pub grouping: RevenueGrouping,
+
+    /// The lines, in order.
+    pub lines: Vec<RevenueLine>,
+
+    /// The totals of the whole range. The outstanding balance is every
+    /// client's balance at the end of `to`.
+    pub total: RevenueLine,
+}
+
+/// Works out the revenue report over a date range (inclusive).
+///
+/// * `db_connection` - A connection to the database.
+/// * `from` - The first date to include.
+/// * `to` - The last date to include.
+/// * `grouping` - How to group the lines.
+pub fn revenue_report(
+    db_connection: &mut DbConnection,
+    from: NaiveDate,
+    to: NaiveDate,
+    grouping: RevenueGrouping,
+) -> dolmen::Result<RevenueReport> {
+    if from > to {
+        return Err(dolmen::Error::new(format!(
+            "report start date {} is after end date {}",
+            from, to
+        )));
+    }
+    let connection = db_connection.connection()?;
+    let lines = match grouping {
+        RevenueGrouping::Month => {
+            month_lines(connection, from, to)?
+        }
+        RevenueGrouping::Trainer => {
+            trainer_lines(connection, from, to)?
+        }
+        RevenueGrouping::Method => {
+            method_lines(connection, from, to)?
+        }
+    };
+    let total = RevenueLine {
+        label: "Total".into(),
+        charges: Some(sum_between(
+            connection, "charge", from, to,
+        )?),
+        payments: sum_between(
+            connection, "payment", from, to,
+        )?,
+        outstanding: Some(balance_on(connection, to)?),
+    };
+
+    Ok(RevenueReport {
+        from,
+        to,
+        grouping,
+        lines,
+        total,
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// The label of the line for charges and payments with no trainer.
+const NO_TRAINER: &str = "No trainer";
+
+/// The label of the line for payments with no method.
+const NO_METHOD: &str = "Unspecified";
+
+/// The formats `report revenue` can write the report in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReportFormat {
+    Table,
+    Csv,
+    Pdf,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "table" => Ok(ReportFormat::Table),
+            "csv" => Ok(ReportFormat::Csv),
+            "pdf" => Ok(ReportFormat::Pdf),
+            _ => Err(format!(
+                "unknown report format {} (expected table, csv or pdf)",
+                s
+            )),
+        }
+    }
+}
+
+/// Sums the amounts of a table (`charge` or `payment`) over a date range,
+/// with the tax on charges.
+fn sum_between(
+    connection: &rusqlite::Connection,
+    table: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> dolmen::Result<Money> {
+    let tax = match table {
+        "charge" => Some("tax"),
+        _ => None,
+    };
+    connection
+        .query_row(
+            &format!(
+                "SELECT {} FROM {}
+                    WHERE date >= ?1 AND date <= ?2",
+                money::total_with_sql("amount", tax),
+                table
+            ),
+            rusqlite::params![from, to],
+            |r| money::read_total(r, 0),
+        )
+        .map_err(sql_error)
+}
+
+/// Gets every client's balance together (charges minus payments) at the
+/// end of a date.
+fn balance_on(
+    connection: &rusqlite::Connection,
+    date: NaiveDate,
+) -> dolmen::Result<Money> {
+    let (charged, paid) = connection
+        .query_row(
+            &format!(
+                "SELECT * FROM
+                    (SELECT {} FROM charge WHERE date <= ?1),
+                    (SELECT {} FROM payment WHERE date <= ?1)",
+                money::total_with_sql("amount", Some("tax")),
+                money::total_sql("amount")
+            ),
+            [date],
+            |r| {
+                Ok((
+                    money::read_total(r, 0)?,
+                    money::read_total(r, 2)?,
+                ))
+            },
+        )
+        .map_err(sql_error)?;
+    Ok(charged.checked_sub(paid)?)
+}
+
+/// Runs a query returning a key and a total (selected with `total_sql`)
+/// per row, and collects the totals by key.
+fn sums_by<K>(
+    connection: &rusqlite::Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> dolmen::Result<HashMap<K, Money>>
+where
+    K: FromSql + Eq + Hash,
+{
+    let mut stmt =
+        connection.prepare(sql).map_err(sql_error)?;
+    stmt.query_map(params, |r| {
+        Ok((
+            r.get::<_, K>(0)?,
+            money::read_total(r, 1)?,
+        ))
+    })
+    .map_err(sql_error)?
+    .collect::<Result<HashMap<_, _>, _>>()
+    .map_err(sql_error)
+}
+
+/// Gets a line per calendar month touching the date range. The first and
+/// last months only count the days in the range.
+fn month_lines(
+    connection: &rusqlite::Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> dolmen::Result<Vec<RevenueLine>> {
+    let mut lines = Vec::new();
+    let mut month_start = from
+        .with_day(1)
+        .expect("every month has a first day");
+    while month_start <= to {
+        let next_month = month_start
+            .checked_add_months(chrono::Months::new(1))
+            .ok_or(dolmen::Error::new(
+                "report end date is out of range",
+            ))?;
+        let start = month_start.max(from);
+        let end = next_month
+            .pred_opt()
+            .expect("a month has a last day")
+            .min(to);
+        lines.push(RevenueLine {
+            label: month_start
+                .format("%Y-%m")
+                .to_string(),
+            charges: Some(sum_between(
+                connection, "charge", start, end,
+            )?),
+            payments: sum_between(
+                connection, "payment", start, end,
+            )?,
+            outstanding: Some(balance_on(
+                connection, end,
+            )?),
+        });
+        month_start = next_month;
+    }
+    Ok(lines)
+}
+
+/// Gets a line per trainer with charges or payments up to the end of the
+/// date range, ordered by name. The outstanding balance is what the
+/// trainer's clients still owe them at the end of the range.
+fn trainer_lines(
+    connection: &rusqlite::Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> dolmen::Result<Vec<RevenueLine>> {
+    let charges_sql = format!(
+        "SELECT {}, {}
+            FROM charge c
+            WHERE (?1 IS NULL OR c.date >= ?1)
+                AND c.date <= ?2
+            GROUP BY 1",
+        charge_trainer_sql(),
+        money::total_with_sql(
+            "c.amount",
+            Some("c.tax")
+        )
+    );
+    let charges = sums_by::<Option<i64>>(
+        connection,
+        &charges_sql,
+        rusqlite::params![Some(from), to],
+    )?;
+    let charged_to_date = sums_by::<Option<i64>>(
+        connection,
+        &charges_sql,
+        rusqlite::params![None::<NaiveDate>, to],
+    )?;
+    let payments_sql = format!(
+        "SELECT trainer, {} FROM payment
+            WHERE (?1 IS NULL OR date >= ?1)
+                AND date <= ?2
+            GROUP BY 1",
+        money::total_sql("amount")
+    );
+    let payments = sums_by::<Option<i64>>(
+        connection,
+        &payments_sql,
+        rusqlite::params![Some(from), to],
+    )?;
+    let paid_to_date = sums_by::<Option<i64>>(
+        connection,
+        &payments_sql,
+        rusqlite::params![None::<NaiveDate>, to],
+    )?;
+
+    let mut trainers = charged_to_date
+        .keys()
+        .chain(paid_to_date.keys())
+        .copied()
+        .collect::<Vec<_>>();
+    trainers.sort();
+    trainers.dedup();
+
+    let mut lines = Vec::new();
+    for trainer in trainers {
+        let label = match trainer {
+            Some(trainer) => connection
+                .query_row(
+                    "SELECT COALESCE(name, '') FROM trainer
+                        WHERE id = ?1",
+                    [trainer],
+                    |r| r.get::<_, String>(0),
+                )
+                .map_err(sql_error)?,
+            None => NO_TRAINER.into(),
+        };
+        let amount =
+            |sums: &HashMap<Option<i64>, Money>| {
+                sums.get(&trainer)
+                    .copied()
+                    .unwrap_or_default()
+            };
+        lines.push((
+            trainer.is_none(),
+            RevenueLine {
+                label,
+                charges: Some(amount(&charges)),
+                payments: amount(&payments),
+                outstanding: Some(
+                    amount(&charged_to_date)
+                        .checked_sub(amount(
+                            &paid_to_date,
+                        ))?,
+                ),
+            },
+        ));
+    }
+    lines.sort_by(|(a_none, a), (b_none, b)| {
+        (a_none, &a.label).cmp(&(b_none, &b.label))
+    });
+    Ok(lines
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect())
+}
+
+/// Gets a line per payment method used in the date range, ordered by name.
+fn method_lines(
+    connection: &rusqlite::Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> dolmen::Result<Vec<RevenueLine>> {
+    let payments = sums_by::<String>(
+        connection,
+        &format!(
+            "SELECT COALESCE(paid_via, ''), {} FROM payment
+                WHERE date >= ?1 AND date <= ?2
+                GROUP BY 1",
+            money::total_sql("amount")
+        ),
+        rusqlite::params![from, to],
+    )?;
+    let mut lines = payments
+        .into_iter()
+        .map(|(method, payments)| RevenueLine {
+            label: if method.is_empty() {
+                NO_METHOD.into()
+            } else {
+                method
+            },
+            charges: None,
+            payments,
+            outstanding: None,
+        })
+        .collect::<Vec<_>>();
+    lines.sort_by(|a, b| a.label.cmp(&b.label));
+    Ok(lines)
+}
+
+/// Gets the report's lines followed by its total, as text: the label, then
+/// the charges, payments and outstanding balance as decimals. Amounts that
+/// can't be broken down are left empty.
+fn report_rows(
+    report: &RevenueReport,
+) -> Vec<[String; 4]> {
+    let decimal = |m: Option<Money>| {
+        m.map(|m| m.to_decimal_string())
+            .unwrap_or_default()
+    };
+    report
+        .lines
+        .iter()
+        .chain([&report.total])
+        .map(|line| {
+            [
+                line.label.clone(),
+                decimal(line.charges),
+                decimal(Some(line.payments)),
+                decimal(line.outstanding),
+            ]
+        })
+        .collect()
+}
+
+/// Gets the column headings of the report.
+fn report_headings(
+    report: &RevenueReport,
+) -> [String; 4] {
+    [
+        report.grouping.heading().into(),
+        format!(
+            "Charges billed ({})",
+            report.total.payments.currency().code()
+        ),
+        format!(
+            "Payments received ({})",
+            report.total.payments.currency().code()
+        ),
+        format!(
+            "Outstanding ({})",
+            report.total.payments.currency().code()
+        ),
+    ]
+}
+
+/// Formats a CSV line, quoting fields that need it.
+fn csv_line<'a>(
+    fields: impl IntoIterator<Item = &'a String>,
+) -> String {
+    fields
+        .into_iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n']) {
+                format!(
+                    "\"{}\"",
+                    field.replace('"', "\"\"")
+                )
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Formats the report as CSV, with a header line.
+fn report_csv(report: &RevenueReport) -> String {
+    let mut lines =
+        vec![csv_line(&report_headings(report))];
+    for row in report_rows(report) {
+        lines.push(csv_line(&row));
+    }
+    lines.join("\n")
+}
+
+/// Generates a LaTeX document from the revenue report.
+///
+/// * `report` - The revenue report.
+fn generate_revenue_latex(
+    report: &RevenueReport,
+) -> dolmen::Result<Document> {
+    let mut data = DocumentData::default();
+    data.set("reportfrom", report.from.to_string());
+    data.set("reportto", report.to.to_string());
+    data.set(
+        "currency",
+        report.total.payments.currency().code(),
+    );
+    data.set(
+        "groupheading",
+        report.grouping.heading(),
+    );
+
+    let mut rows = report_rows(report);
+    let [
+        _,
+        total_charges,
+        total_payments,
+        total_outstanding,
+    ] = rows
+        .pop()
+        .expect("the report has a total row");
+    data.set("totalcharges", total_charges);
+    data.set("totalpayments", total_payments);
+    data.set("totaloutstanding", total_outstanding);
+
+    let mut line_data = DataTable::new([
+        "label",
+        "charges",
+        "payments",
+        "outstanding",
+    ]);
+    for row in rows {
+        line_data.push_row(row);
+    }
+    data.set_table("linedata", line_data);
+
+    latex_backend(include_str!("revenue_template.tex"))
+        .render(&data)
+        .map_err(render_error)
+}
+
+/// Creates the `report` command.
+pub(crate) fn report_command() -> Command {
+    Command::new("report")
+        .about("Billing reports")
+        .subcommand(crate::structured(Command::new("revenue")
+            .about("Totals charges billed, payments received and the \
+                outstanding balance over a date range")
+            .arg(Arg::new("from")
+                .long("from")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .required(true)
+                .help("The first date to include (YYYY-MM-DD)")
+            )
+            .arg(Arg::new("to")
+                .long("to")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .required(true)
+                .help("The last date to include (YYYY-MM-DD)")
+            )
+            .arg(Arg::new("group-by")
+                .long("group-by")
+                .default_value("month")
+                .help("How to break the totals down: month, trainer or \
+                    method")
+            )
+            .arg(Arg::new("format")
+                .long("format")
+                .default_value("table")
+                .help("The format to output: table, csv or pdf")
+            )
+            .arg(Arg::new("out-dir")
+                .long("out-dir")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("The folder to output the PDF to. Required for the \
+                    pdf format.")
+            )
+            .arg(Arg::new("verbose")
+                .long("verbose")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print the LaTeX command and its output")
+            )
+        ))
+        .subcommand_required(true)
+}
+
+/// Processes the `report` command.
+pub(crate) fn process_report_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    match arg_matches.subcommand() {
+        Some(("revenue", sub_m)) => {
+            process_revenue_command(context, sub_m)
+        }
+        _ => Err(dolmen::Error::new(
+            "subcommand not recognized",
+        )),
+    }
+}
+
+/// Processes the `revenue` subcommand of the `report` command.
+fn process_revenue_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let from = *arg_matches
+        .get_one::<NaiveDate>("from")
+        .expect("Missing required argument");
+    let to = *arg_matches
+        .get_one::<NaiveDate>("to")
+        .expect("Missing required argument");
+    let grouping = arg_matches
+        .get_one::<String>("group-by")
+        .expect("Missing required argument")
+        .parse::<RevenueGrouping>()
+        .map_err(dolmen::Error::new)?;
+    let format = arg_matches
+        .get_one::<String>("format")
+        .expect("Missing required argument")
+        .parse::<ReportFormat>()
+        .map_err(dolmen::Error::new)?;
+    let out_folder =
+        arg_matches.get_one::<PathBuf>("out-dir");
+    if format == ReportFormat::Pdf
+        && out_folder.is_none()
+    {
+        return Err(dolmen::Error::new(
+            "--out-dir is required for the pdf format",
+        ));
+    }
+
+    let report = revenue_report(
+        context.db_connection()?,
+        from,
+        to,
+        grouping,
+    )?;
+
+    let mut output = OutputRows::new([
+        (grouping.name(), "String"),
+        ("charges", "Money"),
+        ("payments", "Money"),
+        ("outstanding", "Money"),
+    ]);
+    for [label, charges, payments, outstanding] in
+        report_rows(&report)
+    {
+        output.push_row([
+            label.into(),
+            charges.into(),
+            payments.into(),
+            outstanding.into(),
+        ]);
+    }
+    context.set_output_rows(output);
+
+    match (format, out_folder) {
+        (ReportFormat::Csv, _) => Ok(
+            CommandResponse::new(report_csv(&report)),
+        ),
+        (ReportFormat::Pdf, Some(out_folder)) => {
+            let doc = generate_revenue_latex(&report)?;
+            write_document_with_options(
+                out_folder.as_path(),
+                "revenue",
+                &doc,
+                &crate::write_options(arg_matches),
+            )
+            .map_err(|e| {
+                dolmen::Error::new(format!(
+                    "failed to write document: {}",
+                    e
+                ))
+            })?;
+            Ok(CommandResponse::new(format!(
+                "Successfully generated revenue report at {}.",
+                out_folder
+                    .join("revenue.pdf")
+                    .display()
+            )))
+        }
+        _ => {
+            let mut tabled_builder =
+                TabledBuilder::default();
+            tabled_builder
+                .push_record(report_headings(&report));
+            for row in report_rows(&report) {
+                tabled_builder.push_record(row);
+            }
+            Ok(CommandResponse::new(format!(
+                "Revenue from {} to {}:\n{}",
+                from,
+                to,
+                tabled_builder.build()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{
+        add_test_charge, add_test_payment,
+        add_test_row, add_test_session, date,
+        date_time, setup_test_context,
+    };
+
+    fn line(
+        label: &str,
+        charges: Option<i64>,
+        payments: i64,
+        outstanding: Option<i64>,
+    ) -> RevenueLine {
+        RevenueLine {
+            label: label.into(),
+            charges: charges.map(|c| {
+                Money::from_dollars(c).unwrap()
+            }),
+            payments: Money::from_dollars(payments)
+                .unwrap(),
+            outstanding: outstanding.map(|o| {
+                Money::from_dollars(o).unwrap()
+            }),
+        }
+    }
+
+    // Clarissa trains with Tara, who bills a session; Carl's charge isn't
+    // for a session, so it's counted for Theo, whom he pays.
+    #[test]
+    fn test_revenue_report() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let tara = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let theo = add_test_row(
+            db_connection,
+            "trainer",
+            "Theo",
+        )?;
+        let clarissa = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        let carl = add_test_row(
+            db_connection,
+            "client",
+            "Carl",
+        )?;
+
+        let session = add_test_session(
+            db_connection,
+            tara,
+            clarissa,
+            date_time("2026-01-10T09:00:00"),
+            60,
+        )?;
+        let charge = add_test_charge(
+            db_connection,
+            clarissa,
+            "2026-01-10",
+            60,
+        )?;
+        db_connection.set_field_in_table(
+            "session", session, "charge", charge.0,
+        )?;
+        let payment = add_test_payment(
+            db_connection,
+            clarissa,
+            tara,
+            "2026-02-02",
+            40,
+        )?;
+        db_connection.set_field_in_table(
+            "payment", payment, "paid_via", "Card",
+        )?;
+        add_test_charge(
+            db_connection,
+            carl,
+            "2026-02-05",
+            100,
+        )?;
+        add_test_payment(
+            db_connection,
+            carl,
+            theo,
+            "2026-02-06",
+            100,
+        )?;
+        // outside the report
+        add_test_payment(
+            db_connection,
+            carl,
+            theo,
+            "2026-03-01",
+            10,
+        )?;
+
+        let from = date("2026-01-01");
+        let to = date("2026-02-28");
+        let by_month = revenue_report(
+            db_connection,
+            from,
+            to,
+            RevenueGrouping::Month,
+        )?;
+        assert_eq!(
+            by_month.lines,
+            vec![
+                line("2026-01", Some(60), 0, Some(60)),
+                line(
+                    "2026-02",
+                    Some(100),
+                    140,
+                    Some(20)
+                ),
+            ]
+        );
+        assert_eq!(
+            by_month.total,
+            line("Total", Some(160), 140, Some(20))
+        );
+
+        let by_trainer = revenue_report(
+            db_connection,
+            from,
+            to,
+            RevenueGrouping::Trainer,
+        )?;
+        assert_eq!(
+            by_trainer.lines,
+            vec![
+                line("Tara", Some(60), 40, Some(20)),
+                line("Theo", Some(100), 100, Some(0)),
+            ]
+        );
+
+        let by_method = revenue_report(
+            db_connection,
+            from,
+            to,
+            RevenueGrouping::Method,
+        )?;
+        assert_eq!(
+            by_method.lines,
+            vec![
+                line("Card", None, 40, None),
+                line("Cash", None, 100, None),
+            ]
+        );
+
+        let response = context.execute(
+            "report revenue --from=2026-01-01 --to=2026-02-28 \
+                --group-by=method --format=csv",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Method,Charges billed (USD),Payments received (USD),\
+                Outstanding (USD)\n\
+                Card,,40.00,\n\
+                Cash,,100.00,\n\
+                Total,160.00,140.00,20.00"
+        );
+
+        let latex =
+            generate_revenue_latex(&by_trainer)?;
+        let rendered = latex::print(&latex).unwrap();
+        assert!(rendered.contains(
+            "Tara & 60.00 & 40.00 & 20.00 \\\\"
+        ));
+
+        Ok(())
+    }
+}
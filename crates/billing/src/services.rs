@@ -0,0 +1,1223 @@
+//! The service catalog: the services trainers sell, with their default
+//! prices and the prices agreed with particular clients.
+//!
+//! Charges for a service take its name as their description and its price
+//! for the client as their amount, unless either is overridden. Completed
+//! sessions are billed as the active service with the same duration, if
+//! there is one and no rate has been set for the client or trainer (see
+//! `charge_session`). A service can be offered by one trainer or by all of
+//! them; a trainer's own services come before the shared ones.
+use crate::{
+    Currency, Money, get_money, money, set_money,
+    sql_error,
+};
+use chrono::NaiveDate;
+use clap::{Arg, ArgGroup, ArgMatches, Command};
+use db_commands::{
+    CommandOutputContextExt, OutputRows,
+};
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use tabled::builder::Builder as TabledBuilder;
+use training::Session;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A service in the catalog. Stored in the table `service`.
+#[derive(TableRow, Debug)]
+pub struct Service {
+    /// The name of the service, used as the description of its charges
+    /// (e.g. `"Personal training session (60 min)"`).
+    pub name: String,
+
+    /// How long the service usually takes, or `None` if it isn't a
+    /// session (e.g. a nutrition plan). Completed sessions are billed as
+    /// the active service with the same duration.
+    pub duration_minutes: Option<u32>,
+
+    /// The price charged unless the client has their own price for the
+    /// service (see `ServicePrice`).
+    pub price: Money,
+
+    /// The currency of the price.
+    pub currency: Currency,
+
+    /// Whether sales tax applies to the service.
+    pub taxable: bool,
+
+    /// The rate sales tax is charged at, if the service is taxable. A
+    /// taxable service without a rate isn't taxed.
+    pub tax_rate: Option<RowId>,
+
+    /// Whether the service is still offered. Inactive services can't be
+    /// charged for, but their past charges are kept.
+    pub active: bool,
+
+    /// The trainer who offers the service, or `None` if every trainer
+    /// does.
+    pub trainer: Option<RowId>,
+}
+
+/// The price of a service for a particular client, overriding the
+/// service's default price. Stored in the table `service_price`.
+#[derive(TableRow, Debug)]
+pub struct ServicePrice {
+    /// The service the price is for.
+    #[display_table("service", "name")]
+    pub service: RowId,
+
+    /// The client the price applies to.
+    #[display_table("client", "name")]
+    pub client: RowId,
+
+    /// The price charged to the client for the service.
+    pub price: Money,
+
+    /// The currency of the price.
+    pub currency: Currency,
+}
+
+/// Adds an active service to the catalog. Returns the row ID of the new
+/// service.
+///
+/// * `db_connection` - A connection to the database.
+/// * `name` - The name of the service.
+/// * `duration_minutes` - How long the service usually takes, if it's a
+///   session.
+/// * `price` - The default price of the service.
+/// * `tax_rate` - The rate sales tax is charged at, or `None` if the
+///   service isn't taxable.
+/// * `trainer` - The trainer who offers the service, or `None` if every
+///   trainer does.
+pub fn add_service(
+    db_connection: &mut DbConnection,
+    name: &str,
+    duration_minutes: Option<u32>,
+    price: Money,
+    tax_rate: Option<RowId>,
+    trainer: Option<RowId>,
+) -> dolmen::Result<RowId> {
+    if name.trim().is_empty() {
+        return Err(dolmen::Error::new(
+            "a service must have a name",
+        ));
+    }
+    if duration_minutes == Some(0) {
+        return Err(dolmen::Error::new(
+            "a service can't take 0 minutes",
+        ));
+    }
+
+    let service =
+        db_connection.new_row_in_table("service")?;
+    db_connection.set_field_in_table(
+        "service", service, "name", name,
+    )?;
+    if let Some(duration_minutes) = duration_minutes {
+        db_connection.set_field_in_table(
+            "service",
+            service,
+            "duration_minutes",
+            duration_minutes,
+        )?;
+    }
+    set_money(
+        db_connection,
+        "service",
+        service,
+        "price",
+        Some(price),
+    )?;
+    db_connection.set_field_in_table(
+        "service",
+        service,
+        "taxable",
+        tax_rate.is_some(),
+    )?;
+    if let Some(tax_rate) = tax_rate {
+        db_connection.set_field_in_table(
+            "service", service, "tax_rate", tax_rate.0,
+        )?;
+    }
+    db_connection.set_field_in_table(
+        "service", service, "active", true,
+    )?;
+    if let Some(trainer) = trainer {
+        db_connection.set_field_in_table(
+            "service", service, "trainer", trainer.0,
+        )?;
+    }
+    Ok(service)
+}
+
+/// Sets the price of a service for a client, replacing any price set
+/// before.
+///
+/// * `db_connection` - A connection to the database.
+/// * `service` - The row ID of the service.
+/// * `client` - The row ID of the client.
+/// * `price` - The price to charge the client for the service.
+pub fn set_service_price(
+    db_connection: &mut DbConnection,
+    service: RowId,
+    client: RowId,
+    price: Money,
+) -> dolmen::Result<()> {
+    // make sure the service exists
+    Service::from_table_row(
+        db_connection,
+        "service".into(),
+        service,
+    )?;
+
+    let existing = db_connection
+        .connection()?
+        .query_row(
+            "SELECT id FROM service_price
+                WHERE service = ?1 AND client = ?2
+                ORDER BY id DESC LIMIT 1",
+            [service.0, client.0],
+            |r| r.get::<_, i64>(0),
+        )
+        .map(|p| Some(RowId(p)))
+        .or_else(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => {
+                Ok(None)
+            }
+            e => Err(e),
+        })
+        .map_err(sql_error)?;
+    let service_price = match existing {
+        Some(service_price) => service_price,
+        None => {
+            let service_price = db_connection
+                .new_row_in_table("service_price")?;
+            db_connection.set_field_in_table(
+                "service_price",
+                service_price,
+                "service",
+                service.0,
+            )?;
+            db_connection.set_field_in_table(
+                "service_price",
+                service_price,
+                "client",
+                client.0,
+            )?;
+            service_price
+        }
+    };
+    set_money(
+        db_connection,
+        "service_price",
+        service_price,
+        "price",
+        Some(price),
+    )
+}
+
+/// Finds the price of a service for a client: their own price if one is
+/// set, or else the service's default price.
+///
+/// * `db_connection` - A connection to the database.
+/// * `service` - The row ID of the service.
+/// * `client` - The row ID of the client.
+pub fn find_service_price(
+    db_connection: &mut DbConnection,
+    service: RowId,
+    client: RowId,
+) -> dolmen::Result<Money> {
+    db_connection
+        .connection()?
+        .query_row(
+            "SELECT COALESCE(p.price, s.price),
+                    CASE WHEN p.id IS NULL
+                        THEN s.currency ELSE p.currency END
+                FROM service s
+                LEFT JOIN service_price p ON p.id =
+                    (SELECT id FROM service_price
+                        WHERE service = ?1 AND client = ?2
+                            AND price IS NOT NULL
+                        ORDER BY id DESC LIMIT 1)
+                WHERE s.id = ?1",
+            [service.0, client.0],
+            |r| money::read_optional_money(r, 0),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => {
+                dolmen::Error::new(format!(
+                    "service {} doesn't exist",
+                    service.0
+                ))
+            }
+            e => sql_error(e),
+        })?
+        .ok_or(dolmen::Error::new(format!(
+            "service {} has no price",
+            service.0
+        )))
+}
+
+/// Finds the active service a trainer's session of the given length is
+/// billed as, or `None` if there isn't one. The trainer's own services come
+/// before those every trainer offers.
+///
+/// Fails if more than one service of the trainer's own (or, if they have
+/// none, more than one shared service) takes as long, as there's no telling
+/// which one the session was.
+///
+/// * `db_connection` - A connection to the database.
+/// * `duration_minutes` - The length of the session.
+/// * `trainer` - The row ID of the trainer running the session.
+pub fn find_session_service(
+    db_connection: &mut DbConnection,
+    duration_minutes: u32,
+    trainer: RowId,
+) -> dolmen::Result<Option<RowId>> {
+    let connection = db_connection.connection()?;
+    let mut stmt = connection
+        .prepare_cached(
+            "SELECT id, trainer IS NOT NULL FROM service
+                WHERE duration_minutes = ?1
+                    AND active
+                    AND (trainer = ?2 OR trainer IS NULL)
+                ORDER BY trainer IS NULL, id",
+        )
+        .map_err(sql_error)?;
+    let services = stmt
+        .query_map(
+            rusqlite::params![
+                duration_minutes,
+                trainer.0
+            ],
+            |r| {
+                Ok((
+                    RowId(r.get(0)?),
+                    r.get::<_, bool>(1)?,
+                ))
+            },
+        )
+        .map_err(sql_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sql_error)?;
+
+    match services[..] {
+        [] => Ok(None),
+        [(service, _)] => Ok(Some(service)),
+        [(service, own), (other, other_own), ..] => {
+            if own != other_own {
+                return Ok(Some(service));
+            }
+            Err(dolmen::Error::new(format!(
+                "services {} and {} both take {} minutes, retire one or \
+                    pass --service-id",
+                service.0, other.0, duration_minutes
+            )))
+        }
+    }
+}
+
+/// Charges a client for a service from the catalog, with tax if the
+/// service is taxable. Returns the row ID of the new charge.
+///
+/// Fails if the service is inactive.
+///
+/// * `db_connection` - A connection to the database.
+/// * `client` - The row ID of the client to charge.
+/// * `service` - The row ID of the service.
+/// * `date` - The date of the charge.
+/// * `amount` - The amount to charge instead of the client's price for
+///   the service, if any.
+/// * `description` - The description to use instead of the service's
+///   name, if any.
+pub fn charge_service(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    service: RowId,
+    date: NaiveDate,
+    amount: Option<Money>,
+    description: Option<String>,
+) -> dolmen::Result<RowId> {
+    let details = Service::from_table_row(
+        db_connection,
+        "service".into(),
+        service,
+    )?;
+    if !details.active {
+        return Err(dolmen::Error::new(format!(
+            "service {} ({}) is no longer offered",
+            service.0, details.name
+        )));
+    }
+    let amount = match amount {
+        Some(amount) => amount,
+        None => find_service_price(
+            db_connection,
+            service,
+            client,
+        )?,
+    };
+
+    let charge =
+        db_connection.new_row_in_table("charge")?;
+    db_connection.set_field_in_table(
+        "charge", charge, "date", date,
+    )?;
+    db_connection.set_field_in_table(
+        "charge",
+        charge,
+        "description",
+        description.unwrap_or(details.name),
+    )?;
+    set_money(
+        db_connection,
+        "charge",
+        charge,
+        "amount",
+        Some(amount),
+    )?;
+    db_connection.set_field_in_table(
+        "charge", charge, "client", client.0,
+    )?;
+    db_connection.set_field_in_table(
+        "charge", charge, "service", service.0,
+    )?;
+    apply_service_tax(db_connection, charge, service)?;
+    Ok(charge)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+pub(crate) fn service_command() -> Command {
+    Command::new("service")
+        .alias("svc")
+        .about("Service catalog related commands")
+        .subcommand(Command::new("add")
+            .about("Adds a service to the catalog")
+            .arg(Arg::new("name")
+                .long("name")
+                .required(true)
+                .help("The name of the service, used to describe its \
+                    charges")
+            )
+            .arg(Arg::new("price")
+                .long("price")
+                .value_parser(clap::value_parser!(Money))
+                .required(true)
+                .help("The default price of the service")
+            )
+            .arg(Arg::new("duration")
+                .long("duration")
+                .value_parser(clap::value_parser!(u32))
+                .help("How long the service takes in minutes. Completed \
+                    sessions this long are billed as the service.")
+            )
+            .arg(Arg::new("tax-rate-id")
+                .long("tax-rate-id")
+                .value_parser(clap::value_parser!(i64))
+                .help("The tax rate row ID to tax the service at. The \
+                    service isn't taxable if not set.")
+            )
+            .arg(Arg::new("trainer-id")
+                .long("trainer-id")
+                .value_parser(clap::value_parser!(i64))
+                .help("The trainer row ID offering the service. Every \
+                    trainer offers it if not set.")
+            )
+        )
+        .subcommand(Command::new("price")
+            .about("Sets a client's own price for a service")
+            .arg(Arg::new("service-id")
+                .long("service-id")
+                .value_parser(clap::value_parser!(i64))
+                .required(true)
+                .help("The service row ID to set the price of.")
+            )
+            .arg(Arg::new("client-id")
+                .long("client-id")
+                .value_parser(clap::value_parser!(i64))
+                .required(true)
+                .help("The client row ID to set the price for.")
+            )
+            .arg(Arg::new("price")
+                .long("price")
+                .value_parser(clap::value_parser!(Money))
+                .required(true)
+                .help("The price to charge the client")
+            )
+        )
+        .subcommand(crate::structured(Command::new("list")
+            .about("Lists the services in the catalog")
+            .arg(Arg::new("client-id")
+                .long("client-id")
+                .value_parser(clap::value_parser!(i64))
+                .help("Show prices for this client row ID.")
+            )
+            .arg(Arg::new("all")
+                .long("all")
+                .action(clap::ArgAction::SetTrue)
+                .help("Include services that are no longer offered")
+            )
+        ))
+        .subcommand_required(true)
+}
+
+/// Creates the `charge` subcommand of the `billing` command.
+pub(crate) fn charge_command() -> Command {
+    Command::new("charge")
+        .about("Charges a client for a service from the catalog, or bills \
+            a session")
+        .arg(Arg::new("client-id")
+            .long("client-id")
+            .value_parser(clap::value_parser!(i64))
+            .help("The client row ID to charge.")
+        )
+        .arg(Arg::new("session-id")
+            .long("session-id")
+            .value_parser(clap::value_parser!(i64))
+            .help("The session row ID to bill. Its client and date are \
+                used, and the service defaults to the one the session \
+                would be billed as.")
+        )
+        .group(ArgGroup::new("for")
+            .args(["client-id", "session-id"])
+            .required(true)
+        )
+        .arg(Arg::new("service-id")
+            .long("service-id")
+            .value_parser(clap::value_parser!(i64))
+            .help("The service row ID to charge for.")
+        )
+        .arg(Arg::new("date")
+            .long("date")
+            .value_parser(clap::value_parser!(NaiveDate))
+            .help("The date of the charge (YYYY-MM-DD). Defaults to the \
+                session's date, or today.")
+        )
+        .arg(Arg::new("amount")
+            .long("amount")
+            .value_parser(clap::value_parser!(Money))
+            .help("The amount to charge instead of the client's price for \
+                the service")
+        )
+        .arg(Arg::new("description")
+            .long("description")
+            .help("The description to use instead of the service's name")
+        )
+}
+
+/// Processes the main `service` command.
+pub(crate) fn process_service_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    match arg_matches.subcommand() {
+        Some(("add", sub_m)) => process_add_command(
+            sub_m,
+            context.db_connection()?,
+        ),
+        Some(("price", sub_m)) => {
+            process_price_command(
+                sub_m,
+                context.db_connection()?,
+            )
+        }
+        Some(("list", sub_m)) => {
+            process_list_command(context, sub_m)
+        }
+        _ => Err(dolmen::Error::new(
+            "subcommand not recognized",
+        )),
+    }
+}
+
+/// Processes the `charge` subcommand of the `billing` command.
+pub(crate) fn process_charge_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let db_connection = context.db_connection()?;
+    let session = arg_matches
+        .get_one::<i64>("session-id")
+        .map(|s| RowId(*s));
+    let amount = arg_matches
+        .get_one::<Money>("amount")
+        .copied();
+    let description = arg_matches
+        .get_one::<String>("description")
+        .cloned();
+    let service = arg_matches
+        .get_one::<i64>("service-id")
+        .map(|s| RowId(*s));
+
+    let Some(session) = session else {
+        let client = RowId(
+            *arg_matches
+                .get_one::<i64>("client-id")
+                .expect("Missing required argument"),
+        );
+        let service = service.ok_or(dolmen::Error::new(
+            "--service-id is required unless billing a session",
+        ))?;
+        let date = arg_matches
+            .get_one::<NaiveDate>("date")
+            .copied()
+            .unwrap_or(
+                chrono::Local::now().date_naive(),
+            );
+        let charge = charge_service(
+            db_connection,
+            client,
+            service,
+            date,
+            amount,
+            description,
+        )?;
+        return Ok(CommandResponse::new(format!(
+            "Created charge {} ({}) for client {}.",
+            charge.0,
+            get_money(
+                db_connection,
+                "charge",
+                charge,
+                "amount",
+            )?
+            .unwrap_or_default(),
+            client.0
+        )));
+    };
+
+    let details = Session::from_table_row(
+        db_connection,
+        "session".into(),
+        session,
+    )?;
+    if crate::session_charges::is_billed(
+        db_connection,
+        session,
+        &details,
+    )? {
+        return Err(dolmen::Error::new(format!(
+            "session {} has already been billed",
+            session.0
+        )));
+    }
+    let service = match service {
+        Some(service) => service,
+        None => find_session_service(
+            db_connection,
+            details.duration_minutes(),
+            details.trainer(),
+        )?
+        .ok_or(dolmen::Error::new(format!(
+            "no active service takes {} minutes, pass --service-id",
+            details.duration_minutes()
+        )))?,
+    };
+    let date = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(details.date());
+    let charge = charge_service(
+        db_connection,
+        details.client(),
+        service,
+        date,
+        amount,
+        description,
+    )?;
+    db_connection.set_field_in_table(
+        "session", session, "charge", charge.0,
+    )?;
+    Ok(CommandResponse::new(
+        crate::session_charges::charge_created_text(
+            db_connection,
+            charge,
+            session,
+        )?,
+    ))
+}
+
+/// Processes the `add` subcommand of the `service` command.
+fn process_add_command(
+    arg_matches: &ArgMatches,
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<CommandResponse> {
+    let name = arg_matches
+        .get_one::<String>("name")
+        .expect("Missing required argument");
+    let price = *arg_matches
+        .get_one::<Money>("price")
+        .expect("Missing required argument");
+    let duration_minutes = arg_matches
+        .get_one::<u32>("duration")
+        .copied();
+    let tax_rate = arg_matches
+        .get_one::<i64>("tax-rate-id")
+        .map(|t| RowId(*t));
+    let trainer = arg_matches
+        .get_one::<i64>("trainer-id")
+        .map(|t| RowId(*t));
+
+    let service = add_service(
+        db_connection,
+        name,
+        duration_minutes,
+        price,
+        tax_rate,
+        trainer,
+    )?;
+    Ok(CommandResponse::new(format!(
+        "Added service (id: {}) {} at {}.",
+        service.0, name, price
+    )))
+}
+
+/// Processes the `price` subcommand of the `service` command.
+fn process_price_command(
+    arg_matches: &ArgMatches,
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<CommandResponse> {
+    let service = RowId(
+        *arg_matches
+            .get_one::<i64>("service-id")
+            .expect("Missing required argument"),
+    );
+    let client = RowId(
+        *arg_matches
+            .get_one::<i64>("client-id")
+            .expect("Missing required argument"),
+    );
+    let price = *arg_matches
+        .get_one::<Money>("price")
+        .expect("Missing required argument");
+
+    set_service_price(
+        db_connection,
+        service,
+        client,
+        price,
+    )?;
+    Ok(CommandResponse::new(format!(
+        "Client {} will be charged {} for service {}.",
+        client.0, price, service.0
+    )))
+}
+
+/// Processes the `list` subcommand of the `service` command.
+fn process_list_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let client = arg_matches
+        .get_one::<i64>("client-id")
+        .map(|c| RowId(*c));
+    let all = arg_matches.get_flag("all");
+
+    let db_connection = context.db_connection()?;
+    let mut services = Vec::new();
+    for id in
+        db_connection.get_table_row_ids("service")?
+    {
+        let service = Service::from_table_row(
+            db_connection,
+            "service".into(),
+            id,
+        )?;
+        if !service.active && !all {
+            continue;
+        }
+        let price = match client {
+            Some(client) => find_service_price(
+                db_connection,
+                id,
+                client,
+            )?,
+            None => service
+                .price
+                .with_currency(service.currency),
+        };
+        services.push((id, service, price));
+    }
+    services.sort_by_key(|(id, ..)| id.0);
+
+    let mut output = OutputRows::new([
+        ("id", "i64"),
+        ("name", "String"),
+        ("duration_minutes", "u32"),
+        ("price", "Money"),
+        ("taxable", "bool"),
+        ("active", "bool"),
+    ]);
+    for (id, service, price) in &services {
+        output.push_row([
+            id.0.into(),
+            service.name.clone().into(),
+            service.duration_minutes.into(),
+            price.to_decimal_string().into(),
+            service.taxable.into(),
+            service.active.into(),
+        ]);
+    }
+    context.set_output_rows(output);
+
+    if services.is_empty() {
+        return Ok(CommandResponse::new(
+            "No services in the catalog.",
+        ));
+    }
+    let mut tabled_builder = TabledBuilder::default();
+    tabled_builder.push_record([
+        "ID", "Service", "Minutes", "Price",
+        "Taxable", "Active",
+    ]);
+    for (id, service, price) in services {
+        let yes_no =
+            |b: bool| if b { "yes" } else { "no" };
+        tabled_builder.push_record([
+            id.0.to_string(),
+            service.name,
+            service
+                .duration_minutes
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            price.to_string(),
+            yes_no(service.taxable).into(),
+            yes_no(service.active).into(),
+        ]);
+    }
+    Ok(CommandResponse::new(
+        tabled_builder.build().to_string(),
+    ))
+}
+
+/// Taxes a charge for a service at the service's tax rate, if the service
+/// is taxable and has one.
+pub(crate) fn apply_service_tax(
+    db_connection: &mut DbConnection,
+    charge: RowId,
+    service: RowId,
+) -> dolmen::Result<()> {
+    let service = Service::from_table_row(
+        db_connection,
+        "service".into(),
+        service,
+    )?;
+    if let (true, Some(tax_rate)) =
+        (service.taxable, service.tax_rate)
+    {
+        crate::set_charge_tax_rate(
+            db_connection,
+            charge,
+            Some(tax_rate),
+        )?;
+    }
+    Ok(())
+}
+
+/// Gets the service and the amount to charge for a session. A rate set for
+/// the session's client or trainer comes first, as it was agreed for them
+/// in particular; then the active service with the session's duration, at
+/// the client's price; then the rate set for every session. `None` if none
+/// of them applies.
+pub(crate) fn session_price(
+    db_connection: &mut DbConnection,
+    session: &Session,
+) -> dolmen::Result<Option<(Option<RowId>, Money)>> {
+    let rate_amount = |hourly_rate| {
+        crate::session_charges::session_amount(
+            hourly_rate,
+            session.duration_minutes(),
+        )
+        .map(|amount| (None, amount))
+    };
+
+    if let Some(hourly_rate) =
+        crate::session_charges::find_specific_session_rate(
+            db_connection,
+            session.client(),
+            session.trainer(),
+        )?
+    {
+        return rate_amount(hourly_rate).map(Some);
+    }
+    if let Some(service) = find_session_service(
+        db_connection,
+        session.duration_minutes(),
+        session.trainer(),
+    )? {
+        let price = find_service_price(
+            db_connection,
+            service,
+            session.client(),
+        )?;
+        return Ok(Some((Some(service), price)));
+    }
+    crate::find_session_rate(
+        db_connection,
+        session.client(),
+        session.trainer(),
+    )?
+    .map(rate_amount)
+    .transpose()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{
+        add_test_row, add_test_session, date,
+        date_time, setup_test_context,
+    };
+
+    // Carl has his own price for the assessment; Clarissa pays the default.
+    // Overrides replace the catalog's amount and description.
+    #[test]
+    fn test_charge_service() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let clarissa = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        let carl = add_test_row(
+            db_connection,
+            "client",
+            "Carl",
+        )?;
+        let assessment = add_service(
+            db_connection,
+            "Fitness assessment",
+            None,
+            Money::from_dollars(45)?,
+            None,
+            None,
+        )?;
+        set_service_price(
+            db_connection,
+            assessment,
+            carl,
+            Money::from_dollars(50)?,
+        )?;
+        set_service_price(
+            db_connection,
+            assessment,
+            carl,
+            Money::from_dollars(40)?,
+        )?;
+
+        assert_eq!(
+            find_service_price(
+                db_connection,
+                assessment,
+                clarissa
+            )?,
+            Money::from_dollars(45)?
+        );
+        assert_eq!(
+            find_service_price(
+                db_connection,
+                assessment,
+                carl
+            )?,
+            Money::from_dollars(40)?
+        );
+
+        let charge = charge_service(
+            db_connection,
+            carl,
+            assessment,
+            date("2026-03-02"),
+            None,
+            None,
+        )?;
+        let charge =
+            crate::get_charge(db_connection, charge)?;
+        assert_eq!(
+            charge.description,
+            "Fitness assessment"
+        );
+        assert_eq!(
+            charge.amount,
+            Money::from_dollars(40)?
+        );
+        assert_eq!(charge.service, Some(assessment));
+
+        let response = context.execute(
+            "billing charge --client-id=1 --service-id=1 \
+                --date=2026-03-03 --amount=30 \
+                --description=Reassessment",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Created charge 2 ($30.00) for client 1."
+        );
+        let charge = crate::get_charge(
+            context.db_connection()?,
+            RowId(2),
+        )?;
+        assert_eq!(charge.description, "Reassessment");
+
+        // retired services can't be charged for
+        context.db_connection()?.set_field_in_table(
+            "service", assessment, "active", false,
+        )?;
+        assert!(
+            context
+                .execute(
+                    "billing charge --client-id=1 --service-id=1"
+                )
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    // A completed 60-minute session is billed as the 60-minute service,
+    // even though a rate applies; a 45-minute one falls back to the rate.
+    #[test]
+    fn test_session_billed_from_catalog()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        let rate =
+            db_connection.new_row_in_table("rate")?;
+        db_connection.set_field_in_table(
+            "rate",
+            rate,
+            "hourly_rate",
+            Money::from_dollars(80)?,
+        )?;
+        let hour = add_service(
+            db_connection,
+            "Personal training session (60 min)",
+            Some(60),
+            Money::from_dollars(70)?,
+            None,
+            None,
+        )?;
+        set_service_price(
+            db_connection,
+            hour,
+            client,
+            Money::from_dollars(65)?,
+        )?;
+
+        let mut sessions = Vec::new();
+        for duration_minutes in [60, 45] {
+            sessions.push(add_test_session(
+                db_connection,
+                trainer,
+                client,
+                date_time("2026-03-02T09:00:00"),
+                duration_minutes,
+            )?);
+        }
+        for session in &sessions {
+            context.execute(&format!(
+                "session status --session-id={} --status=completed",
+                session.0
+            ))?;
+        }
+
+        let db_connection = context.db_connection()?;
+        let charges = db_connection
+            .get_table_row_ids("charge")?
+            .into_iter()
+            .map(|c| {
+                crate::get_charge(db_connection, c)
+            })
+            .collect::<dolmen::Result<Vec<_>>>()?;
+        assert_eq!(
+            charges
+                .iter()
+                .map(|c| (
+                    c.description.as_str(),
+                    c.amount,
+                    c.service
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "Personal training session (60 min)",
+                    Money::from_dollars(65)?,
+                    Some(hour)
+                ),
+                (
+                    "Personal training session (45 min)",
+                    Money::from_dollars(60)?,
+                    None
+                ),
+            ]
+        );
+
+        // a session can be billed as another service by hand
+        let session = add_test_session(
+            db_connection,
+            trainer,
+            client,
+            date_time("2026-03-09T09:00:00"),
+            60,
+        )?;
+        let response = context.execute(&format!(
+            "billing charge --session-id={} --amount=50",
+            session.0
+        ))?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Created charge 3 ($50.00) for session 3."
+        );
+
+        Ok(())
+    }
+    // Tara agreed an hourly rate, which beats the catalog. Theo has his own
+    // 60-minute service, which beats the shared one. Tom only has shared
+    // services, and two of them take 60 minutes.
+    #[test]
+    fn test_session_price_precedence()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let tara = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let theo = add_test_row(
+            db_connection,
+            "trainer",
+            "Theo",
+        )?;
+        let tom = add_test_row(
+            db_connection,
+            "trainer",
+            "Tom",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        let rate =
+            db_connection.new_row_in_table("rate")?;
+        db_connection.set_field_in_table(
+            "rate", rate, "trainer", tara.0,
+        )?;
+        db_connection.set_field_in_table(
+            "rate",
+            rate,
+            "hourly_rate",
+            Money::from_dollars(100)?,
+        )?;
+        let shared = add_service(
+            db_connection,
+            "Personal training session (60 min)",
+            Some(60),
+            Money::from_dollars(70)?,
+            None,
+            None,
+        )?;
+        let theos = add_service(
+            db_connection,
+            "Strength session with Theo (60 min)",
+            Some(60),
+            Money::from_dollars(90)?,
+            None,
+            Some(theo),
+        )?;
+
+        assert_eq!(
+            find_session_service(
+                db_connection,
+                60,
+                theo
+            )?,
+            Some(theos)
+        );
+        assert_eq!(
+            find_session_service(
+                db_connection,
+                60,
+                tom
+            )?,
+            Some(shared)
+        );
+
+        for trainer in [tara, theo] {
+            let session = add_test_session(
+                context.db_connection()?,
+                trainer,
+                client,
+                date_time("2026-03-02T09:00:00"),
+                60,
+            )?;
+            context.execute(&format!(
+                "session status --session-id={} --status=completed",
+                session.0
+            ))?;
+        }
+        let db_connection = context.db_connection()?;
+        let charges = db_connection
+            .get_table_row_ids("charge")?
+            .into_iter()
+            .map(|c| {
+                crate::get_charge(db_connection, c)
+            })
+            .collect::<dolmen::Result<Vec<_>>>()?;
+        assert_eq!(
+            charges
+                .iter()
+                .map(|c| (c.amount, c.service))
+                .collect::<Vec<_>>(),
+            vec![
+                (Money::from_dollars(100)?, None),
+                (
+                    Money::from_dollars(90)?,
+                    Some(theos)
+                ),
+            ]
+        );
+
+        add_service(
+            db_connection,
+            "Mobility session (60 min)",
+            Some(60),
+            Money::from_dollars(60)?,
+            None,
+            None,
+        )?;
+        assert!(
+            find_session_service(
+                db_connection,
+                60,
+                tom
+            )
+            .is_err()
+        );
+        assert_eq!(
+            find_session_service(
+                db_connection,
+                60,
+                theo
+            )?,
+            Some(theos)
+        );
+
+        Ok(())
+    }
+}
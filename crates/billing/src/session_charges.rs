@@ -0,0 +1,750 @@
+//! Generates charges from completed training sessions.
+use crate::packages::{
+    client_credits_on, session_credit_package,
+    use_session_credit,
+};
+use crate::services::session_price;
+use crate::{
+    Currency, Money, get_money, money, set_money,
+    sql_error,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use clap::ArgMatches;
+use db_commands::{
+    CommandOutputContextExt, OutputRows,
+    with_transaction,
+};
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use training::{Session, SessionStatus};
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A rate to bill sessions at. Stored in the table `rate`.
+///
+/// A rate can be set for a client, a trainer, or a client with a specific
+/// trainer. When a session is billed, the most specific matching rate is
+/// used: client and trainer, then client only, then trainer only. Any of
+/// these comes before the catalog's service for the session. A rate with
+/// neither set applies to every session without a service as a fallback.
+#[derive(TableRow, Debug)]
+pub struct Rate {
+    /// The client the rate applies to, or `None` for every client.
+    pub client: Option<RowId>,
+
+    /// The trainer the rate applies to, or `None` for every trainer.
+    pub trainer: Option<RowId>,
+
+    /// The amount charged for an hour of training. Sessions of other
+    /// lengths are charged proportionally.
+    pub hourly_rate: Money,
+
+    /// The currency of the hourly rate.
+    pub currency: Currency,
+}
+
+/// Finds the hourly rate to bill a client's session with a trainer at,
+/// or `None` if no rate applies.
+///
+/// * `db_connection` - A connection to the database.
+/// * `client` - The row ID of the client.
+/// * `trainer` - The row ID of the trainer.
+pub fn find_session_rate(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    trainer: RowId,
+) -> dolmen::Result<Option<Money>> {
+    session_rate(
+        db_connection.connection()?,
+        client,
+        trainer,
+        false,
+    )
+}
+
+/// Creates the charge for a completed session and links it to the session
+/// through its `charge` field. Returns the row ID of the new charge.
+///
+/// A rate set for the session's client or trainer comes first (see
+/// `Rate`). Otherwise the session is billed as the active service with the
+/// same duration (see `find_session_service`), at the client's price for it
+/// and with tax if the service is taxable. If no service takes that long,
+/// it's charged at the fallback hourly rate instead, without tax.
+///
+/// Fails if the session isn't completed, has already been charged, or no
+/// service or rate applies to it.
+///
+/// * `db_connection` - A connection to the database.
+/// * `session_row_id` - The row ID of the session to charge.
+pub fn charge_session(
+    db_connection: &mut DbConnection,
+    session_row_id: RowId,
+) -> dolmen::Result<RowId> {
+    let session = Session::from_table_row(
+        db_connection,
+        "session".into(),
+        session_row_id,
+    )?;
+
+    if session.status() != SessionStatus::Completed {
+        return Err(dolmen::Error::new(format!(
+            "session {} is {}, only completed sessions are charged",
+            session_row_id.0,
+            session.status()
+        )));
+    }
+
+    create_session_charge(
+        db_connection,
+        session_row_id,
+        &session,
+        None,
+    )
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// Finds the hourly rate set for a client, a trainer or both that applies
+/// to a session, leaving out the fallback rate set for neither. `None` if
+/// there isn't one.
+pub(crate) fn find_specific_session_rate(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    trainer: RowId,
+) -> dolmen::Result<Option<Money>> {
+    session_rate(
+        db_connection.connection()?,
+        client,
+        trainer,
+        true,
+    )
+}
+
+/// Finds the most specific rate that applies to a client's session with a
+/// trainer. See `Rate`.
+fn session_rate(
+    connection: &rusqlite::Connection,
+    client: RowId,
+    trainer: RowId,
+    specific_only: bool,
+) -> dolmen::Result<Option<Money>> {
+    let mut stmt = connection
+        .prepare_cached(&format!(
+            "SELECT {} FROM rate
+                WHERE (client = ?1 OR client IS NULL)
+                    AND (trainer = ?2 OR trainer IS NULL)
+                    AND (NOT ?3 OR client IS NOT NULL
+                        OR trainer IS NOT NULL)
+                    AND hourly_rate IS NOT NULL
+                ORDER BY client IS NULL, trainer IS NULL, id DESC
+                LIMIT 1",
+            money::money_sql("hourly_rate")
+        ))
+        .map_err(sql_error)?;
+    let mut rows = stmt
+        .query_map(
+            rusqlite::params![
+                client.0,
+                trainer.0,
+                specific_only
+            ],
+            |r| money::read_money(r, 0),
+        )
+        .map_err(sql_error)?;
+    rows.next().transpose().map_err(sql_error)
+}
+
+/// Creates a charge for a session at the applicable price, whatever its
+/// status, and links it to the session through its `charge` field.
+/// Returns the row ID of the new charge. The description defaults to the
+/// service's name, or a generic one if the session isn't billed as a
+/// service.
+///
+/// Fails if the session has already been charged or no service or rate
+/// applies to it.
+pub(crate) fn create_session_charge(
+    db_connection: &mut DbConnection,
+    session_row_id: RowId,
+    session: &Session,
+    description: Option<String>,
+) -> dolmen::Result<RowId> {
+    if let Some(charge) = session.charge() {
+        return Err(dolmen::Error::new(format!(
+            "session {} has already been charged (charge {})",
+            session_row_id.0, charge.0
+        )));
+    }
+
+    let (service, amount) =
+        session_price(db_connection, session)?.ok_or(
+            dolmen::Error::new(format!(
+                "no service or rate applies to session {}, add a \
+                    service that long or a rate",
+                session_row_id.0
+            )),
+        )?;
+    let description = match (description, service) {
+        (Some(description), _) => description,
+        (None, Some(service)) => db_connection
+            .get_field_in_table_row::<String>(
+            "service", service, "name",
+        )?,
+        (None, None) => format!(
+            "Personal training session ({} min)",
+            session.duration_minutes()
+        ),
+    };
+
+    // write the charge and link it in one go, so a failure part way leaves
+    // the session unbilled rather than billed by a charge it doesn't know of
+    with_transaction(db_connection, |db_connection| {
+        let charge = db_connection
+            .new_row_in_table("charge")?;
+        db_connection.set_field_in_table(
+            "charge",
+            charge,
+            "date",
+            session.date(),
+        )?;
+        db_connection.set_field_in_table(
+            "charge",
+            charge,
+            "description",
+            description,
+        )?;
+        set_money(
+            db_connection,
+            "charge",
+            charge,
+            "amount",
+            Some(amount),
+        )?;
+        db_connection.set_field_in_table(
+            "charge",
+            charge,
+            "client",
+            session.client().0,
+        )?;
+        if let Some(service) = service {
+            db_connection.set_field_in_table(
+                "charge", charge, "service", service.0,
+            )?;
+            crate::services::apply_service_tax(
+                db_connection,
+                charge,
+                service,
+            )?;
+        }
+        db_connection.set_field_in_table(
+            "session",
+            session_row_id,
+            "charge",
+            charge.0,
+        )?;
+
+        Ok(charge)
+    })
+}
+
+/// Gets the amount to charge for a session from an hourly rate, rounded
+/// to the nearest cent (half a cent rounds up). Fails if the amount is too
+/// large to be stored.
+pub(crate) fn session_amount(
+    hourly_rate: Money,
+    minutes: u32,
+) -> dolmen::Result<Money> {
+    let cents = hourly_rate
+        .cents()
+        .checked_mul(i64::from(minutes))
+        .and_then(|cents| cents.checked_add(30))
+        .ok_or_else(|| {
+            dolmen::Error::new(format!(
+                "{} minutes at {} an hour is too large",
+                minutes, hourly_rate
+            ))
+        })?;
+    Ok(Money::new(
+        cents.div_euclid(60),
+        hourly_rate.currency(),
+    ))
+}
+
+/// A `training::SessionStatusHook` that bills a session as soon as it's
+/// marked completed. If the client has a package credit left, the session
+/// uses it up; otherwise it's charged as its service or at the applicable
+/// rate. Sessions that have already been billed are left alone. If no
+/// service or rate applies, the status
+/// change still goes through and a warning is reported instead, so the
+/// session can be caught up later with `billing charge-sessions`.
+pub(crate) fn charge_completed_session(
+    db_connection: &mut DbConnection,
+    session_row_id: RowId,
+    status: SessionStatus,
+    _changed_at: NaiveDateTime,
+) -> dolmen::Result<Option<String>> {
+    if status != SessionStatus::Completed {
+        return Ok(None);
+    }
+
+    let session = Session::from_table_row(
+        db_connection,
+        "session".into(),
+        session_row_id,
+    )?;
+    if is_billed(
+        db_connection,
+        session_row_id,
+        &session,
+    )? {
+        return Ok(None);
+    }
+    if let Some(text) = use_credit_for_session(
+        db_connection,
+        session_row_id,
+        &session,
+    )? {
+        return Ok(Some(text));
+    }
+    if session_price(db_connection, &session)?
+        .is_none()
+    {
+        return Ok(Some(format!(
+            "Warning: no service or rate applies to session {}, so it \
+                wasn't charged.",
+            session_row_id.0
+        )));
+    }
+
+    let charge =
+        charge_session(db_connection, session_row_id)?;
+    Ok(Some(charge_created_text(
+        db_connection,
+        charge,
+        session_row_id,
+    )?))
+}
+
+/// Returns whether a session has already been billed, either with a charge
+/// or a package credit.
+pub(crate) fn is_billed(
+    db_connection: &mut DbConnection,
+    session_row_id: RowId,
+    session: &Session,
+) -> dolmen::Result<bool> {
+    Ok(session.charge().is_some()
+        || session_credit_package(
+            db_connection.connection()?,
+            session_row_id,
+        )?
+        .is_some())
+}
+
+/// Pays for a session with one of the client's package credits, if they
+/// have one left. Returns a description of the credit used.
+pub(crate) fn use_credit_for_session(
+    db_connection: &mut DbConnection,
+    session_row_id: RowId,
+    session: &Session,
+) -> dolmen::Result<Option<String>> {
+    let Some(package) = use_session_credit(
+        db_connection,
+        session_row_id,
+        session.client(),
+        session.date(),
+    )?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(format!(
+        "Used a credit from package {} for session {} ({} left).",
+        package.0,
+        session_row_id.0,
+        client_credits_on(
+            db_connection,
+            session.client(),
+            session.date()
+        )?
+    )))
+}
+
+/// Describes a charge created for a session.
+pub(crate) fn charge_created_text(
+    db_connection: &mut DbConnection,
+    charge: RowId,
+    session: RowId,
+) -> dolmen::Result<String> {
+    let amount = get_money(
+        db_connection,
+        "charge",
+        charge,
+        "amount",
+    )?
+    .unwrap_or_default();
+    Ok(format!(
+        "Created charge {} ({}) for session {}.",
+        charge.0, amount, session.0
+    ))
+}
+
+/// Processes the `charge-sessions` subcommand of the `billing` command.
+pub(crate) fn process_charge_sessions_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let from = *arg_matches
+        .get_one::<NaiveDate>("from")
+        .expect("Missing required argument");
+    let to = *arg_matches
+        .get_one::<NaiveDate>("to")
+        .expect("Missing required argument");
+    if from > to {
+        return Err(dolmen::Error::new(format!(
+            "--from ({}) is after --to ({})",
+            from, to
+        )));
+    }
+
+    let db_connection = context.db_connection()?;
+    let completed = training::sessions_between(
+        db_connection,
+        from,
+        to,
+    )?
+    .into_iter()
+    .filter(|(_, s)| {
+        s.status() == SessionStatus::Completed
+    })
+    .collect::<Vec<_>>();
+
+    let mut created = Vec::new();
+    let mut skipped = Vec::new();
+    for (session, details) in completed {
+        if is_billed(db_connection, session, &details)?
+        {
+            continue;
+        }
+        if let Some(text) = use_credit_for_session(
+            db_connection,
+            session,
+            &details,
+        )? {
+            created.push((session, text));
+            continue;
+        }
+        match charge_session(db_connection, session) {
+            Ok(charge) => created.push((
+                session,
+                charge_created_text(
+                    db_connection,
+                    charge,
+                    session,
+                )?,
+            )),
+            Err(e) => skipped.push((
+                session,
+                format!(
+                    "Skipped session {}: {}.",
+                    session.0,
+                    e.message()
+                        .clone()
+                        .unwrap_or_default()
+                ),
+            )),
+        }
+    }
+
+    let mut output = OutputRows::new([
+        ("session", "i64"),
+        ("result", "String"),
+        ("message", "String"),
+    ]);
+    for (result, lines) in
+        [("billed", &created), ("skipped", &skipped)]
+    {
+        for (session, line) in lines {
+            output.push_row([
+                session.0.into(),
+                result.into(),
+                line.as_str().into(),
+            ]);
+        }
+    }
+    context.set_output_rows(output);
+
+    let mut response_text = format!(
+        "Billed {} completed session(s) between {} and {}.",
+        created.len(),
+        from,
+        to
+    );
+    for (_, line) in
+        created.iter().chain(skipped.iter())
+    {
+        response_text += "\n";
+        response_text += line.as_str();
+    }
+    Ok(CommandResponse::new(response_text))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Money;
+    use crate::test_util::{
+        add_test_rate, add_test_row, add_test_session,
+        date_time, setup_test_context,
+    };
+    use dolmen::prelude::*;
+    use reliquary::prelude::*;
+
+    // A client-specific rate wins over the trainer's rate, and 45 minutes
+    // at $70/hour is $52.50.
+    #[test]
+    fn test_charge_completed_session()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        add_test_rate(
+            db_connection,
+            None,
+            Some(trainer),
+            80,
+        )?;
+        add_test_rate(
+            db_connection,
+            Some(client),
+            None,
+            70,
+        )?;
+        let session = add_test_session(
+            db_connection,
+            trainer,
+            client,
+            date_time("2026-06-01T09:00:00"),
+            45,
+        )?;
+
+        let response = context.execute(&format!(
+            "session status --session-id={} --status=completed",
+            session.0
+        ))?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Marked session 1 as completed.\n\
+                Created charge 1 ($52.50) for session 1."
+        );
+
+        let db_connection = context.db_connection()?;
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<i64>(
+                    "session", session, "charge",
+                )?,
+            1
+        );
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<Money>(
+                    "charge",
+                    RowId(1),
+                    "amount",
+                )?,
+            Money::from_cents(5250)
+        );
+
+        // completing it again doesn't charge twice
+        let response = context.execute(&format!(
+            "session status --session-id={} --status=completed",
+            session.0
+        ))?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Marked session 1 as completed."
+        );
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_table_row_ids("charge")?,
+            vec![1]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_charge_sessions_command()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        for starts in [
+            "2026-06-01T09:00:00",
+            "2026-06-03T09:00:00",
+            "2026-06-10T09:00:00",
+        ] {
+            let session = add_test_session(
+                db_connection,
+                trainer,
+                client,
+                date_time(starts),
+                60,
+            )?;
+            training::set_session_status(
+                db_connection,
+                session,
+                training::SessionStatus::Completed,
+            )?;
+        }
+        // scheduled sessions aren't charged
+        add_test_session(
+            db_connection,
+            trainer,
+            client,
+            date_time("2026-06-02T09:00:00"),
+            60,
+        )?;
+
+        let response = context.execute(
+            "billing charge-sessions --from=2026-06-01 --to=2026-06-07",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Billed 0 completed session(s) between 2026-06-01 and 2026-06-07.\n\
+                Skipped session 1: no service or rate applies to session 1, add a service that long or a rate.\n\
+                Skipped session 2: no service or rate applies to session 2, add a service that long or a rate."
+        );
+
+        add_test_rate(
+            context.db_connection()?,
+            None,
+            None,
+            60,
+        )?;
+        let response = context.execute(
+            "billing charge-sessions --from=2026-06-01 --to=2026-06-07",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Billed 2 completed session(s) between 2026-06-01 and 2026-06-07.\n\
+                Created charge 1 ($60.00) for session 1.\n\
+                Created charge 2 ($60.00) for session 2."
+        );
+
+        // already charged sessions are skipped quietly
+        let response = context.execute(
+            "billing charge-sessions --from=2026-06-01 --to=2026-06-30",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Billed 1 completed session(s) between 2026-06-01 and 2026-06-30.\n\
+                Created charge 3 ($60.00) for session 3."
+        );
+
+        Ok(())
+    }
+
+    // Completing a session with `set`, as the Edit Table tab does, bills
+    // it just like `session status`.
+    #[test]
+    fn test_session_amount() {
+        assert_eq!(
+            super::session_amount(
+                Money::from_cents(7000),
+                45,
+            )
+            .unwrap(),
+            Money::from_cents(5250)
+        );
+        assert_eq!(
+            super::session_amount(
+                Money::from_cents(i64::MAX / 2),
+                60,
+            )
+            .unwrap_err()
+            .message()
+            .clone()
+            .unwrap(),
+            format!(
+                "60 minutes at {} an hour is too large",
+                Money::from_cents(i64::MAX / 2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_set_status_charges_session()
+    -> dolmen::Result<()> {
+        let mut context = Context::new();
+        context
+            .add_plugin(DbPlugin)?
+            .add_plugin(db_commands::DbCommandsPlugin)?
+            .add_plugin(crate::BillingPlugin)?
+            .add_plugin(training::TrainingPlugin)?;
+        context
+            .get_resource_mut::<DbConfig>()
+            .unwrap()
+            .open_db_in_memory = true;
+        context.startup()?;
+
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+        add_test_rate(db_connection, None, None, 60)?;
+        let session = add_test_session(
+            db_connection,
+            trainer,
+            client,
+            date_time("2026-06-01T09:00:00"),
+            60,
+        )?;
+
+        let response = context.execute(&format!(
+            "set --table=session --row-id={} \
+                --field=status --value=completed",
+            session.0
+        ))?;
+        assert!(response.text().unwrap().ends_with(
+            "\nCreated charge 1 ($60.00) for session 1."
+        ));
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_field_in_table_row::<i64>(
+                    "session", session, "charge",
+                )?,
+            1
+        );
+
+        Ok(())
+    }
+}
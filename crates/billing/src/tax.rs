@@ -0,0 +1,956 @@
+//! Sales tax (or VAT): the rates charges are taxed at, and the
+//! tax-collected report.
+//!
+//! A charge's tax is worked out when its rate is set, and again whenever
+//! its amount or rate is changed with `set`, and stored on the charge, so
+//! changing a rate's percentage later doesn't change tax already billed.
+//! Tax is worked out per charge and rounded to the nearest cent, with half
+//! a cent rounded away from zero, so a void's tax exactly cancels the tax
+//! on the charge it reverses. Tax subtotals are sums of the rounded tax on
+//! each charge.
+use crate::{
+    Money, get_charge, get_money, money, set_money,
+    sql_error,
+};
+use chrono::NaiveDate;
+use clap::{Arg, ArgMatches, Command};
+use db_commands::{
+    CommandOutputContextExt, OutputRows,
+};
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use rusqlite::types::{
+    FromSql, FromSqlError, FromSqlResult, ToSql,
+    ToSqlOutput, ValueRef,
+};
+use tabled::builder::Builder as TabledBuilder;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A percentage, stored as a whole number of thousandths of a percent so
+/// that rates such as 8.875% are exact.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+pub struct Percentage {
+    thousandths: i64,
+}
+
+impl Percentage {
+    /// Creates a percentage from a number of thousandths of a percent
+    /// (e.g. `8250` for 8.25%).
+    pub fn from_thousandths(thousandths: i64) -> Self {
+        Self { thousandths }
+    }
+
+    /// Gets the percentage in thousandths of a percent.
+    pub fn thousandths(&self) -> i64 {
+        self.thousandths
+    }
+}
+
+impl std::fmt::Display for Percentage {
+    /// Formats the percentage with as few decimals as it needs (e.g.
+    /// `8.25%`, `20%`).
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        let sign = if self.thousandths < 0 {
+            "-"
+        } else {
+            ""
+        };
+        let whole = self.thousandths.abs() / 1000;
+        let fraction = self.thousandths.abs() % 1000;
+        if fraction == 0 {
+            write!(f, "{}{}%", sign, whole)
+        } else {
+            let fraction = format!("{:03}", fraction);
+            write!(
+                f,
+                "{}{}.{}%",
+                sign,
+                whole,
+                fraction.trim_end_matches('0')
+            )
+        }
+    }
+}
+
+impl std::str::FromStr for Percentage {
+    type Err = String;
+
+    /// Parses a percentage such as `"8.25"` or `"20%"`. At most three
+    /// decimal places are accepted, and it can't be negative.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            format!(
+                "invalid percentage: {} (expected e.g. 8.25 or 20%)",
+                s
+            )
+        };
+        let text =
+            s.trim().trim_end_matches('%').trim();
+        let (whole, fraction) =
+            text.split_once('.').unwrap_or((text, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(err());
+        }
+        if !whole.chars().all(|c| c.is_ascii_digit())
+            || !fraction
+                .chars()
+                .all(|c| c.is_ascii_digit())
+            || fraction.len() > 3
+        {
+            return Err(err());
+        }
+        let whole: i64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| err())?
+        };
+        let fraction: i64 =
+            format!("{:0<3}", fraction)
+                .parse()
+                .map_err(|_| err())?;
+        whole
+            .checked_mul(1000)
+            .and_then(|t| t.checked_add(fraction))
+            .map(Percentage::from_thousandths)
+            .ok_or_else(err)
+    }
+}
+
+impl ToSql for Percentage {
+    fn to_sql(
+        &self,
+    ) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.thousandths))
+    }
+}
+
+impl FromSql for Percentage {
+    /// Reads a percentage stored as an integer number of thousandths of a
+    /// percent. Text values (e.g. written by the `set` command) are parsed
+    /// with `Percentage::from_str`.
+    fn column_result(
+        value: ValueRef<'_>,
+    ) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Integer(thousandths) => {
+                Ok(Percentage::from_thousandths(
+                    thousandths,
+                ))
+            }
+            ValueRef::Text(text) => {
+                std::str::from_utf8(text)
+                    .map_err(|e| {
+                        FromSqlError::Other(Box::new(
+                            e,
+                        ))
+                    })?
+                    .parse()
+                    .map_err(|e: String| {
+                        FromSqlError::Other(e.into())
+                    })
+            }
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// A sales tax rate. Stored in the table `tax_rate`.
+#[derive(TableRow, Debug)]
+pub struct TaxRate {
+    /// The name of the tax (e.g. `"Colorado sales tax"`), shown on
+    /// documents and in the tax report.
+    pub name: String,
+
+    /// The rate charged.
+    pub rate: Percentage,
+}
+
+/// A line of the tax-collected report: the tax billed at one rate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaxReportLine {
+    /// The row ID of the tax rate.
+    pub tax_rate: RowId,
+
+    /// The name of the tax rate.
+    pub name: String,
+
+    /// The rate.
+    pub rate: Percentage,
+
+    /// The total of the charges taxed at the rate, before tax.
+    pub taxable_amount: Money,
+
+    /// The total tax billed at the rate.
+    pub tax: Money,
+}
+
+/// Works out the tax on an amount at a rate, rounded to the nearest cent
+/// with half a cent rounded away from zero. Fails if the tax is too large
+/// to be stored.
+///
+/// * `amount` - The amount taxed.
+/// * `rate` - The tax rate.
+pub fn tax_on(
+    amount: Money,
+    rate: Percentage,
+) -> dolmen::Result<Money> {
+    // cents * thousandths of a percent is in 100,000ths of a cent, and
+    // can't overflow an i128
+    let scaled = i128::from(amount.cents())
+        * i128::from(rate.thousandths());
+    let rounded = (scaled.abs() + 50_000) / 100_000;
+    let cents =
+        i64::try_from(rounded * scaled.signum())
+            .map_err(|_| {
+                dolmen::Error::new(format!(
+                    "tax on {} at {} is too large",
+                    amount, rate
+                ))
+            })?;
+    Ok(Money::new(cents, amount.currency()))
+}
+
+/// Adds a tax rate. Returns the row ID of the new tax rate.
+///
+/// * `db_connection` - A connection to the database.
+/// * `name` - The name of the tax.
+/// * `rate` - The rate charged.
+pub fn add_tax_rate(
+    db_connection: &mut DbConnection,
+    name: &str,
+    rate: Percentage,
+) -> dolmen::Result<RowId> {
+    if name.trim().is_empty() {
+        return Err(dolmen::Error::new(
+            "a tax rate must have a name",
+        ));
+    }
+    if rate.thousandths() < 0 {
+        return Err(dolmen::Error::new(
+            "a tax rate can't be negative",
+        ));
+    }
+    let tax_rate =
+        db_connection.new_row_in_table("tax_rate")?;
+    db_connection.set_field_in_table(
+        "tax_rate", tax_rate, "name", name,
+    )?;
+    db_connection.set_field_in_table(
+        "tax_rate", tax_rate, "rate", rate,
+    )?;
+    Ok(tax_rate)
+}
+
+/// Sets the tax rate of a charge and works out its tax, or removes its
+/// tax. Returns the tax on the charge.
+///
+/// Fails if the charge is a void or has been voided, since a void's tax
+/// has to cancel the tax of the charge it reverses.
+///
+/// * `db_connection` - A connection to the database.
+/// * `charge_row_id` - The row ID of the charge.
+/// * `tax_rate` - The row ID of the tax rate, or `None` to remove the
+///   charge's tax.
+pub fn set_charge_tax_rate(
+    db_connection: &mut DbConnection,
+    charge_row_id: RowId,
+    tax_rate: Option<RowId>,
+) -> dolmen::Result<Money> {
+    let charge =
+        get_charge(db_connection, charge_row_id)?;
+    if let Some(voided) = charge.voids {
+        return Err(dolmen::Error::new(format!(
+            "charge {} is the void of charge {}, its tax can't be \
+                changed",
+            charge_row_id.0, voided.0
+        )));
+    }
+    if let Some(void) = crate::voids::find_void(
+        db_connection,
+        charge_row_id,
+    )? {
+        return Err(dolmen::Error::new(format!(
+            "charge {} has been voided (charge {}), its tax can't be \
+                changed",
+            charge_row_id.0, void.0
+        )));
+    }
+
+    let Some(tax_rate) = tax_rate else {
+        db_connection.set_field_in_table(
+            "charge",
+            charge_row_id,
+            "tax_rate",
+            None::<i64>,
+        )?;
+        db_connection.set_field_in_table(
+            "charge",
+            charge_row_id,
+            "tax",
+            None::<Money>,
+        )?;
+        return Ok(Money::zero());
+    };
+    let rate = TaxRate::from_table_row(
+        db_connection,
+        "tax_rate".into(),
+        tax_rate,
+    )?
+    .rate;
+    let tax = tax_on(charge.amount, rate)?;
+    db_connection.set_field_in_table(
+        "charge",
+        charge_row_id,
+        "tax_rate",
+        tax_rate.0,
+    )?;
+    set_money(
+        db_connection,
+        "charge",
+        charge_row_id,
+        "tax",
+        Some(tax),
+    )?;
+    Ok(tax)
+}
+
+/// Works a charge's tax out again from its amount and tax rate, or clears
+/// it if the charge has no amount or tax rate yet. Run after the charge's
+/// amount or tax rate is changed with `set`, so its tax doesn't go stale.
+///
+/// * `db_connection` - A connection to the database.
+/// * `charge_row_id` - The row ID of the charge.
+pub fn refresh_charge_tax(
+    db_connection: &mut DbConnection,
+    charge_row_id: RowId,
+) -> dolmen::Result<()> {
+    // the charge may still be being filled in, so only the fields the tax
+    // comes from are read
+    let amount = get_money(
+        db_connection,
+        "charge",
+        charge_row_id,
+        "amount",
+    )?;
+    let tax_rate = db_connection
+        .get_field_in_table_row::<Option<i64>>(
+            "charge",
+            charge_row_id,
+            "tax_rate",
+        )?;
+    let tax = match (amount, tax_rate) {
+        (Some(amount), Some(tax_rate)) => {
+            let rate = TaxRate::from_table_row(
+                db_connection,
+                "tax_rate".into(),
+                RowId(tax_rate),
+            )?
+            .rate;
+            Some(tax_on(amount, rate)?)
+        }
+        _ => None,
+    };
+    set_money(
+        db_connection,
+        "charge",
+        charge_row_id,
+        "tax",
+        tax,
+    )
+}
+
+/// Works out the tax billed over a date range (inclusive), per tax rate,
+/// ordered by name. Voids count against the rate of the charge they
+/// reverse, so voided charges cancel out.
+///
+/// * `db_connection` - A connection to the database.
+/// * `from` - The first charge date to include.
+/// * `to` - The last charge date to include.
+pub fn tax_report(
+    db_connection: &mut DbConnection,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> dolmen::Result<Vec<TaxReportLine>> {
+    if from > to {
+        return Err(dolmen::Error::new(format!(
+            "report start date {} is after end date {}",
+            from, to
+        )));
+    }
+    let connection = db_connection.connection()?;
+    let mut stmt = connection
+        .prepare(&format!(
+            "SELECT t.id, COALESCE(t.name, ''), t.rate, {}, {}
+                FROM charge c JOIN tax_rate t ON t.id = c.tax_rate
+                WHERE c.date >= ?1 AND c.date <= ?2
+                GROUP BY t.id
+                ORDER BY t.name, t.id",
+            money::total_sql("c.amount"),
+            money::total_sql("c.tax")
+        ))
+        .map_err(sql_error)?;
+    stmt.query_map(rusqlite::params![from, to], |r| {
+        Ok(TaxReportLine {
+            tax_rate: RowId(r.get(0)?),
+            name: r.get(1)?,
+            rate: r.get(2)?,
+            taxable_amount: money::read_total(r, 3)?,
+            tax: money::read_total(r, 5)?,
+        })
+    })
+    .map_err(sql_error)?
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(sql_error)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// Gets the tax on each of a set of charges, as subtotals per tax rate
+/// (name and rate), ordered by name. Rates whose tax cancels out are left
+/// out.
+pub(crate) fn tax_subtotals(
+    db_connection: &mut DbConnection,
+    charges: &[RowId],
+) -> dolmen::Result<Vec<(String, Money)>> {
+    let mut subtotals: Vec<(String, Money)> =
+        Vec::new();
+    for charge in charges {
+        let charge =
+            get_charge(db_connection, *charge)?;
+        let (Some(tax_rate), Some(tax)) =
+            (charge.tax_rate, charge.tax)
+        else {
+            continue;
+        };
+        let tax_rate = TaxRate::from_table_row(
+            db_connection,
+            "tax_rate".into(),
+            tax_rate,
+        )?;
+        let label = format!(
+            "{} ({})",
+            tax_rate.name, tax_rate.rate
+        );
+        match subtotals
+            .iter_mut()
+            .find(|(name, _)| *name == label)
+        {
+            Some((_, subtotal)) => {
+                *subtotal =
+                    subtotal.checked_add(tax)?
+            }
+            None => subtotals.push((label, tax)),
+        }
+    }
+    subtotals.retain(|(_, tax)| !tax.is_zero());
+    subtotals.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(subtotals)
+}
+
+pub(crate) fn tax_command() -> Command {
+    Command::new("tax")
+        .about("Sales tax related commands")
+        .subcommand(Command::new("add")
+            .about("Adds a tax rate")
+            .arg(Arg::new("name")
+                .long("name")
+                .required(true)
+                .help("The name of the tax, shown on documents")
+            )
+            .arg(Arg::new("rate")
+                .long("rate")
+                .value_parser(clap::value_parser!(Percentage))
+                .required(true)
+                .help("The rate as a percentage (e.g. 8.25)")
+            )
+        )
+        .subcommand(Command::new("apply")
+            .about("Sets the tax rate of a charge and works out its tax")
+            .arg(Arg::new("charge-id")
+                .long("charge-id")
+                .value_parser(clap::value_parser!(i64))
+                .required(true)
+                .help("The charge row ID to tax.")
+            )
+            .arg(Arg::new("tax-rate-id")
+                .long("tax-rate-id")
+                .value_parser(clap::value_parser!(i64))
+                .help("The tax rate row ID to tax the charge at. The \
+                    charge's tax is removed if not set.")
+            )
+        )
+        .subcommand(crate::structured(Command::new("report")
+            .about("Totals the tax billed over a date range, per rate")
+            .arg(Arg::new("from")
+                .long("from")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .required(true)
+                .help("The first charge date to include (YYYY-MM-DD)")
+            )
+            .arg(Arg::new("to")
+                .long("to")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .required(true)
+                .help("The last charge date to include (YYYY-MM-DD)")
+            )
+        ))
+        .subcommand_required(true)
+}
+
+/// Processes the main `tax` command.
+pub(crate) fn process_tax_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    match arg_matches.subcommand() {
+        Some(("add", sub_m)) => process_add_command(
+            sub_m,
+            context.db_connection()?,
+        ),
+        Some(("apply", sub_m)) => {
+            process_apply_command(
+                sub_m,
+                context.db_connection()?,
+            )
+        }
+        Some(("report", sub_m)) => {
+            process_report_command(context, sub_m)
+        }
+        _ => Err(dolmen::Error::new(
+            "subcommand not recognized",
+        )),
+    }
+}
+
+/// Processes the `add` subcommand of the `tax` command.
+fn process_add_command(
+    arg_matches: &ArgMatches,
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<CommandResponse> {
+    let name = arg_matches
+        .get_one::<String>("name")
+        .expect("Missing required argument");
+    let rate = *arg_matches
+        .get_one::<Percentage>("rate")
+        .expect("Missing required argument");
+
+    let tax_rate =
+        add_tax_rate(db_connection, name, rate)?;
+    Ok(CommandResponse::new(format!(
+        "Added tax rate (id: {}) {} at {}.",
+        tax_rate.0, name, rate
+    )))
+}
+
+/// Processes the `apply` subcommand of the `tax` command.
+fn process_apply_command(
+    arg_matches: &ArgMatches,
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<CommandResponse> {
+    let charge = RowId(
+        *arg_matches
+            .get_one::<i64>("charge-id")
+            .expect("Missing required argument"),
+    );
+    let tax_rate = arg_matches
+        .get_one::<i64>("tax-rate-id")
+        .map(|t| RowId(*t));
+
+    let tax = set_charge_tax_rate(
+        db_connection,
+        charge,
+        tax_rate,
+    )?;
+    Ok(CommandResponse::new(match tax_rate {
+        Some(tax_rate) => format!(
+            "Charge {} is taxed at rate {}: {} tax.",
+            charge.0, tax_rate.0, tax
+        ),
+        None => format!(
+            "Removed the tax from charge {}.",
+            charge.0
+        ),
+    }))
+}
+
+/// Processes the `report` subcommand of the `tax` command.
+fn process_report_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let from = *arg_matches
+        .get_one::<NaiveDate>("from")
+        .expect("Missing required argument");
+    let to = *arg_matches
+        .get_one::<NaiveDate>("to")
+        .expect("Missing required argument");
+
+    let lines = tax_report(
+        context.db_connection()?,
+        from,
+        to,
+    )?;
+
+    let mut output = OutputRows::new([
+        ("tax_rate", "i64"),
+        ("name", "String"),
+        ("rate", "Percentage"),
+        ("taxable_amount", "Money"),
+        ("tax", "Money"),
+    ]);
+    for line in &lines {
+        output.push_row([
+            line.tax_rate.0.into(),
+            line.name.clone().into(),
+            line.rate.to_string().into(),
+            line.taxable_amount
+                .to_decimal_string()
+                .into(),
+            line.tax.to_decimal_string().into(),
+        ]);
+    }
+    context.set_output_rows(output);
+
+    if lines.is_empty() {
+        return Ok(CommandResponse::new(format!(
+            "No tax billed from {} to {}.",
+            from, to
+        )));
+    }
+    let mut tabled_builder = TabledBuilder::default();
+    tabled_builder.push_record([
+        "Tax",
+        "Rate",
+        "Taxable",
+        "Tax billed",
+    ]);
+    for line in &lines {
+        tabled_builder.push_record([
+            line.name.clone(),
+            line.rate.to_string(),
+            line.taxable_amount.to_string(),
+            line.tax.to_string(),
+        ]);
+    }
+    tabled_builder.push_record([
+        "Total".to_string(),
+        String::new(),
+        Money::total(
+            lines.iter().map(|l| l.taxable_amount),
+        )?
+        .to_string(),
+        Money::total(lines.iter().map(|l| l.tax))?
+            .to_string(),
+    ]);
+    Ok(CommandResponse::new(format!(
+        "Tax billed from {} to {}:\n{}",
+        from,
+        to,
+        tabled_builder.build()
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{date, setup_test_context};
+
+    fn percent(text: &str) -> Percentage {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_percentage() {
+        assert_eq!(
+            percent("8.25").thousandths(),
+            8250
+        );
+        assert_eq!(
+            percent("20%").thousandths(),
+            20000
+        );
+        assert_eq!(
+            percent(" 8.875 % ").thousandths(),
+            8875
+        );
+        assert_eq!(percent(".5").thousandths(), 500);
+        assert_eq!(
+            percent("8.25").to_string(),
+            "8.25%"
+        );
+        assert_eq!(percent("20").to_string(), "20%");
+        assert_eq!(
+            percent("0.125").to_string(),
+            "0.125%"
+        );
+        for bad in
+            ["", "%", "-5", "8.2501", "abc", "1.2.3"]
+        {
+            assert!(
+                bad.parse::<Percentage>().is_err(),
+                "{} parsed",
+                bad
+            );
+        }
+    }
+
+    // Half a cent rounds away from zero, so negative amounts (voids) get
+    // exactly the opposite tax.
+    #[test]
+    fn test_tax_on() {
+        let tax = |cents: i64, rate: &str| {
+            tax_on(
+                Money::from_cents(cents),
+                percent(rate),
+            )
+            .unwrap()
+            .cents()
+        };
+        assert_eq!(tax(6000, "8.25"), 495);
+        assert_eq!(tax(1000, "8.875"), 89);
+        // $0.50 at 5% is exactly 2.5 cents
+        assert_eq!(tax(50, "5"), 3);
+        assert_eq!(tax(-50, "5"), -3);
+        assert_eq!(tax(-6000, "8.25"), -495);
+        assert_eq!(tax(6000, "0"), 0);
+
+        // a huge rate is an error rather than a panic
+        assert_eq!(
+            tax_on(
+                Money::from_cents(i64::MAX),
+                percent("1000000"),
+            )
+            .unwrap_err()
+            .message()
+            .clone()
+            .unwrap(),
+            format!(
+                "tax on {} at 1000000% is too large",
+                Money::from_cents(i64::MAX)
+            )
+        );
+    }
+
+    // Setting a charge's amount or tax rate with `set` works its tax out
+    // again, so it never goes stale.
+    #[test]
+    fn test_set_refreshes_tax() -> dolmen::Result<()> {
+        let mut context = Context::new();
+        context
+            .add_plugin(DbPlugin)?
+            .add_plugin(db_commands::DbCommandsPlugin)?
+            .add_plugin(crate::BillingPlugin)?
+            .add_plugin(training::TrainingPlugin)?;
+        context
+            .get_resource_mut::<DbConfig>()
+            .unwrap()
+            .open_db_in_memory = true;
+        context.startup()?;
+
+        add_tax_rate(
+            context.db_connection()?,
+            "Sales tax",
+            percent("8.25"),
+        )?;
+        context.execute("new --table=charge")?;
+        let tax = |context: &mut Context| {
+            context.db_connection()?.get_field_in_table_row::<
+                Option<Money>,
+            >("charge", RowId(1), "tax")
+        };
+
+        // no tax until the charge has both an amount and a rate
+        context.execute(
+            "set --table=charge --row-id=1 --field=tax_rate \
+                --value=1",
+        )?;
+        assert_eq!(tax(&mut context)?, None);
+
+        context.execute(
+            "set --table=charge --row-id=1 --field=amount \
+                --value=60.00",
+        )?;
+        assert_eq!(
+            tax(&mut context)?,
+            Some(Money::from_cents(495))
+        );
+
+        context.execute(
+            "set --table=charge --row-id=1 --field=amount \
+                --value=70.00",
+        )?;
+        assert_eq!(
+            tax(&mut context)?,
+            Some(Money::from_cents(578))
+        );
+
+        Ok(())
+    }
+
+    // Imported charges get their tax worked out too.
+    #[test]
+    fn test_import_refreshes_tax() -> dolmen::Result<()>
+    {
+        let mut context = Context::new();
+        context
+            .add_plugin(DbPlugin)?
+            .add_plugin(db_commands::DbCommandsPlugin)?
+            .add_plugin(crate::BillingPlugin)?
+            .add_plugin(training::TrainingPlugin)?;
+        context
+            .get_resource_mut::<DbConfig>()
+            .unwrap()
+            .open_db_in_memory = true;
+        context.startup()?;
+
+        let db_connection = context.db_connection()?;
+        add_tax_rate(
+            db_connection,
+            "Sales tax",
+            percent("8.25"),
+        )?;
+        crate::test_util::add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+
+        let dir = std::env::temp_dir().join(
+            "training_assistant_test_import_tax",
+        );
+        std::fs::create_dir_all(&dir).unwrap();
+        let charges = dir.join("charges.csv");
+        std::fs::write(
+            &charges,
+            "date,description,amount,client,tax_rate\n\
+            2026-06-01,Session,60.00,Clarissa,1\n",
+        )
+        .unwrap();
+        context.execute(&format!(
+            "import --table=charge --file={}",
+            charges.display()
+        ))?;
+
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_field_in_table_row::<Option<Money>>(
+                    "charge",
+                    RowId(1),
+                    "tax",
+                )?,
+            Some(Money::from_cents(495))
+        );
+
+        Ok(())
+    }
+
+    // Two taxed charges, one of them voided, and an untaxed one.
+    #[test]
+    fn test_tax_report() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        let sales_tax = add_tax_rate(
+            db_connection,
+            "Sales tax",
+            percent("8.25"),
+        )?;
+        let service = crate::add_service(
+            db_connection,
+            "Personal training session (60 min)",
+            Some(60),
+            Money::from_dollars(60)?,
+            Some(sales_tax),
+            None,
+        )?;
+
+        let mut charges = Vec::new();
+        for day in ["2026-04-01", "2026-04-08"] {
+            charges.push(crate::charge_service(
+                db_connection,
+                client,
+                service,
+                date(day),
+                None,
+                None,
+            )?);
+        }
+        crate::void_charge(
+            db_connection,
+            charges[1],
+            date("2026-04-09"),
+            "booked twice",
+        )?;
+        crate::sell_package(
+            db_connection,
+            client,
+            5,
+            Money::from_dollars(250)?,
+            date("2026-04-01"),
+            None,
+        )?;
+
+        assert!(
+            set_charge_tax_rate(
+                db_connection,
+                charges[1],
+                None
+            )
+            .is_err()
+        );
+
+        assert_eq!(
+            tax_report(
+                db_connection,
+                date("2026-04-01"),
+                date("2026-04-30")
+            )?,
+            vec![TaxReportLine {
+                tax_rate: sales_tax,
+                name: "Sales tax".into(),
+                rate: percent("8.25"),
+                taxable_amount: Money::from_dollars(
+                    60
+                )?,
+                tax: Money::from_cents(495),
+            }]
+        );
+
+        let response = context.execute(
+            "tax report --from=2026-04-01 --to=2026-04-08",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Tax billed from 2026-04-01 to 2026-04-08:\n\
+                +-----------+-------+---------+------------+\n\
+                | Tax       | Rate  | Taxable | Tax billed |\n\
+                +-----------+-------+---------+------------+\n\
+                | Sales tax | 8.25% | $120.00 | $9.90      |\n\
+                +-----------+-------+---------+------------+\n\
+                | Total     |       | $120.00 | $9.90      |\n\
+                +-----------+-------+---------+------------+"
+        );
+
+        Ok(())
+    }
+}
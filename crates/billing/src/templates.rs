@@ -0,0 +1,536 @@
+//! Invoice templates, and the `invoice template` command.
+//!
+//! An invoice is written by filling a template in with the variables in
+//! `INVOICE_VARIABLES` (see `documents::Template` for the syntax). The
+//! template used is the first of:
+//!
+//! 1. the file at the trainer's `invoice_template` path, if it's for the
+//!    format being written (by its extension: `.tex`, `.html` or `.txt`),
+//! 2. `invoice.tex`, `invoice.html` or `invoice.txt` in the template
+//!    directory (`templates` in the config directory, unless changed in
+//!    `TemplateConfig`),
+//! 3. the built-in template.
+use crate::latex_backend;
+use clap::{Arg, ArgMatches, Command};
+use documents::{
+    DataTable, DocumentBackend, DocumentData,
+    HtmlBackend, Template, TemplateError, TextBackend,
+};
+use dolmen::prelude::*;
+use std::path::{Path, PathBuf};
+use training::Trainer;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A variable available to invoice templates.
+#[derive(Clone, Copy, Debug)]
+pub struct TemplateVariable {
+    /// The name used in templates (e.g. `clientname` for
+    /// `{{clientname}}`).
+    pub name: &'static str,
+
+    /// What the variable holds.
+    pub description: &'static str,
+
+    /// An example value, used to check templates.
+    pub example: &'static str,
+}
+
+/// The variables available to invoice templates. Amounts are decimal
+/// numbers without a currency symbol (e.g. `52.50`), and dates are
+/// `YYYY-MM-DD`.
+pub const INVOICE_VARIABLES: &[TemplateVariable] = &[
+    TemplateVariable {
+        name: "trainername",
+        description: "The trainer's name",
+        example: "Tara Trainer",
+    },
+    TemplateVariable {
+        name: "companyname",
+        description: "The trainer's company name",
+        example: "Tara Fitness",
+    },
+    TemplateVariable {
+        name: "companyaddress",
+        description: "The trainer's address",
+        example: "2127 Xanthia St, Denver, CO 80220",
+    },
+    TemplateVariable {
+        name: "companyemail",
+        description: "The trainer's email address",
+        example: "tara@example.com",
+    },
+    TemplateVariable {
+        name: "companyphone",
+        description: "The trainer's phone number",
+        example: "(303) 555-0100",
+    },
+    TemplateVariable {
+        name: "logopath",
+        description: "The path of the trainer's logo, or empty if they \
+            have none. Use {{&logopath}} in LaTeX templates so the path \
+            isn't escaped; documents can't be made if the logo doesn't \
+            exist or its path has any of {}\\%#",
+        example: "logo.png",
+    },
+    TemplateVariable {
+        name: "clientname",
+        description: "The client's name",
+        example: "Clarissa Client",
+    },
+    TemplateVariable {
+        name: "invoicenumber",
+        description: "The receipt number of the payment",
+        example: "2026-0001",
+    },
+    TemplateVariable {
+        name: "paymentmade",
+        description: "The date of the payment",
+        example: "2026-01-04",
+    },
+    TemplateVariable {
+        name: "paidvia",
+        description: "How the payment was made (e.g. Cash)",
+        example: "Cash",
+    },
+    TemplateVariable {
+        name: "lastpayment",
+        description: "The date of the client's previous payment, or \
+            empty if this is their first",
+        example: "2025-12-28",
+    },
+    TemplateVariable {
+        name: "currency",
+        description: "The currency code of the amounts (e.g. USD)",
+        example: "USD",
+    },
+    TemplateVariable {
+        name: "subtotal",
+        description: "The balance owed before the payment",
+        example: "95.00",
+    },
+    TemplateVariable {
+        name: "taxtotal",
+        description: "The total tax on the charges covered by the \
+            payment",
+        example: "4.95",
+    },
+    TemplateVariable {
+        name: "paymentamount",
+        description: "The amount paid",
+        example: "95.00",
+    },
+    TemplateVariable {
+        name: "balancestart",
+        description: "The balance owed as of the previous payment",
+        example: "45.00",
+    },
+    TemplateVariable {
+        name: "balanceend",
+        description: "The balance owed after the payment",
+        example: "0.00",
+    },
+    TemplateVariable {
+        name: "creditsremaining",
+        description: "The number of package session credits the client \
+            has left",
+        example: "3",
+    },
+];
+
+/// The name of the table of charges available to invoice templates, for
+/// use with `{{#each chargedata}}`.
+pub const CHARGE_TABLE: &str = "chargedata";
+
+/// The columns of each row of `CHARGE_TABLE`: one row per charge covered
+/// by the payment.
+pub const CHARGE_COLUMNS: &[TemplateVariable] = &[
+    TemplateVariable {
+        name: "date",
+        description: "The date of the charge",
+        example: "2026-01-04",
+    },
+    TemplateVariable {
+        name: "description",
+        description: "The description of the charge",
+        example: "Personal training session (60 min)",
+    },
+    TemplateVariable {
+        name: "amount",
+        description: "The amount of the charge, before tax",
+        example: "50.00",
+    },
+];
+
+/// The name of the table of tax subtotals available to invoice templates,
+/// for use with `{{#each taxdata}}`.
+pub const TAX_TABLE: &str = "taxdata";
+
+/// The columns of each row of `TAX_TABLE`: one row per tax rate the
+/// charges covered by the payment are taxed at. Empty if none are taxed.
+pub const TAX_COLUMNS: &[TemplateVariable] = &[
+    TemplateVariable {
+        name: "name",
+        description: "The name and rate of the tax",
+        example: "Sales tax (8.25%)",
+    },
+    TemplateVariable {
+        name: "amount",
+        description: "The tax charged at the rate",
+        example: "4.95",
+    },
+];
+
+/// A resource storing where to look for invoice templates.
+#[derive(Resource)]
+pub struct TemplateConfig {
+    /// The directory holding `invoice.tex`, `invoice.html` and
+    /// `invoice.txt`, or `None` to only use trainers' and built-in
+    /// templates.
+    pub template_dir: Option<PathBuf>,
+}
+
+impl Default for TemplateConfig {
+    fn default() -> Self {
+        Self {
+            template_dir:
+                directories::ProjectDirs::from(
+                    "",
+                    "",
+                    "training_assistant",
+                )
+                .map(|dirs| {
+                    dirs.config_dir().join("templates")
+                }),
+        }
+    }
+}
+
+/// Checks that an invoice template parses and only uses the variables in
+/// `INVOICE_VARIABLES`, `CHARGE_COLUMNS` and `TAX_COLUMNS`, in every part
+/// of it (see `Template::check`).
+///
+/// * `template` - The text of the template.
+pub fn check_invoice_template(
+    template: &str,
+) -> Result<(), TemplateError> {
+    Template::parse(template)?
+        .check(&example_invoice_data())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// The formats `invoice generate` can write an invoice in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum InvoiceFormat {
+    /// A PDF, compiled with `pdflatex`.
+    Latex,
+
+    /// A self-contained HTML page.
+    Html,
+
+    /// Plain text.
+    Text,
+}
+
+impl InvoiceFormat {
+    /// Gets the extension of templates for the format.
+    fn template_extension(&self) -> &'static str {
+        match self {
+            InvoiceFormat::Latex => "tex",
+            InvoiceFormat::Html => "html",
+            InvoiceFormat::Text => "txt",
+        }
+    }
+
+    /// Gets the format a template file is for from its extension.
+    ///
+    /// * `path` - The path of the template.
+    fn from_template_path(
+        path: &Path,
+    ) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "tex" => Some(InvoiceFormat::Latex),
+            "html" | "htm" => {
+                Some(InvoiceFormat::Html)
+            }
+            "txt" => Some(InvoiceFormat::Text),
+            _ => None,
+        }
+    }
+
+    /// Gets the built-in template for the format.
+    fn builtin_template(&self) -> &'static str {
+        match self {
+            InvoiceFormat::Latex => {
+                include_str!("invoice_template.tex")
+            }
+            InvoiceFormat::Html => {
+                include_str!("invoice_template.html")
+            }
+            InvoiceFormat::Text => {
+                include_str!("invoice_template.txt")
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for InvoiceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "latex" | "pdf" => {
+                Ok(InvoiceFormat::Latex)
+            }
+            "html" => Ok(InvoiceFormat::Html),
+            "text" | "txt" => Ok(InvoiceFormat::Text),
+            _ => Err(format!(
+                "unknown invoice format {} (expected latex, html or \
+                    text)",
+                s
+            )),
+        }
+    }
+}
+
+/// Loads the template to write a trainer's invoices in a given format with.
+///
+/// * `template_dir` - The directory to look for templates in, if any.
+/// * `trainer` - The trainer whose invoice is being written.
+/// * `format` - The format being written.
+pub(crate) fn load_invoice_template(
+    template_dir: Option<&Path>,
+    trainer: &Trainer,
+    format: InvoiceFormat,
+) -> dolmen::Result<String> {
+    if let Some(path) = trainer.invoice_template() {
+        let path = Path::new(path);
+        if InvoiceFormat::from_template_path(path)
+            == Some(format)
+        {
+            return read_template(path);
+        }
+    }
+
+    if let Some(template_dir) = template_dir {
+        let path = template_dir.join(format!(
+            "invoice.{}",
+            format.template_extension()
+        ));
+        if path.exists() {
+            return read_template(&path);
+        }
+    }
+
+    Ok(format.builtin_template().to_string())
+}
+
+/// Gets the backend that writes invoices in a given format.
+///
+/// * `format` - The format to write.
+/// * `template` - The text of the template to fill in.
+pub(crate) fn invoice_backend(
+    format: InvoiceFormat,
+    template: String,
+) -> Box<dyn DocumentBackend> {
+    match format {
+        InvoiceFormat::Latex => {
+            Box::new(latex_backend(template))
+        }
+        InvoiceFormat::Html => {
+            Box::new(HtmlBackend::new(template))
+        }
+        InvoiceFormat::Text => {
+            Box::new(TextBackend::new(template))
+        }
+    }
+}
+
+fn read_template(
+    path: &Path,
+) -> dolmen::Result<String> {
+    std::fs::read_to_string(path).map_err(|e| {
+        dolmen::Error::new(format!(
+            "failed to read invoice template {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+/// Creates invoice data holding the example value of every variable.
+fn example_invoice_data() -> DocumentData {
+    let mut data = DocumentData::default();
+    for variable in INVOICE_VARIABLES {
+        data.set(variable.name, variable.example);
+    }
+    let mut charges = DataTable::new(
+        CHARGE_COLUMNS
+            .iter()
+            .map(|column| column.name),
+    );
+    charges.push_row(
+        CHARGE_COLUMNS
+            .iter()
+            .map(|column| column.example),
+    );
+    data.set_table(CHARGE_TABLE, charges);
+    let mut taxes = DataTable::new(
+        TAX_COLUMNS.iter().map(|column| column.name),
+    );
+    taxes.push_row(
+        TAX_COLUMNS
+            .iter()
+            .map(|column| column.example),
+    );
+    data.set_table(TAX_TABLE, taxes);
+    data
+}
+
+/// Creates the `template` subcommand of the `invoice` command.
+pub(crate) fn template_command() -> Command {
+    Command::new("template")
+        .about("Invoice template related commands")
+        .subcommand(Command::new("check")
+            .about("Checks that an invoice template is valid")
+            .arg(Arg::new("file")
+                .long("file")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("The template file to check")
+            )
+        )
+        .subcommand(Command::new("variables")
+            .about("Lists the variables invoice templates can use")
+        )
+        .subcommand_required(true)
+}
+
+/// Processes the `template` subcommand of the `invoice` command.
+pub(crate) fn process_invoice_template_command(
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    match arg_matches.subcommand() {
+        Some(("check", sub_m)) => {
+            process_template_check_command(sub_m)
+        }
+        Some(("variables", _)) => {
+            Ok(CommandResponse::new(variables_text()))
+        }
+        _ => Err(dolmen::Error::new(
+            "subcommand not recognized",
+        )),
+    }
+}
+
+/// Processes the `check` subcommand of `invoice template`.
+fn process_template_check_command(
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let path = arg_matches
+        .get_one::<PathBuf>("file")
+        .expect("Missing required argument");
+
+    let format = InvoiceFormat::from_template_path(path)
+        .ok_or(dolmen::Error::new(format!(
+            "can't tell the format of template {} (expected a .tex, \
+                .html or .txt file)",
+            path.display()
+        )))?;
+
+    check_invoice_template(&read_template(path)?)
+        .map_err(|e| {
+            dolmen::Error::new(format!(
+                "invalid invoice template {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+    Ok(CommandResponse::new(format!(
+        "Invoice template {} is valid ({} format).",
+        path.display(),
+        format.template_extension()
+    )))
+}
+
+/// Lists the variables available to invoice templates.
+fn variables_text() -> String {
+    let mut text =
+        String::from("Invoice template variables:");
+    for variable in INVOICE_VARIABLES {
+        text += &format!(
+            "\n  {} - {}",
+            variable.name, variable.description
+        );
+    }
+    text += &format!(
+        "\n  {} - A table of the charges the payment covers, with \
+            columns:",
+        CHARGE_TABLE
+    );
+    for column in CHARGE_COLUMNS {
+        text += &format!(
+            "\n    {} - {}",
+            column.name, column.description
+        );
+    }
+    text += &format!(
+        "\n  {} - A table of the tax on those charges, a row per tax \
+            rate, with columns:",
+        TAX_TABLE
+    );
+    for column in TAX_COLUMNS {
+        text += &format!(
+            "\n    {} - {}",
+            column.name, column.description
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_builtin_templates()
+    -> Result<(), TemplateError> {
+        check_invoice_template(include_str!(
+            "invoice_template.tex"
+        ))?;
+        check_invoice_template(include_str!(
+            "invoice_template.html"
+        ))?;
+        check_invoice_template(include_str!(
+            "invoice_template.txt"
+        ))?;
+
+        assert_eq!(
+            check_invoice_template(
+                "{{clientname}}\n{{clientnmae}}"
+            )
+            .unwrap_err()
+            .to_string(),
+            "line 2: unknown variable: clientnmae"
+        );
+        // the example data has a logo, but templates are checked whether
+        // or not a trainer has one
+        assert_eq!(
+            check_invoice_template(
+                "{{#if logopath}}{{&logopath}}{{else}}\n\
+                    {{companynmae}}{{/if}}"
+            )
+            .unwrap_err()
+            .to_string(),
+            "line 2: unknown variable: companynmae"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! Fixtures shared by the billing tests.
+use crate::{
+    BillingPlugin, Money, TemplateConfig,
+    allocate_receipt_number,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use training::{NewSession, TrainingPlugin};
+
+/// Sets up a context with the billing and training plugins and an
+/// in-memory database.
+pub(crate) fn setup_test_context()
+-> dolmen::Result<Context> {
+    let mut context = Context::new();
+
+    context
+        .add_plugin(DbPlugin)?
+        .add_plugin(BillingPlugin)?
+        .add_plugin(TrainingPlugin)?;
+
+    context
+        .get_resource_mut::<DbConfig>()
+        .unwrap()
+        .open_db_in_memory = true;
+
+    // don't pick up templates from the config dir
+    context
+        .get_resource_mut::<TemplateConfig>()
+        .unwrap()
+        .template_dir = None;
+
+    context.startup()?;
+
+    Ok(context)
+}
+
+/// Parses a date (e.g. `"2026-01-04"`).
+pub(crate) fn date(text: &str) -> NaiveDate {
+    text.parse().unwrap()
+}
+
+/// Parses a date and time (e.g. `"2026-01-04T09:00:00"`).
+pub(crate) fn date_time(text: &str) -> NaiveDateTime {
+    text.parse().unwrap()
+}
+
+/// Adds a row with a name to a table (e.g. a trainer or client).
+pub(crate) fn add_test_row(
+    db_connection: &mut DbConnection,
+    table: &str,
+    name: &str,
+) -> dolmen::Result<RowId> {
+    let row = db_connection.new_row_in_table(table)?;
+    db_connection.set_field_in_table(
+        table, row, "name", name,
+    )?;
+    Ok(row)
+}
+
+/// Adds a trainer with every detail invoices and receipts show.
+pub(crate) fn add_test_trainer(
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<RowId> {
+    let trainer =
+        db_connection.new_row_in_table("trainer")?;
+    db_connection.set_field_in_table(
+        "trainer",
+        trainer,
+        "name",
+        "Tara Trainer",
+    )?;
+    db_connection.set_field_in_table(
+        "trainer",
+        trainer,
+        "company_name",
+        "Tara Fitness",
+    )?;
+    db_connection.set_field_in_table(
+        "trainer",
+        trainer,
+        "address",
+        "2127 Xanthia St, Denver, CO 80220",
+    )?;
+    db_connection.set_field_in_table(
+        "trainer",
+        trainer,
+        "email",
+        "tara@gmail.com",
+    )?;
+    db_connection.set_field_in_table(
+        "trainer",
+        trainer,
+        "phone",
+        "(303) 175-3098",
+    )?;
+    Ok(trainer)
+}
+
+/// Adds a charge of a whole number of dollars for a session.
+pub(crate) fn add_test_charge(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    date: &str,
+    dollars: i64,
+) -> dolmen::Result<RowId> {
+    let charge =
+        db_connection.new_row_in_table("charge")?;
+    db_connection.set_field_in_table(
+        "charge", charge, "date", date,
+    )?;
+    db_connection.set_field_in_table(
+        "charge",
+        charge,
+        "description",
+        "Personal training session (60 min)",
+    )?;
+    db_connection.set_field_in_table(
+        "charge",
+        charge,
+        "amount",
+        Money::from_dollars(dollars)?,
+    )?;
+    db_connection.set_field_in_table(
+        "charge", charge, "client", client.0,
+    )?;
+    Ok(charge)
+}
+
+/// Adds a cash payment of a whole number of dollars.
+pub(crate) fn add_test_payment(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    trainer: RowId,
+    date: &str,
+    dollars: i64,
+) -> dolmen::Result<RowId> {
+    let payment =
+        db_connection.new_row_in_table("payment")?;
+    db_connection.set_field_in_table(
+        "payment", payment, "date", date,
+    )?;
+    db_connection.set_field_in_table(
+        "payment", payment, "client", client.0,
+    )?;
+    db_connection.set_field_in_table(
+        "payment", payment, "trainer", trainer.0,
+    )?;
+    db_connection.set_field_in_table(
+        "payment",
+        payment,
+        "amount",
+        Money::from_dollars(dollars)?,
+    )?;
+    db_connection.set_field_in_table(
+        "payment", payment, "paid_via", "Cash",
+    )?;
+    Ok(payment)
+}
+
+/// Adds a cash payment like `add_test_payment`, numbered as recording it
+/// with `set` would.
+pub(crate) fn add_test_receipt(
+    db_connection: &mut DbConnection,
+    client: RowId,
+    trainer: RowId,
+    date: &str,
+    dollars: i64,
+) -> dolmen::Result<RowId> {
+    let payment = add_test_payment(
+        db_connection,
+        client,
+        trainer,
+        date,
+        dollars,
+    )?;
+    allocate_receipt_number(db_connection, payment)?;
+    Ok(payment)
+}
+
+/// Adds an hourly rate of a whole number of dollars, for a client, a
+/// trainer or, with neither, everyone.
+pub(crate) fn add_test_rate(
+    db_connection: &mut DbConnection,
+    client: Option<RowId>,
+    trainer: Option<RowId>,
+    dollars: i64,
+) -> dolmen::Result<RowId> {
+    let rate =
+        db_connection.new_row_in_table("rate")?;
+    if let Some(client) = client {
+        db_connection.set_field_in_table(
+            "rate", rate, "client", client.0,
+        )?;
+    }
+    if let Some(trainer) = trainer {
+        db_connection.set_field_in_table(
+            "rate", rate, "trainer", trainer.0,
+        )?;
+    }
+    db_connection.set_field_in_table(
+        "rate",
+        rate,
+        "hourly_rate",
+        Money::from_dollars(dollars)?,
+    )?;
+    Ok(rate)
+}
+
+/// Schedules a session at the gym.
+pub(crate) fn add_test_session(
+    db_connection: &mut DbConnection,
+    trainer: RowId,
+    client: RowId,
+    starts: NaiveDateTime,
+    duration_minutes: u32,
+) -> dolmen::Result<RowId> {
+    training::schedule_session(
+        db_connection,
+        &NewSession {
+            date: starts.date(),
+            start_time: starts.time(),
+            duration_minutes,
+            location: "Gym".into(),
+            trainer,
+            client,
+        },
+    )
+}
@@ -0,0 +1,1108 @@
+//! Voids, refunds and the late-cancellation policy.
+//!
+//! Charges and payments on a receipt should be reversed rather than deleted
+//! or changed, so the receipt still adds up. A void is a new charge for the
+//! negative amount of the charge it reverses, and a refund is a new payment
+//! for the negative amount paid back. Both record the entry they reverse and
+//! a reason, and show up on the next receipt and on statements like any
+//! other entry.
+use crate::services::session_price;
+use crate::session_charges::{
+    charge_created_text, create_session_charge,
+    is_billed, use_credit_for_session,
+};
+use crate::{
+    Money, get_charge, get_money, money, set_money,
+    sql_error,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use clap::{Arg, ArgMatches, Command};
+use db_commands::{
+    CommandOutputContextExt, OutputRows,
+    with_transaction,
+};
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use training::{Session, SessionStatus};
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// The notice, in hours, a client has to give when cancelling a session to
+/// not be billed for it.
+pub const LATE_CANCELLATION_HOURS: i64 = 24;
+
+/// Voids a charge by adding a charge for the negative amount, and the
+/// negative of its tax. Returns the row ID of the new charge.
+///
+/// Fails if the charge is a void itself or has already been voided.
+///
+/// * `db_connection` - A connection to the database.
+/// * `charge_row_id` - The row ID of the charge to void.
+/// * `date` - The date of the void.
+/// * `reason` - Why the charge is voided.
+pub fn void_charge(
+    db_connection: &mut DbConnection,
+    charge_row_id: RowId,
+    date: NaiveDate,
+    reason: &str,
+) -> dolmen::Result<RowId> {
+    let charge =
+        get_charge(db_connection, charge_row_id)?;
+    if let Some(voided) = charge.voids {
+        return Err(dolmen::Error::new(format!(
+            "charge {} is the void of charge {}, it can't be voided",
+            charge_row_id.0, voided.0
+        )));
+    }
+    if let Some(void) =
+        find_void(db_connection, charge_row_id)?
+    {
+        return Err(dolmen::Error::new(format!(
+            "charge {} has already been voided (charge {})",
+            charge_row_id.0, void.0
+        )));
+    }
+
+    with_transaction(db_connection, |db_connection| {
+        let void = db_connection
+            .new_row_in_table("charge")?;
+        db_connection.set_field_in_table(
+            "charge", void, "date", date,
+        )?;
+        db_connection.set_field_in_table(
+            "charge",
+            void,
+            "description",
+            format!(
+                "Void: {} ({})",
+                charge.description, reason
+            ),
+        )?;
+        set_money(
+            db_connection,
+            "charge",
+            void,
+            "amount",
+            Some(charge.amount.checked_neg()?),
+        )?;
+        db_connection.set_field_in_table(
+            "charge",
+            void,
+            "client",
+            charge.client.0,
+        )?;
+        db_connection.set_field_in_table(
+            "charge",
+            void,
+            "voids",
+            charge_row_id.0,
+        )?;
+        db_connection.set_field_in_table(
+            "charge", void, "reason", reason,
+        )?;
+        // the void cancels the charge's tax at the same rate
+        if let Some(tax_rate) = charge.tax_rate {
+            db_connection.set_field_in_table(
+                "charge", void, "tax_rate", tax_rate.0,
+            )?;
+        }
+        if let Some(tax) = charge.tax {
+            set_money(
+                db_connection,
+                "charge",
+                void,
+                "tax",
+                Some(tax.checked_neg()?),
+            )?;
+        }
+        Ok(void)
+    })
+}
+
+/// Refunds a payment, in full or in part, by adding a payment for the
+/// negative amount paid back. Returns the row ID of the new payment.
+///
+/// Fails if the payment is a refund itself, or if the amount is zero,
+/// negative, in a different currency than the payment, or more than is
+/// left to refund of the payment.
+///
+/// * `db_connection` - A connection to the database.
+/// * `payment_row_id` - The row ID of the payment to refund.
+/// * `amount` - The amount to pay back, or `None` for everything that
+///   hasn't been refunded yet.
+/// * `date` - The date of the refund.
+/// * `reason` - Why the payment is refunded.
+pub fn refund_payment(
+    db_connection: &mut DbConnection,
+    payment_row_id: RowId,
+    amount: Option<Money>,
+    date: NaiveDate,
+    reason: &str,
+) -> dolmen::Result<RowId> {
+    let connection = db_connection.connection()?;
+    let (trainer, client, paid, paid_via, refunds) =
+        connection
+            .query_row(
+                &format!(
+                    "SELECT trainer, client, {},
+                            COALESCE(paid_via, ''), refunds
+                        FROM payment WHERE id = ?1",
+                    money::money_sql("amount")
+                ),
+                [payment_row_id.0],
+                |r| {
+                    Ok((
+                        r.get::<_, i64>(0)?,
+                        r.get::<_, i64>(1)?,
+                        money::read_money(r, 2)?,
+                        r.get::<_, String>(4)?,
+                        r.get::<_, Option<i64>>(5)?,
+                    ))
+                },
+            )
+            .map_err(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => {
+                    dolmen::Error::new(format!(
+                        "no payment with ID {}",
+                        payment_row_id.0
+                    ))
+                }
+                e => sql_error(e),
+            })?;
+    if let Some(refunded) = refunds {
+        return Err(dolmen::Error::new(format!(
+            "payment {} is a refund of payment {}, it can't be refunded",
+            payment_row_id.0, refunded
+        )));
+    }
+
+    // refunds are negative payments
+    let refunded = connection
+        .query_row(
+            &format!(
+                "SELECT {} FROM payment WHERE refunds = ?1",
+                money::total_sql("amount")
+            ),
+            [payment_row_id.0],
+            |r| money::read_total(r, 0),
+        )
+        .map_err(sql_error)?;
+    let refundable = paid.checked_add(refunded)?;
+    let amount = amount.unwrap_or(refundable);
+    if amount.currency() != paid.currency() {
+        return Err(dolmen::Error::new(format!(
+            "can't refund {} of payment {}, it was paid in {}",
+            amount,
+            payment_row_id.0,
+            paid.currency().code()
+        )));
+    }
+    if amount.cents() <= 0 {
+        return Err(dolmen::Error::new(format!(
+            "nothing to refund of payment {} ({} refundable)",
+            payment_row_id.0, refundable
+        )));
+    }
+    if amount.cents() > refundable.cents() {
+        return Err(dolmen::Error::new(format!(
+            "can't refund {} of payment {}, only {} is refundable",
+            amount, payment_row_id.0, refundable
+        )));
+    }
+
+    with_transaction(db_connection, |db_connection| {
+        let refund = db_connection
+            .new_row_in_table("payment")?;
+        db_connection.set_field_in_table(
+            "payment", refund, "date", date,
+        )?;
+        db_connection.set_field_in_table(
+            "payment", refund, "trainer", trainer,
+        )?;
+        db_connection.set_field_in_table(
+            "payment", refund, "client", client,
+        )?;
+        set_money(
+            db_connection,
+            "payment",
+            refund,
+            "amount",
+            Some(amount.checked_neg()?),
+        )?;
+        db_connection.set_field_in_table(
+            "payment", refund, "paid_via", paid_via,
+        )?;
+        db_connection.set_field_in_table(
+            "payment",
+            refund,
+            "refunds",
+            payment_row_id.0,
+        )?;
+        db_connection.set_field_in_table(
+            "payment", refund, "reason", reason,
+        )?;
+        Ok(refund)
+    })
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// A SQL expression describing a refund `r` on receipts and statements,
+/// given its original payment `o`
+/// (e.g. `Refund of receipt 2026-0001 (session cancelled)`).
+pub(crate) const REFUND_DESCRIPTION: &str = "'Refund of '
+    || COALESCE('receipt ' || NULLIF(o.receipt_number, ''),
+        'payment ' || r.refunds)
+    || COALESCE(' (' || r.reason || ')', '')";
+
+/// Describes a refund for a receipt. See `REFUND_DESCRIPTION`.
+pub(crate) fn refund_description(
+    connection: &rusqlite::Connection,
+    refund: RowId,
+) -> dolmen::Result<String> {
+    connection
+        .query_row(
+            &format!(
+                "SELECT {} FROM payment r
+                    LEFT JOIN payment o ON o.id = r.refunds
+                    WHERE r.id = ?1",
+                REFUND_DESCRIPTION
+            ),
+            [refund.0],
+            |r| r.get::<_, String>(0),
+        )
+        .map_err(sql_error)
+}
+
+/// Gets the charge voiding a charge, or `None` if it hasn't been voided.
+pub(crate) fn find_void(
+    db_connection: &mut DbConnection,
+    charge: RowId,
+) -> dolmen::Result<Option<RowId>> {
+    let connection = db_connection.connection()?;
+    connection
+        .query_row(
+            "SELECT id FROM charge WHERE voids = ?1
+                ORDER BY id LIMIT 1",
+            [charge.0],
+            |r| r.get::<_, i64>(0),
+        )
+        .map(|c| Some(RowId(c)))
+        .or_else(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => {
+                Ok(None)
+            }
+            e => Err(e),
+        })
+        .map_err(sql_error)
+}
+
+/// A `training::SessionStatusHook` that applies the late-cancellation
+/// policy when a session is cancelled. A session cancelled at least
+/// `LATE_CANCELLATION_HOURS` before it starts isn't billed: its charge is
+/// voided on the day of the cancellation, or the package credit it used is
+/// given back. A session cancelled later than that is billed as if it took
+/// place, using up a package credit or charged at the applicable rate.
+pub(crate) fn apply_cancellation_policy(
+    db_connection: &mut DbConnection,
+    session_row_id: RowId,
+    status: SessionStatus,
+    cancelled_at: NaiveDateTime,
+) -> dolmen::Result<Option<String>> {
+    if status != SessionStatus::Cancelled {
+        return Ok(None);
+    }
+
+    let session = Session::from_table_row(
+        db_connection,
+        "session".into(),
+        session_row_id,
+    )?;
+    let starts =
+        session.date().and_time(session.start_time());
+    let in_time = starts - cancelled_at
+        >= chrono::TimeDelta::hours(
+            LATE_CANCELLATION_HOURS,
+        );
+
+    if in_time {
+        unbill_session(
+            db_connection,
+            session_row_id,
+            &session,
+            cancelled_at.date(),
+        )
+    } else {
+        bill_late_cancellation(
+            db_connection,
+            session_row_id,
+            &session,
+        )
+    }
+}
+
+/// Takes back the billing of a session cancelled in time: voids its charge
+/// or gives back its package credit.
+fn unbill_session(
+    db_connection: &mut DbConnection,
+    session_row_id: RowId,
+    session: &Session,
+    cancelled_on: NaiveDate,
+) -> dolmen::Result<Option<String>> {
+    if let Some(charge) = session.charge() {
+        if find_void(db_connection, charge)?.is_some()
+        {
+            return Ok(None);
+        }
+        let void = void_charge(
+            db_connection,
+            charge,
+            cancelled_on,
+            &format!(
+                "session cancelled at least {} hours in advance",
+                LATE_CANCELLATION_HOURS
+            ),
+        )?;
+        return Ok(Some(format!(
+            "Voided charge {} of session {} (charge {}).",
+            charge.0, session_row_id.0, void.0
+        )));
+    }
+
+    let connection = db_connection.connection()?;
+    let returned = connection
+        .execute(
+            "DELETE FROM credit_use WHERE session = ?1",
+            [session_row_id.0],
+        )
+        .map_err(sql_error)?;
+    if returned == 0 {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "Gave back the package credit used by session {}.",
+        session_row_id.0
+    )))
+}
+
+/// Bills a session cancelled too late, unless it's been billed already.
+fn bill_late_cancellation(
+    db_connection: &mut DbConnection,
+    session_row_id: RowId,
+    session: &Session,
+) -> dolmen::Result<Option<String>> {
+    if is_billed(
+        db_connection,
+        session_row_id,
+        session,
+    )? {
+        return Ok(Some(format!(
+            "Session {} was cancelled less than {} hours in advance, so \
+                it stays billed.",
+            session_row_id.0, LATE_CANCELLATION_HOURS
+        )));
+    }
+    if let Some(text) = use_credit_for_session(
+        db_connection,
+        session_row_id,
+        session,
+    )? {
+        return Ok(Some(format!(
+            "Late cancellation: {}",
+            text
+        )));
+    }
+    if session_price(db_connection, session)?.is_none()
+    {
+        return Ok(Some(format!(
+            "Warning: session {} was cancelled late, but no service \
+                or rate applies to it, so it wasn't charged.",
+            session_row_id.0
+        )));
+    }
+
+    let charge = create_session_charge(
+        db_connection,
+        session_row_id,
+        session,
+        Some(format!(
+            "Late cancellation ({} min session)",
+            session.duration_minutes()
+        )),
+    )?;
+    Ok(Some(format!(
+        "Late cancellation: {}",
+        charge_created_text(
+            db_connection,
+            charge,
+            session_row_id,
+        )?
+    )))
+}
+
+/// Creates the `void` subcommand of the `billing` command.
+pub(crate) fn void_command() -> Command {
+    crate::structured(Command::new("void")
+        .about("Voids a charge by adding a reversing charge")
+        .arg(Arg::new("charge-id")
+            .long("charge-id")
+            .value_parser(clap::value_parser!(i64))
+            .required(true)
+            .help("The charge row ID to void.")
+        )
+        .arg(Arg::new("reason")
+            .long("reason")
+            .required(true)
+            .help("Why the charge is voided")
+        )
+        .arg(Arg::new("date")
+            .long("date")
+            .value_parser(clap::value_parser!(NaiveDate))
+            .help("The date of the void (YYYY-MM-DD). Defaults to \
+                today.")
+        ))
+}
+
+/// Creates the `refund` subcommand of the `billing` command.
+pub(crate) fn refund_command() -> Command {
+    crate::structured(Command::new("refund")
+        .about("Refunds a payment by adding a negative payment")
+        .arg(Arg::new("payment-id")
+            .long("payment-id")
+            .value_parser(clap::value_parser!(i64))
+            .required(true)
+            .help("The payment row ID to refund.")
+        )
+        .arg(Arg::new("reason")
+            .long("reason")
+            .required(true)
+            .help("Why the payment is refunded")
+        )
+        .arg(Arg::new("amount")
+            .long("amount")
+            .value_parser(clap::value_parser!(Money))
+            .help("The amount to pay back. Defaults to everything not \
+                refunded yet.")
+        )
+        .arg(Arg::new("date")
+            .long("date")
+            .value_parser(clap::value_parser!(NaiveDate))
+            .help("The date of the refund (YYYY-MM-DD). Defaults to \
+                today.")
+        ))
+}
+
+/// Processes the `void` subcommand of the `billing` command.
+pub(crate) fn process_void_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let charge = RowId(
+        *arg_matches
+            .get_one::<i64>("charge-id")
+            .expect("Missing required argument"),
+    );
+    let reason = arg_matches
+        .get_one::<String>("reason")
+        .expect("Missing required argument");
+    let date = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(chrono::Local::now().date_naive());
+
+    let db_connection = context.db_connection()?;
+    let void = void_charge(
+        db_connection,
+        charge,
+        date,
+        reason,
+    )?;
+
+    let mut output = OutputRows::new([
+        ("charge", "i64"),
+        ("void", "i64"),
+    ]);
+    output.push_row([charge.0.into(), void.0.into()]);
+    context.set_output_rows(output);
+
+    Ok(CommandResponse::new(format!(
+        "Voided charge {} (void: charge {}).",
+        charge.0, void.0
+    )))
+}
+
+/// Processes the `refund` subcommand of the `billing` command.
+pub(crate) fn process_refund_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let payment = RowId(
+        *arg_matches
+            .get_one::<i64>("payment-id")
+            .expect("Missing required argument"),
+    );
+    let reason = arg_matches
+        .get_one::<String>("reason")
+        .expect("Missing required argument");
+    let amount = arg_matches
+        .get_one::<Money>("amount")
+        .copied();
+    let date = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(chrono::Local::now().date_naive());
+
+    let db_connection = context.db_connection()?;
+    let refund = refund_payment(
+        db_connection,
+        payment,
+        amount,
+        date,
+        reason,
+    )?;
+    let refunded = get_money(
+        db_connection,
+        "payment",
+        refund,
+        "amount",
+    )?
+    .unwrap_or_default();
+
+    let mut output = OutputRows::new([
+        ("payment", "i64"),
+        ("refund", "i64"),
+    ]);
+    output
+        .push_row([payment.0.into(), refund.0.into()]);
+    context.set_output_rows(output);
+
+    Ok(CommandResponse::new(format!(
+        "Refunded {} of payment {} (refund: payment {}).",
+        refunded.checked_neg()?,
+        payment.0,
+        refund.0
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{
+        add_test_charge, add_test_payment,
+        add_test_rate, add_test_session, date,
+        date_time, setup_test_context,
+    };
+    use crate::{BillingPlugin, Currency};
+    use training::TrainingPlugin;
+
+    fn balance(
+        db_connection: &mut DbConnection,
+        client: RowId,
+        on: &str,
+    ) -> dolmen::Result<Money> {
+        crate::client_balance_on(
+            db_connection.connection()?,
+            client,
+            date(on),
+        )
+    }
+
+    #[test]
+    fn test_void_charge() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        let charge = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+
+        let void = void_charge(
+            db_connection,
+            charge,
+            date("2026-01-05"),
+            "booked twice",
+        )?;
+        let void_row =
+            get_charge(db_connection, void)?;
+        assert_eq!(
+            void_row.amount,
+            Money::from_dollars(-50)?
+        );
+        assert_eq!(void_row.voids, Some(charge));
+        assert_eq!(
+            void_row.description,
+            "Void: Personal training session (60 min) (booked twice)"
+        );
+
+        // the original charge stays on the books
+        assert_eq!(
+            balance(
+                db_connection,
+                client,
+                "2026-01-04"
+            )?,
+            Money::from_dollars(50)?
+        );
+        assert_eq!(
+            balance(
+                db_connection,
+                client,
+                "2026-01-05"
+            )?,
+            Money::zero()
+        );
+
+        assert!(
+            void_charge(
+                db_connection,
+                charge,
+                date("2026-01-06"),
+                "again"
+            )
+            .is_err()
+        );
+        assert!(
+            void_charge(
+                db_connection,
+                void,
+                date("2026-01-06"),
+                "undo"
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+
+    // A void is in the currency of the charge it reverses.
+    #[test]
+    fn test_void_keeps_currency() -> dolmen::Result<()>
+    {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        let charge = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        set_money(
+            db_connection,
+            "charge",
+            charge,
+            "amount",
+            Some(Money::new(2000, Currency::Eur)),
+        )?;
+
+        let void = void_charge(
+            db_connection,
+            charge,
+            date("2026-01-05"),
+            "booked twice",
+        )?;
+        assert_eq!(
+            get_money(
+                db_connection,
+                "charge",
+                void,
+                "amount",
+            )?,
+            Some(Money::new(-2000, Currency::Eur))
+        );
+        assert!(
+            balance(
+                db_connection,
+                client,
+                "2026-01-05"
+            )?
+            .is_zero()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_refund_payment() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = db_connection
+            .new_row_in_table("trainer")?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        let payment = add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-04",
+            100,
+        )?;
+
+        let refund = refund_payment(
+            db_connection,
+            payment,
+            Some(Money::from_dollars(30)?),
+            date("2026-01-06"),
+            "overpaid",
+        )?;
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<Money>(
+                    "payment", refund, "amount",
+                )?,
+            Money::from_dollars(-30)?
+        );
+        assert_eq!(
+            balance(
+                db_connection,
+                client,
+                "2026-01-06"
+            )?,
+            Money::from_dollars(-20)?
+        );
+
+        // only the $70 left can be refunded
+        assert!(
+            refund_payment(
+                db_connection,
+                payment,
+                Some(Money::from_dollars(80)?),
+                date("2026-01-06"),
+                "overpaid",
+            )
+            .is_err()
+        );
+        // nor in another currency
+        assert_eq!(
+            refund_payment(
+                db_connection,
+                payment,
+                Some("10.00 EUR".parse().unwrap()),
+                date("2026-01-06"),
+                "overpaid",
+            )
+            .err()
+            .unwrap()
+            .message()
+            .clone()
+            .unwrap(),
+            "can't refund €10.00 of payment 1, it was paid in USD"
+        );
+        refund_payment(
+            db_connection,
+            payment,
+            None,
+            date("2026-01-07"),
+            "overpaid",
+        )?;
+        assert_eq!(
+            balance(
+                db_connection,
+                client,
+                "2026-01-07"
+            )?,
+            Money::from_dollars(50)?
+        );
+        assert!(
+            refund_payment(
+                db_connection,
+                payment,
+                None,
+                date("2026-01-07"),
+                "overpaid",
+            )
+            .is_err()
+        );
+        assert!(
+            refund_payment(
+                db_connection,
+                refund,
+                None,
+                date("2026-01-07"),
+                "undo",
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+
+    // `set` checks voids and refunds against the charge and payment tables
+    #[test]
+    fn test_set_voids_and_refunds()
+    -> dolmen::Result<()> {
+        let mut context = Context::new();
+        context
+            .add_plugin(DbPlugin)?
+            .add_plugin(db_commands::DbCommandsPlugin)?
+            .add_plugin(BillingPlugin)?
+            .add_plugin(TrainingPlugin)?;
+        context
+            .get_resource_mut::<DbConfig>()
+            .unwrap()
+            .open_db_in_memory = true;
+        context.startup()?;
+
+        context.execute("new --table=charge")?;
+        context.execute("new --table=charge")?;
+        context.execute("new --table=payment")?;
+        context.execute(
+            "set --table=charge --row-id=2 --field=voids \
+                --value=1",
+        )?;
+
+        let mut error_message = |command: &str| {
+            context
+                .execute(command)
+                .err()
+                .and_then(|e| e.message().clone())
+                .unwrap_or_default()
+        };
+        assert_eq!(
+            error_message(
+                "set --table=charge --row-id=2 --field=voids \
+                    --value=99"
+            ),
+            "invalid value for field voids: no row with id 99 \
+                in table charge"
+        );
+        assert_eq!(
+            error_message(
+                "set --table=payment --row-id=1 \
+                    --field=refunds --value=2"
+            ),
+            "invalid value for field refunds: no row with id 2 \
+                in table payment"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_receipt_shows_voids_and_refunds()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = db_connection
+            .new_row_in_table("trainer")?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        let charge = add_test_charge(
+            db_connection,
+            client,
+            "2026-01-04",
+            50,
+        )?;
+        let first = add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-04",
+            50,
+        )?;
+        crate::allocate_receipt_number(
+            db_connection,
+            first,
+        )?;
+        void_charge(
+            db_connection,
+            charge,
+            date("2026-01-05"),
+            "session cancelled",
+        )?;
+        let refund = refund_payment(
+            db_connection,
+            first,
+            None,
+            date("2026-01-05"),
+            "session cancelled",
+        )?;
+        add_test_charge(
+            db_connection,
+            client,
+            "2026-01-11",
+            50,
+        )?;
+        let second = add_test_payment(
+            db_connection,
+            client,
+            trainer,
+            "2026-01-11",
+            50,
+        )?;
+        crate::allocate_receipt_number(
+            db_connection,
+            second,
+        )?;
+
+        let (_, data) = crate::invoice_data(
+            db_connection,
+            second,
+        )?;
+        let Some(documents::DocumentValue::Table(
+            lines,
+        )) = data.get(crate::CHARGE_TABLE)
+        else {
+            panic!("missing charge table");
+        };
+        assert_eq!(
+            lines.rows,
+            vec![
+                vec![
+                    "2026-01-05",
+                    "Void: Personal training session (60 min) \
+                        (session cancelled)",
+                    "-50.00",
+                ],
+                vec![
+                    "2026-01-05",
+                    "Refund of receipt 2026-0001 (session cancelled)",
+                    "50.00",
+                ],
+                vec![
+                    "2026-01-11",
+                    "Personal training session (60 min)",
+                    "50.00",
+                ],
+            ]
+        );
+        assert_eq!(
+            data.get("balanceend"),
+            Some(&documents::DocumentValue::Text(
+                "0.00".into()
+            ))
+        );
+
+        // a refund has no receipt of its own
+        assert!(
+            crate::invoice_data(db_connection, refund)
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancellation_in_time() -> dolmen::Result<()>
+    {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = db_connection
+            .new_row_in_table("trainer")?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        add_test_rate(db_connection, None, None, 60)?;
+        let session = add_test_session(
+            db_connection,
+            trainer,
+            client,
+            date_time("2026-03-10T09:00:00"),
+            60,
+        )?;
+
+        // billed when completed, then cancelled after all, a day ahead
+        training::update_session_status(
+            &mut context,
+            session,
+            SessionStatus::Completed,
+            date_time("2026-03-07T12:00:00"),
+        )?;
+        let messages =
+            training::update_session_status(
+                &mut context,
+                session,
+                SessionStatus::Cancelled,
+                date_time("2026-03-09T09:00:00"),
+            )?;
+        assert_eq!(
+            messages,
+            vec![
+                "Voided charge 1 of session 1 (charge 2)."
+            ]
+        );
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_field_in_table_row::<NaiveDate>(
+                    "charge",
+                    RowId(2),
+                    "date",
+                )?,
+            date("2026-03-09")
+        );
+
+        // cancelling again doesn't void twice
+        assert!(
+            training::update_session_status(
+                &mut context,
+                session,
+                SessionStatus::Cancelled,
+                date_time("2026-03-09T10:00:00"),
+            )?
+            .is_empty()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_late_cancellation() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = db_connection
+            .new_row_in_table("trainer")?;
+        let client = db_connection
+            .new_row_in_table("client")?;
+        add_test_rate(db_connection, None, None, 60)?;
+        let session = add_test_session(
+            db_connection,
+            trainer,
+            client,
+            date_time("2026-03-10T09:00:00"),
+            60,
+        )?;
+
+        // a minute short of a day ahead is too late
+        let messages =
+            training::update_session_status(
+                &mut context,
+                session,
+                SessionStatus::Cancelled,
+                date_time("2026-03-09T09:01:00"),
+            )?;
+        assert_eq!(
+            messages,
+            vec![
+                "Late cancellation: Created charge 1 ($60.00) for \
+                    session 1."
+            ]
+        );
+        let db_connection = context.db_connection()?;
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<String>(
+                    "charge",
+                    RowId(1),
+                    "description",
+                )?,
+            "Late cancellation (60 min session)"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,440 @@
+//! An append-only audit log of every change to the database.
+//!
+//! `enable_audit_log` installs SQLite triggers on every table registered
+//! with the connection, so every row added, field set and row removed is
+//! recorded in the `audit_log` table, whichever command, plugin or UI made
+//! the change. The triggers belong to the connection, so `startup` installs
+//! them as soon as it opens the database, before any command runs, and
+//! `run_migrations` installs them again once the schema is current so they
+//! see the columns migrations add (and cover every restored database).
+//!
+//! Each entry records where the change came from (e.g. `tacl set ...` or
+//! `tui: Edit Table`). The source is set per connection with
+//! `set_audit_source`. The log can't be changed or deleted from: the
+//! database rejects any `UPDATE` or `DELETE` on it.
+use crate::{
+    CommandOutputContextExt, OutputRows, sql_error,
+};
+use clap::ArgMatches;
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use rusqlite::types::Value;
+use tabled::builder::Builder as TabledBuilder;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A single change recorded in the audit log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// When the change was made, in UTC (e.g.
+    /// `"2026-01-04T09:30:00.000Z"`).
+    pub timestamp: String,
+
+    /// Where the change came from (e.g. `"tacl set --table=charge ..."`).
+    pub source: String,
+
+    /// What happened: `"insert"`, `"update"` or `"delete"`.
+    pub action: String,
+
+    /// The name of the table.
+    pub table: String,
+
+    /// The row ID of the changed row.
+    pub row_id: RowId,
+
+    /// The field that changed, or `None` for the entry recording that the
+    /// row itself was added or removed.
+    pub field: Option<String>,
+
+    /// The value of the field before the change.
+    pub old_value: Value,
+
+    /// The value of the field after the change.
+    pub new_value: Value,
+}
+
+/// Starts recording every change to the tables registered with the
+/// connection in the audit log. Safe to call more than once; each call
+/// picks up columns added since the last one.
+///
+/// * `db_connection` - A connection to the database.
+pub fn enable_audit_log(
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<()> {
+    let tables = db_connection
+        .tables()
+        .iter()
+        .map(|t| t.table_name.clone())
+        .collect::<Vec<_>>();
+    let connection = db_connection.connection()?;
+    ensure_audit_tables(connection)
+        .map_err(sql_error)?;
+    for table in tables {
+        let columns =
+            table_columns(connection, &table)
+                .map_err(sql_error)?;
+        connection
+            .execute_batch(&audit_triggers_sql(
+                &table, &columns,
+            ))
+            .map_err(sql_error)?;
+    }
+    Ok(())
+}
+
+/// Sets where the changes made through a connection from now on come from,
+/// as recorded in the audit log.
+///
+/// * `db_connection` - A connection to the database.
+/// * `source` - A description of the source (e.g. `"tui: Edit Table"`).
+pub fn set_audit_source(
+    db_connection: &mut DbConnection,
+    source: &str,
+) -> dolmen::Result<()> {
+    let connection = db_connection.connection()?;
+    ensure_audit_tables(connection)
+        .map_err(sql_error)?;
+    connection
+        .execute(
+            "UPDATE audit_source SET source = ?1",
+            [source],
+        )
+        .map_err(sql_error)?;
+    Ok(())
+}
+
+/// Gets where the changes made through a connection come from, as set with
+/// `set_audit_source`.
+///
+/// * `db_connection` - A connection to the database.
+pub fn audit_source(
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<String> {
+    let connection = db_connection.connection()?;
+    ensure_audit_tables(connection)
+        .map_err(sql_error)?;
+    connection
+        .query_row(
+            "SELECT source FROM audit_source",
+            [],
+            |r| r.get(0),
+        )
+        .map_err(sql_error)
+}
+
+/// Makes changes through a connection with a different source, then puts
+/// the previous source back.
+///
+/// * `db_connection` - A connection to the database.
+/// * `source` - A description of the source (e.g. `"tui: Edit Table"`).
+/// * `f` - Makes the changes.
+pub fn with_audit_source<T>(
+    db_connection: &mut DbConnection,
+    source: &str,
+    f: impl FnOnce(&mut DbConnection) -> dolmen::Result<T>,
+) -> dolmen::Result<T> {
+    let previous = audit_source(db_connection)?;
+    set_audit_source(db_connection, source)?;
+    let result = f(db_connection);
+    set_audit_source(db_connection, &previous)?;
+    result
+}
+
+/// Gets the history of a row (or of every row of a table), oldest first.
+///
+/// * `db_connection` - A connection to the database.
+/// * `table` - The name of the table.
+/// * `row_id` - The row ID of the row, or `None` for every row.
+pub fn row_history(
+    db_connection: &mut DbConnection,
+    table: &str,
+    row_id: Option<RowId>,
+) -> dolmen::Result<Vec<AuditEntry>> {
+    let connection = db_connection.connection()?;
+    ensure_audit_tables(connection)
+        .map_err(sql_error)?;
+    let mut stmt = connection
+        .prepare_cached(
+            "SELECT timestamp, source, action, table_name, row_id,
+                    field, old_value, new_value
+                FROM audit_log
+                WHERE table_name = ?1
+                    AND (?2 IS NULL OR row_id = ?2)
+                ORDER BY id",
+        )
+        .map_err(sql_error)?;
+    stmt.query_map(
+        rusqlite::params![table, row_id.map(|r| r.0)],
+        |r| {
+            Ok(AuditEntry {
+                timestamp: r.get(0)?,
+                source: r.get(1)?,
+                action: r.get(2)?,
+                table: r.get(3)?,
+                row_id: RowId(r.get(4)?),
+                field: r.get(5)?,
+                old_value: r.get(6)?,
+                new_value: r.get(7)?,
+            })
+        },
+    )
+    .map_err(sql_error)?
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(sql_error)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// The source recorded for changes before `set_audit_source` is called.
+const DEFAULT_AUDIT_SOURCE: &str = "unknown";
+
+/// Creates the audit log, the triggers keeping it append-only, and the
+/// connection's (temporary) source table, if they don't exist yet.
+fn ensure_audit_tables(
+    connection: &rusqlite::Connection,
+) -> rusqlite::Result<()> {
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY,
+            timestamp TEXT NOT NULL,
+            source TEXT NOT NULL,
+            action TEXT NOT NULL,
+            table_name TEXT NOT NULL,
+            row_id INTEGER NOT NULL,
+            field TEXT,
+            old_value,
+            new_value
+        );
+        CREATE INDEX IF NOT EXISTS audit_log_row
+            ON audit_log (table_name, row_id);
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_update
+            BEFORE UPDATE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_delete
+            BEFORE DELETE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;
+        CREATE TEMP TABLE IF NOT EXISTS audit_source (source TEXT NOT NULL);",
+    )?;
+    connection.execute(
+        "INSERT INTO audit_source (source)
+            SELECT ?1 WHERE NOT EXISTS (SELECT 1 FROM audit_source)",
+        [DEFAULT_AUDIT_SOURCE],
+    )?;
+    Ok(())
+}
+
+/// Gets the names of a table's columns, other than `id`.
+fn table_columns(
+    connection: &rusqlite::Connection,
+    table: &str,
+) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = connection.prepare(
+        "SELECT name FROM pragma_table_info(?1) WHERE name != 'id'",
+    )?;
+    stmt.query_map([table], |r| r.get(0))?.collect()
+}
+
+/// Builds the SQL (re)creating the triggers that record changes to a
+/// table. The triggers are temporary, so they belong to the connection
+/// and can refer to its `audit_source` table.
+fn audit_triggers_sql(
+    table: &str,
+    columns: &[String],
+) -> String {
+    let quoted_table = quote_identifier(table);
+    let insert = |action: &str,
+                  row: &str,
+                  field: Option<&str>,
+                  old: &str,
+                  new: &str,
+                  condition: &str| {
+        format!(
+            "INSERT INTO audit_log (timestamp, source, action,
+                    table_name, row_id, field, old_value, new_value)
+                SELECT strftime('%Y-%m-%dT%H:%M:%fZ', 'now'),
+                    (SELECT source FROM audit_source), '{}', {}, {}.id,
+                    {}, {}, {}
+                {};\n",
+            action,
+            quote_literal(table),
+            row,
+            field
+                .map(quote_literal)
+                .unwrap_or("NULL".into()),
+            old,
+            new,
+            condition
+        )
+    };
+
+    let mut on_insert = insert(
+        "insert", "new", None, "NULL", "NULL", "",
+    );
+    let mut on_update = String::new();
+    let mut on_delete = insert(
+        "delete", "old", None, "NULL", "NULL", "",
+    );
+    for column in columns {
+        let old = format!(
+            "old.{}",
+            quote_identifier(column)
+        );
+        let new = format!(
+            "new.{}",
+            quote_identifier(column)
+        );
+        on_insert += &insert(
+            "insert",
+            "new",
+            Some(column),
+            "NULL",
+            &new,
+            &format!("WHERE {} IS NOT NULL", new),
+        );
+        on_update += &insert(
+            "update",
+            "new",
+            Some(column),
+            &old,
+            &new,
+            &format!("WHERE {} IS NOT {}", old, new),
+        );
+        on_delete += &insert(
+            "delete",
+            "old",
+            Some(column),
+            &old,
+            "NULL",
+            &format!("WHERE {} IS NOT NULL", old),
+        );
+    }
+
+    let mut sql = String::new();
+    for (event, body) in [
+        ("INSERT", on_insert),
+        ("UPDATE", on_update),
+        ("DELETE", on_delete),
+    ] {
+        let trigger = quote_identifier(&format!(
+            "audit_{}_{}",
+            table,
+            event.to_lowercase()
+        ));
+        sql += &format!(
+            "DROP TRIGGER IF EXISTS temp.{trigger};
+            CREATE TEMP TRIGGER {trigger} AFTER {event} ON {quoted_table}
+            BEGIN
+            {body}
+            END;\n",
+        );
+    }
+    sql
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn quote_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+/// Formats a value from the audit log for display.
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(r) => r.to_string(),
+        Value::Text(t) => t.clone(),
+        Value::Blob(b) => {
+            format!("<{} bytes>", b.len())
+        }
+    }
+}
+
+/// Processes the `history` command.
+pub(crate) fn process_history_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let table = arg_matches
+        .get_one::<String>("table")
+        .expect("Missing required argument");
+    let row_id = arg_matches
+        .get_one::<i64>("row-id")
+        .map(|r| RowId(*r));
+
+    let db_connection = context.db_connection()?;
+    if !db_connection
+        .tables()
+        .iter()
+        .any(|t| t.table_name == *table)
+    {
+        return Err(dolmen::Error::new(format!(
+            "table does not exist: {}",
+            table
+        )));
+    }
+    let entries =
+        row_history(db_connection, table, row_id)?;
+
+    let mut output = OutputRows::new([
+        ("timestamp", "String"),
+        ("source", "String"),
+        ("action", "String"),
+        ("row_id", "i64"),
+        ("field", "String"),
+        ("old_value", "String"),
+        ("new_value", "String"),
+    ]);
+    let mut tabled_builder = TabledBuilder::default();
+    tabled_builder.push_record([
+        "Time", "Source", "Action", "Row", "Field",
+        "Old", "New",
+    ]);
+    for entry in &entries {
+        let old_value = value_text(&entry.old_value);
+        let new_value = value_text(&entry.new_value);
+        output.push_row([
+            entry.timestamp.as_str().into(),
+            entry.source.as_str().into(),
+            entry.action.as_str().into(),
+            entry.row_id.0.into(),
+            entry.field.clone().into(),
+            old_value.as_str().into(),
+            new_value.as_str().into(),
+        ]);
+        tabled_builder.push_record([
+            entry.timestamp.clone(),
+            entry.source.clone(),
+            entry.action.clone(),
+            entry.row_id.0.to_string(),
+            entry.field.clone().unwrap_or_default(),
+            old_value,
+            new_value,
+        ]);
+    }
+    context.set_output_rows(output);
+
+    if entries.is_empty() {
+        return Ok(CommandResponse::new(
+            match row_id {
+                Some(row_id) => format!(
+                    "No recorded changes to row {} in table {}.",
+                    row_id, table
+                ),
+                None => format!(
+                    "No recorded changes in table {}.",
+                    table
+                ),
+            },
+        ));
+    }
+    Ok(CommandResponse::new(
+        tabled_builder.build().to_string(),
+    ))
+}
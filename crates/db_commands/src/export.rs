@@ -0,0 +1,154 @@
+//! The `export` command, which writes every row of a table to a CSV or
+//! JSON file.
+use crate::{
+    FieldParsers, OutputFormat, OutputRows,
+    OutputValue, RowQuery, read_display_rows,
+    table_field_types,
+};
+use clap::ArgMatches;
+use dolmen::prelude::*;
+use std::path::PathBuf;
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// Processes the `export` command.
+///
+/// Every field is written as a column. References to other rows are written
+/// twice: as the raw row ID in a column named after the field, and as the
+/// referenced row's name in a `<field>_display` column. Fields of types
+/// declared with `add_field_export` (e.g. amounts) are split into the
+/// columns declared for them.
+pub(crate) fn process_export_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let table = arg_matches
+        .get_one::<String>("table")
+        .expect("Missing required argument");
+    let format = arg_matches
+        .get_one::<String>("format")
+        .expect("Missing required argument")
+        .parse::<OutputFormat>()
+        .map_err(dolmen::Error::new)?;
+    let out_file = arg_matches
+        .get_one::<PathBuf>("out-file")
+        .expect("Missing required argument");
+
+    let rows = export_rows(context, table)?;
+    std::fs::write(
+        out_file,
+        rows.format(format) + "\n",
+    )
+    .map_err(|e| {
+        dolmen::Error::new(format!(
+            "failed to write {}: {}",
+            out_file.display(),
+            e
+        ))
+    })?;
+
+    Ok(CommandResponse::new(format!(
+        "Exported {} row(s) from table {} to {}.",
+        rows.rows().len(),
+        table,
+        out_file.display()
+    )))
+}
+
+/// Reads every row of a table as output rows for exporting.
+///
+/// * `context` - The context to use.
+/// * `table` - The name of the table.
+fn export_rows(
+    context: &mut Context,
+    table: &str,
+) -> dolmen::Result<OutputRows> {
+    let field_types =
+        table_field_types(context, table)?;
+    let exports = field_types
+        .iter()
+        .map(|(_, type_name)| {
+            context
+                .get_resource::<FieldParsers>()
+                .and_then(|p| p.export(type_name))
+        })
+        .collect::<Vec<_>>();
+
+    let mut columns =
+        vec![("id".to_string(), "i64".to_string())];
+    for ((name, type_name), export) in
+        field_types.iter().zip(&exports)
+    {
+        if type_name == "RowId" {
+            columns.push((name.clone(), "i64".into()));
+            columns.push((
+                format!("{}_display", name),
+                "String".into(),
+            ));
+        } else if let Some((export_columns, _)) =
+            export
+        {
+            for (suffix, column_type) in
+                *export_columns
+            {
+                columns.push((
+                    format!("{}{}", name, suffix),
+                    column_type.to_string(),
+                ));
+            }
+        } else {
+            columns.push((
+                name.clone(),
+                type_name.clone(),
+            ));
+        }
+    }
+    let mut output = OutputRows::new(columns);
+
+    for (id, values, reference_ids) in
+        read_display_rows(
+            context,
+            table,
+            &field_types,
+            &RowQuery::default(),
+        )?
+    {
+        let mut row = vec![OutputValue::Integer(id)];
+        for (i, (_, type_name)) in
+            field_types.iter().enumerate()
+        {
+            let value = &values[i + 1];
+            if let Some((_, raw)) = reference_ids
+                .iter()
+                .find(|(r, _)| *r == i + 1)
+            {
+                row.push(OutputValue::from_display(
+                    raw, "i64",
+                ));
+                row.push(OutputValue::Text(
+                    value.clone(),
+                ));
+            } else if let Some((
+                export_columns,
+                export_fn,
+            )) = exports[i]
+            {
+                let mut split = export_fn(value);
+                split.resize(
+                    export_columns.len(),
+                    OutputValue::Null,
+                );
+                row.extend(split);
+            } else {
+                row.push(OutputValue::from_display(
+                    value, type_name,
+                ));
+            }
+        }
+        output.push_row(row);
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,625 @@
+//! Parsing and validation of field values given as text, used by `set`,
+//! and the hooks run after a field has been set.
+use crate::OutputValue;
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use rusqlite::types::{Null, ToSql};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A function that parses the text of a field value into a value that can be
+/// stored in the database, or returns a description of why it's invalid.
+pub type FieldParseFn =
+    fn(&str) -> Result<Box<dyn ToSql>, String>;
+
+/// A function run after a field of a row has been set, given the row ID,
+/// to keep values worked out from the field up to date. Returns a message
+/// to report to the user, if there is one. It runs in the same savepoint
+/// as the write, so if it fails the field is left as it was.
+pub type FieldSetHook =
+    fn(
+        &mut Context,
+        RowId,
+    ) -> dolmen::Result<Option<String>>;
+
+/// A function that splits the displayed text of a field value into the
+/// values of the columns it's exported as (see `add_field_export`).
+pub type FieldExportFn = fn(&str) -> Vec<OutputValue>;
+
+/// A resource storing how to parse text into each type of field, keyed by
+/// the field's `TypeId`. The `set` command looks fields up here, so a field
+/// can only be set from text if its type has been registered. It also holds
+/// the hooks run after a field is set.
+#[derive(Resource, Default)]
+pub struct FieldParsers {
+    parsers: HashMap<TypeId, FieldParser>,
+    references: HashMap<(String, String), String>,
+    set_hooks:
+        HashMap<(String, String), Vec<FieldSetHook>>,
+    exports: HashMap<&'static str, FieldExport>,
+}
+
+impl FieldParsers {
+    /// Gets the parse function for a field type, or `None` if the type
+    /// hasn't been registered.
+    ///
+    /// * `type_id` - The `TypeId` of the field type.
+    pub fn parse_fn(
+        &self,
+        type_id: TypeId,
+    ) -> Option<FieldParseFn> {
+        self.parsers.get(&type_id).map(|p| p.parse_fn)
+    }
+
+    /// Gets the name of a registered field type, for use in error
+    /// messages.
+    ///
+    /// * `type_id` - The `TypeId` of the field type.
+    pub fn type_name(
+        &self,
+        type_id: TypeId,
+    ) -> Option<&'static str> {
+        self.parsers.get(&type_id).map(|p| p.type_name)
+    }
+
+    /// Gets the table a reference field refers to, if it has been declared
+    /// with `add_field_reference`.
+    ///
+    /// * `table` - The name of the table the field is in.
+    /// * `field` - The name of the field.
+    pub fn reference_table(
+        &self,
+        table: &str,
+        field: &str,
+    ) -> Option<&str> {
+        self.references
+            .get(&(
+                table.to_string(),
+                field.to_string(),
+            ))
+            .map(|t| t.as_str())
+    }
+
+    /// Gets the hooks run after a field is set, in the order they were
+    /// added with `add_field_set_hook`.
+    ///
+    /// * `table` - The name of the table the field is in.
+    /// * `field` - The name of the field.
+    pub fn set_hooks(
+        &self,
+        table: &str,
+        field: &str,
+    ) -> &[FieldSetHook] {
+        self.set_hooks
+            .get(&(
+                table.to_string(),
+                field.to_string(),
+            ))
+            .map(|h| h.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Gets the columns a field type is exported as, as pairs of column
+    /// name suffix and type name, along with the function splitting a value
+    /// into them. `None` if the type is exported as a single column.
+    ///
+    /// * `type_name` - The name of the field type (e.g. `"Money"`).
+    pub fn export(
+        &self,
+        type_name: &str,
+    ) -> Option<(
+        &'static [(&'static str, &'static str)],
+        FieldExportFn,
+    )> {
+        self.exports
+            .get(type_name)
+            .map(|e| (e.columns, e.export_fn))
+    }
+}
+
+/// An extension trait adding field parsing functionality to `Context`.
+pub trait FieldParsersContextExt {
+    /// Registers a field type (`T`) that can be parsed from text with its
+    /// `FromStr` implementation. `Option<T>` is registered along with it;
+    /// an empty value clears an optional field.
+    fn add_field_type<T>(&mut self)
+    where
+        T: FromStr + ToSql + 'static,
+        T::Err: Display;
+
+    /// Declares the table a `RowId` field refers to, matching its
+    /// `#[display_table]`. Only needed when the field isn't named after the
+    /// table (e.g. `voids` referring to `charge`).
+    ///
+    /// * `table` - The name of the table the field is in.
+    /// * `field` - The name of the field.
+    /// * `target_table` - The name of the table the field refers to.
+    fn add_field_reference(
+        &mut self,
+        table: &str,
+        field: &str,
+        target_table: &str,
+    );
+
+    /// Adds a hook run after a field is set by the `set` command or the
+    /// edit tab (e.g. to work out a value stored from the field again).
+    ///
+    /// * `table` - The name of the table the field is in.
+    /// * `field` - The name of the field.
+    /// * `hook` - The function to run.
+    fn add_field_set_hook(
+        &mut self,
+        table: &str,
+        field: &str,
+        hook: FieldSetHook,
+    );
+
+    /// Declares that fields of type `T` are exported by `export` as several
+    /// columns rather than as their displayed text (e.g. an amount as a
+    /// plain decimal and a currency code). Each column is named after the
+    /// field plus a suffix, so the first is usually `""`.
+    ///
+    /// * `columns` - The suffixes and type names of the columns.
+    /// * `export_fn` - Splits a displayed value into the columns' values.
+    fn add_field_export<T: 'static>(
+        &mut self,
+        columns: &'static [(
+            &'static str,
+            &'static str,
+        )],
+        export_fn: FieldExportFn,
+    );
+}
+
+impl FieldParsersContextExt for Context {
+    fn add_field_type<T>(&mut self)
+    where
+        T: FromStr + ToSql + 'static,
+        T::Err: Display,
+    {
+        if !self.has_resource::<FieldParsers>() {
+            self.add_resource(FieldParsers::default());
+        }
+
+        if let Some(field_parsers) =
+            self.get_resource_mut::<FieldParsers>()
+        {
+            let type_name = short_type_name::<T>();
+            field_parsers.parsers.insert(
+                TypeId::of::<T>(),
+                FieldParser {
+                    type_name,
+                    parse_fn: parse_value::<T>,
+                },
+            );
+            field_parsers.parsers.insert(
+                TypeId::of::<Option<T>>(),
+                FieldParser {
+                    type_name,
+                    parse_fn: parse_optional_value::<T>,
+                },
+            );
+        }
+    }
+
+    fn add_field_reference(
+        &mut self,
+        table: &str,
+        field: &str,
+        target_table: &str,
+    ) {
+        if !self.has_resource::<FieldParsers>() {
+            self.add_resource(FieldParsers::default());
+        }
+
+        if let Some(field_parsers) =
+            self.get_resource_mut::<FieldParsers>()
+        {
+            field_parsers.references.insert(
+                (table.to_string(), field.to_string()),
+                target_table.to_string(),
+            );
+        }
+    }
+
+    fn add_field_set_hook(
+        &mut self,
+        table: &str,
+        field: &str,
+        hook: FieldSetHook,
+    ) {
+        if !self.has_resource::<FieldParsers>() {
+            self.add_resource(FieldParsers::default());
+        }
+
+        if let Some(field_parsers) =
+            self.get_resource_mut::<FieldParsers>()
+        {
+            field_parsers
+                .set_hooks
+                .entry((
+                    table.to_string(),
+                    field.to_string(),
+                ))
+                .or_default()
+                .push(hook);
+        }
+    }
+
+    fn add_field_export<T: 'static>(
+        &mut self,
+        columns: &'static [(
+            &'static str,
+            &'static str,
+        )],
+        export_fn: FieldExportFn,
+    ) {
+        if !self.has_resource::<FieldParsers>() {
+            self.add_resource(FieldParsers::default());
+        }
+
+        if let Some(field_parsers) =
+            self.get_resource_mut::<FieldParsers>()
+        {
+            field_parsers.exports.insert(
+                short_type_name::<T>(),
+                FieldExport { columns, export_fn },
+            );
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// A registered field type.
+struct FieldParser {
+    /// The name of the type, without its module path (e.g. `"NaiveDate"`).
+    type_name: &'static str,
+
+    /// Parses text into a value of the type.
+    parse_fn: FieldParseFn,
+}
+
+/// How a field type is exported, declared with `add_field_export`.
+struct FieldExport {
+    /// The suffixes and type names of the columns.
+    columns: &'static [(&'static str, &'static str)],
+
+    /// Splits a displayed value into the columns' values.
+    export_fn: FieldExportFn,
+}
+
+fn parse_value<T>(
+    text: &str,
+) -> Result<Box<dyn ToSql>, String>
+where
+    T: FromStr + ToSql + 'static,
+    T::Err: Display,
+{
+    text.parse::<T>()
+        .map(|v| Box::new(v) as Box<dyn ToSql>)
+        .map_err(|e| e.to_string())
+}
+
+fn parse_optional_value<T>(
+    text: &str,
+) -> Result<Box<dyn ToSql>, String>
+where
+    T: FromStr + ToSql + 'static,
+    T::Err: Display,
+{
+    if text.trim().is_empty() {
+        Ok(Box::new(Null))
+    } else {
+        parse_value::<T>(text)
+    }
+}
+
+/// Gets the name of a type without its module path.
+fn short_type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Registers the field types every plugin can rely on being settable.
+pub(crate) fn add_builtin_field_types(
+    context: &mut Context,
+) {
+    context.add_field_type::<String>();
+    context.add_field_type::<bool>();
+    context.add_field_type::<i32>();
+    context.add_field_type::<i64>();
+    context.add_field_type::<u32>();
+    context.add_field_type::<u64>();
+    context.add_field_type::<f64>();
+    context.add_field_type::<chrono::NaiveDate>();
+    context.add_field_type::<chrono::NaiveTime>();
+    context.add_field_type::<chrono::NaiveDateTime>();
+}
+
+/// Writes a field of a row with `write`, then runs the field's set hooks,
+/// all in one savepoint, so a failing hook leaves the row as it was.
+/// Returns the messages reported by the hooks.
+///
+/// * `context` - The context to use.
+/// * `table` - The name of the table the field is in.
+/// * `field` - The name of the field.
+/// * `row_id` - The row ID of the row.
+/// * `write` - Writes the field's new value.
+pub(crate) fn set_field_with_hooks(
+    context: &mut Context,
+    table: &str,
+    field: &str,
+    row_id: RowId,
+    write: impl FnOnce(
+        &mut DbConnection,
+    ) -> dolmen::Result<()>,
+) -> dolmen::Result<Vec<String>> {
+    with_savepoint(context, |context| {
+        write(context.db_connection()?)?;
+        run_set_hooks(context, table, field, row_id)
+    })
+}
+
+/// Runs the set hooks of a field that has just been written. Returns the
+/// messages reported by the hooks. Callers should run it in the same
+/// savepoint as the write (see `set_field_with_hooks`).
+///
+/// * `context` - The context to use.
+/// * `table` - The name of the table the field is in.
+/// * `field` - The name of the field.
+/// * `row_id` - The row ID of the row.
+pub(crate) fn run_set_hooks(
+    context: &mut Context,
+    table: &str,
+    field: &str,
+    row_id: RowId,
+) -> dolmen::Result<Vec<String>> {
+    let hooks = context
+        .get_resource::<FieldParsers>()
+        .map(|p| p.set_hooks(table, field).to_vec())
+        .unwrap_or_default();
+
+    let mut messages = Vec::new();
+    for hook in hooks {
+        if let Some(message) = hook(context, row_id)? {
+            messages.push(message);
+        }
+    }
+    Ok(messages)
+}
+
+/// Runs `f` in a savepoint, releasing it if `f` succeeds and rolling it
+/// back if it fails. Works inside or outside a transaction.
+///
+/// * `context` - The context to use.
+/// * `f` - The function to run.
+pub(crate) fn with_savepoint<T>(
+    context: &mut Context,
+    f: impl FnOnce(&mut Context) -> dolmen::Result<T>,
+) -> dolmen::Result<T> {
+    crate::in_savepoint(
+        context,
+        |context| context.db_connection(),
+        f,
+    )
+}
+
+/// Parses and validates the text of a value for a field, checking it
+/// against the field's declared type in the table's `TableConfig`.
+///
+/// `RowId` fields are references to rows in the table declared with
+/// `add_field_reference`, or else the table with the same name as the field
+/// (e.g. the `client` field refers to the `client` table). They're given by
+/// row ID or by the referenced row's `name`, and the referenced row must
+/// exist.
+///
+/// * `context` - The context to use.
+/// * `table` - The name of the table the field is in.
+/// * `field` - The name of the field.
+/// * `text` - The text of the value.
+pub(crate) fn parse_field_value(
+    context: &mut Context,
+    table: &str,
+    field: &str,
+    text: &str,
+) -> dolmen::Result<Box<dyn ToSql>> {
+    let db_connection = context.db_connection()?;
+    let table_config = db_connection
+        .tables()
+        .iter()
+        .find(|t| t.table_name == table)
+        .ok_or(dolmen::Error::new(format!(
+            "table does not exist: {}",
+            table
+        )))?;
+    let field_types = (table_config.field_types_fn)();
+    let field_type = field_types
+        .iter()
+        .find(|f| f.name() == field)
+        .ok_or_else(|| {
+            dolmen::Error::new(format!(
+                "table {} has no field {} (fields: {})",
+                table,
+                field,
+                field_types
+                    .iter()
+                    .map(|f| f.name().clone())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })?;
+    let type_id = field_type.type_id;
+
+    if type_id == TypeId::of::<RowId>()
+        || type_id == TypeId::of::<Option<RowId>>()
+    {
+        if type_id == TypeId::of::<Option<RowId>>()
+            && text.trim().is_empty()
+        {
+            return Ok(Box::new(Null));
+        }
+        let target_table =
+            reference_table(context, table, field)?;
+        return parse_row_reference(
+            context.db_connection()?,
+            field,
+            &target_table,
+            text,
+        );
+    }
+
+    let field_parsers = context
+        .get_resource::<FieldParsers>()
+        .ok_or(dolmen::Error::new(
+            "no field types registered",
+        ))?;
+    let parse_fn =
+        field_parsers.parse_fn(type_id).ok_or(
+            dolmen::Error::new(format!(
+                "field {} of table {} has a type that can't be set \
+                    from text",
+                field, table
+            )),
+        )?;
+    let type_name = field_parsers
+        .type_name(type_id)
+        .unwrap_or_default();
+
+    parse_fn(text).map_err(|e| {
+        dolmen::Error::new(format!(
+            "invalid value for field {} ({}): {}",
+            field, type_name, e
+        ))
+    })
+}
+
+/// Gets the table a reference field refers to: the one declared with
+/// `add_field_reference`, or else the table named after the field.
+pub(crate) fn reference_table(
+    context: &mut Context,
+    table: &str,
+    field: &str,
+) -> dolmen::Result<String> {
+    let declared = context
+        .get_resource::<FieldParsers>()
+        .and_then(|p| p.reference_table(table, field))
+        .map(|t| t.to_string());
+    let target_table =
+        declared.unwrap_or(field.to_string());
+    if !context
+        .db_connection()?
+        .tables()
+        .iter()
+        .any(|t| t.table_name == target_table)
+    {
+        return Err(dolmen::Error::new(format!(
+            "field {} of table {} refers to unknown table {}",
+            field, table, target_table
+        )));
+    }
+    Ok(target_table)
+}
+
+/// Parses a reference to a row in `target_table`, given either as a row ID
+/// or as the `name` of the row, and checks the row exists.
+fn parse_row_reference(
+    db_connection: &mut DbConnection,
+    field: &str,
+    target_table: &str,
+    text: &str,
+) -> dolmen::Result<Box<dyn ToSql>> {
+    let Ok(id) = text.trim().parse::<i64>() else {
+        return find_row_by_name(
+            db_connection,
+            field,
+            target_table,
+            text,
+        )
+        .map(|id| Box::new(id) as Box<dyn ToSql>);
+    };
+
+    if !db_connection
+        .get_table_row_ids(target_table)?
+        .contains(&id)
+    {
+        return Err(dolmen::Error::new(format!(
+            "invalid value for field {}: no row with id {} in table {}",
+            field, id, target_table
+        )));
+    }
+
+    Ok(Box::new(id))
+}
+
+/// Gets the `name` of a row, or `None` if the row doesn't exist or its
+/// table has no `name` field.
+///
+/// * `db_connection` - A connection to the database.
+/// * `table` - The name of the table the row is in.
+/// * `id` - The row ID of the row.
+pub(crate) fn row_name(
+    db_connection: &mut DbConnection,
+    table: &str,
+    id: i64,
+) -> dolmen::Result<Option<String>> {
+    let connection = db_connection.connection()?;
+    Ok(connection
+        .query_row(
+            &format!(
+                "SELECT name FROM {} WHERE id = ?1",
+                table
+            ),
+            [id],
+            |r| r.get::<_, Option<String>>(0),
+        )
+        .ok()
+        .flatten())
+}
+
+/// Finds the single row of a table whose `name` matches the text, ignoring
+/// case.
+fn find_row_by_name(
+    db_connection: &mut DbConnection,
+    field: &str,
+    table: &str,
+    text: &str,
+) -> dolmen::Result<i64> {
+    let connection = db_connection.connection()?;
+    let ids = connection
+        .prepare(&format!(
+            "SELECT id FROM {} WHERE name = ?1 COLLATE NOCASE",
+            table
+        ))
+        .and_then(|mut stmt| {
+            stmt.query_map([text.trim()], |r| r.get::<_, i64>(0))?
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|_| {
+            dolmen::Error::new(format!(
+                "invalid value for field {} (row ID): {}",
+                field, text
+            ))
+        })?;
+
+    match ids.as_slice() {
+        [id] => Ok(*id),
+        [] => Err(dolmen::Error::new(format!(
+            "invalid value for field {}: no row named {} in table {}",
+            field, text, table
+        ))),
+        _ => Err(dolmen::Error::new(format!(
+            "invalid value for field {}: more than one row named {} \
+                in table {}",
+            field, text, table
+        ))),
+    }
+}
@@ -0,0 +1,316 @@
+//! The `import` command, which adds rows to a table from a CSV file.
+use crate::field_parsers::{
+    parse_field_value, run_set_hooks, with_savepoint,
+};
+use crate::table_field_types;
+use clap::ArgMatches;
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+use rusqlite::types::ToSql;
+use std::path::PathBuf;
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// Processes the `import` command.
+///
+/// The first line of the file is a header naming a field of the table for
+/// each column. Every value is parsed and validated as with `set` (so
+/// references can be given by row ID or by name) before anything is
+/// written, and the rows are inserted in a single transaction. Each field's
+/// set hooks are run for every inserted row, as `set` runs them, so values
+/// worked out from the fields (such as a charge's tax) are filled in. If
+/// any row is invalid or a hook fails, nothing is imported and the errors
+/// are reported by line.
+pub(crate) fn process_import_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    let table = arg_matches
+        .get_one::<String>("table")
+        .expect("Missing required argument");
+    let file = arg_matches
+        .get_one::<PathBuf>("file")
+        .expect("Missing required argument");
+
+    let text = std::fs::read_to_string(file).map_err(
+        |e| {
+            dolmen::Error::new(format!(
+                "failed to read {}: {}",
+                file.display(),
+                e
+            ))
+        },
+    )?;
+    let mut records = parse_csv(&text)?.into_iter();
+    let Some((_, header)) = records.next() else {
+        return Err(dolmen::Error::new(format!(
+            "{} is empty",
+            file.display()
+        )));
+    };
+
+    // map the header to the table's fields
+    let field_types =
+        table_field_types(context, table)?;
+    let mut fields = Vec::new();
+    for name in &header {
+        let Some((field, _)) =
+            field_types.iter().find(|(f, _)| {
+                f.eq_ignore_ascii_case(name.trim())
+            })
+        else {
+            return Err(dolmen::Error::new(format!(
+                "table {} has no field {} (fields: {})",
+                table,
+                name,
+                field_types
+                    .iter()
+                    .map(|(f, _)| f.clone())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        };
+        if fields.contains(field) {
+            return Err(dolmen::Error::new(format!(
+                "field {} appears more than once in the header",
+                field
+            )));
+        }
+        fields.push(field.clone());
+    }
+
+    // text fields missing from the file are stored empty, like with `new`
+    for (field, type_name) in &field_types {
+        if type_name == "String"
+            && !fields.contains(field)
+        {
+            fields.push(field.clone());
+        }
+    }
+
+    // parse and validate every row before writing anything
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (line, record) in records {
+        if record.iter().all(|v| v.trim().is_empty()) {
+            continue;
+        }
+        if record.len() != header.len() {
+            errors.push(format!(
+                "line {}: expected {} values, found {}",
+                line,
+                header.len(),
+                record.len()
+            ));
+            continue;
+        }
+
+        let mut values = Vec::new();
+        for (field, text) in fields.iter().zip(
+            record
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::repeat("")),
+        ) {
+            match parse_field_value(
+                context, table, field, text,
+            ) {
+                Ok(value) => values.push(value),
+                Err(e) => errors.push(format!(
+                    "line {}: {}",
+                    line,
+                    e.message()
+                        .clone()
+                        .unwrap_or_default()
+                )),
+            }
+        }
+        rows.push((line, values));
+    }
+
+    // insert the rows and run their set hooks in one savepoint, rolled
+    // back on any error
+    let mut messages = Vec::new();
+    if errors.is_empty() {
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            table,
+            fields.join(", "),
+            vec!["?"; fields.len()].join(", ")
+        );
+        let result =
+            with_savepoint(context, |context| {
+                for (line, values) in &rows {
+                    let params = values
+                        .iter()
+                        .map(|v| v.as_ref())
+                        .collect::<Vec<&dyn ToSql>>();
+                    let connection = context
+                        .db_connection()?
+                        .connection()?;
+                    if let Err(e) = connection.execute(
+                        &sql,
+                        params.as_slice(),
+                    ) {
+                        errors.push(format!(
+                            "line {}: {}",
+                            line, e
+                        ));
+                        continue;
+                    }
+                    let row_id = RowId(
+                        connection.last_insert_rowid(),
+                    );
+                    for field in &fields {
+                        match run_set_hooks(
+                            context, table, field,
+                            row_id,
+                        ) {
+                            Ok(hook_messages) => {
+                                messages.extend(
+                                    hook_messages,
+                                )
+                            }
+                            Err(e) => {
+                                errors.push(format!(
+                            "line {}: {}",
+                            line,
+                            e.message()
+                                .clone()
+                                .unwrap_or_default()
+                        ))
+                            }
+                        }
+                    }
+                }
+                if errors.is_empty() {
+                    Ok(())
+                } else {
+                    Err(dolmen::Error::default())
+                }
+            });
+        if errors.is_empty() {
+            result?;
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(dolmen::Error::new(format!(
+            "failed to import {}, no rows were written:\n{}",
+            file.display(),
+            errors.join("\n")
+        )));
+    }
+
+    let mut response_text = format!(
+        "Imported {} row(s) into table {}.",
+        rows.len(),
+        table
+    );
+    for message in messages {
+        response_text += &format!("\n{}", message);
+    }
+    Ok(CommandResponse::new(response_text))
+}
+
+/// Parses CSV text into records, each paired with the line number it
+/// starts on. Values may be quoted with `"`, in which case they can contain
+/// commas, line breaks and doubled quotes (`""`).
+fn parse_csv(
+    text: &str,
+) -> dolmen::Result<Vec<(usize, Vec<String>)>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = text
+        .strip_prefix('\u{feff}')
+        .unwrap_or(text)
+        .chars()
+        .peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    value.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' if value.is_empty() => {
+                in_quotes = true
+            }
+            ',' if !in_quotes => {
+                record.push(std::mem::take(&mut value))
+            }
+            '\r' if !in_quotes => {}
+            '\n' if !in_quotes => {
+                record
+                    .push(std::mem::take(&mut value));
+                records.push((
+                    record_line,
+                    std::mem::take(&mut record),
+                ));
+                line += 1;
+                record_line = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                value.push(c);
+            }
+        }
+    }
+
+    if in_quotes {
+        return Err(dolmen::Error::new(format!(
+            "unterminated quoted value starting on line {}",
+            record_line
+        )));
+    }
+    if !value.is_empty() || !record.is_empty() {
+        record.push(value);
+        records.push((record_line, record));
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::import::parse_csv;
+
+    #[test]
+    fn test_parse_csv() -> dolmen::Result<()> {
+        let records = parse_csv(
+            "name,notes\r\n\
+            Alice,\"likes \"\"deadlifts\"\", hates\nburpees\"\n\
+            Bob,\n",
+        )?;
+        assert_eq!(
+            records,
+            vec![
+                (1, vec!["name".into(), "notes".into()]),
+                (
+                    2,
+                    vec![
+                        "Alice".into(),
+                        "likes \"deadlifts\", hates\nburpees"
+                            .into()
+                    ]
+                ),
+                (4, vec!["Bob".into(), "".into()]),
+            ]
+        );
+        assert!(parse_csv("name\n\"Alice").is_err());
+
+        Ok(())
+    }
+}
@@ -1,4 +1,11 @@
 //! A plugin that adds a set of commands for editing the database.
+mod audit;
+mod export;
+mod field_parsers;
+mod import;
+mod migrations;
+mod output;
+
 use clap::{Arg, ArgMatches, Command};
 use dolmen::prelude::*;
 use gui::prelude::*;
@@ -16,10 +23,32 @@ use ratatui::{
     },
 };
 use reliquary::prelude::*;
+use rusqlite::{OptionalExtension, ToSql};
+use std::path::{Path, PathBuf};
 use tabled::builder::Builder as TabledBuilder;
 use tui::prelude::*;
 use tui_textarea::Input;
 
+pub use audit::{
+    AuditEntry, audit_source, enable_audit_log,
+    row_history, set_audit_source, with_audit_source,
+};
+pub use field_parsers::{
+    FieldExportFn, FieldParseFn, FieldParsers,
+    FieldParsersContextExt, FieldSetHook,
+};
+pub use migrations::{
+    Migration, MigrationFn, Migrations,
+    MigrationsContextExt, add_column,
+    pending_migrations, run_migrations,
+    schema_version, startup,
+};
+pub use output::{
+    CommandOutput, CommandOutputContextExt,
+    OutputColumn, OutputFormat, OutputRows,
+    OutputValue, structured,
+};
+
 ///////////////////////////////////////////////////////////////////////////////
 // PUBLIC API
 ///////////////////////////////////////////////////////////////////////////////
@@ -29,6 +58,23 @@ use tui_textarea::Input;
 #[derive(Clone)]
 pub struct DbCommandsPlugin;
 
+/// Runs `f` in a database transaction, so that either every row it writes
+/// is written or, if it fails, none are. Uses a savepoint, so it can be
+/// called from inside another transaction.
+///
+/// * `db_connection` - A connection to the database.
+/// * `f` - The writes to make.
+pub fn with_transaction<T>(
+    db_connection: &mut DbConnection,
+    f: impl FnOnce(&mut DbConnection) -> dolmen::Result<T>,
+) -> dolmen::Result<T> {
+    in_savepoint(
+        db_connection,
+        |db_connection| Ok(db_connection),
+        f,
+    )
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // PRIVATE IMPLEMENTATION
 ///////////////////////////////////////////////////////////////////////////////
@@ -37,6 +83,10 @@ impl Plugin for DbCommandsPlugin {
         self,
         context: &mut Context,
     ) -> dolmen::Result<()> {
+        field_parsers::add_builtin_field_types(
+            context,
+        );
+
         context
             .add_command(Command::new("new")
                 .about("Add a new row to a table")
@@ -48,6 +98,49 @@ impl Plugin for DbCommandsPlugin {
                 ),
                 process_new_command
             )?
+            .add_command(Command::new("export")
+                .about("Writes every row of a table to a CSV or JSON file")
+                .arg(
+                    Arg::new("table")
+                        .long("table")
+                        .required(true)
+                        .help("Name of the table to export")
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["csv", "json"])
+                        .default_value("csv")
+                        .help("Format to write the file in")
+                )
+                .arg(
+                    Arg::new("out-file")
+                        .long("out-file")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(true)
+                        .help("File to write to")
+                ),
+                export::process_export_command
+            )?
+            .add_command(Command::new("import")
+                .about("Adds rows to a table from a CSV file")
+                .arg(
+                    Arg::new("table")
+                        .long("table")
+                        .required(true)
+                        .help("Name of the table to add rows to")
+                )
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(true)
+                        .help("CSV file to import. The header names the \
+                            field of each column; references to other \
+                            tables can be given by row ID or name.")
+                ),
+                import::process_import_command
+            )?
             .add_command(
                 Command::new("remove").alias("rm")
                     .about("Removes a row from a table")
@@ -92,53 +185,35 @@ impl Plugin for DbCommandsPlugin {
                         Arg::new("value")
                             .long("value")
                             .required(true)
-                            .help("Value to set the field to")
+                            .allow_hyphen_values(true)
+                            .help("Value to set the field to. Parsed as \
+                                the field's type; leave empty to clear an \
+                                optional field.")
                     ),
                 process_set_command
             )?
             .add_command(
-                Command::new("list").alias("ls")
-                    .about("Lists the rows of a table")
-                    .arg(
-                        Arg::new("table")
-                            .long("table")
-                            .required(true)
-                            .help("Name of the table to list rows from")
-                    ),
-                process_list_command
+                list_command(),
+                process_list_command,
             )?
-            .add_command(Command::new("db")
-                .about("View and update database configuration")
-                .subcommand(Command::new("info")
-                    .about("Prints information about the database")
-                )
-                .subcommand(Command::new("erase")
-                    .about("Erases the database")
-                )
-                .subcommand(
-                    Command::new("backup")
-                        .about("Copies the database to a new file")
-                        .arg(
-                            Arg::new("out-file")
-                                .long("out-file")
-                                .required(true)
-                                .help("File path to copy the database to (will be overwritten)")
-                        )
-                )
-                .subcommand(
-                    Command::new("restore")
-                        .about("Restores the database from a given file")
-                        .arg(
-                            Arg::new("file")
-                                .long("file")
-                                .required(true)
-                                .help("File path to restore the database from")
-                        )
-                )
-                .subcommand_required(true),
-                process_db_command
+            .add_command(
+                history_command(),
+                audit::process_history_command,
+            )?
+            .add_command(
+                db_command(),
+                process_db_command,
             )?;
 
+        // the commands that can print rows with --format json or csv
+        for command in [
+            list_command(),
+            history_command(),
+            db_command(),
+        ] {
+            context.add_output_command(command);
+        }
+
         if context.has_resource::<TuiNewTabTypes>() {
             context.get_resource_mut::<TuiNewTabTypes>().unwrap().register_new_tab_type::<DbInfoTabImpl>("Database Info");
             context
@@ -156,6 +231,123 @@ impl Plugin for DbCommandsPlugin {
     }
 }
 
+/// Builds the `list` command. It prints rows with `--format json` or
+/// `--format csv`.
+fn list_command() -> Command {
+    structured(Command::new("list").alias("ls")
+        .about("Lists the rows of a table")
+        .arg(
+            Arg::new("table")
+                .long("table")
+                .required(true)
+                .help("Name of the table to list rows from")
+        )
+        .arg(
+            Arg::new("where")
+                .long("where")
+                .value_parser(clap::value_parser!(ListFilter))
+                .action(clap::ArgAction::Append)
+                .help("Only list rows where field=value. Can be \
+                    given more than once. References to other \
+                    tables match the referenced row's name or ID.")
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .help("Field to sort the rows by")
+        )
+        .arg(
+            Arg::new("desc")
+                .long("desc")
+                .action(clap::ArgAction::SetTrue)
+                .help("Sort in descending order")
+        )
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .value_parser(clap::value_parser!(usize))
+                .help("Maximum number of rows to list")
+        )
+        .arg(
+            Arg::new("offset")
+                .long("offset")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("0")
+                .help("Number of rows to skip")
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .value_delimiter(',')
+                .help("Comma-separated list of fields to show")
+        ))
+}
+
+/// Builds the `history` command. It prints rows with `--format json` or
+/// `--format csv`.
+fn history_command() -> Command {
+    structured(Command::new("history")
+        .about("Shows the recorded changes to a table's rows")
+        .arg(
+            Arg::new("table")
+                .long("table")
+                .required(true)
+                .help("Name of the table")
+        )
+        .arg(
+            Arg::new("row-id")
+                .long("row-id")
+                .value_parser(clap::value_parser!(i64))
+                .help("Row ID to show the changes of. Shows \
+                    every row of the table if not set.")
+        ))
+}
+
+/// Builds the `db` command. `db info` prints rows with `--format json` or
+/// `--format csv`.
+fn db_command() -> Command {
+    Command::new("db")
+        .about("View and update database configuration")
+        .subcommand(structured(Command::new("info")
+            .about("Prints information about the database")
+        ))
+        .subcommand(Command::new("erase")
+            .about("Erases the database")
+        )
+        .subcommand(Command::new("migrate")
+            .about("Applies pending schema migrations")
+            .arg(
+                Arg::new("dry-run")
+                    .long("dry-run")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("List the pending migrations without applying them")
+            )
+        )
+        .subcommand(
+            Command::new("backup")
+                .about("Copies the database to a new file")
+                .arg(
+                    Arg::new("out-file")
+                        .long("out-file")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(true)
+                        .help("File path to copy the database to (will be overwritten)")
+                )
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Restores the database from a given file")
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(true)
+                        .help("File path to restore the database from")
+                )
+        )
+        .subcommand_required(true)
+}
+
 fn process_new_command(
     context: &mut Context,
     arg_matches: &ArgMatches,
@@ -164,9 +356,39 @@ fn process_new_command(
     let table: &String = arg_matches
         .get_one::<String>("table")
         .expect("Missing required argument");
+    let Some(table_config) = db_connection
+        .tables()
+        .iter()
+        .find(|t| t.table_name == *table)
+    else {
+        return Err(dolmen::Error::new(format!(
+            "table does not exist: {}",
+            table
+        )));
+    };
+    let string_fields =
+        (table_config.field_types_fn)()
+            .into_iter()
+            .filter(|f| {
+                f.type_id
+                    == std::any::TypeId::of::<String>()
+            })
+            .map(|f| f.name().clone())
+            .collect::<Vec<_>>();
+
     let new_row_id = db_connection
-        .new_row_in_table(table.clone())
-        .expect("couldn't insert new row!");
+        .new_row_in_table(table.clone())?;
+
+    // start text fields out empty rather than NULL, so the new row can be
+    // read (and listed) before every field has been set
+    for field in string_fields {
+        db_connection.set_field_in_table(
+            table.clone(),
+            new_row_id,
+            field,
+            "",
+        )?;
+    }
 
     Ok(CommandResponse::new(format!(
         "Inserted new row (id: {}) in table {}.",
@@ -178,7 +400,6 @@ fn process_set_command(
     context: &mut Context,
     arg_matches: &ArgMatches,
 ) -> dolmen::Result<CommandResponse> {
-    let db_connection = context.db_connection()?;
     let table = arg_matches
         .get_one::<String>("table")
         .expect("Missing required argument");
@@ -190,66 +411,665 @@ fn process_set_command(
     let field = arg_matches
         .get_one::<String>("field")
         .expect("Missing required argument");
-    let value = arg_matches
+    let text = arg_matches
         .get_one::<String>("value")
         .expect("Missing required argument");
 
-    db_connection
-        .set_field_in_table(
-            table.clone(),
+    let value = field_parsers::parse_field_value(
+        context, table, field, text,
+    )?;
+
+    let db_connection = context.db_connection()?;
+    if !db_connection
+        .get_table_row_ids(table.clone())?
+        .contains(&row_id.0)
+    {
+        return Err(dolmen::Error::new(format!(
+            "no row with id {} in table {}",
+            row_id, table
+        )));
+    }
+    let messages =
+        field_parsers::set_field_with_hooks(
+            context,
+            table,
+            field,
             row_id,
-            field.clone(),
-            value.clone(),
-        )
-        .expect("couldn't set field!");
-    Ok(CommandResponse::default())
+            |db_connection| {
+                db_connection.set_field_in_table(
+                    table.clone(),
+                    row_id,
+                    field.clone(),
+                    value,
+                )?;
+                Ok(())
+            },
+        )?;
+
+    let mut response_text = format!(
+        "Set field {} of row {} in table {}.",
+        field, row_id, table
+    );
+    for message in messages {
+        response_text += &format!("\n{}", message);
+    }
+    Ok(CommandResponse::new(response_text))
 }
 
 fn process_list_command(
     context: &mut Context,
     arg_matches: &ArgMatches,
 ) -> dolmen::Result<CommandResponse> {
-    let db_connection = context.db_connection()?;
     let table = arg_matches
         .get_one::<String>("table")
         .expect("Missing required argument");
+    let filters = arg_matches
+        .get_many::<ListFilter>("where")
+        .unwrap_or_default()
+        .collect::<Vec<_>>();
+    let sort = arg_matches.get_one::<String>("sort");
+    let desc = arg_matches.get_flag("desc");
+    let limit = arg_matches.get_one::<usize>("limit");
+    let offset = *arg_matches
+        .get_one::<usize>("offset")
+        .expect("Missing required argument");
+    let columns = arg_matches
+        .get_many::<String>("columns")
+        .map(|c| c.cloned().collect::<Vec<_>>());
 
-    let ids = db_connection
-        .get_table_row_ids(table.clone())
-        .expect("couldn't get table row ids");
+    // the column names, as accepted by --where, --sort and --columns, and
+    // their type names for structured output
+    let field_types =
+        table_field_types(context, table)?;
+    let mut field_names = vec!["id".to_string()];
+    let mut type_names = vec!["i64".to_string()];
+    for (name, type_name) in &field_types {
+        field_names.push(name.clone());
+        type_names.push(type_name.clone());
+    }
+    let field_index = |name: &String| {
+        field_names
+            .iter()
+            .position(|f| f.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                dolmen::Error::new(format!(
+                    "table {} has no field {} (fields: {})",
+                    table,
+                    name,
+                    field_names.join(", ")
+                ))
+            })
+    };
+    let query = RowQuery {
+        filters: filters
+            .into_iter()
+            .map(|f| {
+                Ok((
+                    field_index(&f.field)?,
+                    f.value.clone(),
+                ))
+            })
+            .collect::<dolmen::Result<Vec<_>>>()?,
+        sort: sort.map(field_index).transpose()?,
+        desc,
+        limit: limit.copied(),
+        offset,
+    };
+    let selected_columns = columns
+        .as_ref()
+        .map(|c| {
+            c.iter()
+                .map(field_index)
+                .collect::<dolmen::Result<Vec<_>>>()
+        })
+        .transpose()?;
+    let output_columns = selected_columns
+        .clone()
+        .unwrap_or((0..field_names.len()).collect());
+    let mut output = OutputRows::new(
+        output_columns.iter().map(|c| {
+            (
+                field_names[*c].clone(),
+                type_names[*c].clone(),
+            )
+        }),
+    );
 
-    let response_text = if ids.is_empty() {
+    let rows = read_display_rows(
+        context,
+        table,
+        &field_types,
+        &query,
+    )?;
+    let db_connection = context.db_connection()?;
+    let table_empty = rows.is_empty()
+        && db_connection
+            .get_table_row_ids(table.clone())?
+            .is_empty();
+
+    for (_, values, _) in &rows {
+        output.push_row(output_columns.iter().map(
+            |c| {
+                OutputValue::from_display(
+                    &values[*c],
+                    &type_names[*c],
+                )
+            },
+        ));
+    }
+
+    let response_text = if table_empty {
         format!("No entries in table {}.", table)
+    } else if rows.is_empty() {
+        format!(
+            "No matching entries in table {}.",
+            table
+        )
     } else {
-        let Some(table_config) = db_connection
-            .tables()
-            .iter()
-            .find(|t| t.table_name == *table)
-        else {
-            return Err(dolmen::Error::new(format!(
-                "table does not exist: {}",
-                table
-            )));
-        };
-
         let mut tabled_builder =
             TabledBuilder::default();
-        (table_config.push_tabled_header_fn)(
-            &mut tabled_builder,
-        );
-        for id in ids {
-            (table_config.push_tabled_record_fn)(
+        if let Some(columns) = &selected_columns {
+            tabled_builder.push_record(
+                columns.iter().map(|c| match *c {
+                    0 => "ID".to_string(),
+                    c => field_names[c].clone(),
+                }),
+            );
+            for (_, values, _) in &rows {
+                tabled_builder.push_record(
+                    columns
+                        .iter()
+                        .map(|c| values[*c].clone()),
+                );
+            }
+        } else if let Some(table_config) =
+            db_connection
+                .tables()
+                .iter()
+                .find(|t| t.table_name == *table)
+        {
+            (table_config.push_tabled_header_fn)(
                 &mut tabled_builder,
-                db_connection,
-                table.to_string(),
-                RowId(id),
-            )
+            );
+            for (id, _, _) in &rows {
+                (table_config.push_tabled_record_fn)(
+                    &mut tabled_builder,
+                    db_connection,
+                    table.to_string(),
+                    RowId(*id),
+                )
+            }
         }
         tabled_builder.build().to_string()
     };
+
+    context.set_output_rows(output);
     Ok(CommandResponse::new(response_text))
 }
 
+/// Gets the names of a table's fields along with the names of their types,
+/// as registered with `FieldParsers`. References to other rows have the
+/// type name `"RowId"`.
+///
+/// * `context` - The context to use.
+/// * `table` - The name of the table.
+pub(crate) fn table_field_types(
+    context: &mut Context,
+    table: &str,
+) -> dolmen::Result<Vec<(String, String)>> {
+    let db_connection = context.db_connection()?;
+    let field_types = db_connection
+        .tables()
+        .iter()
+        .find(|t| t.table_name == table)
+        .map(|t| (t.field_types_fn)())
+        .ok_or(dolmen::Error::new(format!(
+            "table does not exist: {}",
+            table
+        )))?;
+
+    let field_parsers =
+        context.get_resource::<FieldParsers>();
+    Ok(field_types
+        .iter()
+        .map(|f| {
+            let type_name = if f.type_id
+                == std::any::TypeId::of::<RowId>()
+                || f.type_id
+                    == std::any::TypeId::of::<
+                        Option<RowId>,
+                    >() {
+                Some("RowId")
+            } else {
+                field_parsers.and_then(|p| {
+                    p.type_name(f.type_id)
+                })
+            };
+            (
+                f.name().clone(),
+                type_name
+                    .unwrap_or("unknown")
+                    .to_string(),
+            )
+        })
+        .collect())
+}
+
+/// Reads the rows of a table picked by a query, as displayed. Each row is
+/// its row ID, the displayed values starting with the ID (so index `i + 1`
+/// is the `i`th field), and the raw row IDs of reference fields (empty if
+/// unset) paired with the index of their value. References are displayed
+/// as their `#[display_table]` field. A reference without one is displayed
+/// as the `name` of the referenced row, found in the table the field
+/// refers to (see `add_field_reference`), or as its row ID if that table
+/// has no names.
+///
+/// * `context` - The context to use.
+/// * `table` - The name of the table.
+/// * `field_types` - The table's fields, from `table_field_types`.
+/// * `query` - Which rows to read, and in what order.
+pub(crate) fn read_display_rows(
+    context: &mut Context,
+    table: &str,
+    field_types: &[(String, String)],
+    query: &RowQuery,
+) -> dolmen::Result<Vec<DisplayRow>> {
+    let reference_fields = field_types
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, type_name))| {
+            type_name == "RowId"
+        })
+        .map(|(i, (name, _))| {
+            (
+                i + 1,
+                name.clone(),
+                field_parsers::reference_table(
+                    context, table, name,
+                )
+                .ok(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let ids = query_row_ids(
+        context,
+        table,
+        field_types,
+        &reference_fields,
+        query,
+    )?;
+    let db_connection = context.db_connection()?;
+    let Some(table_config) = db_connection
+        .tables()
+        .iter()
+        .find(|t| t.table_name == table)
+    else {
+        return Err(dolmen::Error::new(format!(
+            "table does not exist: {}",
+            table
+        )));
+    };
+
+    let mut rows = Vec::new();
+    for id in ids {
+        let mut values = vec![id.to_string()];
+        values.extend((table_config
+            .get_fields_as_strings_fn)(
+            db_connection,
+            table.to_string(),
+            RowId(id),
+        ));
+        let mut reference_ids = Vec::new();
+        for (index, field, target_table) in
+            &reference_fields
+        {
+            let reference = db_connection
+                .get_field_in_table_row::<Option<i64>>(
+                    table,
+                    RowId(id),
+                    field.clone(),
+                )?;
+            // a reference without a `#[display_table]` is displayed as
+            // its row ID
+            let name = match (reference, target_table)
+            {
+                (
+                    Some(reference),
+                    Some(target_table),
+                ) if values[*index]
+                    == reference.to_string() =>
+                {
+                    field_parsers::row_name(
+                        db_connection,
+                        target_table,
+                        reference,
+                    )?
+                }
+                _ => None,
+            };
+            if let Some(name) = name {
+                values[*index] = name;
+            }
+            reference_ids.push((
+                *index,
+                reference
+                    .map(|r| r.to_string())
+                    .unwrap_or_default(),
+            ));
+        }
+        rows.push((id, values, reference_ids));
+    }
+    Ok(rows)
+}
+
+/// A row read by `read_display_rows`: the row ID, the displayed values and
+/// the raw row IDs of references.
+pub(crate) type DisplayRow =
+    (i64, Vec<String>, Vec<(usize, String)>);
+
+/// Which rows of a table `read_display_rows` reads, and in what order:
+/// the `--where`, `--sort`, `--desc`, `--limit` and `--offset` of the
+/// `list` command. Fields are given by index, 0 being the ID and `i + 1`
+/// the `i`th field. The default reads every row in row ID order.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RowQuery {
+    /// The fields rows must match, and the values they must match.
+    pub filters: Vec<(usize, String)>,
+
+    /// The field to sort by, if not the ID.
+    pub sort: Option<usize>,
+
+    /// Whether to sort in descending order.
+    pub desc: bool,
+
+    /// The maximum number of rows to read.
+    pub limit: Option<usize>,
+
+    /// The number of rows to skip.
+    pub offset: usize,
+}
+
+/// A `--where` filter of the `list` command, written as `field=value`.
+#[derive(Clone, Debug)]
+struct ListFilter {
+    field: String,
+    value: String,
+}
+
+impl std::str::FromStr for ListFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value) = s.split_once('=').ok_or(
+            format!("expected field=value, got {}", s),
+        )?;
+        Ok(ListFilter {
+            field: field.trim().to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Gets the row IDs of the rows a query picks, in order, filtering,
+/// sorting and paging in SQL.
+///
+/// A value matches a field if it's the field's value as stored, ignoring
+/// case and surrounding spaces, or if it parses as the field's type (as
+/// with `set`) to the stored value, so amounts match whatever way they're
+/// typed. References also match the referenced row as `list` displays it
+/// (see `read_display_rows`), and sort by it.
+///
+/// * `context` - The context to use.
+/// * `table` - The name of the table.
+/// * `field_types` - The table's fields, from `table_field_types`.
+/// * `reference_fields` - The index, name and referenced table of each
+///   reference field.
+/// * `query` - Which rows to pick, and in what order.
+fn query_row_ids(
+    context: &mut Context,
+    table: &str,
+    field_types: &[(String, String)],
+    reference_fields: &[(
+        usize,
+        String,
+        Option<String>,
+    )],
+    query: &RowQuery,
+) -> dolmen::Result<Vec<i64>> {
+    let column = |index: usize| match index {
+        0 => "t.id".to_string(),
+        i => format!("t.{}", field_types[i - 1].0),
+    };
+
+    // how the referenced rows are displayed, for the references that are
+    // filtered or sorted by
+    let mut reference_values = Vec::new();
+    for (index, field, target_table) in
+        reference_fields
+    {
+        if query.sort != Some(*index)
+            && !query
+                .filters
+                .iter()
+                .any(|(i, _)| i == index)
+        {
+            continue;
+        }
+        reference_values.push((
+            *index,
+            reference_display_values(
+                context.db_connection()?,
+                table,
+                *index,
+                field,
+                target_table.as_deref(),
+            )?,
+        ));
+    }
+    let display_values = |index: usize| {
+        reference_values
+            .iter()
+            .find(|(i, _)| *i == index)
+            .map(|(_, v)| v)
+    };
+
+    let mut conditions = Vec::new();
+    let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+    for (index, value) in &query.filters {
+        let value = value.trim();
+        if value.is_empty() {
+            conditions.push(format!(
+                "TRIM(COALESCE({}, '')) = ''",
+                column(*index)
+            ));
+            continue;
+        }
+
+        params.push(Box::new(value.to_string()));
+        let mut condition = format!(
+            "TRIM({}) = ?{} COLLATE NOCASE",
+            column(*index),
+            params.len()
+        );
+        if let Some(display_values) =
+            display_values(*index)
+        {
+            let matching = display_values
+                .iter()
+                .filter(|(_, v)| {
+                    v.trim()
+                        .eq_ignore_ascii_case(value)
+                })
+                .map(|(id, _)| id.to_string())
+                .collect::<Vec<_>>();
+            if !matching.is_empty() {
+                condition += &format!(
+                    " OR {} IN ({})",
+                    column(*index),
+                    matching.join(", ")
+                );
+            }
+        } else if *index > 0
+            && field_types[*index - 1].1 != "RowId"
+        {
+            let typed =
+                field_parsers::parse_field_value(
+                    context,
+                    table,
+                    &field_types[*index - 1].0,
+                    value,
+                )
+                .ok()
+                .filter(|v| v.to_sql().is_ok());
+            if let Some(typed) = typed {
+                params.push(typed);
+                condition += &format!(
+                    " OR {} = ?{}",
+                    column(*index),
+                    params.len()
+                );
+            }
+        }
+        conditions.push(format!("({})", condition));
+    }
+
+    let direction =
+        if query.desc { "DESC" } else { "ASC" };
+    let mut sql =
+        format!("SELECT t.id FROM {} AS t", table);
+    if !conditions.is_empty() {
+        sql += " WHERE ";
+        sql += &conditions.join(" AND ");
+    }
+    sql += " ORDER BY ";
+    if let Some(index) = query.sort {
+        let sort_column = match display_values(index) {
+            Some(display_values)
+                if !display_values.is_empty() =>
+            {
+                let mut cases = String::new();
+                for (id, value) in display_values {
+                    params
+                        .push(Box::new(value.clone()));
+                    cases += &format!(
+                        " WHEN {} THEN ?{}",
+                        id,
+                        params.len()
+                    );
+                }
+                format!(
+                    "CASE {}{} END",
+                    column(index),
+                    cases
+                )
+            }
+            _ => column(index),
+        };
+        sql += &format!(
+            "{} {}, ",
+            sort_column, direction
+        );
+    }
+    sql += &format!(
+        "t.id {} LIMIT {} OFFSET {}",
+        direction,
+        query
+            .limit
+            .map(|l| i64::try_from(l)
+                .unwrap_or(i64::MAX))
+            .unwrap_or(-1),
+        query.offset
+    );
+
+    let params = params
+        .iter()
+        .map(|p| p.as_ref())
+        .collect::<Vec<&dyn ToSql>>();
+    let connection =
+        context.db_connection()?.connection()?;
+    let mut stmt =
+        connection.prepare(&sql).map_err(sql_error)?;
+    let ids = stmt
+        .query_map(params.as_slice(), |r| r.get(0))
+        .map_err(sql_error)?
+        .collect::<rusqlite::Result<Vec<i64>>>()
+        .map_err(sql_error)?;
+    Ok(ids)
+}
+
+/// Gets how `list` displays each row a reference field refers to (see
+/// `read_display_rows`), as pairs of referenced row ID and displayed
+/// value. Each referenced row is displayed as the table displays the
+/// first row referring to it.
+///
+/// * `db_connection` - A connection to the database.
+/// * `table` - The name of the table the field is in.
+/// * `index` - The index of the field, `i + 1` being the `i`th field.
+/// * `field` - The name of the field.
+/// * `target_table` - The table the field refers to, if known.
+fn reference_display_values(
+    db_connection: &mut DbConnection,
+    table: &str,
+    index: usize,
+    field: &str,
+    target_table: Option<&str>,
+) -> dolmen::Result<Vec<(i64, String)>> {
+    let references = db_connection
+        .connection()?
+        .prepare(&format!(
+            "SELECT MIN(id), {} FROM {}
+                WHERE {} IS NOT NULL GROUP BY {}",
+            field, table, field, field
+        ))
+        .and_then(|mut stmt| {
+            stmt.query_map([], |r| {
+                Ok((r.get::<_, i64>(0)?, r.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(i64, i64)>>>()
+        })
+        .map_err(sql_error)?;
+
+    let Some(table_config) = db_connection
+        .tables()
+        .iter()
+        .find(|t| t.table_name == table)
+    else {
+        return Err(dolmen::Error::new(format!(
+            "table does not exist: {}",
+            table
+        )));
+    };
+
+    let mut display_values = Vec::new();
+    for (row, reference) in references {
+        let value = (table_config
+            .get_fields_as_strings_fn)(
+            db_connection,
+            table.to_string(),
+            RowId(row),
+        )
+        .swap_remove(index - 1);
+        // a reference without a `#[display_table]` is displayed as its
+        // row ID
+        let name = match target_table {
+            Some(target_table)
+                if value == reference.to_string() =>
+            {
+                field_parsers::row_name(
+                    db_connection,
+                    target_table,
+                    reference,
+                )?
+            }
+            _ => None,
+        };
+        display_values
+            .push((reference, name.unwrap_or(value)));
+    }
+    Ok(display_values)
+}
+
 fn process_remove_command(
     context: &mut Context,
     arg_matches: &ArgMatches,
@@ -262,12 +1082,10 @@ fn process_remove_command(
         .get_one::<i64>("row-id")
         .expect("Missing required argument");
 
-    db_connection
-        .remove_row_in_table(
-            table.clone(),
-            RowId(*row_id),
-        )
-        .expect("Couldn't remove row from table");
+    db_connection.remove_row_in_table(
+        table.clone(),
+        RowId(*row_id),
+    )?;
 
     Ok(CommandResponse::default())
 }
@@ -278,20 +1096,59 @@ fn process_db_command(
 ) -> dolmen::Result<CommandResponse> {
     match matches.subcommand() {
         Some(("info", _)) => {
-            let db_connection =
-                context.db_connection()?;
-            process_db_info_command(db_connection)
+            process_db_info_command(context)
+        }
+        Some(("migrate", sub_m)) => {
+            migrations::process_db_migrate_command(
+                context,
+                sub_m.get_flag("dry-run"),
+            )
         }
         Some(("erase", _)) => {
             let db_connection =
                 context.db_connection()?;
             erase_db(db_connection)
         }
-        Some(("backup", _)) => {
-            Ok(CommandResponse::default())
+        Some(("backup", sub_m)) => {
+            let out_file = sub_m
+                .get_one::<PathBuf>("out-file")
+                .expect("Missing required argument");
+            let table_versions =
+                migrations::latest_table_versions(
+                    context,
+                )?;
+            let db_connection =
+                context.db_connection()?;
+            backup_db(
+                db_connection,
+                out_file,
+                &table_versions,
+            )
         }
-        Some(("restore", _)) => {
-            Ok(CommandResponse::default())
+        Some(("restore", sub_m)) => {
+            let file = sub_m
+                .get_one::<PathBuf>("file")
+                .expect("Missing required argument");
+            let table_versions =
+                migrations::latest_table_versions(
+                    context,
+                )?;
+            let db_connection =
+                context.db_connection()?;
+            let mut response_text = restore_db(
+                db_connection,
+                file,
+                &table_versions,
+            )?;
+            // bring a backup taken before later migrations up to date
+            for migration in run_migrations(context)? {
+                response_text += format!(
+                    "\nApplied migration {}.",
+                    migration
+                )
+                .as_str();
+            }
+            Ok(CommandResponse::new(response_text))
         }
         _ => Ok(CommandResponse::default()),
     }
@@ -313,6 +1170,10 @@ fn db_info_text(
         } else {
             response_text += "No database path (in-memory connection)";
         }
+        response_text += "\nSchema versions: ";
+        response_text +=
+            schema_versions_text(db_connection)
+                .as_str();
     } else {
         response_text +=
             "No database connection open.";
@@ -320,21 +1181,428 @@ fn db_info_text(
     response_text
 }
 
-fn process_db_info_command(
-    db_connection: &mut DbConnection,
-) -> dolmen::Result<CommandResponse> {
-    let response_text = db_info_text(db_connection);
-
-    Ok(CommandResponse::new(response_text))
-}
-
-fn erase_db(
+/// Describes the schema version of every table, e.g.
+/// `"charge v1, client v0"`.
+fn schema_versions_text(
+    db_connection: &mut DbConnection,
+) -> String {
+    let mut tables = db_connection
+        .tables()
+        .iter()
+        .map(|t| t.table_name.clone())
+        .collect::<Vec<_>>();
+    tables.sort();
+    tables
+        .iter()
+        .map(|t| {
+            match schema_version(db_connection, t) {
+                Ok(version) => {
+                    format!("{} v{}", t, version)
+                }
+                Err(_) => format!("{} unknown", t),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn process_db_info_command(
+    context: &mut Context,
+) -> dolmen::Result<CommandResponse> {
+    let db_connection = context.db_connection()?;
+    let response_text = db_info_text(db_connection);
+
+    let mut output = OutputRows::new([
+        ("open", "bool"),
+        ("path", "String"),
+    ]);
+    output.push_row([
+        db_connection.is_open().into(),
+        db_connection
+            .db_path()
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .into(),
+    ]);
+    context.set_output_rows(output);
+
+    Ok(CommandResponse::new(response_text))
+}
+
+fn erase_db(
     db_connection: &mut DbConnection,
 ) -> dolmen::Result<CommandResponse> {
     db_connection.delete_db()?;
     Ok(CommandResponse::default())
 }
 
+/// Converts a SQLite error into a `dolmen::Error`.
+fn sql_error(e: rusqlite::Error) -> dolmen::Error {
+    dolmen::Error::new(e.to_string())
+}
+
+/// Runs `f` in a savepoint, releasing it if `f` succeeds and rolling it
+/// back if it fails. Shared by `with_transaction` and
+/// `field_parsers::with_savepoint`, which hand `f` a connection or a
+/// context.
+///
+/// * `target` - What `f` is run with.
+/// * `db_connection` - Gets the database connection from `target`.
+/// * `f` - The function to run.
+fn in_savepoint<C, T>(
+    target: &mut C,
+    db_connection: fn(
+        &mut C,
+    ) -> dolmen::Result<
+        &mut DbConnection,
+    >,
+    f: impl FnOnce(&mut C) -> dolmen::Result<T>,
+) -> dolmen::Result<T> {
+    let execute = |target: &mut C, sql: &str| {
+        db_connection(target)?
+            .connection()?
+            .execute_batch(sql)
+            .map_err(sql_error)
+    };
+    execute(target, "SAVEPOINT db_commands")?;
+    match f(target) {
+        Ok(value) => {
+            execute(target, "RELEASE db_commands")?;
+            Ok(value)
+        }
+        Err(e) => {
+            execute(
+                target,
+                "ROLLBACK TO db_commands; RELEASE db_commands",
+            )?;
+            Err(e)
+        }
+    }
+}
+
+/// Copies the open database to `out_file` using SQLite's online backup API,
+/// so the copy is consistent even while the connection is in use.
+///
+/// The backup is written to a temporary file next to `out_file` and checked
+/// with `check_db_file` before it replaces `out_file`, so a failed backup
+/// leaves any previous one in place. Backing up over the open database
+/// itself is refused.
+///
+/// * `db_connection` - The connection to back up.
+/// * `out_file` - The file to write the backup to. Overwritten if it exists.
+/// * `table_versions` - The latest schema version of every table, from
+///   `latest_table_versions`.
+fn backup_db(
+    db_connection: &mut DbConnection,
+    out_file: &Path,
+    table_versions: &[(String, u32)],
+) -> dolmen::Result<CommandResponse> {
+    if !db_connection.is_open() {
+        return Err(dolmen::Error::new(
+            "no database connection open",
+        ));
+    }
+
+    let is_open_db = db_connection
+        .db_path()
+        .as_ref()
+        .and_then(|db_path| {
+            canonical_file_path(db_path)
+        })
+        .is_some_and(|db_path| {
+            canonical_file_path(out_file)
+                == Some(db_path)
+        });
+    if is_open_db {
+        return Err(dolmen::Error::new(format!(
+            "can't back up the database over itself: {}",
+            out_file.display()
+        )));
+    }
+
+    let Some(file_name) = out_file.file_name() else {
+        return Err(dolmen::Error::new(format!(
+            "not a file path: {}",
+            out_file.display()
+        )));
+    };
+    let temp_file = out_file.with_file_name(format!(
+        ".{}.partial",
+        file_name.to_string_lossy()
+    ));
+    if temp_file.exists() {
+        std::fs::remove_file(&temp_file).map_err(
+            |e| {
+                dolmen::Error::new(format!(
+                    "couldn't overwrite {}: {}",
+                    temp_file.display(),
+                    e
+                ))
+            },
+        )?;
+    }
+
+    // make sure what we wrote can actually be read back before it replaces
+    // the previous backup
+    let result = db_connection
+        .connection()
+        .and_then(|connection| {
+            connection
+                .backup(
+                    rusqlite::MAIN_DB,
+                    &temp_file,
+                    None,
+                )
+                .map_err(sql_error)
+        })
+        .and_then(|_| {
+            check_db_file(&temp_file, table_versions)
+        })
+        .and_then(|_| {
+            std::fs::rename(&temp_file, out_file)
+                .map_err(|e| {
+                    dolmen::Error::new(format!(
+                        "couldn't overwrite {}: {}",
+                        out_file.display(),
+                        e
+                    ))
+                })
+        });
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temp_file);
+        return Err(e);
+    }
+
+    Ok(CommandResponse::new(format!(
+        "Backed up database to {}.",
+        out_file.display()
+    )))
+}
+
+/// Resolves a file path to an absolute path with symbolic links followed,
+/// so two paths to the same file compare equal. The file doesn't have to
+/// exist yet, but its directory does. Returns `None` if the path can't be
+/// resolved.
+///
+/// * `file` - The file path to resolve.
+fn canonical_file_path(
+    file: &Path,
+) -> Option<PathBuf> {
+    if let Ok(path) = file.canonicalize() {
+        return Some(path);
+    }
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => {
+            dir
+        }
+        _ => Path::new("."),
+    };
+    Some(
+        dir.canonicalize()
+            .ok()?
+            .join(file.file_name()?),
+    )
+}
+
+/// Replaces the contents of the open database with the database in `file`.
+/// Returns a description of what was done.
+///
+/// The file is checked with `check_db_file` before anything is touched. If
+/// the open database lives on disk, a copy of it is written next to it
+/// first (with a `.pre-restore` extension) so a bad restore can be undone.
+/// The restore itself goes through SQLite's backup API, so the connection
+/// sees the restored data as soon as this returns. Tables the backup
+/// doesn't have yet are created as they are in the open database; the
+/// caller runs migrations afterwards to bring the rest up to date.
+///
+/// * `db_connection` - The connection to restore into.
+/// * `file` - The database file to restore from.
+/// * `table_versions` - The latest schema version of every table, from
+///   `latest_table_versions`.
+fn restore_db(
+    db_connection: &mut DbConnection,
+    file: &Path,
+    table_versions: &[(String, u32)],
+) -> dolmen::Result<String> {
+    if !db_connection.is_open() {
+        return Err(dolmen::Error::new(
+            "no database connection open",
+        ));
+    }
+
+    let table_count =
+        check_db_file(file, table_versions)?;
+
+    let connection = db_connection.connection()?;
+    let mut table_sql = Vec::new();
+    for (table, _) in table_versions {
+        let sql = connection
+            .query_row(
+                "SELECT sql FROM sqlite_master
+                    WHERE type = 'table' AND name = ?1",
+                [table],
+                |r| r.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(sql_error)?;
+        table_sql.extend(sql.map(|sql| (table, sql)));
+    }
+
+    let mut response_text = String::default();
+    if let Some(db_path) = db_connection.db_path() {
+        let safety_path =
+            db_path.with_extension("pre-restore");
+        if safety_path.exists() {
+            std::fs::remove_file(&safety_path)
+                .map_err(|e| {
+                    dolmen::Error::new(format!(
+                        "couldn't overwrite {}: {}",
+                        safety_path.display(),
+                        e
+                    ))
+                })?;
+        }
+        db_connection
+            .connection()?
+            .backup(
+                rusqlite::MAIN_DB,
+                &safety_path,
+                None,
+            )
+            .map_err(sql_error)?;
+        response_text += format!(
+            "Saved previous database to {}.\n",
+            safety_path.display()
+        )
+        .as_str();
+    }
+
+    db_connection
+        .connection_mut()?
+        .restore(
+            rusqlite::MAIN_DB,
+            file,
+            None::<fn(rusqlite::backup::Progress)>,
+        )
+        .map_err(sql_error)?;
+
+    let connection = db_connection.connection()?;
+    for (table, sql) in table_sql {
+        if migrations::table_columns(connection, table)
+            .map_err(sql_error)?
+            .is_empty()
+        {
+            connection
+                .execute(&sql, [])
+                .map_err(sql_error)?;
+        }
+    }
+
+    response_text += format!(
+        "Restored database from {} ({} tables).",
+        file.display(),
+        table_count
+    )
+    .as_str();
+
+    Ok(response_text)
+}
+
+/// Checks that `file` is an intact SQLite database with at least one of the
+/// registered tables, none of them at a schema version newer than this
+/// build knows. Tables and fields added since the file was written are
+/// fine, as migrations add them. Returns the number of tables found.
+///
+/// * `file` - The database file to check.
+/// * `table_versions` - The latest schema version of every table, from
+///   `latest_table_versions`.
+fn check_db_file(
+    file: &Path,
+    table_versions: &[(String, u32)],
+) -> dolmen::Result<usize> {
+    if !file.is_file() {
+        return Err(dolmen::Error::new(format!(
+            "database file not found: {}",
+            file.display()
+        )));
+    }
+
+    let other = rusqlite::Connection::open_with_flags(
+        file,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )
+    .map_err(sql_error)?;
+
+    let integrity: String = other
+        .query_row("PRAGMA quick_check", [], |r| {
+            r.get(0)
+        })
+        .map_err(|e| {
+            dolmen::Error::new(format!(
+                "{} is not a valid database: {}",
+                file.display(),
+                e
+            ))
+        })?;
+    if integrity != "ok" {
+        return Err(dolmen::Error::new(format!(
+            "{} failed the integrity check: {}",
+            file.display(),
+            integrity
+        )));
+    }
+
+    let has_versions = !migrations::table_columns(
+        &other,
+        "schema_version",
+    )
+    .map_err(sql_error)?
+    .is_empty();
+    let mut table_count = 0;
+    for (table, latest_version) in table_versions {
+        if migrations::table_columns(&other, table)
+            .map_err(sql_error)?
+            .is_empty()
+        {
+            continue;
+        }
+        table_count += 1;
+
+        let version = if has_versions {
+            other
+                .query_row(
+                    "SELECT COALESCE(MAX(version), 0)
+                        FROM schema_version
+                        WHERE table_name = ?1",
+                    [table],
+                    |r| r.get::<_, u32>(0),
+                )
+                .map_err(sql_error)?
+        } else {
+            0
+        };
+        if version > *latest_version {
+            return Err(dolmen::Error::new(format!(
+                "{} has table {} at schema version {}, but this version \
+                    only knows up to version {}",
+                file.display(),
+                table,
+                version,
+                latest_version
+            )));
+        }
+    }
+
+    if table_count == 0 {
+        return Err(dolmen::Error::new(format!(
+            "{} has none of the database's tables",
+            file.display()
+        )));
+    }
+
+    Ok(table_count)
+}
+
 struct DbInfoTabImpl;
 
 #[derive(Default)]
@@ -387,6 +1655,10 @@ impl TabImpl for DbInfoTabImpl {
 
 struct EditTabImpl;
 
+/// The source recorded in the audit log for changes made in the Edit Table
+/// tab.
+const EDIT_TAB_AUDIT_SOURCE: &str = "tui: Edit Table";
+
 #[derive(Default)]
 struct EditTabState {
     list_state: ListState,
@@ -660,10 +1932,32 @@ impl TabImpl for EditTabImpl {
                     .table_name
                     .clone()
                     .unwrap();
-                context
+                let result = context
                     .db_connection()
-                    .unwrap()
-                    .new_row_in_table(table_name);
+                    .and_then(|db_connection| {
+                        with_audit_source(
+                            db_connection,
+                            EDIT_TAB_AUDIT_SOURCE,
+                            |db_connection| {
+                                db_connection
+                                    .new_row_in_table(
+                                        table_name,
+                                    )
+                            },
+                        )
+                    });
+                if let Err(e) = result {
+                    context
+                        .tab_state_mut::<EditTabState>(
+                            tab_id,
+                        )
+                        .unwrap()
+                        .display_err = Some(
+                        e.message()
+                            .clone()
+                            .unwrap_or("".into()),
+                    );
+                }
             }
             "delete_row" => {
                 let tab_state = context
@@ -685,13 +1979,27 @@ impl TabImpl for EditTabImpl {
                         .unwrap()
                         + 1;
 
-                    context
+                    let result = context
                         .db_connection()
-                        .unwrap()
-                        .remove_row_in_table(
-                            table_name,
-                            RowId(row_id as i64),
-                        );
+                        .and_then(|db_connection| {
+                            with_audit_source(
+                                db_connection,
+                                EDIT_TAB_AUDIT_SOURCE,
+                                |db_connection| {
+                                    db_connection
+                                        .remove_row_in_table(
+                                            table_name,
+                                            RowId(
+                                                row_id
+                                                    as i64,
+                                            ),
+                                        )
+                                },
+                            )
+                        });
+                    if let Err(e) = result {
+                        context.tab_state_mut::<EditTabState>(tab_id).unwrap().display_err = Some(e.message().clone().unwrap_or("".into()));
+                    }
                 }
             }
             _ => {}
@@ -756,15 +2064,27 @@ impl TabImpl for EditTabImpl {
                     .unwrap()
                     .into_lines()[0]
                     .clone();
-                context
-                    .db_connection()
-                    .unwrap()
-                    .set_field_in_table(
-                        table_name,
-                        RowId((edit_row + 1) as i64),
-                        field_name,
-                        text,
+                let row_id =
+                    RowId((edit_row + 1) as i64);
+                let result = set_field_from_edit_tab(
+                    context,
+                    &table_name,
+                    &field_name,
+                    row_id,
+                    &text,
+                );
+                if let Err(e) = result {
+                    context
+                        .tab_state_mut::<EditTabState>(
+                            tab_id,
+                        )
+                        .unwrap()
+                        .display_err = Some(
+                        e.message()
+                            .clone()
+                            .unwrap_or("".into()),
                     );
+                }
                 context
                     .get_resource_mut::<Tui>()
                     .unwrap()
@@ -805,6 +2125,53 @@ impl TabImpl for EditTabImpl {
     }
 }
 
+/// Sets a field to text typed into the Edit Table tab. The text is parsed
+/// as the field's type, as with `set`, and the field's set hooks run with
+/// the write. Returns the messages reported by the hooks.
+///
+/// * `context` - The context to use.
+/// * `table` - The name of the table the field is in.
+/// * `field` - The name of the field.
+/// * `row_id` - The row ID of the row.
+/// * `text` - The text typed in.
+fn set_field_from_edit_tab(
+    context: &mut Context,
+    table: &str,
+    field: &str,
+    row_id: RowId,
+    text: &str,
+) -> dolmen::Result<Vec<String>> {
+    let value = field_parsers::parse_field_value(
+        context, table, field, text,
+    )?;
+
+    // the hooks get the context, so the audit source is switched by hand
+    // rather than with `with_audit_source`
+    let previous =
+        audit_source(context.db_connection()?)?;
+    set_audit_source(
+        context.db_connection()?,
+        EDIT_TAB_AUDIT_SOURCE,
+    )?;
+    let result = field_parsers::set_field_with_hooks(
+        context,
+        table,
+        field,
+        row_id,
+        |db_connection| {
+            db_connection.set_field_in_table(
+                table, row_id, field, value,
+            )?;
+            Ok(())
+        },
+    );
+    set_audit_source(
+        context.db_connection()?,
+        &previous,
+    )?;
+    result
+}
+
 fn on_select_cell(
     context: &mut Context,
     tab_id: usize,
@@ -838,19 +2205,12 @@ fn on_select_cell(
         ))?;
     let field_type = field_types.get(selected_cell.1).ok_or(dolmen::Error::new(format!("couldn't get field type id, field_types: {:?}", field_types)))?;
 
-    let field_type_id = field_type.type_id;
+    // what's typed is parsed as the field's type when it's entered
     context
         .tab_state_mut::<EditTabState>(tab_id)
         .unwrap()
         .edit_field_name =
         Some(field_type.name.clone());
-    if field_type_id
-        != std::any::TypeId::of::<String>()
-    {
-        return Err(dolmen::Error::new(
-            "can't edit field type",
-        ));
-    }
     if let Some(table_state) = &mut context
         .tab_state_mut::<EditTabState>(tab_id)?
         .table_state
@@ -1005,3 +2365,1309 @@ fn render_table_view(
 }
 
 struct TableEditorWindow;
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        CommandOutputContextExt, DbCommandsPlugin,
+        MigrationsContextExt, add_column, row_history,
+        run_migrations, schema_version,
+        set_audit_source, set_field_from_edit_tab,
+        startup, with_audit_source,
+    };
+    use chrono::{NaiveDate, NaiveTime};
+    use dolmen::prelude::*;
+    use reliquary::prelude::*;
+    use rusqlite::types::Value;
+
+    #[derive(TableRow, Debug)]
+    struct Trainer {
+        name: String,
+        company_name: String,
+        address: String,
+        email: String,
+        phone: String,
+    }
+
+    #[derive(TableRow, Debug)]
+    struct Client {
+        name: String,
+    }
+
+    #[derive(TableRow, Debug)]
+    struct Exercise {
+        name: String,
+    }
+
+    #[derive(TableRow, Debug)]
+    struct Session {
+        date: NaiveDate,
+        start_time: NaiveTime,
+        duration_minutes: u32,
+        location: String,
+        status: String,
+        #[display_table("trainer", "name")]
+        trainer: RowId,
+        #[display_table("client", "name")]
+        client: RowId,
+        charge: Option<RowId>,
+    }
+
+    // a table whose reference is displayed as something other than a name
+    #[derive(TableRow, Debug)]
+    struct Message {
+        #[display_table("trainer", "email")]
+        trainer: RowId,
+        text: String,
+    }
+
+    // Sets up a context with the test tables and an in-memory database,
+    // without starting it.
+    fn test_context() -> dolmen::Result<Context> {
+        let mut context = Context::new();
+
+        context
+            .add_plugin(DbPlugin)?
+            .add_plugin(DbCommandsPlugin)?;
+        context
+            .add_table(TableConfig::new::<Trainer>(
+                "trainer",
+            ))
+            .add_table(TableConfig::new::<Client>(
+                "client",
+            ))
+            .add_table(TableConfig::new::<Exercise>(
+                "exercise",
+            ))
+            .add_table(TableConfig::new::<Session>(
+                "session",
+            ));
+
+        context
+            .get_resource_mut::<DbConfig>()
+            .unwrap()
+            .open_db_in_memory = true;
+
+        Ok(context)
+    }
+
+    fn setup_test_context() -> dolmen::Result<Context>
+    {
+        let mut context = test_context()?;
+        context.startup()?;
+        Ok(context)
+    }
+
+    #[test]
+    fn test_backup_restore() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+
+        let backup_path = std::env::temp_dir()
+            .join("training_assistant_backup_test.db");
+
+        context.execute("new --table=trainer")?;
+        let response = context.execute(
+            format!(
+                "db backup --out-file={}",
+                backup_path.display()
+            )
+            .as_str(),
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            format!(
+                "Backed up database to {}.",
+                backup_path.display()
+            )
+        );
+
+        context.execute("new --table=trainer")?;
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_table_row_ids("trainer")?,
+            vec![1, 2]
+        );
+
+        context.execute(
+            format!(
+                "db restore --file={}",
+                backup_path.display()
+            )
+            .as_str(),
+        )?;
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_table_row_ids("trainer")?,
+            vec![1]
+        );
+
+        // backing up again replaces the previous backup
+        context.execute(
+            format!(
+                "db backup --out-file={}",
+                backup_path.display()
+            )
+            .as_str(),
+        )?;
+        assert!(
+            !backup_path
+                .with_file_name(
+                    ".training_assistant_backup_test.db.partial"
+                )
+                .exists()
+        );
+
+        std::fs::remove_file(backup_path).unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_file()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+
+        let bad_path = std::env::temp_dir()
+            .join("training_assistant_bad_restore.db");
+        std::fs::write(&bad_path, "not a database")
+            .unwrap();
+
+        assert!(
+            context
+                .execute(
+                    format!(
+                        "db restore --file={}",
+                        bad_path.display()
+                    )
+                    .as_str()
+                )
+                .is_err()
+        );
+
+        std::fs::remove_file(bad_path).unwrap();
+
+        Ok(())
+    }
+
+    // Backups taken before later migrations restore and get migrated.
+    #[test]
+    fn test_restore_older_backup() -> dolmen::Result<()>
+    {
+        let mut context = setup_test_context()?;
+        context.add_migration(
+            "trainer",
+            1,
+            "add invoice_pattern",
+            |c| {
+                add_column(
+                    c,
+                    "trainer",
+                    "invoice_pattern",
+                    "TEXT",
+                )
+            },
+        );
+        run_migrations(&mut context)?;
+
+        let backup_path = std::env::temp_dir()
+            .join("training_assistant_old_backup.db");
+        context.execute("new --table=client")?;
+        context.execute(
+            format!(
+                "db backup --out-file={}",
+                backup_path.display()
+            )
+            .as_str(),
+        )?;
+
+        // make the backup look like it's from before the trainer migration
+        // and the exercise table
+        rusqlite::Connection::open(&backup_path)
+            .unwrap()
+            .execute_batch(
+                "ALTER TABLE trainer DROP COLUMN invoice_pattern;
+                UPDATE schema_version SET version = 0
+                    WHERE table_name = 'trainer';
+                DROP TABLE exercise;",
+            )
+            .unwrap();
+
+        let response = context.execute(
+            format!(
+                "db restore --file={}",
+                backup_path.display()
+            )
+            .as_str(),
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            format!(
+                "Restored database from {} (3 tables).\n\
+                Applied migration trainer v1: add invoice_pattern.",
+                backup_path.display()
+            )
+        );
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_table_row_ids("client")?,
+            vec![1]
+        );
+        context.execute("new --table=exercise")?;
+        context.execute("new --table=session")?;
+
+        // but not ones from a newer schema
+        rusqlite::Connection::open(&backup_path)
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO schema_version
+                    (table_name, version) VALUES ('trainer', 2)",
+                [],
+            )
+            .unwrap();
+        assert!(
+            context
+                .execute(
+                    format!(
+                        "db restore --file={}",
+                        backup_path.display()
+                    )
+                    .as_str()
+                )
+                .is_err()
+        );
+
+        std::fs::remove_file(backup_path).unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_validates_values() -> dolmen::Result<()>
+    {
+        let mut context = setup_test_context()?;
+
+        context.execute("new --table=trainer")?;
+        context.execute("new --table=client")?;
+        context.execute("new --table=session")?;
+
+        let response = context.execute(
+            "set --table=trainer --row-id=1 --field=name \
+                --value=Tara",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Set field name of row 1 in table trainer."
+        );
+
+        context.execute(
+            "set --table=session --row-id=1 --field=date \
+                --value=2026-06-01",
+        )?;
+        context.execute(
+            "set --table=session --row-id=1 --field=trainer \
+                --value=1",
+        )?;
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_field_in_table_row::<i64>(
+                    "session",
+                    RowId(1),
+                    "trainer",
+                )?,
+            1
+        );
+
+        let mut error_message = |command: &str| {
+            context
+                .execute(command)
+                .err()
+                .and_then(|e| e.message().clone())
+                .unwrap_or_default()
+        };
+        assert_eq!(
+            error_message(
+                "set --table=session --row-id=1 --field=date \
+                    --value=2026-13-01"
+            ),
+            "invalid value for field date (NaiveDate): \
+                input is out of range"
+        );
+        assert_eq!(
+            error_message(
+                "set --table=session --row-id=1 \
+                    --field=duration_minutes --value=sixty"
+            ),
+            "invalid value for field duration_minutes (u32): \
+                invalid digit found in string"
+        );
+        assert_eq!(
+            error_message(
+                "set --table=session --row-id=1 --field=client \
+                    --value=7"
+            ),
+            "invalid value for field client: no row with id 7 \
+                in table client"
+        );
+        assert_eq!(
+            error_message(
+                "set --table=session --row-id=2 --field=date \
+                    --value=2026-06-01"
+            ),
+            "no row with id 2 in table session"
+        );
+        assert_eq!(
+            error_message(
+                "set --table=session --row-id=1 --field=nope \
+                    --value=1"
+            ),
+            "table session has no field nope (fields: date, \
+                start_time, duration_minutes, location, status, \
+                trainer, client, charge)"
+        );
+
+        Ok(())
+    }
+
+    // The Edit Table tab stores what's typed as the field's type, as `set`
+    // does, and rejects text that isn't one.
+    #[test]
+    fn test_edit_tab_parses_values()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        context.execute("new --table=session")?;
+
+        assert!(
+            set_field_from_edit_tab(
+                &mut context,
+                "session",
+                "duration_minutes",
+                RowId(1),
+                "sixty",
+            )
+            .is_err()
+        );
+        set_field_from_edit_tab(
+            &mut context,
+            "session",
+            "duration_minutes",
+            RowId(1),
+            "45",
+        )?;
+        let (value, value_type): (i64, String) =
+            context
+                .db_connection()?
+                .connection()?
+                .query_row(
+                    "SELECT duration_minutes,
+                    typeof(duration_minutes)
+                FROM session WHERE id = 1",
+                    [],
+                    |r| Ok((r.get(0)?, r.get(1)?)),
+                )
+                .unwrap();
+        assert_eq!(value, 45);
+        assert_eq!(value_type, "integer");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+
+        let dir = std::env::temp_dir()
+            .join("training_assistant_test_import");
+        std::fs::create_dir_all(&dir).unwrap();
+        let clients = dir.join("clients.csv");
+        let sessions = dir.join("sessions.csv");
+        let bad_sessions =
+            dir.join("bad_sessions.csv");
+        std::fs::write(
+            &clients,
+            "Name\nAlice\n\"Smith, Jo\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &sessions,
+            "date,start_time,duration_minutes,client\n\
+            2026-06-01,09:00:00,60,smith, jo\n",
+        )
+        .unwrap();
+
+        let response = context.execute(
+            format!(
+                "import --table=client --file={}",
+                clients.display()
+            )
+            .as_str(),
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Imported 2 row(s) into table client."
+        );
+
+        // names containing commas have to be quoted
+        assert!(
+            context
+                .execute(
+                    format!(
+                        "import --table=session --file={}",
+                        sessions.display()
+                    )
+                    .as_str(),
+                )
+                .is_err()
+        );
+        std::fs::write(
+            &sessions,
+            "date,start_time,duration_minutes,client\n\
+            2026-06-01,09:00:00,60,\"smith, jo\"\n\
+            2026-06-02,10:00:00,45,1\n",
+        )
+        .unwrap();
+        context.execute(
+            format!(
+                "import --table=session --file={}",
+                sessions.display()
+            )
+            .as_str(),
+        )?;
+        let db_connection = context.db_connection()?;
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<i64>(
+                    "session",
+                    RowId(1),
+                    "client",
+                )?,
+            2
+        );
+        assert_eq!(
+            db_connection
+                .get_field_in_table_row::<String>(
+                    "session",
+                    RowId(2),
+                    "location",
+                )?,
+            ""
+        );
+
+        // any invalid row means nothing is written
+        std::fs::write(
+            &bad_sessions,
+            "date,duration_minutes,client\n\
+            2026-06-03,60,Alice\n\
+            2026-06-04,sixty,Alice\n\
+            2026-06-05,60,Bob\n",
+        )
+        .unwrap();
+        let error = context
+            .execute(
+                format!(
+                    "import --table=session --file={}",
+                    bad_sessions.display()
+                )
+                .as_str(),
+            )
+            .err()
+            .and_then(|e| e.message().clone())
+            .unwrap_or_default();
+        assert_eq!(
+            error,
+            format!(
+                "failed to import {}, no rows were written:\n\
+                line 3: invalid value for field duration_minutes \
+                (u32): invalid digit found in string\n\
+                line 4: invalid value for field client: no row \
+                named Bob in table client",
+                bad_sessions.display()
+            )
+        );
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_table_row_ids("session")?
+                .len(),
+            2
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+
+        context.execute("new --table=trainer")?;
+        context.execute(
+            "set --table=trainer --row-id=1 --field=name \
+                --value='Tara Jones'",
+        )?;
+        context.execute("new --table=session")?;
+        context.execute(
+            "set --table=session --row-id=1 --field=trainer \
+                --value=1",
+        )?;
+        context.execute(
+            "set --table=session --row-id=1 --field=date \
+                --value=2026-06-01",
+        )?;
+        context.execute(
+            "set --table=session --row-id=1 \
+                --field=duration_minutes --value=60",
+        )?;
+
+        let dir = std::env::temp_dir()
+            .join("training_assistant_test_export");
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv_file = dir.join("sessions.csv");
+        let json_file = dir.join("sessions.json");
+
+        let response = context.execute(
+            format!(
+                "export --table=session --out-file={}",
+                csv_file.display()
+            )
+            .as_str(),
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            format!(
+                "Exported 1 row(s) from table session to {}.",
+                csv_file.display()
+            )
+        );
+        let csv = std::fs::read_to_string(&csv_file)
+            .unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "id,date,start_time,duration_minutes,location,status,\
+                trainer,trainer_display,client,client_display,charge,\
+                charge_display"
+        );
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .starts_with("1,2026-06-01,")
+        );
+        assert!(csv.contains(",1,Tara Jones,"));
+
+        context.execute(
+            format!(
+                "export --table=session --format=json \
+                    --out-file={}",
+                json_file.display()
+            )
+            .as_str(),
+        )?;
+        let json = std::fs::read_to_string(&json_file)
+            .unwrap();
+        assert!(json.contains("\"trainer\": 1,"));
+        assert!(json.contains(
+            "\"trainer_display\": \"Tara Jones\","
+        ));
+        assert!(
+            json.contains("\"duration_minutes\": 60,")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrations() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        // start from a database with the plugins' own migrations applied
+        run_migrations(&mut context)?;
+
+        context.execute("new --table=client")?;
+        context.execute(
+            "set --table=client --row-id=1 --field=name \
+                --value=Alice",
+        )?;
+
+        // declared out of order on purpose
+        context.add_migration(
+            "client",
+            2,
+            "default nicknames to names",
+            |c| {
+                c.execute(
+                    "UPDATE client SET nickname = name",
+                    [],
+                )
+                .map(|_| ())
+            },
+        );
+        context.add_migration(
+            "client",
+            1,
+            "add nickname",
+            |c| {
+                add_column(
+                    c, "client", "nickname", "TEXT",
+                )
+            },
+        );
+
+        let response =
+            context.execute("db migrate --dry-run")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Would apply 2 migration(s):\n\
+            client v1: add nickname\n\
+            client v2: default nicknames to names"
+        );
+        assert_eq!(
+            schema_version(
+                context.db_connection()?,
+                "client"
+            )?,
+            0
+        );
+
+        let response =
+            context.execute("db migrate")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Applied 2 migration(s):\n\
+            client v1: add nickname\n\
+            client v2: default nicknames to names"
+        );
+        let nickname: String = context
+            .db_connection()?
+            .connection()?
+            .query_row(
+                "SELECT nickname FROM client WHERE id = 1",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(nickname, "Alice");
+
+        let response =
+            context.execute("db migrate")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Database schema is up to date."
+        );
+        let response = context.execute("db info")?;
+        assert!(
+            response
+                .text()
+                .unwrap()
+                .contains("client v2, exercise v0")
+        );
+
+        // a gap in the numbering is an error
+        context.add_migration(
+            "exercise",
+            2,
+            "skips version 1",
+            |_| Ok(()),
+        );
+        assert!(
+            context.execute("db migrate").is_err()
+        );
+
+        Ok(())
+    }
+
+    // a table's setup runs on new tables too, which skip their migrations
+    #[test]
+    fn test_table_setup() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        run_migrations(&mut context)?;
+        // a client table that wasn't in the database when migrations last
+        // ran
+        context
+            .db_connection()?
+            .connection()?
+            .execute(
+                "DELETE FROM schema_version
+                    WHERE table_name = 'client'",
+                [],
+            )
+            .unwrap();
+
+        context.add_migration(
+            "client",
+            1,
+            "index names",
+            |c| {
+                c.execute_batch(
+                    "CREATE INDEX client_name
+                        ON client (name);",
+                )
+            },
+        );
+        context.add_table_setup("client", |c| {
+            c.execute_batch(
+                "CREATE INDEX IF NOT EXISTS client_name
+                    ON client (name);",
+            )
+        });
+
+        assert!(
+            run_migrations(&mut context)?.is_empty()
+        );
+        run_migrations(&mut context)?;
+        assert_eq!(
+            schema_version(
+                context.db_connection()?,
+                "client"
+            )?,
+            1
+        );
+        let indexed: bool = context
+            .db_connection()?
+            .connection()?
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM sqlite_master
+                    WHERE type = 'index'
+                        AND name = 'client_name')",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert!(indexed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_query() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+
+        for name in ["Clarissa", "Bob", "Alice"] {
+            context.execute("new --table=client")?;
+            context.execute(
+                format!(
+                    "set --table=client --row-id={} \
+                        --field=name --value={}",
+                    context
+                        .db_connection()?
+                        .get_table_row_ids("client")?
+                        .len(),
+                    name
+                )
+                .as_str(),
+            )?;
+        }
+        for (client, date) in [
+            (1, "2026-06-03"),
+            (2, "2026-06-01"),
+            (1, "2026-06-02"),
+        ] {
+            context.execute("new --table=session")?;
+            let row_id = context
+                .db_connection()?
+                .get_table_row_ids("session")?
+                .len();
+            context.execute(
+                format!(
+                    "set --table=session --row-id={} \
+                        --field=client --value={}",
+                    row_id, client
+                )
+                .as_str(),
+            )?;
+            context.execute(
+                format!(
+                    "set --table=session --row-id={} \
+                        --field=date --value={}",
+                    row_id, date
+                )
+                .as_str(),
+            )?;
+        }
+
+        let response = context.execute(
+            "list --table=client --sort=name --columns=name",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "+----------+\n\
+            | name     |\n\
+            +----------+\n\
+            | Alice    |\n\
+            +----------+\n\
+            | Bob      |\n\
+            +----------+\n\
+            | Clarissa |\n\
+            +----------+"
+        );
+
+        // filter sessions by client name, and by client ID
+        for filter in ["client=clarissa", "client=1"] {
+            let response = context.execute(
+                format!(
+                    "list --table=session --where={} \
+                        --sort=date --desc --columns=id,date",
+                    filter
+                )
+                .as_str(),
+            )?;
+            assert_eq!(
+                response.text().unwrap(),
+                "+----+------------+\n\
+                | ID | date       |\n\
+                +----+------------+\n\
+                | 1  | 2026-06-03 |\n\
+                +----+------------+\n\
+                | 3  | 2026-06-02 |\n\
+                +----+------------+"
+            );
+        }
+
+        // references sort by the referenced row's name, and the limit
+        // applies to the matching rows
+        let response = context.execute(
+            "list --table=session --sort=client --columns=id",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "+----+\n\
+            | ID |\n\
+            +----+\n\
+            | 2  |\n\
+            +----+\n\
+            | 1  |\n\
+            +----+\n\
+            | 3  |\n\
+            +----+"
+        );
+        let response = context.execute(
+            "list --table=session --where=client=Clarissa \
+                --limit=1 --offset=1 --columns=id",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "+----+\n\
+            | ID |\n\
+            +----+\n\
+            | 3  |\n\
+            +----+"
+        );
+
+        let response = context.execute(
+            "list --table=client --sort=id --limit=1 \
+                --offset=1 --columns=id,name",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "+----+------+\n\
+            | ID | name |\n\
+            +----+------+\n\
+            | 2  | Bob  |\n\
+            +----+------+"
+        );
+
+        let response = context.execute(
+            "list --table=client --where=name=Zed",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "No matching entries in table client."
+        );
+        assert!(
+            context
+                .execute(
+                    "list --table=client --sort=age"
+                )
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    // references filter and sort by their `#[display_table]` field, as
+    // they're displayed
+    #[test]
+    fn test_list_query_display_table()
+    -> dolmen::Result<()> {
+        let mut context = test_context()?;
+        context.add_table(
+            TableConfig::new::<Message>("message"),
+        );
+        context.startup()?;
+
+        for (name, email) in [
+            ("Alice", "zoe@example.com"),
+            ("Zed", "abe@example.com"),
+        ] {
+            context.execute("new --table=trainer")?;
+            let row_id = context
+                .db_connection()?
+                .get_table_row_ids("trainer")?
+                .len();
+            context.execute(
+                format!(
+                    "set --table=trainer --row-id={} \
+                        --field=name --value={}",
+                    row_id, name
+                )
+                .as_str(),
+            )?;
+            context.execute(
+                format!(
+                    "set --table=trainer --row-id={} \
+                        --field=email --value={}",
+                    row_id, email
+                )
+                .as_str(),
+            )?;
+        }
+        for trainer in [1, 2, 1] {
+            context.execute("new --table=message")?;
+            let row_id = context
+                .db_connection()?
+                .get_table_row_ids("message")?
+                .len();
+            context.execute(
+                format!(
+                    "set --table=message --row-id={} \
+                        --field=trainer --value={}",
+                    row_id, trainer
+                )
+                .as_str(),
+            )?;
+        }
+
+        let response = context.execute(
+            "list --table=message \
+                --where=trainer=zoe@example.com \
+                --columns=id,trainer",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "+----+-----------------+\n\
+            | ID | trainer         |\n\
+            +----+-----------------+\n\
+            | 1  | zoe@example.com |\n\
+            +----+-----------------+\n\
+            | 3  | zoe@example.com |\n\
+            +----+-----------------+"
+        );
+        let response = context.execute(
+            "list --table=message --where=trainer=Alice",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "No matching entries in table message."
+        );
+
+        let response = context.execute(
+            "list --table=message --sort=trainer \
+                --columns=id",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "+----+\n\
+            | ID |\n\
+            +----+\n\
+            | 2  |\n\
+            +----+\n\
+            | 1  |\n\
+            +----+\n\
+            | 3  |\n\
+            +----+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_output_rows() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+
+        context.execute("new --table=client")?;
+        context.execute(
+            "set --table=client --row-id=1 --field=name \
+                --value='Smith, Jo'",
+        )?;
+        context.execute("list --table=client")?;
+        let rows = context.take_output_rows().unwrap();
+        assert_eq!(
+            rows.to_csv(),
+            "id,name\n1,\"Smith, Jo\""
+        );
+        assert_eq!(rows.columns()[0].type_name, "i64");
+        assert_eq!(
+            rows.columns()[1].type_name,
+            "String"
+        );
+
+        context.execute(
+            "list --table=client --where=name=nobody",
+        )?;
+        let rows = context.take_output_rows().unwrap();
+        assert_eq!(rows.to_csv(), "id,name");
+
+        Ok(())
+    }
+
+    // Starting up brings the database up to date, so commands can be run
+    // on it straight away.
+    #[test]
+    fn test_startup() -> dolmen::Result<()> {
+        let mut context = test_context()?;
+        assert!(startup(&mut context)?.is_empty());
+
+        let response = context.execute("db info")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Database connection open.\n\
+                No database path (in-memory connection)\n\
+                Schema versions: client v0, exercise v0, session v0, \
+                trainer v0"
+        );
+
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_table_row_ids("trainer")?,
+            Vec::<i64>::new()
+        );
+        let response =
+            context.execute("new --table=trainer")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Inserted new row (id: 1) in table trainer."
+        );
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_table_row_ids("trainer")?,
+            vec![1]
+        );
+
+        let response =
+            context.execute("list --table=trainer")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "+----+------+--------------+---------+-------+-------+\n\
+            | ID | name | company_name | address | email | phone |\n\
+            +----+------+--------------+---------+-------+-------+\n\
+            | 1  |      |              |         |       |       |\n\
+            +----+------+--------------+---------+-------+-------+"
+        );
+
+        context.execute("db erase")?;
+        assert!(!context.db_connection()?.is_open());
+
+        Ok(())
+    }
+
+    // The audit log is on as soon as `startup` opens the database, even if
+    // bringing the schema up to date then fails.
+    #[test]
+    fn test_startup_audit_log() -> dolmen::Result<()> {
+        let mut context = test_context()?;
+        context.add_migration(
+            "trainer",
+            1,
+            "fails",
+            |c| {
+                c.execute_batch(
+                    "SELECT * FROM missing",
+                )
+            },
+        );
+        assert!(startup(&mut context).is_err());
+
+        context.execute("new --table=trainer")?;
+        let history = row_history(
+            context.db_connection()?,
+            "trainer",
+            Some(RowId(1)),
+        )?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].action, "insert");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_history() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        // bringing the schema up to date turns on the audit log
+        run_migrations(&mut context)?;
+        set_audit_source(
+            context.db_connection()?,
+            "test",
+        )?;
+
+        context.execute("new --table=client")?;
+        context.execute(
+            "set --table=client --row-id=1 --field=name \
+                --value=Jo",
+        )?;
+        with_audit_source(
+            context.db_connection()?,
+            "tui: Edit Table",
+            |db_connection| {
+                db_connection.set_field_in_table(
+                    "client",
+                    RowId(1),
+                    "name",
+                    "Sam",
+                )
+            },
+        )?;
+        context
+            .execute("rm --table=client --row-id=1")?;
+
+        let db_connection = context.db_connection()?;
+        let history = row_history(
+            db_connection,
+            "client",
+            Some(RowId(1)),
+        )?
+        .into_iter()
+        .map(|e| {
+            (
+                e.source,
+                e.action,
+                e.field,
+                e.old_value,
+                e.new_value,
+            )
+        })
+        .collect::<Vec<_>>();
+        let text = |t: &str| Value::Text(t.into());
+        assert_eq!(
+            history,
+            vec![
+                (
+                    "test".into(),
+                    "insert".into(),
+                    None,
+                    Value::Null,
+                    Value::Null
+                ),
+                (
+                    "test".into(),
+                    "update".into(),
+                    Some("name".into()),
+                    Value::Null,
+                    text("")
+                ),
+                (
+                    "test".into(),
+                    "update".into(),
+                    Some("name".into()),
+                    text(""),
+                    text("Jo")
+                ),
+                (
+                    "tui: Edit Table".into(),
+                    "update".into(),
+                    Some("name".into()),
+                    text("Jo"),
+                    text("Sam")
+                ),
+                (
+                    "test".into(),
+                    "delete".into(),
+                    None,
+                    Value::Null,
+                    Value::Null
+                ),
+                (
+                    "test".into(),
+                    "delete".into(),
+                    Some("name".into()),
+                    text("Sam"),
+                    Value::Null
+                ),
+            ]
+        );
+
+        // the log can only be added to
+        let connection = db_connection.connection()?;
+        assert!(
+            connection
+                .execute("DELETE FROM audit_log", [])
+                .is_err()
+        );
+        assert!(
+            connection
+                .execute(
+                    "UPDATE audit_log SET new_value = 'x'",
+                    []
+                )
+                .is_err()
+        );
+
+        context.execute(
+            "history --table=client --row-id=1",
+        )?;
+        assert_eq!(
+            context
+                .take_output_rows()
+                .unwrap()
+                .rows()
+                .len(),
+            6
+        );
+        let response = context.execute(
+            "history --table=client --row-id=2",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "No recorded changes to row 2 in table client."
+        );
+
+        Ok(())
+    }
+
+    // A freshly inserted row lists with empty text fields, not errors.
+    #[test]
+    fn test_new_row_lists() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+
+        context.execute("new --table=client")?;
+        let response =
+            context.execute("list --table=client")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "+----+------+\n\
+            | ID | name |\n\
+            +----+------+\n\
+            | 1  |      |\n\
+            +----+------+"
+        );
+
+        // every text field of a new trainer starts out empty, so a trainer
+        // lists before its details have been filled in
+        context.execute("new --table=trainer")?;
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_field_in_table_row::<String>(
+                    "trainer",
+                    RowId(1),
+                    "company_name",
+                )?,
+            ""
+        );
+        let response =
+            context.execute("list --table=trainer")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "+----+------+--------------+---------+-------+-------+\n\
+            | ID | name | company_name | address | email | phone |\n\
+            +----+------+--------------+---------+-------+-------+\n\
+            | 1  |      |              |         |       |       |\n\
+            +----+------+--------------+---------+-------+-------+"
+        );
+
+        Ok(())
+    }
+}
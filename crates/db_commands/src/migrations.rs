@@ -0,0 +1,526 @@
+//! Versioned schema migrations for plugin tables.
+//!
+//! Each plugin declares numbered migrations for the tables it owns. The
+//! version each table has been migrated to is stored in the `schema_version`
+//! table of the database, and pending migrations are applied in order by
+//! `run_migrations`. Applications start up with `startup` instead of
+//! `Context::startup()`, which runs them as soon as the database is open,
+//! before any command can change it.
+//!
+//! Every run records a version for every table in the database, so a table
+//! with no recorded version wasn't in the database the last time migrations
+//! ran: it was created since by `startup()`, with its current fields. It's
+//! stamped at its latest version instead of being migrated, as replaying
+//! migrations could change data that's already current (e.g. multiply
+//! amounts already in cents by 100 again). A database with no recorded
+//! versions at all predates migrations, so all of its tables are at version
+//! 0 and get every migration; a new database is treated the same, which is
+//! safe as its tables are still empty. Migrations still have to be safe to
+//! run on a table that has some of their changes already: use `add_column`
+//! rather than a bare `ALTER TABLE ... ADD COLUMN`.
+//!
+//! What a table needs besides its columns (e.g. indices), which `startup()`
+//! doesn't create, is declared with `add_table_setup` instead of a
+//! migration: it runs every time migrations are run, so new tables get it
+//! too.
+use crate::{enable_audit_log, sql_error};
+use dolmen::prelude::*;
+use reliquary::prelude::*;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A function that migrates a table from the previous version to the next.
+/// It runs inside the transaction that records the new version.
+pub type MigrationFn =
+    fn(&rusqlite::Connection) -> rusqlite::Result<()>;
+
+/// A single numbered migration of a table.
+#[derive(Clone)]
+pub struct Migration {
+    /// The name of the table the migration applies to.
+    pub table: &'static str,
+
+    /// The version the table is at after the migration. Versions of a table
+    /// start at 1 and count up by one.
+    pub version: u32,
+
+    /// A short description of the change (e.g. `"store amounts in cents"`).
+    pub description: &'static str,
+
+    /// Applies the migration.
+    pub migrate_fn: MigrationFn,
+}
+
+/// A resource storing the migrations every plugin has declared.
+#[derive(Resource, Default)]
+pub struct Migrations {
+    migrations: Vec<Migration>,
+    setups: Vec<(&'static str, MigrationFn)>,
+}
+
+impl Migrations {
+    /// Gets the latest version declared for a table, or 0 if the table has
+    /// no migrations.
+    ///
+    /// * `table` - The name of the table.
+    pub fn latest_version(&self, table: &str) -> u32 {
+        self.migrations
+            .iter()
+            .filter(|m| m.table == table)
+            .map(|m| m.version)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// An extension trait adding schema migrations to `Context`.
+pub trait MigrationsContextExt {
+    /// Declares a migration of a table. Migrations of the same table must
+    /// be numbered 1, 2, 3, ... and can be added in any order.
+    ///
+    /// * `table` - The name of the table to migrate.
+    /// * `version` - The version the table is at after the migration.
+    /// * `description` - A short description of the change.
+    /// * `migrate_fn` - Applies the migration.
+    fn add_migration(
+        &mut self,
+        table: &'static str,
+        version: u32,
+        description: &'static str,
+        migrate_fn: MigrationFn,
+    );
+
+    /// Declares SQL creating what a table needs besides its columns (e.g.
+    /// indices), which `startup()` doesn't create. It runs every time
+    /// migrations are run, after the table's migrations, on new and
+    /// migrated tables alike, so it has to be safe to run again (e.g.
+    /// `CREATE INDEX IF NOT EXISTS`).
+    ///
+    /// * `table` - The name of the table.
+    /// * `setup_fn` - Creates what the table needs.
+    fn add_table_setup(
+        &mut self,
+        table: &'static str,
+        setup_fn: MigrationFn,
+    );
+}
+
+impl MigrationsContextExt for Context {
+    fn add_migration(
+        &mut self,
+        table: &'static str,
+        version: u32,
+        description: &'static str,
+        migrate_fn: MigrationFn,
+    ) {
+        if !self.has_resource::<Migrations>() {
+            self.add_resource(Migrations::default());
+        }
+
+        if let Some(migrations) =
+            self.get_resource_mut::<Migrations>()
+        {
+            migrations.migrations.push(Migration {
+                table,
+                version,
+                description,
+                migrate_fn,
+            });
+        }
+    }
+
+    fn add_table_setup(
+        &mut self,
+        table: &'static str,
+        setup_fn: MigrationFn,
+    ) {
+        if !self.has_resource::<Migrations>() {
+            self.add_resource(Migrations::default());
+        }
+
+        if let Some(migrations) =
+            self.get_resource_mut::<Migrations>()
+        {
+            migrations.setups.push((table, setup_fn));
+        }
+    }
+}
+
+/// Starts the context, enables the audit log on the connection it opens,
+/// then brings the database up to date with `run_migrations`. Applications
+/// using these commands call this instead of `Context::startup()`. Returns
+/// a description of each migration applied.
+///
+/// * `context` - The context to start.
+pub fn startup(
+    context: &mut Context,
+) -> dolmen::Result<Vec<String>> {
+    context.startup()?;
+    enable_audit_log(context.db_connection()?)?;
+    run_migrations(context)
+}
+
+/// Gets the migrations that haven't been applied to the open database yet,
+/// in the order they would run.
+///
+/// * `context` - The context to use.
+pub fn pending_migrations(
+    context: &mut Context,
+) -> dolmen::Result<Vec<Migration>> {
+    let mut migrations = context
+        .get_resource::<Migrations>()
+        .map(|m| m.migrations.clone())
+        .unwrap_or_default();
+    migrations.sort_by_key(|m| (m.table, m.version));
+
+    for (i, m) in migrations.iter().enumerate() {
+        let expected = if i > 0
+            && migrations[i - 1].table == m.table
+        {
+            migrations[i - 1].version + 1
+        } else {
+            1
+        };
+        if m.version != expected {
+            return Err(dolmen::Error::new(format!(
+                "migrations of table {} must be numbered 1, 2, 3, ... \
+                    (found version {} where {} was expected)",
+                m.table, m.version, expected
+            )));
+        }
+    }
+
+    let db_connection = context.db_connection()?;
+    let new_tables = new_tables(db_connection)?;
+    let mut pending = Vec::new();
+    for m in migrations {
+        if !new_tables.iter().any(|t| t == m.table)
+            && m.version
+                > schema_version(
+                    db_connection,
+                    m.table,
+                )?
+        {
+            pending.push(m);
+        }
+    }
+    Ok(pending)
+}
+
+/// Applies every pending migration to the open database. Each table's
+/// migrations run in one transaction along with recording its new version,
+/// so a failed migration leaves the table as it was. New tables are stamped
+/// at their latest version first, and every other table without a recorded
+/// version is recorded at the version it's been migrated to. Once the schema is current, every table's
+/// setup (see `add_table_setup`) runs, and the audit log is enabled on the
+/// connection, so its triggers see every column. Returns a description of
+/// each migration applied.
+///
+/// * `context` - The context to use.
+pub fn run_migrations(
+    context: &mut Context,
+) -> dolmen::Result<Vec<String>> {
+    let pending = pending_migrations(context)?;
+
+    let latest_versions = {
+        let db_connection = context.db_connection()?;
+        new_tables(db_connection)?
+    }
+    .into_iter()
+    .map(|table| {
+        let version = context
+            .get_resource::<Migrations>()
+            .map(|m| m.latest_version(&table))
+            .unwrap_or(0);
+        (table, version)
+    })
+    .collect::<Vec<_>>();
+
+    let connection =
+        context.db_connection()?.connection_mut()?;
+    let transaction =
+        connection.transaction().map_err(sql_error)?;
+    for (table, version) in &latest_versions {
+        set_schema_version(
+            &transaction,
+            table,
+            *version,
+        )
+        .map_err(sql_error)?;
+    }
+    transaction.commit().map_err(sql_error)?;
+
+    let mut applied = Vec::new();
+    let mut i = 0;
+    while i < pending.len() {
+        let table = pending[i].table;
+        let transaction = connection
+            .transaction()
+            .map_err(sql_error)?;
+        while i < pending.len()
+            && pending[i].table == table
+        {
+            let m = &pending[i];
+            (m.migrate_fn)(&transaction).map_err(|e| {
+                dolmen::Error::new(format!(
+                    "migration {} of table {} ({}) failed: {}",
+                    m.version, table, m.description, e
+                ))
+            })?;
+            set_schema_version(
+                &transaction,
+                table,
+                m.version,
+            )
+            .map_err(sql_error)?;
+            applied.push(migration_text(m));
+            i += 1;
+        }
+        transaction.commit().map_err(sql_error)?;
+    }
+
+    // record the tables without migrations too, so that the next run can
+    // tell a table that's missing from the versions was created since
+    let tables = latest_table_versions(context)?;
+    let connection =
+        context.db_connection()?.connection()?;
+    for (table, _) in tables {
+        connection
+            .execute(
+                "INSERT INTO schema_version (table_name, version)
+                    VALUES (?1, 0)
+                    ON CONFLICT (table_name) DO NOTHING",
+                [&table],
+            )
+            .map_err(sql_error)?;
+    }
+
+    let setups = context
+        .get_resource::<Migrations>()
+        .map(|m| m.setups.clone())
+        .unwrap_or_default();
+    let connection =
+        context.db_connection()?.connection_mut()?;
+    let transaction =
+        connection.transaction().map_err(sql_error)?;
+    for (table, setup_fn) in setups {
+        setup_fn(&transaction).map_err(|e| {
+            dolmen::Error::new(format!(
+                "setup of table {} failed: {}",
+                table, e
+            ))
+        })?;
+    }
+    transaction.commit().map_err(sql_error)?;
+
+    enable_audit_log(context.db_connection()?)?;
+
+    Ok(applied)
+}
+
+/// Gets the version a table has been migrated to in the open database, or
+/// 0 if it has never been migrated.
+///
+/// * `db_connection` - A connection to the database.
+/// * `table` - The name of the table.
+pub fn schema_version(
+    db_connection: &mut DbConnection,
+    table: &str,
+) -> dolmen::Result<u32> {
+    let connection = db_connection.connection()?;
+    ensure_schema_version_table(connection)
+        .map_err(sql_error)?;
+    connection
+        .query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_version
+                WHERE table_name = ?1",
+            [table],
+            |r| r.get(0),
+        )
+        .map_err(sql_error)
+}
+
+/// Adds a column to a table if it doesn't have it yet. Tables are created
+/// with every current field, so a migration adding a field has to skip
+/// tables that already have it.
+///
+/// * `connection` - The connection (or transaction) to use.
+/// * `table` - The name of the table.
+/// * `column` - The name of the column to add.
+/// * `definition` - The column's type and constraints (e.g. `"TEXT"`).
+pub fn add_column(
+    connection: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    let exists: bool = connection.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1)
+            WHERE name = ?2)",
+        [table, column],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        connection.execute(
+            &format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table, column, definition
+            ),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// Gets the name and latest version of every registered table.
+///
+/// * `context` - The context to use.
+pub(crate) fn latest_table_versions(
+    context: &mut Context,
+) -> dolmen::Result<Vec<(String, u32)>> {
+    let tables = context
+        .db_connection()?
+        .tables()
+        .iter()
+        .map(|t| t.table_name.clone())
+        .collect::<Vec<_>>();
+    Ok(tables
+        .into_iter()
+        .map(|table| {
+            let version = context
+                .get_resource::<Migrations>()
+                .map(|m| m.latest_version(&table))
+                .unwrap_or(0);
+            (table, version)
+        })
+        .collect())
+}
+
+/// Gets the names of a table's columns in a database, or an empty list if
+/// it doesn't have the table.
+///
+/// * `connection` - The connection to the database.
+/// * `table` - The name of the table.
+pub(crate) fn table_columns(
+    connection: &rusqlite::Connection,
+    table: &str,
+) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = connection.prepare_cached(
+        "SELECT name FROM pragma_table_info(?1)",
+    )?;
+    stmt.query_map([table], |r| r.get(0))?.collect()
+}
+
+/// Gets the registered tables that weren't in the database the last time
+/// migrations ran, i.e. have no recorded version in a database that has
+/// versions recorded. A database with none recorded predates migrations,
+/// so none of its tables are new.
+///
+/// * `db_connection` - A connection to the database.
+fn new_tables(
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<Vec<String>> {
+    let tables = db_connection
+        .tables()
+        .iter()
+        .map(|t| t.table_name.clone())
+        .collect::<Vec<_>>();
+
+    let connection = db_connection.connection()?;
+    ensure_schema_version_table(connection)
+        .map_err(sql_error)?;
+    let mut stmt = connection
+        .prepare_cached(
+            "SELECT table_name FROM schema_version",
+        )
+        .map_err(sql_error)?;
+    let recorded = stmt
+        .query_map([], |r| r.get::<_, String>(0))
+        .map_err(sql_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(sql_error)?;
+    if recorded.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(tables
+        .into_iter()
+        .filter(|t| !recorded.contains(t))
+        .collect())
+}
+
+/// Describes a migration for command output.
+pub(crate) fn migration_text(m: &Migration) -> String {
+    format!(
+        "{} v{}: {}",
+        m.table, m.version, m.description
+    )
+}
+
+fn ensure_schema_version_table(
+    connection: &rusqlite::Connection,
+) -> rusqlite::Result<()> {
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            table_name TEXT PRIMARY KEY,
+            version INTEGER NOT NULL
+        );",
+    )
+}
+
+fn set_schema_version(
+    connection: &rusqlite::Connection,
+    table: &str,
+    version: u32,
+) -> rusqlite::Result<()> {
+    ensure_schema_version_table(connection)?;
+    connection.execute(
+        "INSERT INTO schema_version (table_name, version)
+            VALUES (?1, ?2)
+            ON CONFLICT (table_name)
+                DO UPDATE SET version = excluded.version",
+        rusqlite::params![table, version],
+    )?;
+    Ok(())
+}
+
+/// Processes the `migrate` subcommand of the `db` command.
+pub(crate) fn process_db_migrate_command(
+    context: &mut Context,
+    dry_run: bool,
+) -> dolmen::Result<CommandResponse> {
+    let migrations = if dry_run {
+        pending_migrations(context)?
+            .iter()
+            .map(migration_text)
+            .collect()
+    } else {
+        run_migrations(context)?
+    };
+
+    if migrations.is_empty() {
+        return Ok(CommandResponse::new(
+            "Database schema is up to date.",
+        ));
+    }
+
+    let mut response_text = format!(
+        "{} {} migration(s):",
+        if dry_run {
+            "Would apply"
+        } else {
+            "Applied"
+        },
+        migrations.len()
+    );
+    for m in migrations {
+        response_text += "\n";
+        response_text += m.as_str();
+    }
+    Ok(CommandResponse::new(response_text))
+}
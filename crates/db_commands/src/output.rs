@@ -0,0 +1,612 @@
+//! Structured rows that commands can return alongside their text response,
+//! so callers like `tacl --format json` don't have to scrape tables.
+use clap::{Arg, ArgAction, Command};
+use dolmen::prelude::*;
+use tabled::builder::Builder as TabledBuilder;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// The format to print a command's output in.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+pub enum OutputFormat {
+    /// The command's text response, as a human-readable table.
+    #[default]
+    Table,
+
+    /// The command's rows as a JSON array of objects.
+    Json,
+
+    /// The command's rows as CSV with a header line.
+    Csv,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!(
+                "unknown output format: {} (expected one of \
+                    table, json, csv)",
+                s
+            )),
+        }
+    }
+}
+
+/// A single value in a row of command output.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputValue {
+    /// No value (e.g. an unset optional field).
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl OutputValue {
+    /// Converts a field's displayed text into a typed value, using the
+    /// name of the field's type (as registered with `FieldParsers`).
+    /// Empty text is `Null` for anything but text fields.
+    ///
+    /// * `text` - The displayed text of the value.
+    /// * `type_name` - The name of the field's type (e.g. `"i64"`).
+    pub fn from_display(
+        text: &str,
+        type_name: &str,
+    ) -> Self {
+        if type_name == "String" {
+            return OutputValue::Text(text.into());
+        }
+        if text.is_empty() {
+            return OutputValue::Null;
+        }
+        let parsed = match type_name {
+            "bool" => text
+                .parse()
+                .ok()
+                .map(OutputValue::Bool),
+            "i32" | "i64" | "u32" | "u64" => text
+                .parse()
+                .ok()
+                .map(OutputValue::Integer),
+            "f64" => text
+                .parse()
+                .ok()
+                .map(OutputValue::Real),
+            _ => None,
+        };
+        parsed
+            .unwrap_or(OutputValue::Text(text.into()))
+    }
+}
+
+impl std::fmt::Display for OutputValue {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            OutputValue::Null => Ok(()),
+            OutputValue::Bool(b) => b.fmt(f),
+            OutputValue::Integer(i) => i.fmt(f),
+            OutputValue::Real(r) => r.fmt(f),
+            OutputValue::Text(t) => f.write_str(t),
+        }
+    }
+}
+
+impl From<bool> for OutputValue {
+    fn from(value: bool) -> Self {
+        OutputValue::Bool(value)
+    }
+}
+
+impl From<i64> for OutputValue {
+    fn from(value: i64) -> Self {
+        OutputValue::Integer(value)
+    }
+}
+
+impl From<u32> for OutputValue {
+    fn from(value: u32) -> Self {
+        OutputValue::Integer(value.into())
+    }
+}
+
+impl From<String> for OutputValue {
+    fn from(value: String) -> Self {
+        OutputValue::Text(value)
+    }
+}
+
+impl From<&str> for OutputValue {
+    fn from(value: &str) -> Self {
+        OutputValue::Text(value.into())
+    }
+}
+
+impl<T: Into<OutputValue>> From<Option<T>>
+    for OutputValue
+{
+    fn from(value: Option<T>) -> Self {
+        value
+            .map(Into::into)
+            .unwrap_or(OutputValue::Null)
+    }
+}
+
+/// A column of command output.
+#[derive(Clone, Debug)]
+pub struct OutputColumn {
+    /// The name of the column (e.g. a field name).
+    pub name: String,
+
+    /// The name of the column's type (e.g. `"i64"` or `"NaiveDate"`).
+    pub type_name: String,
+}
+
+/// Rows of structured command output.
+#[derive(Clone, Debug, Default)]
+pub struct OutputRows {
+    columns: Vec<OutputColumn>,
+    rows: Vec<Vec<OutputValue>>,
+}
+
+impl OutputRows {
+    /// Creates empty output with the given columns, as pairs of column
+    /// name and type name.
+    ///
+    /// * `columns` - The names and type names of the columns.
+    pub fn new<N, T>(
+        columns: impl IntoIterator<Item = (N, T)>,
+    ) -> Self
+    where
+        N: Into<String>,
+        T: Into<String>,
+    {
+        Self {
+            columns: columns
+                .into_iter()
+                .map(|(name, type_name)| {
+                    OutputColumn {
+                        name: name.into(),
+                        type_name: type_name.into(),
+                    }
+                })
+                .collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row. Missing values are filled with `Null` and extra values
+    /// are dropped.
+    ///
+    /// * `values` - The values of the row, one per column.
+    pub fn push_row(
+        &mut self,
+        values: impl IntoIterator<Item = OutputValue>,
+    ) {
+        let mut row = values
+            .into_iter()
+            .take(self.columns.len())
+            .collect::<Vec<_>>();
+        row.resize(
+            self.columns.len(),
+            OutputValue::Null,
+        );
+        self.rows.push(row);
+    }
+
+    /// Gets the columns of the output.
+    pub fn columns(&self) -> &Vec<OutputColumn> {
+        &self.columns
+    }
+
+    /// Gets the rows of the output.
+    pub fn rows(&self) -> &Vec<Vec<OutputValue>> {
+        &self.rows
+    }
+
+    /// Formats the rows in the given format.
+    ///
+    /// * `format` - The format to use.
+    pub fn format(
+        &self,
+        format: OutputFormat,
+    ) -> String {
+        match format {
+            OutputFormat::Table => self.to_table(),
+            OutputFormat::Json => self.to_json(),
+            OutputFormat::Csv => self.to_csv(),
+        }
+    }
+
+    /// Formats the rows as a human-readable table.
+    pub fn to_table(&self) -> String {
+        let mut tabled_builder =
+            TabledBuilder::default();
+        tabled_builder.push_record(
+            self.columns
+                .iter()
+                .map(|c| c.name.clone()),
+        );
+        for row in &self.rows {
+            tabled_builder.push_record(
+                row.iter().map(|v| v.to_string()),
+            );
+        }
+        tabled_builder.build().to_string()
+    }
+
+    /// Formats the rows as a JSON array with one object per row, keyed by
+    /// column name.
+    pub fn to_json(&self) -> String {
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| {
+                serde_json::Value::Object(
+                    self.columns
+                        .iter()
+                        .zip(row)
+                        .map(|(c, v)| {
+                            (
+                                c.name.clone(),
+                                json_value(v),
+                            )
+                        })
+                        .collect(),
+                )
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_string_pretty(&rows)
+            .unwrap_or_default()
+    }
+
+    /// Formats the rows as CSV, with a header line of column names.
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec![csv_line(
+            self.columns
+                .iter()
+                .map(|c| c.name.clone()),
+        )];
+        for row in &self.rows {
+            lines.push(csv_line(
+                row.iter().map(|v| v.to_string()),
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Marks a command (or subcommand) as storing rows with `set_output_rows`,
+/// so it can be run with `--format json` or `--format csv`. The command it
+/// belongs to has to be declared with `add_output_command` as well.
+///
+/// * `command` - The command to mark.
+pub fn structured(command: Command) -> Command {
+    command.arg(
+        Arg::new(STRUCTURED_ARG)
+            .long(STRUCTURED_ARG)
+            .action(ArgAction::SetTrue)
+            .hide(true),
+    )
+}
+
+/// A resource holding the rows produced by the last command that returned
+/// structured output. `CommandResponse` only carries text, so commands
+/// store their rows here for the caller to pick up. It also holds the
+/// commands declared with `add_output_command`, so callers can reject
+/// `--format json` or `--format csv` for commands that don't store rows
+/// before running them.
+#[derive(Resource, Default)]
+pub struct CommandOutput {
+    rows: Option<OutputRows>,
+    commands: Vec<Command>,
+}
+
+impl CommandOutput {
+    /// Gets the commands marked with `structured`, as their command and
+    /// subcommand names separated by spaces (e.g. `"billing aging"`),
+    /// sorted.
+    pub fn structured_commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        for command in &self.commands {
+            push_structured_commands(
+                command,
+                "",
+                &mut commands,
+            );
+        }
+        commands.sort();
+        commands
+    }
+
+    /// Returns whether a command line runs a command marked with
+    /// `structured`. The command line is parsed with the declared command,
+    /// so aliases and options given before a subcommand resolve as they do
+    /// when the command runs.
+    ///
+    /// * `command_args` - The words of the command line.
+    pub fn is_structured(
+        &self,
+        command_args: &[String],
+    ) -> bool {
+        let Some(name) = command_args.first() else {
+            return false;
+        };
+        let Some(mut command) =
+            self.commands.iter().find(|c| {
+                c.get_name() == name.as_str()
+                    || c.get_all_aliases()
+                        .any(|a| a == name.as_str())
+            })
+        else {
+            return false;
+        };
+        let Ok(matches) = command
+            .clone()
+            .ignore_errors(true)
+            .try_get_matches_from(command_args)
+        else {
+            return false;
+        };
+
+        let mut matches = &matches;
+        while let Some((name, sub_matches)) =
+            matches.subcommand()
+        {
+            let Some(subcommand) =
+                command.find_subcommand(name)
+            else {
+                return false;
+            };
+            command = subcommand;
+            matches = sub_matches;
+        }
+        is_structured_command(command)
+    }
+}
+
+/// An extension trait adding structured command output to `Context`.
+pub trait CommandOutputContextExt {
+    /// Stores the structured rows of the command being run.
+    ///
+    /// * `rows` - The rows the command produced.
+    fn set_output_rows(&mut self, rows: OutputRows);
+
+    /// Takes the rows stored by the last command, if it stored any.
+    fn take_output_rows(
+        &mut self,
+    ) -> Option<OutputRows>;
+
+    /// Declares a command some of whose subcommands (or itself) are
+    /// marked with `structured`, so they can be run with `--format json`
+    /// or `--format csv`. Give it the same command as `add_command`.
+    ///
+    /// * `command` - The command.
+    fn add_output_command(&mut self, command: Command);
+}
+
+impl CommandOutputContextExt for Context {
+    fn set_output_rows(&mut self, rows: OutputRows) {
+        if !self.has_resource::<CommandOutput>() {
+            self.add_resource(CommandOutput::default());
+        }
+
+        if let Some(output) =
+            self.get_resource_mut::<CommandOutput>()
+        {
+            output.rows = Some(rows);
+        }
+    }
+
+    fn take_output_rows(
+        &mut self,
+    ) -> Option<OutputRows> {
+        self.get_resource_mut::<CommandOutput>()
+            .and_then(|output| output.rows.take())
+    }
+
+    fn add_output_command(
+        &mut self,
+        command: Command,
+    ) {
+        if !self.has_resource::<CommandOutput>() {
+            self.add_resource(CommandOutput::default());
+        }
+
+        if let Some(output) =
+            self.get_resource_mut::<CommandOutput>()
+        {
+            output.commands.push(command);
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// The hidden flag `structured` marks commands with.
+const STRUCTURED_ARG: &str = "structured-output";
+
+/// Returns whether a command is marked with `structured`.
+fn is_structured_command(command: &Command) -> bool {
+    command
+        .get_arguments()
+        .any(|a| a.get_id() == STRUCTURED_ARG)
+}
+
+/// Adds the names of a command and its subcommands that are marked with
+/// `structured` to a list.
+///
+/// * `command` - The command.
+/// * `parent` - The names of the commands it's a subcommand of, separated
+///   by spaces, or `""` for a top-level command.
+/// * `commands` - The list to add the names to.
+fn push_structured_commands(
+    command: &Command,
+    parent: &str,
+    commands: &mut Vec<String>,
+) {
+    let name = if parent.is_empty() {
+        command.get_name().to_string()
+    } else {
+        format!("{} {}", parent, command.get_name())
+    };
+    if is_structured_command(command) {
+        commands.push(name.clone());
+    }
+    for subcommand in command.get_subcommands() {
+        push_structured_commands(
+            subcommand, &name, commands,
+        );
+    }
+}
+
+fn json_value(
+    value: &OutputValue,
+) -> serde_json::Value {
+    match value {
+        OutputValue::Null => serde_json::Value::Null,
+        OutputValue::Bool(b) => (*b).into(),
+        OutputValue::Integer(i) => (*i).into(),
+        OutputValue::Real(r) => (*r).into(),
+        OutputValue::Text(t) => t.clone().into(),
+    }
+}
+
+/// Joins values into a CSV line, quoting values that contain commas,
+/// quotes or line breaks.
+pub(crate) fn csv_line(
+    values: impl IntoIterator<Item = String>,
+) -> String {
+    values
+        .into_iter()
+        .map(|v| {
+            if v.contains([',', '"', '\n', '\r']) {
+                format!(
+                    "\"{}\"",
+                    v.replace('"', "\"\"")
+                )
+            } else {
+                v
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        CommandOutput, CommandOutputContextExt,
+        OutputFormat, OutputRows, OutputValue,
+        structured,
+    };
+    use clap::{Arg, Command};
+    use dolmen::prelude::*;
+
+    #[test]
+    fn test_output_formats() {
+        let mut rows = OutputRows::new([
+            ("id", "i64"),
+            ("name", "String"),
+            ("expires", "NaiveDate"),
+        ]);
+        rows.push_row([
+            1i64.into(),
+            "Smith, \"Jo\"".into(),
+            OutputValue::from_display(
+                "2026-01-31",
+                "NaiveDate",
+            ),
+        ]);
+        rows.push_row([
+            OutputValue::from_display("2", "i64"),
+            "Alex".into(),
+            OutputValue::from_display("", "NaiveDate"),
+        ]);
+
+        assert_eq!(
+            rows.to_csv(),
+            "id,name,expires\n\
+            1,\"Smith, \"\"Jo\"\"\",2026-01-31\n\
+            2,Alex,"
+        );
+        assert_eq!(
+            rows.to_json(),
+            "[\n  {\n    \"id\": 1,\n    \"name\": \"Smith, \\\"Jo\\\"\",\n    \"expires\": \"2026-01-31\"\n  },\n  {\n    \"id\": 2,\n    \"name\": \"Alex\",\n    \"expires\": null\n  }\n]"
+        );
+        assert_eq!(
+            "JSON".parse::<OutputFormat>(),
+            Ok(OutputFormat::Json)
+        );
+        assert!(
+            "xml".parse::<OutputFormat>().is_err()
+        );
+    }
+
+    // Only command lines starting with a declared command have structured
+    // output.
+    #[test]
+    fn test_structured_commands() {
+        let mut context = Context::new();
+        context.add_output_command(structured(
+            Command::new("list")
+                .alias("ls")
+                .arg(Arg::new("table").long("table")),
+        ));
+        context.add_output_command(
+            Command::new("billing")
+                .subcommand(structured(
+                    Command::new("aging").arg(
+                        Arg::new("date").long("date"),
+                    ),
+                ))
+                .subcommand(Command::new("void")),
+        );
+
+        let output = context
+            .get_resource::<CommandOutput>()
+            .unwrap();
+        let args = |line: &str| {
+            line.split(' ')
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        assert!(output.is_structured(&args(
+            "list --table=client"
+        )));
+        assert!(output.is_structured(&args(
+            "ls --table=client"
+        )));
+        assert!(output.is_structured(&args(
+            "billing aging --date=2026-01-31"
+        )));
+        assert!(
+            !output
+                .is_structured(&args("billing void"))
+        );
+        assert!(
+            !output.is_structured(&args("billing"))
+        );
+        assert!(!output.is_structured(&args(
+            "set --table=client"
+        )));
+        assert_eq!(
+            output.structured_commands(),
+            vec!["billing aging", "list"]
+        );
+    }
+}
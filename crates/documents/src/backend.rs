@@ -0,0 +1,412 @@
+//! Backends that write `DocumentData` out as a file.
+use crate::{
+    DocumentData, DocumentError, Template,
+    WriteOptions, write_document_with_options,
+};
+use latex::{
+    Document, DocumentClass, Element, PreambleElement,
+};
+use std::path::{Path, PathBuf};
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A way of writing a document out from its data (e.g. as a PDF through
+/// LaTeX, or as HTML).
+pub trait DocumentBackend {
+    /// Gets the name of the backend (e.g. `"latex"`).
+    fn name(&self) -> &'static str;
+
+    /// Gets the extension of the files the backend writes, without the dot
+    /// (e.g. `"pdf"`).
+    fn extension(&self) -> &'static str;
+
+    /// Writes a document to a file in a given directory. Returns the path
+    /// of the file.
+    ///
+    /// * `out_folder` - The directory to put the file in.
+    /// * `file_name` - The file name to use (excluding the extension)
+    /// * `data` - The values and tables to fill the document with.
+    /// * `options` - Options for writing.
+    fn write(
+        &self,
+        out_folder: &Path,
+        file_name: &str,
+        data: &DocumentData,
+        options: &WriteOptions,
+    ) -> Result<PathBuf, DocumentError>;
+}
+
+/// A backend that fills a LaTeX template (see `Template`) and compiles it
+/// to PDF with `pdflatex`. Values are escaped with `escape_latex`.
+#[derive(Clone, Debug)]
+pub struct LatexBackend {
+    packages: Vec<(String, Option<String>)>,
+    template: String,
+}
+
+impl LatexBackend {
+    /// Creates a LaTeX backend.
+    ///
+    /// * `template` - The text of the template for the document body.
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            packages: Vec::new(),
+            template: template.into(),
+        }
+    }
+
+    /// Adds a package to the preamble.
+    ///
+    /// * `package` - The name of the package (e.g. `"geometry"`).
+    /// * `argument` - The package's options (e.g. `"margin=0.5in"`), if any.
+    pub fn with_package(
+        mut self,
+        package: impl Into<String>,
+        argument: Option<&str>,
+    ) -> Self {
+        self.packages.push((
+            package.into(),
+            argument.map(Into::into),
+        ));
+        self
+    }
+
+    /// Creates the LaTeX document for some document data.
+    ///
+    /// * `data` - The values and tables to fill the template with.
+    pub fn render(
+        &self,
+        data: &DocumentData,
+    ) -> Result<Document, DocumentError> {
+        let body = Template::parse(&self.template)?
+            .render(data, escape_latex)?;
+
+        let mut doc =
+            Document::new(DocumentClass::Article);
+        for (package, argument) in &self.packages {
+            doc.preamble.push(
+                PreambleElement::UsePackage {
+                    package: package.clone(),
+                    argument: argument.clone(),
+                },
+            );
+        }
+        doc.push(Element::UserDefined(body));
+        Ok(doc)
+    }
+}
+
+impl DocumentBackend for LatexBackend {
+    fn name(&self) -> &'static str {
+        "latex"
+    }
+
+    fn extension(&self) -> &'static str {
+        "pdf"
+    }
+
+    fn write(
+        &self,
+        out_folder: &Path,
+        file_name: &str,
+        data: &DocumentData,
+        options: &WriteOptions,
+    ) -> Result<PathBuf, DocumentError> {
+        write_document_with_options(
+            out_folder,
+            file_name,
+            &self.render(data)?,
+            options,
+        )
+    }
+}
+
+/// A backend that fills an HTML template (see `Template`) and writes it as a
+/// single `.html` file. Values are HTML-escaped. The template should be
+/// self-contained (e.g. with inline CSS) so the file can be sent on its own.
+#[derive(Clone, Debug)]
+pub struct HtmlBackend {
+    template: String,
+}
+
+impl HtmlBackend {
+    /// Creates an HTML backend.
+    ///
+    /// * `template` - The text of the HTML template.
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+        }
+    }
+
+    /// Fills the template in.
+    ///
+    /// * `data` - The values and tables to fill the template with.
+    pub fn render(
+        &self,
+        data: &DocumentData,
+    ) -> Result<String, DocumentError> {
+        Ok(Template::parse(&self.template)?
+            .render(data, escape_html)?)
+    }
+}
+
+impl DocumentBackend for HtmlBackend {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn extension(&self) -> &'static str {
+        "html"
+    }
+
+    fn write(
+        &self,
+        out_folder: &Path,
+        file_name: &str,
+        data: &DocumentData,
+        _options: &WriteOptions,
+    ) -> Result<PathBuf, DocumentError> {
+        write_text(
+            out_folder,
+            file_name,
+            self.extension(),
+            &self.render(data)?,
+        )
+    }
+}
+
+/// A backend that fills a plain text template (see `Template`) and writes it
+/// as a `.txt` file. Values are written as-is.
+#[derive(Clone, Debug)]
+pub struct TextBackend {
+    template: String,
+}
+
+impl TextBackend {
+    /// Creates a plain text backend.
+    ///
+    /// * `template` - The text of the template.
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+        }
+    }
+
+    /// Fills the template in.
+    ///
+    /// * `data` - The values and tables to fill the template with.
+    pub fn render(
+        &self,
+        data: &DocumentData,
+    ) -> Result<String, DocumentError> {
+        Ok(Template::parse(&self.template)?
+            .render(data, |text| text.to_string())?)
+    }
+}
+
+impl DocumentBackend for TextBackend {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn extension(&self) -> &'static str {
+        "txt"
+    }
+
+    fn write(
+        &self,
+        out_folder: &Path,
+        file_name: &str,
+        data: &DocumentData,
+        _options: &WriteOptions,
+    ) -> Result<PathBuf, DocumentError> {
+        write_text(
+            out_folder,
+            file_name,
+            self.extension(),
+            &self.render(data)?,
+        )
+    }
+}
+
+/// Escapes text for use in LaTeX, so it's typeset as written and can't
+/// run commands. Special characters become the commands that print them
+/// (e.g. `&` becomes `\&`), brackets and `*` are braced so they can't be
+/// taken as arguments of a preceding `\\`, and line breaks become spaces.
+///
+/// * `text` - The text to escape.
+pub fn escape_latex(text: &str) -> String {
+    let mut escaped =
+        String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' | '%' | '$' | '#' | '_' | '{'
+            | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\\' => {
+                escaped.push_str("\\textbackslash{}")
+            }
+            '~' => {
+                escaped.push_str("\\textasciitilde{}")
+            }
+            '^' => {
+                escaped.push_str("\\textasciicircum{}")
+            }
+            '[' | ']' | '*' => {
+                escaped.push('{');
+                escaped.push(c);
+                escaped.push('}');
+            }
+            '\n' => escaped.push(' '),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes text for use in HTML.
+///
+/// * `text` - The text to escape.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped =
+        String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+fn write_text(
+    out_folder: &Path,
+    file_name: &str,
+    extension: &str,
+    text: &str,
+) -> Result<PathBuf, DocumentError> {
+    let dest_path = out_folder
+        .join(format!("{}.{}", file_name, extension));
+    std::fs::write(&dest_path, text).map_err(|e| {
+        DocumentError::Io(e.to_string())
+    })?;
+    Ok(dest_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DataTable;
+
+    fn test_data() -> DocumentData {
+        let mut data = DocumentData::default();
+        data.set("client", "<Jo & Sam>");
+        let mut charges =
+            DataTable::new(["date", "amount"]);
+        charges.push_row(["2026-01-04", "50.00"]);
+        data.set_table("charges", charges);
+        data
+    }
+
+    #[test]
+    fn test_latex_backend() -> Result<(), DocumentError>
+    {
+        let doc = LatexBackend::new(
+            "\\textbf{{{client}}}\n\
+            {{#each charges}}{{date}} & {{amount}} \\\\\n{{/each}}",
+        )
+        .with_package("geometry", Some("margin=0.5in"))
+        .render(&test_data())?;
+        let rendered = latex::print(&doc).unwrap();
+
+        assert!(rendered.contains(
+            "\\usepackage[margin=0.5in]{geometry}"
+        ));
+        assert!(rendered.contains(
+            "\\textbf{<Jo \\& Sam>}\n2026-01-04 & 50.00 \\\\\n"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_escape_latex() {
+        assert_eq!(
+            escape_latex(
+                "Smith & Sons: 50% off $20 #1 a_b {x}"
+            ),
+            "Smith \\& Sons: 50\\% off \\$20 \\#1 a\\_b \\{x\\}"
+        );
+        assert_eq!(
+            escape_latex("\\input{/etc/passwd} ~^"),
+            "\\textbackslash{}input\\{/etc/passwd\\} \
+                \\textasciitilde{}\\textasciicircum{}"
+        );
+        assert_eq!(
+            escape_latex("[note] *new*\r\nline"),
+            "{[}note{]} {*}new{*} line"
+        );
+    }
+
+    #[test]
+    fn test_html_backend() -> Result<(), DocumentError>
+    {
+        let backend = HtmlBackend::new(
+            "<p>{{client}}</p>{{#each charges}}<td>{{amount}}</td>{{/each}}",
+        );
+        assert_eq!(
+            backend.render(&test_data())?,
+            "<p>&lt;Jo &amp; Sam&gt;</p><td>50.00</td>"
+        );
+
+        let out_dir =
+            tempfile::tempdir().map_err(|e| {
+                DocumentError::Io(e.to_string())
+            })?;
+        let path = backend.write(
+            out_dir.path(),
+            "invoice",
+            &test_data(),
+            &WriteOptions::default(),
+        )?;
+        assert_eq!(
+            path,
+            out_dir.path().join("invoice.html")
+        );
+        assert!(path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_backend() -> Result<(), DocumentError>
+    {
+        assert_eq!(
+            TextBackend::new("{{client}}")
+                .render(&test_data())?,
+            "<Jo & Sam>"
+        );
+        assert!(matches!(
+            TextBackend::new(
+                "{{#each client}}{{/each}}"
+            )
+            .render(&test_data()),
+            Err(DocumentError::Template(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! The content of a document, independent of the format it's written in.
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// Named values and tables to fill a document template with, in the order
+/// they were set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DocumentData {
+    entries: Vec<(String, DocumentValue)>,
+}
+
+/// A single entry of `DocumentData`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DocumentValue {
+    /// A line of text (e.g. a client's name or an amount).
+    Text(String),
+
+    /// A table of text (e.g. the charges on a receipt).
+    Table(DataTable),
+}
+
+impl DocumentData {
+    /// Sets a text value, replacing any entry with the same name.
+    ///
+    /// * `name` - The name of the value (e.g. `"clientname"`).
+    /// * `value` - The text of the value.
+    pub fn set(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) {
+        self.set_entry(
+            name.into(),
+            DocumentValue::Text(value.into()),
+        );
+    }
+
+    /// Sets a table, replacing any entry with the same name.
+    ///
+    /// * `name` - The name of the table (e.g. `"chargedata"`).
+    /// * `table` - The table.
+    pub fn set_table(
+        &mut self,
+        name: impl Into<String>,
+        table: DataTable,
+    ) {
+        self.set_entry(
+            name.into(),
+            DocumentValue::Table(table),
+        );
+    }
+
+    /// Gets an entry by name.
+    ///
+    /// * `name` - The name of the entry.
+    pub fn get(
+        &self,
+        name: &str,
+    ) -> Option<&DocumentValue> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    /// Gets every entry, in the order they were first set.
+    pub fn entries(
+        &self,
+    ) -> &Vec<(String, DocumentValue)> {
+        &self.entries
+    }
+
+    fn set_entry(
+        &mut self,
+        name: String,
+        value: DocumentValue,
+    ) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|(n, _)| *n == name)
+        {
+            entry.1 = value;
+        } else {
+            self.entries.push((name, value));
+        }
+    }
+}
+
+/// A table in `DocumentData`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DataTable {
+    /// The names of the columns (e.g. `"date"`).
+    pub columns: Vec<String>,
+
+    /// The rows, each with one value per column.
+    pub rows: Vec<Vec<String>>,
+}
+
+impl DataTable {
+    /// Creates an empty table with the given columns.
+    ///
+    /// * `columns` - The names of the columns.
+    pub fn new<S: Into<String>>(
+        columns: impl IntoIterator<Item = S>,
+    ) -> Self {
+        Self {
+            columns: columns
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row.
+    ///
+    /// * `row` - The values of the row, one per column.
+    pub fn push_row<S: Into<String>>(
+        &mut self,
+        row: impl IntoIterator<Item = S>,
+    ) {
+        self.rows.push(
+            row.into_iter().map(Into::into).collect(),
+        );
+    }
+}
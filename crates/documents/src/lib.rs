@@ -1,11 +1,143 @@
-//! A utility library for exporting LaTeX documents.
-use directories::ProjectDirs;
+//! A utility library for exporting documents, as PDF through LaTeX or as
+//! HTML or plain text.
+mod backend;
+mod data;
+mod template;
+
 use latex::Document;
 use std::io::Write;
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+pub use backend::{
+    DocumentBackend, HtmlBackend, LatexBackend,
+    TextBackend, escape_html, escape_latex,
+};
+pub use data::{
+    DataTable, DocumentData, DocumentValue,
+};
+pub use template::{Template, TemplateError};
+
+/// Options for exporting a document.
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    /// How much to print while exporting.
+    pub verbosity: Verbosity,
+}
+
+/// How much `write_document_with_options` prints while exporting.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+pub enum Verbosity {
+    /// Print nothing.
+    #[default]
+    Quiet,
+
+    /// Print the `pdflatex` command and its output to stderr.
+    Verbose,
+}
+
+/// An error from exporting a document.
+#[derive(Debug)]
+pub enum DocumentError {
+    /// The document couldn't be rendered to LaTeX source.
+    Render(String),
+
+    /// A template couldn't be parsed or filled in.
+    Template(TemplateError),
+
+    /// `pdflatex` isn't installed or isn't on the `PATH`.
+    PdflatexNotFound,
+
+    /// Reading or writing a file, or running `pdflatex`, failed.
+    Io(String),
+
+    /// `pdflatex` ran but failed to compile the document.
+    Latex {
+        /// The errors parsed from the LaTeX log. May be empty if the log
+        /// had no recognizable errors.
+        errors: Vec<LatexError>,
+
+        /// The full LaTeX log.
+        log: String,
+    },
+}
+
+impl std::fmt::Display for DocumentError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            DocumentError::Render(e) => {
+                write!(
+                    f,
+                    "failed to render LaTeX: {}",
+                    e
+                )
+            }
+            DocumentError::Template(e) => {
+                write!(f, "template error: {}", e)
+            }
+            DocumentError::PdflatexNotFound => {
+                f.write_str("pdflatex not found")
+            }
+            DocumentError::Io(e) => f.write_str(e),
+            DocumentError::Latex {
+                errors, ..
+            } => {
+                f.write_str("LaTeX failed to compile the document")?;
+                for error in errors {
+                    write!(f, "\n{}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {}
+
+impl From<TemplateError> for DocumentError {
+    fn from(e: TemplateError) -> Self {
+        DocumentError::Template(e)
+    }
+}
+
+/// An error message from a LaTeX log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LatexError {
+    /// The error message, without the leading `!`
+    /// (e.g. ``"LaTeX Error: File `handout.sty' not found."``).
+    pub message: String,
+
+    /// The line of the `.tex` source the error was raised on, if the log
+    /// gives one.
+    pub line: Option<u32>,
 
-/// Exports a LaTeX document to PDF in a given directory.
+    /// The source text LaTeX had read up to when the error was raised.
+    pub context: Option<String>,
+}
+
+impl std::fmt::Display for LatexError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// Exports a LaTeX document to PDF in a given directory, printing nothing.
+/// Returns the path of the PDF.
 ///
 /// * `out_folder` - The directory to put the PDF in.
 /// * `file_name` - The file name to use (excluding the .pdf extension)
@@ -14,86 +146,220 @@ pub fn write_document(
     out_folder: &Path,
     file_name: &str,
     doc: &Document,
-) -> Result<(), String> {
-    let project_dirs = ProjectDirs::from(
-        "",
-        "training_assistant",
-        "training_assistant",
+) -> Result<PathBuf, DocumentError> {
+    write_document_with_options(
+        out_folder,
+        file_name,
+        doc,
+        &WriteOptions::default(),
     )
-    .unwrap();
-    let temp_dir =
-        project_dirs.cache_dir().join("documents");
-
-    let tex_path =
-        temp_dir.join(format!("{}.tex", file_name));
-    let pdf_path =
-        temp_dir.join(format!("{}.pdf", file_name));
+}
+
+/// Exports a LaTeX document to PDF in a given directory. Returns the path
+/// of the PDF.
+///
+/// The document is compiled in a new temporary directory, which is removed
+/// afterwards, so concurrent exports don't interfere. The PDF is only copied
+/// to `out_folder` if `pdflatex` succeeds; otherwise the errors in its log
+/// are returned.
+///
+/// * `out_folder` - The directory to put the PDF in.
+/// * `file_name` - The file name to use (excluding the .pdf extension)
+/// * `doc` - The document to export.
+/// * `options` - Options for exporting.
+pub fn write_document_with_options(
+    out_folder: &Path,
+    file_name: &str,
+    doc: &Document,
+    options: &WriteOptions,
+) -> Result<PathBuf, DocumentError> {
+    let verbose =
+        options.verbosity == Verbosity::Verbose;
+
+    let temp_dir = tempfile::Builder::new()
+        .prefix("training_assistant_documents")
+        .tempdir()
+        .map_err(io_error)?;
+
+    let tex_path = temp_dir
+        .path()
+        .join(format!("{}.tex", file_name));
+    let pdf_path = temp_dir
+        .path()
+        .join(format!("{}.pdf", file_name));
+    let log_path = temp_dir
+        .path()
+        .join(format!("{}.log", file_name));
     let dest_path =
         out_folder.join(format!("{}.pdf", file_name));
 
-    std::fs::create_dir_all(temp_dir.clone())
-        .map_err(|e| e.to_string())?;
-
-    let rendered = latex::print(doc)
-        .map_err(|e| e.to_string())?;
+    let rendered = latex::print(doc).map_err(|e| {
+        DocumentError::Render(e.to_string())
+    })?;
 
-    std::fs::write(tex_path.clone(), rendered)
-        .map_err(|e| e.to_string())?;
+    std::fs::write(&tex_path, rendered)
+        .map_err(io_error)?;
 
     let mut cmd = Command::new("pdflatex");
-    cmd.stdout(Stdio::null()).stderr(Stdio::null());
     cmd.arg(format!(
         "-output-directory={}",
-        temp_dir.display()
+        temp_dir.path().display()
     ))
-    .arg(tex_path)
-    .arg("-interaction=nonstopmode");
+    .arg("-interaction=nonstopmode")
+    .arg("-halt-on-error")
+    .arg(&tex_path);
 
-    println!("executing command: {:?}", cmd);
+    if verbose {
+        eprintln!("executing command: {:?}", cmd);
+    }
+
+    let output = cmd.output().map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            DocumentError::PdflatexNotFound
+        } else {
+            io_error(e)
+        }
+    })?;
+
+    if verbose {
+        let mut stderr = std::io::stderr();
+        stderr
+            .write_all(&output.stdout)
+            .map_err(io_error)?;
+        stderr
+            .write_all(&output.stderr)
+            .map_err(io_error)?;
+        stderr.flush().map_err(io_error)?;
+    }
+
+    if !output.status.success() || !pdf_path.exists() {
+        let log = std::fs::read(&log_path)
+            .map(|l| {
+                String::from_utf8_lossy(&l)
+                    .into_owned()
+            })
+            .unwrap_or_default();
+        return Err(DocumentError::Latex {
+            errors: parse_latex_log(&log),
+            log,
+        });
+    }
+
+    if verbose {
+        eprintln!(
+            "copying {:?} to {:?}",
+            pdf_path, dest_path
+        );
+    }
 
-    let cmd_output = cmd.output();
+    std::fs::copy(&pdf_path, &dest_path)
+        .map_err(io_error)?;
 
-    match cmd_output {
-        Err(e) => {
-            if e.kind() == std::io::ErrorKind::NotFound
+    Ok(dest_path)
+}
+
+/// Parses the error messages out of a LaTeX log.
+///
+/// Errors are the lines starting with `!`. The `l.<number>` line that
+/// follows an error gives the line of the source it was raised on; it
+/// applies to every error since the previous one (e.g. a missing package
+/// and the emergency stop it causes).
+///
+/// * `log` - The contents of the `.log` file.
+pub fn parse_latex_log(log: &str) -> Vec<LatexError> {
+    let mut errors: Vec<LatexError> = Vec::new();
+    let mut unlocated = 0;
+
+    for line in log.lines() {
+        if let Some(message) = line.strip_prefix('!') {
+            let message = message.trim();
+            // the summary pdflatex ends a failed run with
+            if message.is_empty()
+                || message.starts_with("==>")
             {
-                return Err(
-                    "pdflatex not found".into()
-                );
-            } else {
-                return Err(e.to_string());
+                continue;
             }
-        }
-        Ok(output) => {
-            // TODO: make an argument to enable this
-            println!("piping command output...");
-
-            std::io::stdout()
-                .write(&output.stdout)
-                .map_err(|e| e.to_string())?;
-            std::io::stderr()
-                .write(&output.stderr)
-                .map_err(|e| e.to_string())?;
-            std::io::stdout()
-                .flush()
-                .map_err(|e| e.to_string())?;
-            std::io::stderr()
-                .flush()
-                .map_err(|e| e.to_string())?;
-
-            // TODO: only attempt copy if no errors were returned
-            println!(
-                "copying {:?} to {:?}",
-                pdf_path, dest_path
-            );
-
-            std::fs::copy(
-                pdf_path.clone(),
-                dest_path.clone(),
-            )
-            .map_err(|e| e.to_string())?;
+            errors.push(LatexError {
+                message: message.to_string(),
+                line: None,
+                context: None,
+            });
+            unlocated += 1;
+        } else if unlocated > 0 {
+            let Some((number, context)) =
+                parse_line_marker(line)
+            else {
+                continue;
+            };
+            let start = errors.len() - unlocated;
+            for error in &mut errors[start..] {
+                error.line = Some(number);
+                error.context =
+                    Some(context.trim().to_string());
+            }
+            unlocated = 0;
         }
     }
 
-    Ok(())
+    errors
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+fn io_error(e: std::io::Error) -> DocumentError {
+    DocumentError::Io(e.to_string())
+}
+
+/// Parses a `l.<number> <context>` line of a LaTeX log into the line number
+/// and the context.
+fn parse_line_marker(
+    line: &str,
+) -> Option<(u32, &str)> {
+    let rest = line.strip_prefix("l.")?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    Some((rest[..end].parse().ok()?, &rest[end..]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_latex_log() {
+        let errors = parse_latex_log(include_str!(
+            "../tests/fixtures/handout.log"
+        ));
+
+        assert_eq!(
+            errors,
+            vec![
+                LatexError {
+                    message: "LaTeX Error: File `handout.sty' not \
+                        found."
+                        .into(),
+                    line: Some(3),
+                    context: Some("^^M".into()),
+                },
+                LatexError {
+                    message: "Emergency stop.".into(),
+                    line: Some(3),
+                    context: Some("^^M".into()),
+                },
+            ]
+        );
+        assert_eq!(
+            DocumentError::Latex {
+                errors,
+                log: String::new()
+            }
+            .to_string(),
+            "LaTeX failed to compile the document\n\
+            line 3: LaTeX Error: File `handout.sty' not found.\n\
+            line 3: Emergency stop."
+        );
+    }
 }
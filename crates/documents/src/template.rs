@@ -0,0 +1,615 @@
+//! A small template language for document backends.
+//!
+//! * `{{name}}` is replaced with a value, escaped for the output format.
+//!   `{{&name}}` is replaced with the value as-is, for values that are
+//!   markup already or mustn't be escaped (e.g. a file path in LaTeX).
+//! * `{{#each table}}...{{/each}}` is repeated for each row of a table;
+//!   inside it, `{{column}}` is the row's value for a column.
+//! * `{{#if name}}...{{else}}...{{/if}}` includes the first part if the
+//!   value is set to something other than empty text or zero (or the table
+//!   has rows), and the optional `{{else}}` part otherwise.
+//!
+//! A tag can sit directly inside braces, as LaTeX needs: in
+//! `\textbf{{{name}}}` the outer braces are kept as text.
+use crate::{DataTable, DocumentData, DocumentValue};
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// A parsed template.
+#[derive(Clone, Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+/// An error in a template, or in filling it in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TemplateError {
+    /// The line of the template the error is on.
+    pub line: usize,
+
+    /// A description of the error.
+    pub message: String,
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}: {}",
+            self.line, self.message
+        )
+    }
+}
+
+impl Template {
+    /// Parses a template.
+    ///
+    /// * `text` - The text of the template.
+    pub fn parse(
+        text: &str,
+    ) -> Result<Self, TemplateError> {
+        let mut stack = vec![Block {
+            tag: None,
+            line: 1,
+            nodes: Vec::new(),
+            else_nodes: None,
+        }];
+        let mut rest = text;
+        let mut line = 1;
+
+        while let Some(mut start) = rest.find("{{") {
+            // in `{{{name}}}`, the first brace is text
+            while rest[start + 2..].starts_with('{') {
+                start += 1;
+            }
+            push_text(&mut stack, &rest[..start]);
+            line +=
+                rest[..start].matches('\n').count();
+            let after = &rest[start + 2..];
+            let Some(end) = after.find("}}") else {
+                return Err(TemplateError {
+                    line,
+                    message: "unclosed {{".into(),
+                });
+            };
+            let tag = after[..end].trim();
+            let tag_line = line;
+            line += after[..end].matches('\n').count();
+            rest = &after[end + 2..];
+
+            let error =
+                |message: String| TemplateError {
+                    line: tag_line,
+                    message,
+                };
+            if let Some(name) =
+                tag.strip_prefix("#each ")
+            {
+                stack.push(Block {
+                    tag: Some(OpenTag::Each(
+                        name.trim().into(),
+                    )),
+                    line: tag_line,
+                    nodes: Vec::new(),
+                    else_nodes: None,
+                });
+            } else if let Some(name) =
+                tag.strip_prefix("#if ")
+            {
+                stack.push(Block {
+                    tag: Some(OpenTag::If(
+                        name.trim().into(),
+                    )),
+                    line: tag_line,
+                    nodes: Vec::new(),
+                    else_nodes: None,
+                });
+            } else if tag == "else" {
+                let block = stack
+                    .last_mut()
+                    .expect("root block");
+                if !matches!(
+                    block.tag,
+                    Some(OpenTag::If(_))
+                ) || block.else_nodes.is_some()
+                {
+                    return Err(error(
+                        "{{else}} outside of {{#if}}"
+                            .into(),
+                    ));
+                }
+                block.else_nodes = Some(Vec::new());
+            } else if tag == "/each" || tag == "/if" {
+                let block =
+                    stack.pop().expect("root block");
+                let node = match (block.tag, tag) {
+                    (
+                        Some(OpenTag::Each(name)),
+                        "/each",
+                    ) => Node::Each(
+                        name,
+                        block.nodes,
+                        block.line,
+                    ),
+                    (
+                        Some(OpenTag::If(name)),
+                        "/if",
+                    ) => Node::If(
+                        name,
+                        block.nodes,
+                        block
+                            .else_nodes
+                            .unwrap_or_default(),
+                        block.line,
+                    ),
+                    _ => {
+                        return Err(error(format!(
+                            "unexpected {{{{{}}}}}",
+                            tag
+                        )));
+                    }
+                };
+                stack
+                    .last_mut()
+                    .ok_or(error(format!(
+                        "unexpected {{{{{}}}}}",
+                        tag
+                    )))?
+                    .current()
+                    .push(node);
+            } else {
+                let (name, raw) = match tag
+                    .strip_prefix('&')
+                {
+                    Some(name) => (name.trim(), true),
+                    None => (tag, false),
+                };
+                if name.is_empty()
+                    || !name.chars().all(|c| {
+                        c.is_ascii_alphanumeric()
+                            || c == '_'
+                    })
+                {
+                    return Err(error(format!(
+                        "invalid tag {{{{{}}}}}",
+                        tag
+                    )));
+                }
+                stack
+                    .last_mut()
+                    .expect("root block")
+                    .current()
+                    .push(Node::Value(
+                        name.into(),
+                        raw,
+                        tag_line,
+                    ));
+            }
+        }
+        push_text(&mut stack, rest);
+
+        if stack.len() > 1 {
+            let block =
+                stack.pop().expect("open block");
+            return Err(TemplateError {
+                line: block.line,
+                message: match block.tag {
+                    Some(OpenTag::Each(name)) => {
+                        format!(
+                            "{{{{#each {}}}}} is never closed",
+                            name
+                        )
+                    }
+                    Some(OpenTag::If(name)) => {
+                        format!(
+                            "{{{{#if {}}}}} is never closed",
+                            name
+                        )
+                    }
+                    None => {
+                        "block is never closed".into()
+                    }
+                },
+            });
+        }
+
+        Ok(Template {
+            nodes: stack
+                .pop()
+                .expect("root block")
+                .nodes,
+        })
+    }
+
+    /// Fills the template in. Every name the template uses has to be in
+    /// `data`.
+    ///
+    /// * `data` - The values and tables to fill the template with.
+    /// * `escape` - Escapes a value for the output format.
+    pub fn render(
+        &self,
+        data: &DocumentData,
+        escape: fn(&str) -> String,
+    ) -> Result<String, TemplateError> {
+        let mut out = String::new();
+        render_nodes(
+            &self.nodes,
+            data,
+            None,
+            escape,
+            &mut out,
+        )?;
+        Ok(out)
+    }
+
+    /// Checks that every name the template uses is in `data`, including in
+    /// parts that filling it in with `data` would leave out (the other side
+    /// of an `{{#if}}`, or an `{{#each}}` over a table with no rows). Names
+    /// inside an `{{#each}}` can also be the table's columns.
+    ///
+    /// * `data` - Values and tables with the names the template can use.
+    pub fn check(
+        &self,
+        data: &DocumentData,
+    ) -> Result<(), TemplateError> {
+        check_nodes(&self.nodes, data, None)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug)]
+enum Node {
+    Text(String),
+    Value(String, bool, usize),
+    Each(String, Vec<Node>, usize),
+    If(String, Vec<Node>, Vec<Node>, usize),
+}
+
+enum OpenTag {
+    Each(String),
+    If(String),
+}
+
+/// A block being parsed: the root, or an `{{#each}}` or `{{#if}}`.
+struct Block {
+    tag: Option<OpenTag>,
+    line: usize,
+    nodes: Vec<Node>,
+    else_nodes: Option<Vec<Node>>,
+}
+
+impl Block {
+    /// Gets the nodes new nodes are added to.
+    fn current(&mut self) -> &mut Vec<Node> {
+        self.else_nodes
+            .as_mut()
+            .unwrap_or(&mut self.nodes)
+    }
+}
+
+fn push_text(stack: &mut [Block], text: &str) {
+    if !text.is_empty() {
+        stack
+            .last_mut()
+            .expect("root block")
+            .current()
+            .push(Node::Text(text.into()));
+    }
+}
+
+/// A row of a table being repeated by `{{#each}}`.
+type RowScope<'a> = (&'a DataTable, &'a Vec<String>);
+
+fn render_nodes(
+    nodes: &[Node],
+    data: &DocumentData,
+    row: Option<RowScope>,
+    escape: fn(&str) -> String,
+    out: &mut String,
+) -> Result<(), TemplateError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Value(name, raw, line) => {
+                match lookup(data, row, name) {
+                    Some(Lookup::Text(text))
+                        if *raw =>
+                    {
+                        out.push_str(text)
+                    }
+                    Some(Lookup::Text(text)) => {
+                        out.push_str(&escape(text))
+                    }
+                    Some(Lookup::Table(_)) => {
+                        return Err(TemplateError {
+                            line: *line,
+                            message: format!(
+                                "{} is a table, use {{{{#each {}}}}}",
+                                name, name
+                            ),
+                        });
+                    }
+                    None => {
+                        return Err(unknown(
+                            name, *line,
+                        ));
+                    }
+                }
+            }
+            Node::Each(name, body, line) => {
+                let Some(Lookup::Table(table)) =
+                    lookup(data, row, name)
+                else {
+                    return Err(TemplateError {
+                        line: *line,
+                        message: format!(
+                            "unknown table: {}",
+                            name
+                        ),
+                    });
+                };
+                for table_row in &table.rows {
+                    render_nodes(
+                        body,
+                        data,
+                        Some((table, table_row)),
+                        escape,
+                        out,
+                    )?;
+                }
+            }
+            Node::If(
+                name,
+                then_nodes,
+                else_nodes,
+                line,
+            ) => {
+                let truthy =
+                    match lookup(data, row, name) {
+                        Some(Lookup::Text(text)) => {
+                            is_truthy(text)
+                        }
+                        Some(Lookup::Table(table)) => {
+                            !table.rows.is_empty()
+                        }
+                        None => {
+                            return Err(unknown(
+                                name, *line,
+                            ));
+                        }
+                    };
+                render_nodes(
+                    if truthy {
+                        then_nodes
+                    } else {
+                        else_nodes
+                    },
+                    data,
+                    row,
+                    escape,
+                    out,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the names nodes use. See `Template::check`.
+fn check_nodes(
+    nodes: &[Node],
+    data: &DocumentData,
+    table: Option<&DataTable>,
+) -> Result<(), TemplateError> {
+    // the columns of the table being repeated come first, as in `lookup`
+    let find = |name: &str| {
+        if table.is_some_and(|table| {
+            table.columns.iter().any(|c| c == name)
+        }) {
+            return Some(None);
+        }
+        match data.get(name)? {
+            DocumentValue::Text(_) => Some(None),
+            DocumentValue::Table(table) => {
+                Some(Some(table))
+            }
+        }
+    };
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Value(name, _, line) => {
+                match find(name) {
+                    Some(None) => {}
+                    Some(Some(_)) => {
+                        return Err(TemplateError {
+                            line: *line,
+                            message: format!(
+                                "{} is a table, use {{{{#each {}}}}}",
+                                name, name
+                            ),
+                        });
+                    }
+                    None => {
+                        return Err(unknown(
+                            name, *line,
+                        ));
+                    }
+                }
+            }
+            Node::Each(name, body, line) => {
+                let Some(Some(each_table)) =
+                    find(name)
+                else {
+                    return Err(TemplateError {
+                        line: *line,
+                        message: format!(
+                            "unknown table: {}",
+                            name
+                        ),
+                    });
+                };
+                check_nodes(
+                    body,
+                    data,
+                    Some(each_table),
+                )?;
+            }
+            Node::If(
+                name,
+                then_nodes,
+                else_nodes,
+                line,
+            ) => {
+                if find(name).is_none() {
+                    return Err(unknown(name, *line));
+                }
+                check_nodes(then_nodes, data, table)?;
+                check_nodes(else_nodes, data, table)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+enum Lookup<'a> {
+    Text(&'a str),
+    Table(&'a DataTable),
+}
+
+/// Looks a name up in the current row, then in the document's values.
+fn lookup<'a>(
+    data: &'a DocumentData,
+    row: Option<RowScope<'a>>,
+    name: &str,
+) -> Option<Lookup<'a>> {
+    if let Some((table, values)) = row {
+        if let Some(i) = table
+            .columns
+            .iter()
+            .position(|c| c == name)
+        {
+            return values
+                .get(i)
+                .map(|v| Lookup::Text(v.as_str()));
+        }
+    }
+
+    match data.get(name)? {
+        DocumentValue::Text(text) => {
+            Some(Lookup::Text(text))
+        }
+        DocumentValue::Table(table) => {
+            Some(Lookup::Table(table))
+        }
+    }
+}
+
+/// A value is true unless it's empty or a number equal to zero.
+fn is_truthy(text: &str) -> bool {
+    let text = text.trim();
+    !text.is_empty()
+        && text
+            .parse::<f64>()
+            .map_or(true, |n| n != 0.0)
+}
+
+fn unknown(name: &str, line: usize) -> TemplateError {
+    TemplateError {
+        line,
+        message: format!("unknown variable: {}", name),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_template() -> Result<(), TemplateError> {
+        let mut data = DocumentData::default();
+        data.set("client", "Jo & Sam");
+        data.set("balance", "0.00");
+        let mut charges =
+            DataTable::new(["date", "amount"]);
+        charges.push_row(["2026-01-04", "50.00"]);
+        charges.push_row(["2026-01-11", "45.00"]);
+        data.set_table("charges", charges);
+
+        let template = Template::parse(
+            "Client: {{ client }}\n\
+            {{#each charges}}{{date}} {{amount}}\n{{/each}}\
+            {{#if balance}}Owing {{balance}}{{else}}Paid up{{/if}}",
+        )?;
+        assert_eq!(
+            template.render(&data, |t| t
+                .replace('&', "and"))?,
+            "Client: Jo and Sam\n\
+            2026-01-04 50.00\n\
+            2026-01-11 45.00\n\
+            Paid up"
+        );
+        assert_eq!(
+            Template::parse("\\textbf{{{client}}}")?
+                .render(&data, |t| t.into())?,
+            "\\textbf{Jo & Sam}"
+        );
+        assert_eq!(
+            Template::parse(
+                "{{client}} / {{& client}}"
+            )?
+            .render(&data, |t| t
+                .replace('&', "and"))?,
+            "Jo and Sam / Jo & Sam"
+        );
+
+        assert_eq!(
+            Template::parse("a\n{{#if x}}\nb")
+                .unwrap_err()
+                .to_string(),
+            "line 2: {{#if x}} is never closed"
+        );
+        assert_eq!(
+            Template::parse("{{nope}}")?
+                .render(&data, |t| t.into())
+                .unwrap_err()
+                .to_string(),
+            "line 1: unknown variable: nope"
+        );
+
+        // checking looks at the parts rendering leaves out
+        let template = Template::parse(
+            "{{#if client}}{{client}}{{else}}\n{{nope}}{{/if}}\
+            {{#each charges}}{{amount}}{{/each}}",
+        )?;
+        assert!(
+            template
+                .render(&data, |t| t.into())
+                .is_ok()
+        );
+        assert_eq!(
+            template
+                .check(&data)
+                .unwrap_err()
+                .to_string(),
+            "line 2: unknown variable: nope"
+        );
+        assert_eq!(
+            Template::parse(
+                "{{#each charges}}{{#if date}}{{amount}}{{/if}}{{/each}}"
+            )?
+            .check(&data),
+            Ok(())
+        );
+
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
 //! The command-line interface for Training Assistant.
+#[cfg(feature = "db_commands")]
+use db_commands::{
+    CommandOutput, CommandOutputContextExt,
+    OutputFormat,
+};
 use dolmen::prelude::*;
 use reliquary::prelude::*;
 use tui::Tui;
@@ -20,8 +25,6 @@ fn main() -> dolmen::Result<()> {
     context
         .add_plugin(db_commands::DbCommandsPlugin)?;
 
-    context.startup()?;
-
     let mut command_args =
         std::env::args().collect::<Vec<_>>();
 
@@ -29,23 +32,88 @@ fn main() -> dolmen::Result<()> {
     // TODO: this isn't guaranteed to be the executable name, should probably check it's what we expect
     command_args.remove(0);
 
-    let response = context.execute(
-        shlex::try_join(
-            command_args.iter().map(|e| e.as_str()),
-        )
-        .expect("failed to join args")
-        .as_str(),
-    );
+    #[cfg(feature = "db_commands")]
+    let format =
+        take_format_arg(&context, &mut command_args)?;
+
+    // start up, bringing the database schema up to date, unless the command
+    // is the one that does it by hand (so `db migrate --dry-run` has
+    // something to show)
+    #[cfg(feature = "db_commands")]
+    if command_args
+        .starts_with(&["db".into(), "migrate".into()])
+    {
+        context.startup()?;
+        db_commands::enable_audit_log(
+            context.db_connection()?,
+        )?;
+    } else {
+        for migration in
+            db_commands::startup(&mut context)?
+        {
+            eprintln!(
+                "Applied migration {}.",
+                migration
+            );
+        }
+    }
+    #[cfg(not(feature = "db_commands"))]
+    context.startup()?;
+
+    // reject json and csv up front for commands that only print text,
+    // rather than running them and ignoring the format
+    #[cfg(feature = "db_commands")]
+    check_format_supported(
+        &context,
+        &command_args,
+        format,
+    )?;
+
+    let command_line = shlex::try_join(
+        command_args.iter().map(|e| e.as_str()),
+    )
+    .expect("failed to join args");
+
+    // record where the command's changes come from in the audit log
+    #[cfg(feature = "db_commands")]
+    db_commands::set_audit_source(
+        context.db_connection()?,
+        &format!("tacl {}", command_line),
+    )?;
+
+    let response =
+        context.execute(command_line.as_str());
 
     match response {
         Ok(r) => {
-            if let Some(text) = r.text() {
+            // print structured rows in the requested format if the
+            // command returned any, otherwise fall back to the text
+            #[cfg(feature = "db_commands")]
+            let structured = context
+                .take_output_rows()
+                .filter(|_| {
+                    format != OutputFormat::Table
+                })
+                .map(|rows| rows.format(format));
+            #[cfg(not(feature = "db_commands"))]
+            let structured: Option<
+                String,
+            > = None;
+
+            if let Some(structured) = structured {
+                println!("{}", structured);
+            } else if let Some(text) = r.text() {
                 println!("{}", text);
             }
             let tui_requested =
                 context.has_resource::<Tui>();
 
             if tui_requested {
+                #[cfg(feature = "db_commands")]
+                db_commands::set_audit_source(
+                    context.db_connection()?,
+                    "tui",
+                )?;
                 tui::run_tui(&mut context).expect(
                     "failed to run tui session",
                 );
@@ -58,3 +126,212 @@ fn main() -> dolmen::Result<()> {
 
     Ok(())
 }
+
+/// Removes the global `--format` option from the arguments and parses it.
+/// Defaults to `table`. It can come before the command name, or anywhere
+/// among the arguments of a command that prints structured rows (see
+/// `structured`). Other commands can have a `--format` option of their own
+/// (e.g. `export`), which is left to them.
+///
+/// * `context` - The context the command will run in.
+/// * `command_args` - The arguments, without the executable name.
+#[cfg(feature = "db_commands")]
+fn take_format_arg(
+    context: &Context,
+    command_args: &mut Vec<String>,
+) -> dolmen::Result<OutputFormat> {
+    let value = if let Some(value) =
+        take_format_arg_at(command_args, 0)?
+    {
+        value
+    } else {
+        let Some(index) = command_args
+            .iter()
+            .take_while(|a| *a != "--")
+            .position(|a| {
+                a == "--format"
+                    || a.starts_with("--format=")
+            })
+        else {
+            return Ok(OutputFormat::Table);
+        };
+        let mut rest = command_args.clone();
+        let value =
+            take_format_arg_at(&mut rest, index)?
+                .unwrap_or_default();
+        if !context
+            .get_resource::<CommandOutput>()
+            .is_some_and(|o| o.is_structured(&rest))
+        {
+            return Ok(OutputFormat::Table);
+        }
+        *command_args = rest;
+        value
+    };
+
+    value.parse().map_err(dolmen::Error::new)
+}
+
+/// Removes a `--format` option, given as `--format=value` or
+/// `--format value`, from the arguments at an index, returning its value,
+/// or `None` if there's no `--format` there.
+///
+/// * `command_args` - The arguments, without the executable name.
+/// * `index` - The index of the option.
+#[cfg(feature = "db_commands")]
+fn take_format_arg_at(
+    command_args: &mut Vec<String>,
+    index: usize,
+) -> dolmen::Result<Option<String>> {
+    let Some(arg) = command_args.get(index) else {
+        return Ok(None);
+    };
+
+    if let Some(value) = arg.strip_prefix("--format=")
+    {
+        let value = value.to_string();
+        command_args.remove(index);
+        Ok(Some(value))
+    } else if arg == "--format" {
+        if command_args.len() < index + 2 {
+            return Err(dolmen::Error::new(
+                "--format requires a value (table, json or csv)",
+            ));
+        }
+        command_args.remove(index);
+        Ok(Some(command_args.remove(index)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Checks that the command can print its output in the requested format.
+/// Every command prints text as a table; only the commands marked with
+/// `structured` and registered with `add_output_command` print rows as JSON
+/// or CSV.
+///
+/// * `context` - The context the command will run in.
+/// * `command_args` - The arguments, without the executable name.
+/// * `format` - The requested format.
+#[cfg(feature = "db_commands")]
+fn check_format_supported(
+    context: &Context,
+    command_args: &[String],
+    format: OutputFormat,
+) -> dolmen::Result<()> {
+    if format == OutputFormat::Table {
+        return Ok(());
+    }
+    let Some(output) =
+        context.get_resource::<CommandOutput>()
+    else {
+        return Err(dolmen::Error::new(
+            "no command supports --format json or csv",
+        ));
+    };
+    if output.is_structured(command_args) {
+        return Ok(());
+    }
+    Err(dolmen::Error::new(format!(
+        "this command only prints text, so --format must be table \
+            (commands that support json and csv: {})",
+        output.structured_commands().join(", ")
+    )))
+}
+
+#[cfg(all(test, feature = "db_commands"))]
+mod test {
+    use crate::take_format_arg;
+    use db_commands::OutputFormat;
+    use dolmen::prelude::*;
+    use reliquary::prelude::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ')
+            .map(|a| a.to_string())
+            .collect()
+    }
+
+    // `--format` can come before the command or after its arguments, but a
+    // command's own `--format` option is left to it.
+    #[test]
+    fn test_take_format_arg() -> dolmen::Result<()> {
+        let mut context = Context::new();
+        context.add_plugin(DbPlugin)?.add_plugin(
+            db_commands::DbCommandsPlugin,
+        )?;
+
+        let mut command_args =
+            args("--format=json list --table=client");
+        assert_eq!(
+            take_format_arg(
+                &context,
+                &mut command_args
+            )?,
+            OutputFormat::Json
+        );
+        assert_eq!(
+            command_args,
+            args("list --table=client")
+        );
+
+        let mut command_args =
+            args("list --table=client --format=csv");
+        assert_eq!(
+            take_format_arg(
+                &context,
+                &mut command_args
+            )?,
+            OutputFormat::Csv
+        );
+        assert_eq!(
+            command_args,
+            args("list --table=client")
+        );
+
+        let mut command_args =
+            args("list --format csv --table=client");
+        assert_eq!(
+            take_format_arg(
+                &context,
+                &mut command_args
+            )?,
+            OutputFormat::Csv
+        );
+        assert_eq!(
+            command_args,
+            args("list --table=client")
+        );
+
+        let mut command_args = args(
+            "export --table=client --format=json \
+                --out-file=clients.json",
+        );
+        assert_eq!(
+            take_format_arg(
+                &context,
+                &mut command_args
+            )?,
+            OutputFormat::Table
+        );
+        assert_eq!(
+            command_args,
+            args(
+                "export --table=client --format=json \
+                    --out-file=clients.json"
+            )
+        );
+
+        let mut command_args =
+            args("list --table=client --format");
+        assert!(
+            take_format_arg(
+                &context,
+                &mut command_args
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -19,7 +19,11 @@ fn main() -> dolmen::Result<()> {
     context.add_plugin(GuiPlugin)?;
     context.add_plugin(BillingPlugin)?;
 
-    context.startup()?;
+    db_commands::startup(&mut context)?;
+    db_commands::set_audit_source(
+        context.db_connection()?,
+        "gui",
+    )?;
 
     eframe::run_simple_native(
         "Training Assistant",
@@ -1,9 +1,25 @@
 //! A core plugin for training administration.
-use chrono::NaiveDate;
+mod schedule;
+
+use chrono::{NaiveDate, NaiveTime};
+use db_commands::{
+    FieldParsersContextExt, MigrationsContextExt,
+};
 use dolmen::prelude::*;
 use reliquary::prelude::*;
+use rusqlite::types::{
+    FromSql, FromSqlError, FromSqlResult, ToSql,
+    ToSqlOutput, ValueRef,
+};
 use tui::prelude::*;
 
+pub use schedule::{
+    NewSession, SessionStatusHook, SessionStatusHooks,
+    add_session_status_hook, find_trainer_conflicts,
+    move_session, schedule_session, sessions_between,
+    set_session_status, update_session_status,
+};
+
 /// The plugin for the Training system.
 /// Add this to set up the required tables and commands.
 #[derive(Clone)]
@@ -28,11 +44,150 @@ impl Plugin for TrainingPlugin {
                 "session",
             ));
 
+        if !context
+            .has_resource::<SessionStatusHooks>()
+        {
+            context.add_resource(
+                SessionStatusHooks::default(),
+            );
+        }
+
+        schedule::add_session_command(context)?;
+
+        // let the `set` command parse session statuses
+        context.add_field_type::<SessionStatus>();
+
+        // bill or void sessions whose status is changed with `set`
+        context.add_field_set_hook(
+            "session",
+            "status",
+            schedule::session_status_set_hook,
+        );
+
+        // trainers can have their own invoice templates
+        context.add_migration(
+            "trainer",
+            1,
+            "add invoice_template",
+            |c| {
+                db_commands::add_column(
+                    c,
+                    "trainer",
+                    "invoice_template",
+                    "TEXT",
+                )
+            },
+        );
+
+        // trainers can number their receipts their own way
+        context.add_migration(
+            "trainer",
+            2,
+            "add receipt_pattern",
+            |c| {
+                db_commands::add_column(
+                    c,
+                    "trainer",
+                    "receipt_pattern",
+                    "TEXT",
+                )
+            },
+        );
+
+        // and their invoices
+        context.add_migration(
+            "trainer",
+            3,
+            "add invoice_pattern",
+            |c| {
+                db_commands::add_column(
+                    c,
+                    "trainer",
+                    "invoice_pattern",
+                    "TEXT",
+                )
+            },
+        );
+
+        // sessions used to be just a date, trainer, client and charge; the
+        // ones from then had no time, so they're taken to start at midnight
+        // and last no time at all, which keeps them on the schedule
+        context.add_migration(
+            "session",
+            1,
+            "add start_time",
+            |c| {
+                db_commands::add_column(
+                    c,
+                    "session",
+                    "start_time",
+                    "TEXT",
+                )?;
+                c.execute(
+                    "UPDATE session SET start_time = '00:00:00'
+                        WHERE start_time IS NULL",
+                    [],
+                )?;
+                Ok(())
+            },
+        );
+
+        context.add_migration(
+            "session",
+            2,
+            "add duration_minutes",
+            |c| {
+                db_commands::add_column(
+                    c,
+                    "session",
+                    "duration_minutes",
+                    "INTEGER",
+                )?;
+                c.execute(
+                    "UPDATE session SET duration_minutes = 0
+                        WHERE duration_minutes IS NULL",
+                    [],
+                )?;
+                Ok(())
+            },
+        );
+
+        context.add_migration(
+            "session",
+            3,
+            "add location",
+            |c| {
+                db_commands::add_column(
+                    c, "session", "location", "TEXT",
+                )?;
+                c.execute(
+                    "UPDATE session SET location = ''
+                        WHERE location IS NULL",
+                    [],
+                )?;
+                Ok(())
+            },
+        );
+
+        context.add_migration(
+            "session",
+            4,
+            "add status",
+            |c| {
+                db_commands::add_column(
+                    c,
+                    "session",
+                    "status",
+                    "TEXT NOT NULL DEFAULT 'scheduled'",
+                )
+            },
+        );
+
         // TODO: conditionally compile this
         if let Some(new_tab_types) = context
             .get_resource_mut::<TuiNewTabTypes>(
         ) {
-            new_tab_types.register_new_tab_type::<ScheduleTabImpl>("Schedule");
+            new_tab_types.register_new_tab_type::<schedule::ScheduleTabImpl>("Schedule");
         }
 
         Ok(())
@@ -48,6 +203,9 @@ pub struct Trainer {
     email: String,
     phone: String,
     logo_path: Option<String>,
+    invoice_template: Option<String>,
+    receipt_pattern: Option<String>,
+    invoice_pattern: Option<String>,
 }
 
 impl Trainer {
@@ -80,6 +238,24 @@ impl Trainer {
     pub fn logo_path(&self) -> &Option<String> {
         &self.logo_path
     }
+
+    /// Gets the path of the trainer's own invoice template, or `None` if
+    /// they use the default one.
+    pub fn invoice_template(&self) -> &Option<String> {
+        &self.invoice_template
+    }
+
+    /// Gets the pattern the trainer's receipt numbers are made from (e.g.
+    /// `{year}-{seq:04}`), or `None` if they use the default one.
+    pub fn receipt_pattern(&self) -> &Option<String> {
+        &self.receipt_pattern
+    }
+
+    /// Gets the pattern the trainer's invoice numbers are made from (e.g.
+    /// `INV-{year}-{seq:04}`), or `None` if they use the default one.
+    pub fn invoice_pattern(&self) -> &Option<String> {
+        &self.invoice_pattern
+    }
 }
 
 /// Contains data about a single training client.
@@ -102,55 +278,286 @@ pub struct Exercise {
     name: String,
 }
 
-// TODO: implement this
-struct ScheduleTabImpl;
+/// Represents a scheduled training session.
+#[derive(TableRow, Debug)]
+pub struct Session {
+    date: NaiveDate,
+    start_time: NaiveTime,
+    duration_minutes: u32,
+    location: String,
+    status: SessionStatus,
+    #[display_table("trainer", "name")]
+    trainer: RowId,
+    #[display_table("client", "name")]
+    client: RowId,
+    charge: Option<RowId>,
+}
+
+impl Session {
+    /// Gets the date the session takes place on.
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+
+    /// Gets the time the session starts.
+    pub fn start_time(&self) -> NaiveTime {
+        self.start_time
+    }
 
-#[derive(Default)]
-struct ScheduleTabState;
+    /// Gets the length of the session in minutes.
+    pub fn duration_minutes(&self) -> u32 {
+        self.duration_minutes
+    }
 
-impl TabImpl for ScheduleTabImpl {
-    type State = ScheduleTabState;
+    /// Gets the time the session ends.
+    pub fn end_time(&self) -> NaiveTime {
+        self.start_time
+            + chrono::Duration::minutes(
+                self.duration_minutes.into(),
+            )
+    }
 
-    fn title() -> String {
-        "Schedule".into()
+    /// Gets where the session takes place.
+    pub fn location(&self) -> &String {
+        &self.location
     }
 
-    fn render(
-        _: &mut Context,
-        buffer: &mut Buffer,
-        rect: Rect,
-        block: Block,
-        _: usize,
-    ) {
-        Paragraph::new(Line::from(
-            "Schedule UI not implemented.",
-        ))
-        .block(block)
-        .render(rect, buffer);
+    /// Gets the status of the session.
+    pub fn status(&self) -> SessionStatus {
+        self.status
     }
 
-    fn keybinds() -> Vec<KeyBind> {
-        vec![]
+    /// Gets the row ID of the trainer running the session.
+    pub fn trainer(&self) -> RowId {
+        self.trainer
     }
 
-    fn handle_key(_: &mut Context, _: &str, _: usize) {
+    /// Gets the row ID of the client attending the session.
+    pub fn client(&self) -> RowId {
+        self.client
     }
 
-    fn handle_text(
-        _: &mut Context,
-        _: ratatui::crossterm::event::Event,
-        _: usize,
-    ) {
+    /// Gets the row ID of the charge billed for the session, or `None` if
+    /// it hasn't been billed.
+    pub fn charge(&self) -> Option<RowId> {
+        self.charge
     }
 }
 
-/// Represents a scheduled training session.
-#[derive(TableRow, Debug)]
-pub struct Session {
-    date: NaiveDate,
-    #[display_table("trainer", "name")]
-    trainer: RowId,
-    #[display_table("client", "name")]
-    client: RowId,
-    charge: Option<RowId>,
+/// The status of a training session. Stored in the database as lowercase
+/// text (e.g. `"no-show"`).
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+pub enum SessionStatus {
+    /// The session is planned and hasn't happened yet.
+    #[default]
+    Scheduled,
+
+    /// The session took place.
+    Completed,
+
+    /// The session was called off ahead of time.
+    Cancelled,
+
+    /// The client didn't turn up.
+    NoShow,
+}
+
+impl SessionStatus {
+    /// All session statuses.
+    pub const ALL: [SessionStatus; 4] = [
+        SessionStatus::Scheduled,
+        SessionStatus::Completed,
+        SessionStatus::Cancelled,
+        SessionStatus::NoShow,
+    ];
+
+    /// Gets the name the status is stored and parsed as.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionStatus::Scheduled => "scheduled",
+            SessionStatus::Completed => "completed",
+            SessionStatus::Cancelled => "cancelled",
+            SessionStatus::NoShow => "no-show",
+        }
+    }
+}
+
+impl std::fmt::Display for SessionStatus {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for SessionStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SessionStatus::ALL
+            .into_iter()
+            .find(|status| {
+                status.name().eq_ignore_ascii_case(s.trim())
+            })
+            .ok_or(format!(
+                "unknown session status: {} (expected one of \
+                    scheduled, completed, cancelled, no-show)",
+                s
+            ))
+    }
+}
+
+impl ToSql for SessionStatus {
+    fn to_sql(
+        &self,
+    ) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.name()))
+    }
+}
+
+impl FromSql for SessionStatus {
+    fn column_result(
+        value: ValueRef<'_>,
+    ) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e: String| {
+            FromSqlError::Other(e.into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::TrainingPlugin;
+    use dolmen::prelude::*;
+    use reliquary::prelude::*;
+
+    fn setup_test_context() -> dolmen::Result<Context>
+    {
+        let mut context = Context::new();
+        context
+            .add_plugin(DbPlugin)?
+            .add_plugin(db_commands::DbCommandsPlugin)?
+            .add_plugin(TrainingPlugin)?;
+        context
+            .get_resource_mut::<DbConfig>()
+            .unwrap()
+            .open_db_in_memory = true;
+        db_commands::startup(&mut context)?;
+        Ok(context)
+    }
+
+    // A new database is brought up to date when it's started, so the
+    // trainer table starts at the version its migrations reach, and rows
+    // can be added to it straight away.
+    #[test]
+    fn test_new_database() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+
+        let response = context.execute("db info")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Database connection open.\n\
+                No database path (in-memory connection)\n\
+                Schema versions: client v0, exercise v0, session v4, \
+                trainer v3"
+        );
+
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_table_row_ids("trainer")?,
+            Vec::<i64>::new()
+        );
+        let response =
+            context.execute("new --table=trainer")?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Inserted new row (id: 1) in table trainer."
+        );
+        assert_eq!(
+            context
+                .db_connection()?
+                .get_table_row_ids("trainer")?,
+            vec![1]
+        );
+
+        Ok(())
+    }
+
+    // A session table from before scheduling gets the scheduling fields
+    // added, with existing sessions left scheduled at midnight so they stay
+    // on the schedule and can still be read.
+    #[test]
+    fn test_migrate_sessions() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        context
+            .db_connection()?
+            .connection()?
+            .execute_batch(
+                "DROP TABLE session;
+                CREATE TABLE session (
+                    id INTEGER PRIMARY KEY,
+                    date TEXT,
+                    trainer INTEGER,
+                    client INTEGER,
+                    charge INTEGER
+                );
+                INSERT INTO session (date, trainer, client)
+                    VALUES ('2026-06-01', 1, 1);
+                UPDATE schema_version SET version = 0
+                    WHERE table_name = 'session';",
+            )
+            .map_err(|e| dolmen::Error::new(e.to_string()))?;
+
+        let applied =
+            db_commands::run_migrations(&mut context)?;
+        assert_eq!(
+            applied,
+            vec![
+                "session v1: add start_time",
+                "session v2: add duration_minutes",
+                "session v3: add location",
+                "session v4: add status",
+            ]
+        );
+
+        // the session is read as it was migrated, without filling anything
+        // in by hand
+        let session = crate::Session::from_table_row(
+            context.db_connection()?,
+            "session".into(),
+            RowId(1),
+        )?;
+        assert_eq!(
+            session.start_time(),
+            chrono::NaiveTime::MIN
+        );
+        assert_eq!(session.location(), "");
+
+        let sessions = crate::sessions_between(
+            context.db_connection()?,
+            chrono::NaiveDate::from_ymd_opt(
+                2026, 6, 1,
+            )
+            .unwrap(),
+            chrono::NaiveDate::from_ymd_opt(
+                2026, 6, 1,
+            )
+            .unwrap(),
+        )?;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(
+            sessions[0].1.status(),
+            crate::SessionStatus::Scheduled
+        );
+        assert_eq!(
+            sessions[0].1.duration_minutes(),
+            0
+        );
+
+        Ok(())
+    }
 }
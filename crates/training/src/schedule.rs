@@ -0,0 +1,1927 @@
+//! Session scheduling: the `session` command and the Schedule tab.
+use crate::{Session, SessionStatus};
+use chrono::{
+    Datelike, Days, NaiveDate, NaiveDateTime,
+    NaiveTime,
+};
+use clap::{Arg, ArgMatches, Command};
+use db_commands::with_transaction;
+use dolmen::prelude::*;
+use ratatui::crossterm::event::{
+    KeyCode, KeyModifiers,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::widgets::{
+    List, ListItem, ListState, StatefulWidget,
+};
+use reliquary::prelude::*;
+use std::collections::{HashMap, HashSet};
+use tabled::builder::Builder as TabledBuilder;
+use tui::prelude::*;
+use tui_textarea::Input;
+
+///////////////////////////////////////////////////////////////////////////////
+// PUBLIC API
+///////////////////////////////////////////////////////////////////////////////
+
+/// The details needed to put a new session on the schedule.
+#[derive(Clone, Debug)]
+pub struct NewSession {
+    /// The date the session takes place on.
+    pub date: NaiveDate,
+
+    /// The time the session starts.
+    pub start_time: NaiveTime,
+
+    /// The length of the session in minutes.
+    pub duration_minutes: u32,
+
+    /// Where the session takes place.
+    pub location: String,
+
+    /// The row ID of the trainer running the session.
+    pub trainer: RowId,
+
+    /// The row ID of the client attending the session.
+    pub client: RowId,
+}
+
+/// Adds a new session to the `session` table with the status `scheduled`.
+/// Returns the row ID of the new session.
+///
+/// * `db_connection` - A connection to the database.
+/// * `new_session` - The details of the session.
+pub fn schedule_session(
+    db_connection: &mut DbConnection,
+    new_session: &NewSession,
+) -> dolmen::Result<RowId> {
+    let session =
+        db_connection.new_row_in_table("session")?;
+    db_connection.set_field_in_table(
+        "session",
+        session,
+        "date",
+        new_session.date,
+    )?;
+    db_connection.set_field_in_table(
+        "session",
+        session,
+        "start_time",
+        new_session.start_time,
+    )?;
+    db_connection.set_field_in_table(
+        "session",
+        session,
+        "duration_minutes",
+        new_session.duration_minutes,
+    )?;
+    db_connection.set_field_in_table(
+        "session",
+        session,
+        "location",
+        new_session.location.clone(),
+    )?;
+    db_connection.set_field_in_table(
+        "session",
+        session,
+        "status",
+        SessionStatus::Scheduled,
+    )?;
+    db_connection.set_field_in_table(
+        "session",
+        session,
+        "trainer",
+        new_session.trainer.0,
+    )?;
+    db_connection.set_field_in_table(
+        "session",
+        session,
+        "client",
+        new_session.client.0,
+    )?;
+
+    Ok(session)
+}
+
+/// Moves a session to a new date and start time, keeping its duration.
+///
+/// * `db_connection` - A connection to the database.
+/// * `session` - The row ID of the session to move.
+/// * `date` - The new date.
+/// * `start_time` - The new start time.
+pub fn move_session(
+    db_connection: &mut DbConnection,
+    session: RowId,
+    date: NaiveDate,
+    start_time: NaiveTime,
+) -> dolmen::Result<()> {
+    // make sure the session exists before touching it
+    get_session(db_connection, session)?;
+
+    db_connection.set_field_in_table(
+        "session", session, "date", date,
+    )?;
+    db_connection.set_field_in_table(
+        "session",
+        session,
+        "start_time",
+        start_time,
+    )?;
+
+    Ok(())
+}
+
+/// Sets the status of a session.
+///
+/// * `db_connection` - A connection to the database.
+/// * `session` - The row ID of the session to update.
+/// * `status` - The new status.
+pub fn set_session_status(
+    db_connection: &mut DbConnection,
+    session: RowId,
+    status: SessionStatus,
+) -> dolmen::Result<()> {
+    get_session(db_connection, session)?;
+
+    db_connection.set_field_in_table(
+        "session", session, "status", status,
+    )
+}
+
+/// A function run after a session's status is changed with
+/// `update_session_status`. Takes the session's row ID, its new status and
+/// when it changed, and returns a message to report to the user, if there
+/// is one.
+pub type SessionStatusHook =
+    fn(
+        &mut DbConnection,
+        RowId,
+        SessionStatus,
+        NaiveDateTime,
+    ) -> dolmen::Result<Option<String>>;
+
+/// A resource storing the hooks to run when a session's status changes.
+/// Other plugins (such as billing) register hooks here to react to
+/// sessions being completed or cancelled.
+#[derive(Resource, Default)]
+pub struct SessionStatusHooks {
+    hooks: Vec<SessionStatusHook>,
+}
+
+/// Registers a hook to run whenever a session's status is changed with
+/// `update_session_status`.
+///
+/// * `context` - The context to register the hook in.
+/// * `hook` - The function to run.
+pub fn add_session_status_hook(
+    context: &mut Context,
+    hook: SessionStatusHook,
+) {
+    if !context.has_resource::<SessionStatusHooks>() {
+        context.add_resource(
+            SessionStatusHooks::default(),
+        );
+    }
+
+    if let Some(hooks) = context
+        .get_resource_mut::<SessionStatusHooks>()
+    {
+        hooks.hooks.push(hook);
+    }
+}
+
+/// Sets the status of a session, then runs every registered
+/// `SessionStatusHook`. Returns the messages reported by the hooks. The
+/// status is written and the hooks run in one transaction, so if a hook
+/// fails the status is left unchanged.
+///
+/// * `context` - The context to use.
+/// * `session` - The row ID of the session to update.
+/// * `status` - The new status.
+/// * `changed_at` - When the status changed (e.g. when the client called
+///   to cancel).
+pub fn update_session_status(
+    context: &mut Context,
+    session: RowId,
+    status: SessionStatus,
+    changed_at: NaiveDateTime,
+) -> dolmen::Result<Vec<String>> {
+    let hooks = session_status_hooks(context);
+    with_transaction(
+        context.db_connection()?,
+        |db_connection| {
+            set_session_status(
+                db_connection,
+                session,
+                status,
+            )?;
+            run_session_status_hooks(
+                db_connection,
+                &hooks,
+                session,
+                status,
+                changed_at,
+            )
+        },
+    )
+}
+
+/// Gets every scheduled session between two dates (inclusive), ordered by
+/// date and start time. Sessions without a date, start time, trainer or
+/// client (for example rows added with `new --table=session` and not yet
+/// filled in) are skipped.
+///
+/// * `db_connection` - A connection to the database.
+/// * `from` - The first date to include.
+/// * `to` - The last date to include.
+pub fn sessions_between(
+    db_connection: &mut DbConnection,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> dolmen::Result<Vec<(RowId, Session)>> {
+    let connection = db_connection.connection()?;
+    let mut stmt = connection
+        .prepare_cached(&format!(
+            "{} WHERE date >= ?1 AND date <= ?2
+                    AND start_time IS NOT NULL
+                    AND trainer IS NOT NULL
+                    AND client IS NOT NULL
+                ORDER BY date, start_time, id",
+            SELECT_SESSIONS
+        ))
+        .map_err(sql_error)?;
+    stmt.query_map(
+        rusqlite::params![from, to],
+        session_from_row,
+    )
+    .map_err(sql_error)?
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(sql_error)
+}
+
+/// Finds the other sessions the trainer of `session` is booked for at the
+/// same time, including sessions that run past midnight into the next day.
+/// Cancelled sessions never conflict. Returns the row IDs of the
+/// conflicting sessions, or an empty list if the trainer isn't
+/// double-booked.
+///
+/// * `db_connection` - A connection to the database.
+/// * `session` - The row ID of the session to check.
+pub fn find_trainer_conflicts(
+    db_connection: &mut DbConnection,
+    session: RowId,
+) -> dolmen::Result<Vec<RowId>> {
+    let this = get_session(db_connection, session)?;
+    if this.status == SessionStatus::Cancelled {
+        return Ok(Vec::new());
+    }
+
+    let overrun = session_overrun(db_connection)?;
+    let (_, end) = datetime_span(&this);
+    let nearby = sessions_between(
+        db_connection,
+        this.date - overrun,
+        end.date(),
+    )?;
+
+    Ok(nearby
+        .iter()
+        .filter(|(id, other)| {
+            *id != session
+                && other.trainer == this.trainer
+                && other.status
+                    != SessionStatus::Cancelled
+                && overlaps(&this, other)
+        })
+        .map(|(id, _)| *id)
+        .collect())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVATE IMPLEMENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// The columns of the `session` table, in the order `session_from_row`
+/// expects them. Missing optional fields fall back to their defaults.
+const SELECT_SESSIONS: &str =
+    "SELECT id, date, start_time,
+        COALESCE(duration_minutes, 0),
+        COALESCE(location, ''),
+        COALESCE(status, 'scheduled'),
+        trainer, client, charge
+    FROM session";
+
+/// Runs the status hooks for a session whose status was changed with the
+/// `set` command, so that editing the field by hand (e.g. in the Edit
+/// Table tab) bills or voids the session just like `session status`.
+/// `set` already runs this inside a transaction with the write.
+///
+/// * `context` - The context to use.
+/// * `session` - The row ID of the session that changed.
+pub(crate) fn session_status_set_hook(
+    context: &mut Context,
+    session: RowId,
+) -> dolmen::Result<Option<String>> {
+    let hooks = session_status_hooks(context);
+    let db_connection = context.db_connection()?;
+    let Some(status) = db_connection
+        .get_field_in_table_row::<Option<SessionStatus>>(
+            "session", session, "status",
+        )?
+    else {
+        return Ok(None);
+    };
+    let messages = run_session_status_hooks(
+        db_connection,
+        &hooks,
+        session,
+        status,
+        chrono::Local::now().naive_local(),
+    )?;
+    Ok((!messages.is_empty())
+        .then(|| messages.join("\n")))
+}
+
+fn session_status_hooks(
+    context: &Context,
+) -> Vec<SessionStatusHook> {
+    context
+        .get_resource::<SessionStatusHooks>()
+        .map(|h| h.hooks.clone())
+        .unwrap_or_default()
+}
+
+fn run_session_status_hooks(
+    db_connection: &mut DbConnection,
+    hooks: &[SessionStatusHook],
+    session: RowId,
+    status: SessionStatus,
+    changed_at: NaiveDateTime,
+) -> dolmen::Result<Vec<String>> {
+    let mut messages = Vec::new();
+    for hook in hooks {
+        if let Some(message) = hook(
+            db_connection,
+            session,
+            status,
+            changed_at,
+        )? {
+            messages.push(message);
+        }
+    }
+    Ok(messages)
+}
+
+fn sql_error(e: rusqlite::Error) -> dolmen::Error {
+    dolmen::Error::new(e.to_string())
+}
+
+fn session_from_row(
+    r: &rusqlite::Row,
+) -> rusqlite::Result<(RowId, Session)> {
+    Ok((
+        RowId(r.get(0)?),
+        Session {
+            date: r.get(1)?,
+            start_time: r.get(2)?,
+            duration_minutes: r.get(3)?,
+            location: r.get(4)?,
+            status: r.get(5)?,
+            trainer: RowId(r.get(6)?),
+            client: RowId(r.get(7)?),
+            charge: r
+                .get::<_, Option<i64>>(8)?
+                .map(RowId),
+        },
+    ))
+}
+
+/// Gets a single session by row ID.
+fn get_session(
+    db_connection: &mut DbConnection,
+    session: RowId,
+) -> dolmen::Result<Session> {
+    db_connection
+        .connection()?
+        .query_row(
+            &format!(
+                "{} WHERE id = ?1",
+                SELECT_SESSIONS
+            ),
+            [session.0],
+            session_from_row,
+        )
+        .map(|(_, s)| s)
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => {
+                dolmen::Error::new(format!(
+                    "session not found: {}",
+                    session.0
+                ))
+            }
+            e => dolmen::Error::new(format!(
+                "session {} is incomplete: {}",
+                session.0, e
+            )),
+        })
+}
+
+/// Gets the date and time a session starts and ends at. The end may be on
+/// the next day.
+fn datetime_span(
+    session: &Session,
+) -> (NaiveDateTime, NaiveDateTime) {
+    let start =
+        session.date.and_time(session.start_time);
+    (
+        start,
+        start
+            + chrono::Duration::minutes(
+                session.duration_minutes.into(),
+            ),
+    )
+}
+
+/// Returns whether two sessions overlap in time, even across midnight.
+/// Sessions that only touch (one ends as the other starts) don't overlap.
+fn overlaps(a: &Session, b: &Session) -> bool {
+    let (a_start, a_end) = datetime_span(a);
+    let (b_start, b_end) = datetime_span(b);
+    a_start < b_end && b_start < a_end
+}
+
+/// Gets how many days past its start date the longest session runs into,
+/// so that conflict checks look far enough around a date.
+fn session_overrun(
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<Days> {
+    let longest: u32 = db_connection
+        .connection()?
+        .query_row(
+            "SELECT COALESCE(MAX(duration_minutes), 0) FROM session",
+            [],
+            |r| r.get(0),
+        )
+        .map_err(sql_error)?;
+    Ok(Days::new(longest.div_ceil(24 * 60).into()))
+}
+
+/// Finds every session between two dates (inclusive) whose trainer is
+/// double-booked, counting sessions that run in from before `from` or on
+/// past `to`.
+fn double_booked_between(
+    db_connection: &mut DbConnection,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> dolmen::Result<HashSet<i64>> {
+    let overrun = session_overrun(db_connection)?;
+    let sessions = sessions_between(
+        db_connection,
+        from - overrun,
+        to + overrun,
+    )?;
+    Ok(double_booked(&sessions))
+}
+
+/// Finds every session in `sessions` whose trainer is double-booked.
+fn double_booked(
+    sessions: &[(RowId, Session)],
+) -> HashSet<i64> {
+    let mut result = HashSet::new();
+    for (i, (a_id, a)) in sessions.iter().enumerate() {
+        for (b_id, b) in &sessions[i + 1..] {
+            if a.trainer == b.trainer
+                && a.status != SessionStatus::Cancelled
+                && b.status != SessionStatus::Cancelled
+                && overlaps(a, b)
+            {
+                result.insert(a_id.0);
+                result.insert(b_id.0);
+            }
+        }
+    }
+    result
+}
+
+/// Resolves a row in `table` from either its row ID or its (unique,
+/// case-insensitive) name.
+fn resolve_named_row(
+    db_connection: &mut DbConnection,
+    table: &str,
+    text: &str,
+) -> dolmen::Result<RowId> {
+    let connection = db_connection.connection()?;
+    let ids = if let Ok(id) =
+        text.trim().parse::<i64>()
+    {
+        let mut stmt = connection
+            .prepare(&format!(
+                "SELECT id FROM {} WHERE id = ?1",
+                table
+            ))
+            .map_err(sql_error)?;
+        stmt.query_map([id], |r| r.get::<_, i64>(0))
+            .map_err(sql_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sql_error)?
+    } else {
+        let mut stmt = connection
+            .prepare(&format!(
+                "SELECT id FROM {} WHERE name = ?1 \
+                    COLLATE NOCASE",
+                table
+            ))
+            .map_err(sql_error)?;
+        stmt.query_map([text.trim()], |r| {
+            r.get::<_, i64>(0)
+        })
+        .map_err(sql_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(sql_error)?
+    };
+
+    match ids.as_slice() {
+        [id] => Ok(RowId(*id)),
+        [] => Err(dolmen::Error::new(format!(
+            "no {} found matching {}",
+            table, text
+        ))),
+        _ => Err(dolmen::Error::new(format!(
+            "more than one {} is named {}, use the row ID instead",
+            table, text
+        ))),
+    }
+}
+
+/// Builds the double-booking warning for a session, if there is one.
+fn conflict_warning(
+    db_connection: &mut DbConnection,
+    session: RowId,
+) -> dolmen::Result<Option<String>> {
+    let conflicts = find_trainer_conflicts(
+        db_connection,
+        session,
+    )?;
+    if conflicts.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "Warning: trainer is double-booked with session(s) {}.",
+        conflicts
+            .iter()
+            .map(|c| c.0.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )))
+}
+
+/// Gets the Monday of the week `date` falls in.
+fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Days::new(
+        date.weekday().num_days_from_monday().into(),
+    )
+}
+
+/// Gets the names of every row in a table that has a `name` field, keyed
+/// by row ID.
+fn names_by_id(
+    db_connection: &mut DbConnection,
+    table: &str,
+) -> dolmen::Result<HashMap<i64, String>> {
+    let connection = db_connection.connection()?;
+    let mut stmt = connection
+        .prepare(&format!(
+            "SELECT id, COALESCE(name, '') FROM {}",
+            table
+        ))
+        .map_err(sql_error)?;
+    stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+        .map_err(sql_error)?
+        .collect::<Result<HashMap<_, _>, _>>()
+        .map_err(sql_error)
+}
+
+pub(crate) fn add_session_command(
+    context: &mut Context,
+) -> dolmen::Result<()> {
+    context.add_command(Command::new("session")
+        .about("Schedule and manage training sessions")
+        .subcommand(Command::new("new")
+            .about("Schedules a new session")
+            .arg(Arg::new("trainer")
+                .long("trainer")
+                .required(true)
+                .help("The trainer's row ID or name")
+            )
+            .arg(Arg::new("client")
+                .long("client")
+                .required(true)
+                .help("The client's row ID or name")
+            )
+            .arg(Arg::new("date")
+                .long("date")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .required(true)
+                .help("The date of the session (YYYY-MM-DD)")
+            )
+            .arg(Arg::new("start")
+                .long("start")
+                .value_parser(clap::value_parser!(NaiveTime))
+                .required(true)
+                .help("The start time of the session (HH:MM)")
+            )
+            .arg(Arg::new("duration")
+                .long("duration")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("60")
+                .help("The length of the session in minutes")
+            )
+            .arg(Arg::new("location")
+                .long("location")
+                .default_value("")
+                .help("Where the session takes place")
+            )
+        )
+        .subcommand(Command::new("move")
+            .about("Moves a session to a new date and/or time")
+            .arg(Arg::new("session-id")
+                .long("session-id")
+                .value_parser(clap::value_parser!(i64))
+                .required(true)
+                .help("The session row ID to move")
+            )
+            .arg(Arg::new("date")
+                .long("date")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .help("The new date (YYYY-MM-DD)")
+            )
+            .arg(Arg::new("start")
+                .long("start")
+                .value_parser(clap::value_parser!(NaiveTime))
+                .help("The new start time (HH:MM)")
+            )
+        )
+        .subcommand(Command::new("status")
+            .about("Sets the status of a session")
+            .arg(Arg::new("session-id")
+                .long("session-id")
+                .value_parser(clap::value_parser!(i64))
+                .required(true)
+                .help("The session row ID to update")
+            )
+            .arg(Arg::new("status")
+                .long("status")
+                .value_parser(clap::value_parser!(SessionStatus))
+                .required(true)
+                .help("scheduled, completed, cancelled or no-show")
+            )
+        )
+        .subcommand(Command::new("week")
+            .about("Lists the sessions in a week")
+            .arg(Arg::new("date")
+                .long("date")
+                .value_parser(clap::value_parser!(NaiveDate))
+                .help("Any date in the week to list (defaults to today)")
+            )
+        )
+        .subcommand_required(true),
+        process_session_command
+    )?;
+    Ok(())
+}
+
+fn process_session_command(
+    context: &mut Context,
+    arg_matches: &ArgMatches,
+) -> dolmen::Result<CommandResponse> {
+    match arg_matches.subcommand() {
+        Some(("new", sub_m)) => {
+            process_session_new_command(
+                sub_m,
+                context.db_connection()?,
+            )
+        }
+        Some(("move", sub_m)) => {
+            process_session_move_command(
+                sub_m,
+                context.db_connection()?,
+            )
+        }
+        Some(("status", sub_m)) => {
+            let session = RowId(
+                *sub_m
+                    .get_one::<i64>("session-id")
+                    .expect(
+                        "Missing required argument",
+                    ),
+            );
+            let status = *sub_m
+                .get_one::<SessionStatus>("status")
+                .expect("Missing required argument");
+            let mut response_text = format!(
+                "Marked session {} as {}.",
+                session.0, status
+            );
+            for message in update_session_status(
+                context,
+                session,
+                status,
+                chrono::Local::now().naive_local(),
+            )? {
+                response_text += "\n";
+                response_text += message.as_str();
+            }
+            Ok(CommandResponse::new(response_text))
+        }
+        Some(("week", sub_m)) => {
+            let date = sub_m
+                .get_one::<NaiveDate>("date")
+                .copied()
+                .unwrap_or(
+                    chrono::Local::now().date_naive(),
+                );
+            week_text(
+                context.db_connection()?,
+                week_start(date),
+            )
+            .map(CommandResponse::new)
+        }
+        _ => Err(dolmen::Error::new(
+            "subcommand not recognized",
+        )),
+    }
+}
+
+fn process_session_new_command(
+    arg_matches: &ArgMatches,
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<CommandResponse> {
+    let trainer = resolve_named_row(
+        db_connection,
+        "trainer",
+        arg_matches
+            .get_one::<String>("trainer")
+            .expect("Missing required argument"),
+    )?;
+    let client = resolve_named_row(
+        db_connection,
+        "client",
+        arg_matches
+            .get_one::<String>("client")
+            .expect("Missing required argument"),
+    )?;
+    let new_session = NewSession {
+        date: *arg_matches
+            .get_one::<NaiveDate>("date")
+            .expect("Missing required argument"),
+        start_time: *arg_matches
+            .get_one::<NaiveTime>("start")
+            .expect("Missing required argument"),
+        duration_minutes: *arg_matches
+            .get_one::<u32>("duration")
+            .expect("Missing required argument"),
+        location: arg_matches
+            .get_one::<String>("location")
+            .expect("Missing required argument")
+            .clone(),
+        trainer,
+        client,
+    };
+
+    let session =
+        schedule_session(db_connection, &new_session)?;
+
+    let mut response_text = format!(
+        "Scheduled session (id: {}) on {} at {}.",
+        session.0,
+        new_session.date,
+        new_session.start_time.format("%H:%M")
+    );
+    if let Some(warning) =
+        conflict_warning(db_connection, session)?
+    {
+        response_text += "\n";
+        response_text += warning.as_str();
+    }
+    Ok(CommandResponse::new(response_text))
+}
+
+fn process_session_move_command(
+    arg_matches: &ArgMatches,
+    db_connection: &mut DbConnection,
+) -> dolmen::Result<CommandResponse> {
+    let session = RowId(
+        *arg_matches
+            .get_one::<i64>("session-id")
+            .expect("Missing required argument"),
+    );
+    let current = get_session(db_connection, session)?;
+    let date = arg_matches
+        .get_one::<NaiveDate>("date")
+        .copied()
+        .unwrap_or(current.date);
+    let start_time = arg_matches
+        .get_one::<NaiveTime>("start")
+        .copied()
+        .unwrap_or(current.start_time);
+
+    move_session(
+        db_connection,
+        session,
+        date,
+        start_time,
+    )?;
+
+    let mut response_text = format!(
+        "Moved session {} to {} at {}.",
+        session.0,
+        date,
+        start_time.format("%H:%M")
+    );
+    if let Some(warning) =
+        conflict_warning(db_connection, session)?
+    {
+        response_text += "\n";
+        response_text += warning.as_str();
+    }
+    Ok(CommandResponse::new(response_text))
+}
+
+/// Lists the sessions in the week starting on `monday` as a table.
+fn week_text(
+    db_connection: &mut DbConnection,
+    monday: NaiveDate,
+) -> dolmen::Result<String> {
+    let sunday = monday + Days::new(6);
+    let sessions = sessions_between(
+        db_connection,
+        monday,
+        sunday,
+    )?;
+    if sessions.is_empty() {
+        return Ok(format!(
+            "No sessions in the week of {}.",
+            monday
+        ));
+    }
+
+    let trainers =
+        names_by_id(db_connection, "trainer")?;
+    let clients =
+        names_by_id(db_connection, "client")?;
+    let conflicts = double_booked_between(
+        db_connection,
+        monday,
+        sunday,
+    )?;
+
+    let mut tabled_builder = TabledBuilder::default();
+    tabled_builder.push_record([
+        "ID", "Date", "Time", "Trainer", "Client",
+        "Location", "Status",
+    ]);
+    for (id, session) in &sessions {
+        let mut status = session.status.to_string();
+        if conflicts.contains(&id.0) {
+            status += " (double-booked)";
+        }
+        tabled_builder.push_record([
+            id.0.to_string(),
+            session
+                .date
+                .format("%a %Y-%m-%d")
+                .to_string(),
+            format!(
+                "{}-{}",
+                session.start_time.format("%H:%M"),
+                session.end_time().format("%H:%M")
+            ),
+            trainers
+                .get(&session.trainer.0)
+                .cloned()
+                .unwrap_or_default(),
+            clients
+                .get(&session.client.0)
+                .cloned()
+                .unwrap_or_default(),
+            session.location.clone(),
+            status,
+        ]);
+    }
+    Ok(tabled_builder.build().to_string())
+}
+
+pub(crate) struct ScheduleTabImpl;
+
+pub(crate) struct ScheduleTabState {
+    /// The Monday of the week being shown.
+    week_start: NaiveDate,
+
+    /// The selected day of the week (0 is Monday).
+    selected_day: usize,
+
+    /// The index of the selected session within the selected day.
+    selected_session: usize,
+
+    /// The text input for a new session, while one is being entered.
+    text_area: Option<tui_textarea::TextArea<'static>>,
+
+    /// A message to show at the bottom of the tab, such as an error.
+    message: Option<String>,
+}
+
+impl Default for ScheduleTabState {
+    fn default() -> Self {
+        let today = chrono::Local::now().date_naive();
+        Self {
+            week_start: week_start(today),
+            selected_day: today
+                .weekday()
+                .num_days_from_monday()
+                as usize,
+            selected_session: 0,
+            text_area: None,
+            message: None,
+        }
+    }
+}
+
+impl ScheduleTabState {
+    fn selected_date(&self) -> NaiveDate {
+        self.week_start
+            + Days::new(self.selected_day as u64)
+    }
+
+    fn select_date(&mut self, date: NaiveDate) {
+        self.week_start = week_start(date);
+        self.selected_day =
+            date.weekday().num_days_from_monday()
+                as usize;
+        self.selected_session = 0;
+    }
+}
+
+/// The text shown above the new session input, describing what to type.
+const NEW_SESSION_PROMPT: &str = "New session: HH:MM, minutes, trainer, client, location";
+
+impl TabImpl for ScheduleTabImpl {
+    type State = ScheduleTabState;
+
+    fn title() -> String {
+        "📅 Schedule".into()
+    }
+
+    fn render(
+        context: &mut Context,
+        buffer: &mut Buffer,
+        rect: Rect,
+        block: Block,
+        tab_id: usize,
+    ) {
+        if let Err(e) = render_schedule(
+            context,
+            tab_id,
+            block.clone(),
+            rect,
+            buffer,
+        ) {
+            Paragraph::new(Line::from(
+                e.message()
+                    .clone()
+                    .unwrap_or_default(),
+            ))
+            .block(block)
+            .render(rect, buffer);
+        }
+    }
+
+    fn keybinds() -> Vec<KeyBind> {
+        vec![
+            KeyBind {
+                name: "prev_day".into(),
+                display_key: "Left".into(),
+                display_name: "Prev Day".into(),
+                key_code: KeyCode::Left,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "next_day".into(),
+                display_key: "Right".into(),
+                display_name: "Next Day".into(),
+                key_code: KeyCode::Right,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "prev_session".into(),
+                display_key: "Up".into(),
+                display_name: "Prev Session".into(),
+                key_code: KeyCode::Up,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "next_session".into(),
+                display_key: "Down".into(),
+                display_name: "Next Session".into(),
+                key_code: KeyCode::Down,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "prev_week".into(),
+                display_key: "PgUp".into(),
+                display_name: "Prev Week".into(),
+                key_code: KeyCode::PageUp,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "next_week".into(),
+                display_key: "PgDn".into(),
+                display_name: "Next Week".into(),
+                key_code: KeyCode::PageDown,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "today".into(),
+                display_key: "Home".into(),
+                display_name: "Today".into(),
+                key_code: KeyCode::Home,
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "new_session".into(),
+                display_key: "Ctrl+N".into(),
+                display_name: "New Session".into(),
+                key_code: KeyCode::Char('n'),
+                modifiers: KeyModifiers::CONTROL,
+            },
+            KeyBind {
+                name: "move_earlier_day".into(),
+                display_key: "Shift+Left".into(),
+                display_name: "Move Day Earlier"
+                    .into(),
+                key_code: KeyCode::Left,
+                modifiers: KeyModifiers::SHIFT,
+            },
+            KeyBind {
+                name: "move_later_day".into(),
+                display_key: "Shift+Right".into(),
+                display_name: "Move Day Later".into(),
+                key_code: KeyCode::Right,
+                modifiers: KeyModifiers::SHIFT,
+            },
+            KeyBind {
+                name: "move_earlier_time".into(),
+                display_key: "Shift+Up".into(),
+                display_name: "Move 15m Earlier"
+                    .into(),
+                key_code: KeyCode::Up,
+                modifiers: KeyModifiers::SHIFT,
+            },
+            KeyBind {
+                name: "move_later_time".into(),
+                display_key: "Shift+Down".into(),
+                display_name: "Move 15m Later".into(),
+                key_code: KeyCode::Down,
+                modifiers: KeyModifiers::SHIFT,
+            },
+            KeyBind {
+                name: "mark_completed".into(),
+                display_key: "C".into(),
+                display_name: "Completed".into(),
+                key_code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "mark_cancelled".into(),
+                display_key: "X".into(),
+                display_name: "Cancelled".into(),
+                key_code: KeyCode::Char('x'),
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "mark_no_show".into(),
+                display_key: "N".into(),
+                display_name: "No-Show".into(),
+                key_code: KeyCode::Char('n'),
+                modifiers: KeyModifiers::NONE,
+            },
+            KeyBind {
+                name: "mark_scheduled".into(),
+                display_key: "S".into(),
+                display_name: "Scheduled".into(),
+                key_code: KeyCode::Char('s'),
+                modifiers: KeyModifiers::NONE,
+            },
+        ]
+    }
+
+    fn handle_key(
+        context: &mut Context,
+        bind: &str,
+        tab_id: usize,
+    ) {
+        let result = match bind {
+            "prev_day" => {
+                shift_selected_day(context, tab_id, -1)
+            }
+            "next_day" => {
+                shift_selected_day(context, tab_id, 1)
+            }
+            "prev_week" => {
+                shift_selected_day(context, tab_id, -7)
+            }
+            "next_week" => {
+                shift_selected_day(context, tab_id, 7)
+            }
+            "today" => context
+                .tab_state_mut::<ScheduleTabState>(
+                    tab_id,
+                )
+                .map(|state| {
+                    state.select_date(
+                        chrono::Local::now()
+                            .date_naive(),
+                    )
+                }),
+            "prev_session" => context
+                .tab_state_mut::<ScheduleTabState>(
+                    tab_id,
+                )
+                .map(|state| {
+                    state.selected_session = state
+                        .selected_session
+                        .saturating_sub(1)
+                }),
+            "next_session" => context
+                .tab_state_mut::<ScheduleTabState>(
+                    tab_id,
+                )
+                .map(|state| {
+                    state.selected_session += 1
+                }),
+            "new_session" => {
+                start_new_session(context, tab_id)
+            }
+            "move_earlier_day" => {
+                move_selected_session(
+                    context, tab_id, -1, 0,
+                )
+            }
+            "move_later_day" => move_selected_session(
+                context, tab_id, 1, 0,
+            ),
+            "move_earlier_time" => {
+                move_selected_session(
+                    context, tab_id, 0, -15,
+                )
+            }
+            "move_later_time" => {
+                move_selected_session(
+                    context, tab_id, 0, 15,
+                )
+            }
+            "mark_completed" => set_selected_status(
+                context,
+                tab_id,
+                SessionStatus::Completed,
+            ),
+            "mark_cancelled" => set_selected_status(
+                context,
+                tab_id,
+                SessionStatus::Cancelled,
+            ),
+            "mark_no_show" => set_selected_status(
+                context,
+                tab_id,
+                SessionStatus::NoShow,
+            ),
+            "mark_scheduled" => set_selected_status(
+                context,
+                tab_id,
+                SessionStatus::Scheduled,
+            ),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            if let Ok(state) = context
+                .tab_state_mut::<ScheduleTabState>(
+                    tab_id,
+                )
+            {
+                state.message = e.message().clone();
+            }
+        }
+    }
+
+    fn handle_text(
+        context: &mut Context,
+        ev: ratatui::crossterm::event::Event,
+        tab_id: usize,
+    ) {
+        match ev.into() {
+            Input {
+                key: tui_textarea::Key::Esc,
+                ..
+            } => {
+                end_text_input(context, tab_id);
+            }
+            Input {
+                key: tui_textarea::Key::Enter,
+                ..
+            } => {
+                let result = submit_new_session(
+                    context, tab_id,
+                );
+                end_text_input(context, tab_id);
+                let message = match result {
+                    Ok(message) => message,
+                    Err(e) => e.message().clone(),
+                };
+                if let Ok(state) = context
+                    .tab_state_mut::<ScheduleTabState>(
+                    tab_id,
+                ) {
+                    state.message = message;
+                }
+            }
+            input => {
+                if let Ok(state) = context
+                    .tab_state_mut::<ScheduleTabState>(
+                    tab_id,
+                ) && let Some(text_area) =
+                    &mut state.text_area
+                {
+                    text_area.input(input);
+                }
+            }
+        }
+    }
+}
+
+fn render_schedule(
+    context: &mut Context,
+    tab_id: usize,
+    block: Block,
+    rect: Rect,
+    buffer: &mut Buffer,
+) -> dolmen::Result<()> {
+    let monday = context
+        .tab_state::<ScheduleTabState>(tab_id)?
+        .week_start;
+    let sunday = monday + Days::new(6);
+
+    let db_connection = context.db_connection()?;
+    let sessions = sessions_between(
+        db_connection,
+        monday,
+        sunday,
+    )?;
+    let trainers =
+        names_by_id(db_connection, "trainer")?;
+    let clients =
+        names_by_id(db_connection, "client")?;
+    let conflicts = double_booked_between(
+        db_connection,
+        monday,
+        sunday,
+    )?;
+
+    let state = context
+        .tab_state_mut::<ScheduleTabState>(tab_id)?;
+
+    // keep the selected session in range of the selected day
+    let selected_date = state.selected_date();
+    let day_count = sessions
+        .iter()
+        .filter(|(_, s)| s.date == selected_date)
+        .count();
+    state.selected_session = state
+        .selected_session
+        .min(day_count.saturating_sub(1));
+
+    let block = block.title(Line::from(format!(
+        " Week of {} ",
+        monday.format("%B %-d, %Y")
+    )));
+    let inner = block.inner(rect);
+    Widget::render(block, rect, buffer);
+
+    let [week_area, footer_area] = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(3),
+    ])
+    .areas(inner);
+    let day_areas = Layout::horizontal(
+        [Constraint::Ratio(1, 7); 7],
+    )
+    .split(week_area);
+
+    let mut selected_conflict = None;
+    for (day_idx, day_area) in
+        day_areas.iter().enumerate()
+    {
+        let date = monday + Days::new(day_idx as u64);
+        let is_selected_day =
+            day_idx == state.selected_day;
+        let day_sessions = sessions
+            .iter()
+            .filter(|(_, s)| s.date == date)
+            .collect::<Vec<_>>();
+
+        let items = day_sessions
+            .iter()
+            .map(|(id, s)| {
+                let style = if conflicts
+                    .contains(&id.0)
+                {
+                    Style::new().fg(Color::Red)
+                } else if s.status
+                    == SessionStatus::Cancelled
+                {
+                    Style::new().fg(Color::DarkGray)
+                } else {
+                    Style::new()
+                };
+                ListItem::new(vec![
+                    Line::from(format!(
+                        "{}-{}",
+                        s.start_time.format("%H:%M"),
+                        s.end_time().format("%H:%M")
+                    ))
+                    .bold(),
+                    Line::from(
+                        clients
+                            .get(&s.client.0)
+                            .cloned()
+                            .unwrap_or_default(),
+                    ),
+                    Line::from(format!(
+                        "{} {}",
+                        trainers
+                            .get(&s.trainer.0)
+                            .cloned()
+                            .unwrap_or_default(),
+                        s.location
+                    )),
+                    Line::from(format!(
+                        "[{}]",
+                        s.status
+                    )),
+                ])
+                .style(style)
+            })
+            .collect::<Vec<_>>();
+
+        let day_block = Block::bordered()
+            .title(Line::from(
+                date.format("%a %m-%d").to_string(),
+            ))
+            .border_style(if is_selected_day {
+                Style::new().fg(Color::Yellow)
+            } else {
+                Style::new()
+            });
+
+        let mut list_state = ListState::default();
+        if is_selected_day && !day_sessions.is_empty()
+        {
+            list_state
+                .select(Some(state.selected_session));
+            let selected_id =
+                day_sessions[state.selected_session].0;
+            if conflicts.contains(&selected_id.0) {
+                selected_conflict = Some(selected_id);
+            }
+        }
+
+        StatefulWidget::render(
+            List::new(items)
+                .block(day_block)
+                .highlight_style(
+                    Style::new().reversed(),
+                ),
+            *day_area,
+            buffer,
+            &mut list_state,
+        );
+    }
+
+    let footer_block = Block::bordered();
+    if let Some(text_area) = &mut state.text_area {
+        text_area.set_block(
+            footer_block.title(NEW_SESSION_PROMPT),
+        );
+        text_area.render(footer_area, buffer);
+    } else {
+        let footer_text = if let Some(message) =
+            &state.message
+        {
+            message.clone()
+        } else if let Some(conflict) =
+            selected_conflict
+        {
+            format!(
+                "Warning: session {} double-books its trainer.",
+                conflict.0
+            )
+        } else {
+            String::new()
+        };
+        Paragraph::new(Line::from(footer_text))
+            .block(footer_block)
+            .render(footer_area, buffer);
+    }
+
+    Ok(())
+}
+
+/// Moves the selection by a number of days, changing weeks as needed.
+fn shift_selected_day(
+    context: &mut Context,
+    tab_id: usize,
+    days: i64,
+) -> dolmen::Result<()> {
+    let state = context
+        .tab_state_mut::<ScheduleTabState>(tab_id)?;
+    state.message = None;
+    let date = state.selected_date()
+        + chrono::Duration::days(days);
+    state.select_date(date);
+    Ok(())
+}
+
+/// Gets the row ID of the selected session, if there is one.
+fn selected_session(
+    context: &mut Context,
+    tab_id: usize,
+) -> dolmen::Result<Option<RowId>> {
+    let state = context
+        .tab_state::<ScheduleTabState>(tab_id)?;
+    let date = state.selected_date();
+    let index = state.selected_session;
+    let sessions = sessions_between(
+        context.db_connection()?,
+        date,
+        date,
+    )?;
+    Ok(sessions.get(index).map(|(id, _)| *id))
+}
+
+/// Moves the selected session by a number of days and minutes, then
+/// follows it with the selection.
+fn move_selected_session(
+    context: &mut Context,
+    tab_id: usize,
+    days: i64,
+    minutes: i64,
+) -> dolmen::Result<()> {
+    let Some(session) =
+        selected_session(context, tab_id)?
+    else {
+        return Ok(());
+    };
+    let db_connection = context.db_connection()?;
+    let current = get_session(db_connection, session)?;
+    let date =
+        current.date + chrono::Duration::days(days);
+    let start_time = current.start_time
+        + chrono::Duration::minutes(minutes);
+    move_session(
+        db_connection,
+        session,
+        date,
+        start_time,
+    )?;
+    let warning =
+        conflict_warning(db_connection, session)?;
+
+    // find where the session ended up so it stays selected
+    let index =
+        sessions_between(db_connection, date, date)?
+            .iter()
+            .position(|(id, _)| *id == session)
+            .unwrap_or(0);
+    let state = context
+        .tab_state_mut::<ScheduleTabState>(tab_id)?;
+    state.select_date(date);
+    state.selected_session = index;
+    state.message = warning;
+    Ok(())
+}
+
+fn set_selected_status(
+    context: &mut Context,
+    tab_id: usize,
+    status: SessionStatus,
+) -> dolmen::Result<()> {
+    let Some(session) =
+        selected_session(context, tab_id)?
+    else {
+        return Ok(());
+    };
+    let mut message = format!(
+        "Marked session {} as {}.",
+        session.0, status
+    );
+    for hook_message in update_session_status(
+        context,
+        session,
+        status,
+        chrono::Local::now().naive_local(),
+    )? {
+        message += " ";
+        message += hook_message.as_str();
+    }
+    context
+        .tab_state_mut::<ScheduleTabState>(tab_id)?
+        .message = Some(message);
+    Ok(())
+}
+
+fn start_new_session(
+    context: &mut Context,
+    tab_id: usize,
+) -> dolmen::Result<()> {
+    let state = context
+        .tab_state_mut::<ScheduleTabState>(tab_id)?;
+    state.message = None;
+    state.text_area =
+        Some(tui_textarea::TextArea::default());
+    context
+        .get_resource_mut::<Tui>()
+        .ok_or(dolmen::Error::default())?
+        .set_input_mode(tui::TuiInputMode::Text);
+    Ok(())
+}
+
+fn end_text_input(
+    context: &mut Context,
+    tab_id: usize,
+) {
+    if let Some(tui) =
+        context.get_resource_mut::<Tui>()
+    {
+        tui.set_input_mode(tui::TuiInputMode::Bind);
+    }
+    if let Ok(state) = context
+        .tab_state_mut::<ScheduleTabState>(tab_id)
+    {
+        state.text_area = None;
+    }
+}
+
+/// Schedules a session on the selected day from the text typed into the
+/// new session input (see `NEW_SESSION_PROMPT`). Returns the message to
+/// show afterwards.
+fn submit_new_session(
+    context: &mut Context,
+    tab_id: usize,
+) -> dolmen::Result<Option<String>> {
+    let state = context
+        .tab_state::<ScheduleTabState>(tab_id)?;
+    let date = state.selected_date();
+    let text = state
+        .text_area
+        .as_ref()
+        .and_then(|t| t.lines().first().cloned())
+        .unwrap_or_default();
+
+    let parts = text
+        .split(',')
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    let [
+        start,
+        duration,
+        trainer,
+        client,
+        location @ ..,
+    ] = parts.as_slice()
+    else {
+        return Err(dolmen::Error::new(format!(
+            "expected {}",
+            NEW_SESSION_PROMPT
+                .trim_start_matches("New session: ")
+        )));
+    };
+    let start_time =
+        start.parse::<NaiveTime>().map_err(|e| {
+            dolmen::Error::new(format!(
+                "invalid start time {}: {}",
+                start, e
+            ))
+        })?;
+    let duration_minutes =
+        duration.parse::<u32>().map_err(|e| {
+            dolmen::Error::new(format!(
+                "invalid duration {}: {}",
+                duration, e
+            ))
+        })?;
+
+    let db_connection = context.db_connection()?;
+    let new_session = NewSession {
+        date,
+        start_time,
+        duration_minutes,
+        location: location.join(", "),
+        trainer: resolve_named_row(
+            db_connection,
+            "trainer",
+            trainer,
+        )?,
+        client: resolve_named_row(
+            db_connection,
+            "client",
+            client,
+        )?,
+    };
+    let session =
+        schedule_session(db_connection, &new_session)?;
+
+    Ok(Some(
+        conflict_warning(db_connection, session)?
+            .unwrap_or(format!(
+                "Scheduled session {} on {} at {}.",
+                session.0,
+                date,
+                start_time.format("%H:%M")
+            )),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        NewSession, SessionStatus, TrainingPlugin,
+        find_trainer_conflicts, schedule_session,
+        sessions_between,
+    };
+    use chrono::{NaiveDate, NaiveTime};
+    use dolmen::prelude::*;
+    use reliquary::prelude::*;
+
+    fn setup_test_context() -> dolmen::Result<Context>
+    {
+        let mut context = Context::new();
+
+        context
+            .add_plugin(DbPlugin)?
+            .add_plugin(TrainingPlugin)?;
+
+        context
+            .get_resource_mut::<DbConfig>()
+            .unwrap()
+            .open_db_in_memory = true;
+
+        context.startup()?;
+
+        Ok(context)
+    }
+
+    fn add_test_row(
+        db_connection: &mut DbConnection,
+        table: &str,
+        name: &str,
+    ) -> dolmen::Result<RowId> {
+        let row =
+            db_connection.new_row_in_table(table)?;
+        db_connection.set_field_in_table(
+            table, row, "name", name,
+        )?;
+        Ok(row)
+    }
+
+    fn test_session(
+        trainer: RowId,
+        client: RowId,
+        start: &str,
+        duration_minutes: u32,
+    ) -> NewSession {
+        NewSession {
+            date: NaiveDate::from_ymd_opt(2026, 6, 1)
+                .unwrap(),
+            start_time: start
+                .parse::<NaiveTime>()
+                .unwrap(),
+            duration_minutes,
+            location: "Gym".into(),
+            trainer,
+            client,
+        }
+    }
+
+    fn conflict_ids(
+        db_connection: &mut DbConnection,
+        session: RowId,
+    ) -> dolmen::Result<Vec<i64>> {
+        Ok(find_trainer_conflicts(
+            db_connection,
+            session,
+        )?
+        .iter()
+        .map(|c| c.0)
+        .collect())
+    }
+
+    // One trainer with a 9:00-10:00 session. A 9:30 session overlaps it, a
+    // 10:00 session only touches it, and another trainer's 9:00 session is
+    // unrelated. Cancelling the 9:30 session clears the conflict.
+    #[test]
+    fn test_trainer_conflicts() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let other_trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tim",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+
+        let first = schedule_session(
+            db_connection,
+            &test_session(
+                trainer, client, "09:00", 60,
+            ),
+        )?;
+        let overlapping = schedule_session(
+            db_connection,
+            &test_session(
+                trainer, client, "09:30", 30,
+            ),
+        )?;
+        let touching = schedule_session(
+            db_connection,
+            &test_session(
+                trainer, client, "10:00", 60,
+            ),
+        )?;
+        let other = schedule_session(
+            db_connection,
+            &test_session(
+                other_trainer,
+                client,
+                "09:00",
+                60,
+            ),
+        )?;
+
+        assert_eq!(
+            conflict_ids(db_connection, first)?,
+            vec![overlapping.0]
+        );
+        assert_eq!(
+            conflict_ids(db_connection, touching)?,
+            Vec::<i64>::new()
+        );
+        assert_eq!(
+            conflict_ids(db_connection, other)?,
+            Vec::<i64>::new()
+        );
+
+        crate::set_session_status(
+            db_connection,
+            overlapping,
+            SessionStatus::Cancelled,
+        )?;
+        assert_eq!(
+            conflict_ids(db_connection, first)?,
+            Vec::<i64>::new()
+        );
+
+        let day = NaiveDate::from_ymd_opt(2026, 6, 1)
+            .unwrap();
+        let sessions =
+            sessions_between(db_connection, day, day)?;
+        assert_eq!(sessions.len(), 4);
+        assert_eq!(
+            sessions[2].1.status(),
+            SessionStatus::Cancelled
+        );
+
+        Ok(())
+    }
+
+    // A 23:30 session running 60 minutes overlaps a 00:00 session on the
+    // next day, but not a 00:30 one.
+    #[test]
+    fn test_trainer_conflicts_past_midnight()
+    -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        let trainer = add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        let client = add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+
+        let late = schedule_session(
+            db_connection,
+            &test_session(
+                trainer, client, "23:30", 60,
+            ),
+        )?;
+        let next_day = |start: &str| NewSession {
+            date: NaiveDate::from_ymd_opt(2026, 6, 2)
+                .unwrap(),
+            ..test_session(trainer, client, start, 60)
+        };
+        let midnight = schedule_session(
+            db_connection,
+            &next_day("00:00"),
+        )?;
+        let after = schedule_session(
+            db_connection,
+            &next_day("00:30"),
+        )?;
+
+        assert_eq!(
+            conflict_ids(db_connection, late)?,
+            vec![midnight.0]
+        );
+        assert_eq!(
+            conflict_ids(db_connection, midnight)?,
+            vec![late.0, after.0]
+        );
+        assert_eq!(
+            conflict_ids(db_connection, after)?,
+            vec![midnight.0]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_commands() -> dolmen::Result<()> {
+        let mut context = setup_test_context()?;
+        let db_connection = context.db_connection()?;
+        add_test_row(
+            db_connection,
+            "trainer",
+            "Tara",
+        )?;
+        add_test_row(
+            db_connection,
+            "client",
+            "Clarissa",
+        )?;
+
+        let response = context.execute(
+            "session new --trainer=Tara --client=clarissa \
+                --date=2026-06-01 --start=09:00",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Scheduled session (id: 1) on 2026-06-01 at 09:00."
+        );
+
+        let response = context.execute(
+            "session new --trainer=1 --client=1 \
+                --date=2026-06-02 --start=09:30 --duration=45",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Scheduled session (id: 2) on 2026-06-02 at 09:30."
+        );
+
+        let response = context.execute(
+            "session move --session-id=2 --date=2026-06-01",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Moved session 2 to 2026-06-01 at 09:30.\n\
+                Warning: trainer is double-booked with session(s) 1."
+        );
+
+        let response = context.execute(
+            "session status --session-id=1 --status=no-show",
+        )?;
+        assert_eq!(
+            response.text().unwrap(),
+            "Marked session 1 as no-show."
+        );
+
+        assert!(
+            context
+                .execute("session new --trainer=Nobody --client=1 --date=2026-06-01 --start=09:00")
+                .is_err()
+        );
+
+        Ok(())
+    }
+}